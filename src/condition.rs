@@ -0,0 +1,94 @@
+//! Conditions of `when` blocks.
+//!
+//! A block such as `when(profile == "prod") { ... }` holds attributes and nodes that
+//! only belong to the enclosing node if its condition holds.  Conditions compare
+//! variables with strings using `==` and `!=`, and can be combined with `&&`, `||`, `!`
+//! and parentheses.  They are evaluated against the profiles and variables given in
+//! `LoadOptions` while the document is being built, and the contents of inactive
+//! blocks are dropped.
+//!
+//! The variable `profile` is special: `profile == "prod"` holds if `prod` is one of
+//! the active profiles, so several profiles can be active at once.  Any other variable
+//! must be given a value in `LoadOptions::variables`.
+
+use super::figtree::LoadOptions;
+use super::parser::ParseError;
+
+/// The name of the variable that is compared against the active profiles.
+pub const PROFILE: &str = "profile";
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Condition {
+    Equals(String, String),
+    NotEquals(String, String),
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+impl Condition {
+    /// Decide whether the condition holds for the profiles and variables in `options`.
+    ///
+    /// # Failures
+    /// Returns `ParseError::UnknownVariable` if the condition uses a variable that has
+    /// not been given a value.
+    pub fn evaluate(&self, options: &LoadOptions) -> Result<bool, ParseError> {
+        match *self {
+            Condition::Equals(ref name, ref value) => is_equal(name, value, options),
+            Condition::NotEquals(ref name, ref value) =>
+                is_equal(name, value, options).map(|equal| !equal),
+            Condition::Not(ref inner) => inner.evaluate(options).map(|holds| !holds),
+            // both sides are always evaluated, so that unknown variables are reported
+            // whatever the profile
+            Condition::And(ref left, ref right) =>
+                Ok(left.evaluate(options)? & right.evaluate(options)?),
+            Condition::Or(ref left, ref right) =>
+                Ok(left.evaluate(options)? | right.evaluate(options)?),
+        }
+    }
+}
+
+fn is_equal(name: &str, value: &str, options: &LoadOptions) -> Result<bool, ParseError> {
+    if name == PROFILE {
+        return Ok(options.profiles.iter().any(|profile| profile == value));
+    }
+    match options.variables.get(name) {
+        Some(variable) => Ok(variable == value),
+        None => Err(ParseError::UnknownVariable(name.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn equals(name: &str, value: &str) -> Box<Condition> {
+        Box::new(Condition::Equals(name.to_string(), value.to_string()))
+    }
+
+    #[test]
+    fn evaluate_conditions() {
+        let mut options = LoadOptions {
+            profiles: vec!["prod".to_string(), "eu".to_string()],
+            ..LoadOptions::default()
+        };
+        options.variables.insert("tier".to_string(), "web".to_string());
+
+        assert_eq!(equals("profile", "prod").evaluate(&options), Ok(true));
+        assert_eq!(equals("profile", "eu").evaluate(&options), Ok(true));
+        assert_eq!(equals("profile", "dev").evaluate(&options), Ok(false));
+        assert_eq!(
+            Condition::NotEquals("tier".to_string(), "web".to_string()).evaluate(&options),
+            Ok(false));
+        assert_eq!(Condition::Not(equals("tier", "db")).evaluate(&options), Ok(true));
+        assert_eq!(
+            Condition::And(equals("profile", "prod"), equals("tier", "db")).evaluate(&options),
+            Ok(false));
+        assert_eq!(
+            Condition::Or(equals("profile", "prod"), equals("tier", "db")).evaluate(&options),
+            Ok(true));
+        assert_eq!(
+            Condition::Or(equals("profile", "prod"), equals("region", "eu")).evaluate(&options),
+            Err(ParseError::UnknownVariable("region".to_string())));
+    }
+}
@@ -0,0 +1,561 @@
+//! Dates and times, written as RFC 3339 literals such as `2026-10-16T12:00:00Z`.
+//!
+//! Three forms can be written in a document:
+//!
+//! - a date, as in `2026-10-16`;
+//! - a time of day, as in `12:00:00` or `08:30:00.250`;
+//! - a date and time, as in `2026-10-16T12:00:00`, optionally followed by an offset from
+//!   UTC, as in `2026-10-16T12:00:00Z` or `2026-10-16T14:00:00+02:00`.
+//!
+//! Literals are checked while the document is lexed, so a date such as `2026-02-30` is
+//! an error that points at the day.  Leap seconds are not accepted.
+//!
+//! A date and time with an offset is an instant, and can be converted to a
+//! `SystemTime`.  With the `chrono` feature enabled, dates and times can also be
+//! converted to the corresponding chrono types.
+//!
+//! # Examples
+//! ```
+//! use std::time::{Duration, UNIX_EPOCH};
+//! use figtree::datetime::DateTime;
+//!
+//! let expiry: DateTime = "1970-01-02T01:00:00+01:00".parse().unwrap();
+//! assert_eq!(expiry.to_system_time(), Some(UNIX_EPOCH + Duration::from_secs(86400)));
+//! assert_eq!(expiry.to_string(), "1970-01-02T01:00:00+01:00");
+//! ```
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// A calendar date between the years 0 and 9999.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    year: u16,
+    month: u8,
+    day: u8,
+}
+
+/// A time of day, to the nanosecond.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Time {
+    hour: u8,
+    minute: u8,
+    second: u8,
+    nanosecond: u32,
+}
+
+/// A date, a time of day, or both, with an optional offset from UTC.
+///
+/// Only a date and time can have an offset.  Values are compared field by field, so
+/// the same instant written with different offsets is not equal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    date: Option<Date>,
+    time: Option<Time>,
+    offset: Option<i16>,
+}
+
+fn is_leap_year(year: u16) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// The number of days between 1970-01-01 and a date, from Howard Hinnant's
+// `days_from_civil`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// The inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = if days >= 0 { days } else { days - 146_096 } / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+impl Date {
+    /// Construct a date, returning `None` if it does not exist.
+    pub fn new(year: u16, month: u8, day: u8) -> Option<Date> {
+        if year > 9999 || !(1..=12).contains(&month) || day < 1
+            || day > days_in_month(year, month) {
+
+            return None;
+        }
+        Some(Date { year, month, day })
+    }
+
+    pub fn year(&self) -> u16 { self.year }
+
+    pub fn month(&self) -> u8 { self.month }
+
+    pub fn day(&self) -> u8 { self.day }
+
+    fn days_since_epoch(&self) -> i64 {
+        days_from_civil(i64::from(self.year), i64::from(self.month), i64::from(self.day))
+    }
+}
+
+impl Time {
+    /// Construct a time of day, returning `None` if any field is out of range.
+    pub fn new(hour: u8, minute: u8, second: u8, nanosecond: u32) -> Option<Time> {
+        if hour > 23 || minute > 59 || second > 59 || nanosecond > 999_999_999 {
+            return None;
+        }
+        Some(Time { hour, minute, second, nanosecond })
+    }
+
+    pub fn hour(&self) -> u8 { self.hour }
+
+    pub fn minute(&self) -> u8 { self.minute }
+
+    pub fn second(&self) -> u8 { self.second }
+
+    pub fn nanosecond(&self) -> u32 { self.nanosecond }
+
+    fn seconds_since_midnight(&self) -> i64 {
+        i64::from(self.hour) * 3600 + i64::from(self.minute) * 60 + i64::from(self.second)
+    }
+}
+
+impl DateTime {
+    /// Construct a date on its own.
+    pub fn from_date(date: Date) -> DateTime {
+        DateTime { date: Some(date), time: None, offset: None }
+    }
+
+    /// Construct a time of day on its own.
+    pub fn from_time(time: Time) -> DateTime {
+        DateTime { date: None, time: Some(time), offset: None }
+    }
+
+    /// Construct a date and time without an offset, which is not tied to a time zone.
+    pub fn local(date: Date, time: Time) -> DateTime {
+        DateTime { date: Some(date), time: Some(time), offset: None }
+    }
+
+    /// Construct a date and time with an offset from UTC in minutes, returning `None`
+    /// if the offset is a day or more.
+    pub fn with_offset(date: Date, time: Time, offset: i16) -> Option<DateTime> {
+        if offset.abs() >= 24 * 60 {
+            return None;
+        }
+        Some(DateTime { date: Some(date), time: Some(time), offset: Some(offset) })
+    }
+
+    /// Construct the date and time in UTC of a `SystemTime`, returning `None` if it is
+    /// not between the years 0 and 9999.
+    pub fn from_system_time(time: SystemTime) -> Option<DateTime> {
+        let (seconds, nanos) = match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => (i64::try_from(since.as_secs()).ok()?, since.subsec_nanos()),
+            // count back whole seconds, then forward the fraction
+            Err(error) => match error.duration() {
+                before if before.subsec_nanos() == 0 => (-i64::try_from(before.as_secs()).ok()?, 0),
+                before => (
+                    -i64::try_from(before.as_secs()).ok()? - 1,
+                    1_000_000_000 - before.subsec_nanos()),
+            },
+        };
+
+        let (year, month, day) = civil_from_days(seconds.div_euclid(SECONDS_PER_DAY));
+        if !(0..=9999).contains(&year) {
+            return None;
+        }
+        let seconds = seconds.rem_euclid(SECONDS_PER_DAY);
+        let date = Date::new(year as u16, month as u8, day as u8)?;
+        let time = Time::new(
+            (seconds / 3600) as u8, (seconds / 60 % 60) as u8, (seconds % 60) as u8, nanos)?;
+        DateTime::with_offset(date, time, 0)
+    }
+
+    /// The date, unless this is only a time of day.
+    pub fn date(&self) -> Option<Date> { self.date }
+
+    /// The time of day, unless this is only a date.
+    pub fn time(&self) -> Option<Time> { self.time }
+
+    /// The offset from UTC in minutes, if one was given.
+    pub fn offset(&self) -> Option<i16> { self.offset }
+
+    /// Convert a date and time with an offset to a `SystemTime`.
+    ///
+    /// Returns `None` for dates, times and dates and times without an offset, which do
+    /// not describe a single instant, and for instants the system cannot represent.
+    pub fn to_system_time(&self) -> Option<SystemTime> {
+        let (date, time, offset) = match (self.date, self.time, self.offset) {
+            (Some(date), Some(time), Some(offset)) => (date, time, offset),
+            _ => { return None; },
+        };
+        let seconds = date.days_since_epoch() * SECONDS_PER_DAY + time.seconds_since_midnight()
+            - i64::from(offset) * 60;
+        let nanos = Duration::new(0, time.nanosecond);
+        if seconds >= 0 {
+            UNIX_EPOCH.checked_add(Duration::from_secs(seconds as u64))?.checked_add(nanos)
+        } else {
+            UNIX_EPOCH.checked_sub(Duration::from_secs(seconds.unsigned_abs()))?.checked_add(nanos)
+        }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02}:{:02}:{:02}", self.hour, self.minute, self.second)?;
+        if self.nanosecond > 0 {
+            let fraction = format!("{:09}", self.nanosecond);
+            write!(f, ".{}", fraction.trim_end_matches('0'))?;
+        }
+        Ok(())
+    }
+}
+
+/// Date and times are displayed in RFC 3339 format, as they are written in documents.
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.date, self.time) {
+            (Some(date), Some(time)) => write!(f, "{}T{}", date, time)?,
+            (Some(date), None) => write!(f, "{}", date)?,
+            (None, Some(time)) => write!(f, "{}", time)?,
+            (None, None) => unreachable!("a datetime has a date or a time"),
+        }
+        match self.offset {
+            None => Ok(()),
+            Some(0) => write!(f, "Z"),
+            Some(offset) => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                let offset = offset.abs();
+                write!(f, "{}{:02}:{:02}", sign, offset / 60, offset % 60)
+            },
+        }
+    }
+}
+
+/// Parse an RFC 3339 date, time, or date and time.
+impl FromStr for DateTime {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<DateTime, String> {
+        parse(text).map_err(|(message, _)| message)
+    }
+}
+
+// Reads the fields of a literal, keeping track of where each one starts.
+struct Fields<'a> {
+    text: &'a [u8],
+    index: usize,
+}
+
+type FieldResult<T> = Result<T, (String, usize)>;
+
+impl<'a> Fields<'a> {
+    fn error<T>(&self, message: String, at: usize) -> FieldResult<T> {
+        Err((message, at))
+    }
+
+    fn number(&mut self, width: usize, format: &str) -> FieldResult<u32> {
+        let start = self.index;
+        let digits = self.text.get(start..start + width)
+            .filter(|digits| digits.iter().all(u8::is_ascii_digit));
+        match digits {
+            Some(digits) => {
+                self.index += width;
+                Ok(digits.iter().fold(0, |total, &digit| total * 10 + u32::from(digit - b'0')))
+            },
+            None => self.error(format!("expected `{}`", format), start),
+        }
+    }
+
+    fn separator(&mut self, separators: &[u8], format: &str) -> FieldResult<()> {
+        match self.text.get(self.index) {
+            Some(ch) if separators.contains(ch) => {
+                self.index += 1;
+                Ok(())
+            },
+            _ => self.error(format!("expected `{}`", format), self.index),
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.index).cloned()
+    }
+
+    fn date(&mut self) -> FieldResult<Date> {
+        let year = self.number(4, "YYYY-MM-DD")?;
+        self.separator(b"-", "YYYY-MM-DD")?;
+        let month_start = self.index;
+        let month = self.number(2, "YYYY-MM-DD")?;
+        self.separator(b"-", "YYYY-MM-DD")?;
+        let day_start = self.index;
+        let day = self.number(2, "YYYY-MM-DD")?;
+
+        if !(1..=12).contains(&month) {
+            return self.error(format!("month {} is out of range", month), month_start);
+        }
+        match Date::new(year as u16, month as u8, day as u8) {
+            Some(date) => Ok(date),
+            None => self.error(
+                format!("day {} is out of range for {:04}-{:02}", day, year, month), day_start),
+        }
+    }
+
+    fn time(&mut self) -> FieldResult<Time> {
+        let mut starts = [0; 3];
+        let mut fields = [0; 3];
+        for (index, (start, field)) in starts.iter_mut().zip(fields.iter_mut()).enumerate() {
+            if index > 0 {
+                self.separator(b":", "HH:MM:SS")?;
+            }
+            *start = self.index;
+            *field = self.number(2, "HH:MM:SS")?;
+        }
+
+        let mut nanosecond = 0;
+        if self.peek() == Some(b'.') {
+            self.index += 1;
+            let start = self.index;
+            while self.peek().is_some_and(|ch| ch.is_ascii_digit()) {
+                self.index += 1;
+            }
+            let digits = self.index - start;
+            if digits == 0 {
+                return self.error("expected digits after `.`".to_string(), start);
+            } else if digits > 9 {
+                return self.error("time is more precise than a nanosecond".to_string(), start + 9);
+            }
+            self.index = start;
+            nanosecond = self.number(digits, "digits")? * 10u32.pow(9 - digits as u32);
+        }
+
+        let names = ["hour", "minute", "second"];
+        let limits = [23, 59, 59];
+        for index in 0..3 {
+            if fields[index] > limits[index] {
+                return self.error(
+                    format!("{} {} is out of range", names[index], fields[index]), starts[index]);
+            }
+        }
+        Ok(Time::new(fields[0] as u8, fields[1] as u8, fields[2] as u8, nanosecond)
+            .expect("fields are in range"))
+    }
+
+    fn offset(&mut self) -> FieldResult<Option<i16>> {
+        let sign = match self.peek() {
+            None => { return Ok(None); },
+            Some(b'Z') | Some(b'z') => {
+                self.index += 1;
+                return Ok(Some(0));
+            },
+            Some(b'+') => 1,
+            Some(b'-') => -1,
+            Some(_) => {
+                return self.error(
+                    "expected an offset such as `Z` or `+01:00`".to_string(), self.index);
+            },
+        };
+        self.index += 1;
+        let hours_start = self.index;
+        let hours = self.number(2, "+HH:MM")?;
+        self.separator(b":", "+HH:MM")?;
+        let minutes_start = self.index;
+        let minutes = self.number(2, "+HH:MM")?;
+        if hours > 23 {
+            return self.error(format!("offset hour {} is out of range", hours), hours_start);
+        } else if minutes > 59 {
+            return self.error(format!("offset minute {} is out of range", minutes), minutes_start);
+        }
+        Ok(Some(sign * (hours * 60 + minutes) as i16))
+    }
+}
+
+/// Parse an RFC 3339 date, time, or date and time.
+///
+/// # Failures
+/// Fails with a message and the index of the character the problem was found at, if the
+/// text is not well-formed or describes a date or time that does not exist.
+pub(crate) fn parse(text: &str) -> Result<DateTime, (String, usize)> {
+    let mut fields = Fields { text: text.as_bytes(), index: 0 };
+    let is_time = text.as_bytes().get(2) == Some(&b':');
+    let datetime = if is_time {
+        DateTime::from_time(fields.time()?)
+    } else {
+        let date = fields.date()?;
+        if fields.peek().is_none() {
+            DateTime::from_date(date)
+        } else {
+            fields.separator(b"Tt", "YYYY-MM-DDTHH:MM:SS")?;
+            let time = fields.time()?;
+            match fields.offset()? {
+                Some(offset) =>
+                    DateTime::with_offset(date, time, offset).expect("offset is in range"),
+                None => DateTime::local(date, time),
+            }
+        }
+    };
+    let end = fields.index;
+    if end < text.len() {
+        let message = if is_time && fields.offset().is_ok() {
+            "a time without a date cannot have an offset".to_string()
+        } else {
+            format!("unexpected `{}`", &text[end..].chars().next().unwrap())
+        };
+        return Err((message, end));
+    }
+    Ok(datetime)
+}
+
+#[cfg(feature = "chrono")]
+mod chrono_conversions {
+    pub extern crate chrono;
+
+    use self::chrono::{FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+    use super::{Date, DateTime, Time};
+
+    impl Date {
+        /// Convert to a chrono date.  Only available with the `chrono` feature.
+        pub fn to_chrono(&self) -> NaiveDate {
+            let (month, day) = (u32::from(self.month), u32::from(self.day));
+            NaiveDate::from_ymd_opt(i32::from(self.year), month, day)
+                .expect("dates are valid")
+        }
+    }
+
+    impl Time {
+        /// Convert to a chrono time.  Only available with the `chrono` feature.
+        pub fn to_chrono(&self) -> NaiveTime {
+            NaiveTime::from_hms_nano_opt(
+                u32::from(self.hour), u32::from(self.minute), u32::from(self.second),
+                self.nanosecond)
+                .expect("times are valid")
+        }
+    }
+
+    impl DateTime {
+        /// Convert a date and time to a chrono date and time, ignoring any offset.
+        /// Only available with the `chrono` feature.
+        pub fn to_chrono_naive(&self) -> Option<NaiveDateTime> {
+            match (self.date, self.time) {
+                (Some(date), Some(time)) =>
+                    Some(NaiveDateTime::new(date.to_chrono(), time.to_chrono())),
+                _ => None,
+            }
+        }
+
+        /// Convert a date and time with an offset to a chrono date and time.  Only
+        /// available with the `chrono` feature.
+        pub fn to_chrono(&self) -> Option<chrono::DateTime<FixedOffset>> {
+            let offset = FixedOffset::east_opt(i32::from(self.offset?) * 60)?;
+            offset.from_local_datetime(&self.to_chrono_naive()?).single()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> (String, usize) {
+        parse(text).unwrap_err()
+    }
+
+    #[test]
+    fn parse_datetimes() {
+        let date = Date::new(2026, 10, 16).unwrap();
+        let noon = Time::new(12, 0, 0, 0).unwrap();
+        assert_eq!(parse("2026-10-16"), Ok(DateTime::from_date(date)));
+        assert_eq!(parse("12:00:00"), Ok(DateTime::from_time(noon)));
+        assert_eq!(parse("2026-10-16T12:00:00"), Ok(DateTime::local(date, noon)));
+        assert_eq!(parse("2026-10-16t12:00:00z").ok(), DateTime::with_offset(date, noon, 0));
+        assert_eq!(
+            parse("2026-10-16T12:00:00.25-05:30").unwrap(),
+            DateTime::with_offset(date, Time::new(12, 0, 0, 250_000_000).unwrap(), -330).unwrap());
+        assert!(parse("2024-02-29").is_ok());
+        assert!(parse("2000-02-29").is_ok());
+        assert!(parse("23:59:59.999999999").is_ok());
+
+        assert_eq!(error("2026-13-01"), ("month 13 is out of range".to_string(), 5));
+        assert_eq!(error("2026-02-29"), ("day 29 is out of range for 2026-02".to_string(), 8));
+        assert_eq!(error("1900-02-29").1, 8);
+        assert_eq!(error("2026-10-16T24:00:00"), ("hour 24 is out of range".to_string(), 11));
+        assert_eq!(error("12:60:00"), ("minute 60 is out of range".to_string(), 3));
+        assert_eq!(error("12:00:60"), ("second 60 is out of range".to_string(), 6));
+        assert_eq!(error("2026-1-16"), ("expected `YYYY-MM-DD`".to_string(), 5));
+        assert_eq!(error("2026-10-16X"), ("expected `YYYY-MM-DDTHH:MM:SS`".to_string(), 10));
+        assert_eq!(
+            error("12:00:00Z"),
+            ("a time without a date cannot have an offset".to_string(), 8));
+        assert_eq!(error("12:00:00.1234567891").1, 18);
+        assert_eq!(error("2026-10-16T12:00:00+24:00").1, 20);
+        assert_eq!(error("2026-10-16T12:00:00Z1"), ("unexpected `1`".to_string(), 20));
+    }
+
+    #[test]
+    fn display_datetimes() {
+        for text in &["2026-10-16", "08:30:00.25", "2026-10-16T12:00:00", "0001-01-01T00:00:00Z",
+                      "2026-10-16T12:00:00.000000001-05:30"] {
+            assert_eq!(parse(text).unwrap().to_string(), *text);
+        }
+    }
+
+    #[test]
+    fn system_times() {
+        let at = |text: &str| text.parse::<DateTime>().unwrap().to_system_time();
+        assert_eq!(at("1970-01-01T00:00:00Z"), Some(UNIX_EPOCH));
+        assert_eq!(
+            at("2001-09-09T01:46:40Z"), Some(UNIX_EPOCH + Duration::from_secs(1_000_000_000)));
+        assert_eq!(at("1969-12-31T23:59:59.5Z"), Some(UNIX_EPOCH - Duration::from_millis(500)));
+        assert_eq!(at("1970-01-01T01:00:00+01:00"), Some(UNIX_EPOCH));
+        assert_eq!(at("1970-01-01T00:00:00"), None);
+        assert_eq!(at("1970-01-01"), None);
+
+        for text in &["2026-10-16T12:00:00.5Z", "1969-12-31T23:59:59.5Z", "1600-02-29T00:00:00Z"] {
+            let time = at(text).unwrap();
+            assert_eq!(DateTime::from_system_time(time).unwrap().to_string(), *text);
+        }
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn chrono_conversions() {
+        use self::chrono_conversions::chrono::{NaiveDate, NaiveTime};
+
+        let datetime: DateTime = "2026-10-16T12:00:00.5+01:00".parse().unwrap();
+        let date = NaiveDate::from_ymd_opt(2026, 10, 16).unwrap();
+        let time = NaiveTime::from_hms_milli_opt(12, 0, 0, 500).unwrap();
+        let converted = datetime.to_chrono().unwrap();
+        assert_eq!(converted.timestamp(), 1_792_148_400);
+        assert_eq!(converted.timestamp_subsec_millis(), 500);
+        assert_eq!(converted.offset().local_minus_utc(), 3600);
+        assert_eq!(datetime.to_chrono_naive(), Some(date.and_time(time)));
+        assert_eq!(datetime.date().unwrap().to_chrono(), date);
+        assert!(DateTime::from_date(datetime.date().unwrap()).to_chrono().is_none());
+    }
+}
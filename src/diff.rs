@@ -0,0 +1,282 @@
+//! Structural differences between documents.
+//!
+//! `Document::diff` compares two documents node by node and value by value, and returns
+//! the list of changes needed to turn the first document into the second.  Each change
+//! is addressed by a `Path`, so changes deep inside dicts and lists are reported where
+//! they happen, rather than as a change to the whole attribute.
+//!
+//! Dicts are compared by key, so the order of keys does not matter.  Lists are compared
+//! element by element: elements at the same index are compared recursively, and any
+//! extra elements at the end of either list are reported as added or removed.  Repeated
+//! nodes are compared in the same way as lists, and addressed by index.  Nodes with
+//! labels are only compared with nodes that have the same labels.
+//!
+//! Changes are returned in a stable order: nodes and attributes are visited sorted by
+//! name, attributes before subnodes, starting with the top-level attributes.
+//!
+//! # Examples
+//! ```
+//! use figtree::Figtree;
+//! use figtree::diff;
+//!
+//! let old = Figtree::from_string("server { 'port': 80, 'hosts': ['a'] }")
+//!     .parse().ok().expect("invalid document");
+//! let new = Figtree::from_string("server { 'port': 8080, 'hosts': ['a', 'b'] } tls {}")
+//!     .parse().ok().expect("invalid document");
+//!
+//! let changes = old.diff(&new);
+//! assert_eq!(diff::render(&changes), "\
+//! + server.hosts[1]: \"b\"
+//! ~ server.port: 80 -> 8080
+//! + tls {}
+//! ");
+//! ```
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use super::path::Path;
+use super::types::*;
+use super::writer;
+
+/// A single difference between two documents.
+///
+/// Changes borrow the nodes and values they refer to from the documents that were
+/// compared.  Removed nodes and values, and the old side of a changed value, come from
+/// the first document; everything else comes from the second.
+#[derive(Debug, PartialEq)]
+pub enum Change<'a> {
+    /// A node is present only in the second document.
+    NodeAdded(Path, &'a Node),
+    /// A node is present only in the first document.
+    NodeRemoved(Path, &'a Node),
+    /// An attribute, dict key, or list element is present only in the second document.
+    ValueAdded(Path, &'a Value),
+    /// An attribute, dict key, or list element is present only in the first document.
+    ValueRemoved(Path, &'a Value),
+    /// A value differs between the documents.  Dicts and lists are never reported as
+    /// changed as a whole unless the other side is not a dict or list.
+    ValueChanged(Path, &'a Value, &'a Value),
+}
+
+impl<'a> Change<'a> {
+    /// The path of the node or value that changed.
+    pub fn path(&self) -> &Path {
+        match *self {
+            Change::NodeAdded(ref path, _) |
+            Change::NodeRemoved(ref path, _) |
+            Change::ValueAdded(ref path, _) |
+            Change::ValueRemoved(ref path, _) |
+            Change::ValueChanged(ref path, _, _) => path,
+        }
+    }
+}
+
+impl Document {
+    /// Find the differences between this document and another.
+    ///
+    /// The result is empty if and only if the documents are equal.  See the `diff`
+    /// module for details of how documents are compared.
+    pub fn diff<'a>(&'a self, other: &'a Document) -> Vec<Change<'a>> {
+        let mut changes = Vec::new();
+        diff_nodes(&Path::new(), self.as_node(), other.as_node(), &mut changes);
+        changes
+    }
+}
+
+fn diff_nodes<'a>(path: &Path, old: &'a Node, new: &'a Node, changes: &mut Vec<Change<'a>>) {
+    let keys: BTreeSet<&String> = old.iter_attrs().chain(new.iter_attrs())
+        .map(|(key, _)| key)
+        .collect();
+    for key in keys {
+        let path = path.child(key.as_str());
+        match (old.get_attr(key.as_str()), new.get_attr(key.as_str())) {
+            (Some(old), Some(new)) => diff_values(&path, old, new, changes),
+            (Some(old), None) => changes.push(Change::ValueRemoved(path, old)),
+            (None, Some(new)) => changes.push(Change::ValueAdded(path, new)),
+            (None, None) => unreachable!(),
+        }
+    }
+
+    let names: BTreeSet<&String> = old.iter_nodes().chain(new.iter_nodes())
+        .map(|(name, _)| name)
+        .collect();
+    for name in names {
+        let (old_all, new_all) = (old.get_nodes(name.as_str()), new.get_nodes(name.as_str()));
+        for labels in label_sets(&[old_all, new_all]) {
+            let path = path.node_child(name, labels);
+            let (old_nodes, new_nodes) = (labelled(old_all, labels), labelled(new_all, labels));
+            if old_nodes.len() <= 1 && new_nodes.len() <= 1 {
+                let (old, new) = (old_nodes.first().cloned(), new_nodes.first().cloned());
+                diff_node_entry(path, old, new, changes);
+            } else {
+                for index in 0..old_nodes.len().max(new_nodes.len()) {
+                    let (old, new) = (old_nodes.get(index).cloned(), new_nodes.get(index).cloned());
+                    diff_node_entry(path.index(index), old, new, changes);
+                }
+            }
+        }
+    }
+}
+
+fn diff_node_entry<'a>(path: Path, old: Option<&'a Node>, new: Option<&'a Node>,
+                       changes: &mut Vec<Change<'a>>) {
+    match (old, new) {
+        (Some(old), Some(new)) => diff_nodes(&path, old, new, changes),
+        (Some(old), None) => changes.push(Change::NodeRemoved(path, old)),
+        (None, Some(new)) => changes.push(Change::NodeAdded(path, new)),
+        (None, None) => unreachable!(),
+    }
+}
+
+fn diff_values<'a>(path: &Path, old: &'a Value, new: &'a Value, changes: &mut Vec<Change<'a>>) {
+    match (old, new) {
+        (Value::Dict(old_dict), Value::Dict(new_dict)) => {
+            let keys: BTreeSet<&String> = old_dict.keys().chain(new_dict.keys()).collect();
+            for key in keys {
+                let path = path.child(key.as_str());
+                match (old_dict.get(key), new_dict.get(key)) {
+                    (Some(old), Some(new)) => diff_values(&path, old, new, changes),
+                    (Some(old), None) => changes.push(Change::ValueRemoved(path, old)),
+                    (None, Some(new)) => changes.push(Change::ValueAdded(path, new)),
+                    (None, None) => unreachable!(),
+                }
+            }
+        },
+        (Value::List(old_list), Value::List(new_list)) => {
+            for index in 0..old_list.len().max(new_list.len()) {
+                let path = path.index(index);
+                match (old_list.get(index), new_list.get(index)) {
+                    (Some(old), Some(new)) => diff_values(&path, old, new, changes),
+                    (Some(old), None) => changes.push(Change::ValueRemoved(path, old)),
+                    (None, Some(new)) => changes.push(Change::ValueAdded(path, new)),
+                    (None, None) => unreachable!(),
+                }
+            }
+        },
+        (old, new) => if old != new {
+            changes.push(Change::ValueChanged(path.clone(), old, new));
+        },
+    }
+}
+
+/// Render a list of changes as text, one change per line.
+///
+/// Each line starts with `+` for additions, `-` for removals, and `~` for changed
+/// values.
+pub fn render(changes: &[Change]) -> String {
+    let mut out = String::new();
+    for change in changes {
+        out.push_str(&change.to_string());
+        out.push('\n');
+    }
+    out
+}
+
+impl<'a> fmt::Display for Change<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let node_summary = |node: &Node| if node.is_empty() { "{}" } else { "{ ... }" };
+        match *self {
+            Change::NodeAdded(ref path, node) =>
+                write!(f, "+ {} {}", path, node_summary(node)),
+            Change::NodeRemoved(ref path, node) =>
+                write!(f, "- {} {}", path, node_summary(node)),
+            Change::ValueAdded(ref path, value) =>
+                write!(f, "+ {}: {}", path, writer::value_to_string(value)),
+            Change::ValueRemoved(ref path, value) =>
+                write!(f, "- {}: {}", path, writer::value_to_string(value)),
+            Change::ValueChanged(ref path, old, new) =>
+                write!(f, "~ {}: {} -> {}", path,
+                    writer::value_to_string(old), writer::value_to_string(new)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::figtree::{Figtree, LoadOptions};
+
+    fn parse(source: &str) -> Document {
+        Figtree::from_string(source).parse().unwrap()
+    }
+
+    #[test]
+    fn equal_documents() {
+        let doc = parse("a { 'x': {'k': 1, 'l': [1, 2]} b { 'y': null } }");
+        assert_eq!(doc.diff(&doc), vec![]);
+        assert_eq!(
+            doc.diff(&parse("a { b { 'y': null } 'x': {'l': [1, 2], 'k': 1} }")),
+            vec![]);
+    }
+
+    #[test]
+    fn node_and_attribute_changes() {
+        let old = parse("a { 'x': 1, 'y': 'two' gone {} } b {}");
+        let new = parse("a { 'x': 1.0, 'z': true kept {} } c { 'w': 1 }");
+        let path = |path: &str| Path::parse(path).unwrap();
+
+        assert_eq!(old.diff(&new), vec![
+            Change::ValueChanged(path("a.x"), &Value::new_int(1), &Value::new_float(1.0)),
+            Change::ValueRemoved(path("a.y"), &Value::new_string("two")),
+            Change::ValueAdded(path("a.z"), &Value::new_bool(true)),
+            Change::NodeRemoved(path("a.gone"), &Node::new()),
+            Change::NodeAdded(path("a.kept"), &Node::new()),
+            Change::NodeRemoved(path("b"), &Node::new()),
+            Change::NodeAdded(path("c"), new.get_node("c").unwrap()),
+        ]);
+    }
+
+    #[test]
+    fn nested_value_changes() {
+        let old = parse("a { 'd': {'k': [1, 2, 3], 'gone': null}, 'l': [{'x': 1}] }");
+        let new = parse("a { 'd': {'k': [1, 5], 'new': !ident}, 'l': [{'x': 2}, []] }");
+        let changes = old.diff(&new);
+        assert_eq!(render(&changes), concat!(
+            "- a.d.gone: null\n",
+            "~ a.d.k[1]: 2 -> 5\n",
+            "- a.d.k[2]: 3\n",
+            "+ a.d.new: !ident\n",
+            "~ a.l[0].x: 1 -> 2\n",
+            "+ a.l[1]: []\n"));
+        assert_eq!(changes[1].path(), &Path::parse("a.d.k[1]").unwrap());
+    }
+
+    #[test]
+    fn rendering_nodes() {
+        let (old, new) = (parse(""), parse("a { 'x': 1 } b {}"));
+        assert_eq!(render(&old.diff(&new)), "+ a { ... }\n+ b {}\n");
+        assert_eq!(render(&new.diff(&old)), "- a { ... }\n- b {}\n");
+    }
+
+    #[test]
+    fn repeated_nodes() {
+        let options = LoadOptions { repeated_nodes: true, .. LoadOptions::default() };
+        let parse = |source: &str| Figtree::from_string(source).parse_with(&options).unwrap();
+        let old = parse("a { 'x': 1 } a { 'x': 2 } b {}");
+        let new = parse("a { 'x': 1 } a { 'x': 3 } a {}");
+        assert_eq!(render(&old.diff(&new)), "~ a[1].x: 2 -> 3\n+ a[2] {}\n- b {}\n");
+        assert_eq!(render(&new.diff(&old)), "~ a[1].x: 3 -> 2\n- a[2] {}\n+ b {}\n");
+    }
+
+    #[test]
+    fn top_level_attributes() {
+        let (old, new) = (parse("'v': 1, 'gone': 2 a {}"), parse("'v': 2 a {}"));
+        assert_eq!(render(&old.diff(&new)), "- gone: 2\n~ v: 1 -> 2\n");
+    }
+
+    #[test]
+    fn labelled_nodes() {
+        let old = parse("b 'x' { 'p': 1 } b 'y' {} b { 'p': 2 }");
+        let new = parse("b 'y' {} b 'x' { 'p': 3 } b 'z' {}");
+        assert_eq!(render(&old.diff(&new)), concat!(
+            "~ b[\"x\"].p: 1 -> 3\n",
+            "- b { ... }\n",
+            "+ b[\"z\"] {}\n"));
+
+        // labelled nodes are distinct, so their order does not matter
+        let reordered = parse("b { 'p': 2 } b 'y' {} b 'x' { 'p': 1 }");
+        assert!(old.diff(&reordered).is_empty());
+        assert_eq!(old, reordered);
+    }
+}
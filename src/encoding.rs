@@ -0,0 +1,157 @@
+//! Base64 and hex encodings of byte strings, as used in `b64"..."` and `hex"..."`
+//! literals.
+//!
+//! Whitespace is ignored when decoding, so long values can be split across lines.
+//! Base64 uses the standard alphabet, and padding is optional, but bits left over at
+//! the end must be zero, so that each value has only one encoding.  Errors give the
+//! index of the character that is wrong.
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_digit(ch: char) -> Option<u32> {
+    match ch {
+        'A'..='Z' => Some(ch as u32 - 'A' as u32),
+        'a'..='z' => Some(ch as u32 - 'a' as u32 + 26),
+        '0'..='9' => Some(ch as u32 - '0' as u32 + 52),
+        '+' => Some(62),
+        '/' => Some(63),
+        _ => None,
+    }
+}
+
+/// Encode bytes as base64, with padding.
+pub fn encode_base64(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate()
+            .fold(0u32, |bits, (index, &byte)| bits | u32::from(byte) << (16 - 8 * index));
+        for index in 0..4 {
+            if index <= chunk.len() {
+                text.push(BASE64_ALPHABET[(bits >> (18 - 6 * index) & 0x3f) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// Decode base64 text.
+///
+/// # Failures
+/// Fails with a message and the index of the offending character if the text contains
+/// a character outside the alphabet, is cut off part of the way through a byte, or has
+/// misplaced padding.
+pub fn decode_base64(text: &str) -> Result<Vec<u8>, (String, usize)> {
+    let mut bytes = Vec::new();
+    let mut bits = 0u32;
+    let mut digits = 0;
+    let mut padding = 0;
+    let mut last = 0;
+    for (index, ch) in text.char_indices() {
+        if ch.is_whitespace() {
+            continue;
+        }
+        if ch == '=' && digits % 4 >= 2 && digits % 4 + padding < 4 {
+            padding += 1;
+        } else if padding > 0 || ch == '=' {
+            return Err((format!("misplaced {:?}", ch), index));
+        } else if let Some(digit) = base64_digit(ch) {
+            bits = bits << 6 | digit;
+            digits += 1;
+            if digits % 4 == 0 {
+                bytes.extend_from_slice(&[(bits >> 16) as u8, (bits >> 8) as u8, bits as u8]);
+                bits = 0;
+            }
+        } else {
+            return Err((format!("{:?} is not a base64 digit", ch), index));
+        }
+        last = index;
+    }
+
+    match digits % 4 {
+        0 if padding == 0 => Ok(bytes),
+        1 => Err(("base64 ends part of the way through a byte".to_string(), last)),
+        leftover if padding == 0 || leftover + padding == 4 => {
+            // two digits hold one byte and four spare bits, three hold two and two spare
+            let spare = if leftover == 2 { 4 } else { 2 };
+            if bits & ((1 << spare) - 1) != 0 {
+                return Err(("base64 has bits left over at the end".to_string(), last));
+            }
+            let bits = bits >> spare;
+            if leftover == 3 {
+                bytes.push((bits >> 8) as u8);
+            }
+            bytes.push(bits as u8);
+            Ok(bytes)
+        },
+        _ => Err(("base64 padding is incomplete".to_string(), last)),
+    }
+}
+
+/// Encode bytes as lowercase hex.
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decode hex text, in either case.
+///
+/// # Failures
+/// Fails with a message and the index of the offending character if the text contains
+/// a character that is not a hex digit, or an odd number of digits.
+pub fn decode_hex(text: &str) -> Result<Vec<u8>, (String, usize)> {
+    let mut bytes = Vec::new();
+    let mut high = None;
+    let mut last = 0;
+    for (index, ch) in text.char_indices() {
+        if ch.is_whitespace() {
+            continue;
+        }
+        let digit = ch.to_digit(16)
+            .ok_or_else(|| (format!("{:?} is not a hex digit", ch), index))? as u8;
+        match high.take() {
+            Some(high) => bytes.push(high << 4 | digit),
+            None => { high = Some(digit); },
+        }
+        last = index;
+    }
+    match high {
+        Some(_) => Err(("hex has an odd number of digits".to_string(), last)),
+        None => Ok(bytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64() {
+        for (bytes, text) in &[(&b""[..], ""), (b"f", "Zg=="), (b"fo", "Zm8="), (b"foo", "Zm9v"),
+                               (b"foob", "Zm9vYg=="), (&[0xff, 0xfe, 0x00][..], "//4A")] {
+            assert_eq!(encode_base64(bytes), *text);
+            assert_eq!(decode_base64(text).as_deref(), Ok(*bytes));
+        }
+        assert_eq!(decode_base64("Zm9v\n  Yg").unwrap(), b"foob");
+        assert_eq!(decode_base64("Zm8").unwrap(), b"fo");
+
+        assert_eq!(decode_base64("Zm9v!"), Err(("'!' is not a base64 digit".to_string(), 4)));
+        assert_eq!(decode_base64("Zg=a"), Err(("misplaced 'a'".to_string(), 3)));
+        assert_eq!(decode_base64("Z==="), Err(("misplaced '='".to_string(), 1)));
+        assert_eq!(decode_base64("Zg==="), Err(("misplaced '='".to_string(), 4)));
+        assert_eq!(decode_base64("Zm9vY").unwrap_err().1, 4);
+        assert_eq!(decode_base64("Zg=").unwrap_err().0, "base64 padding is incomplete");
+        assert_eq!(decode_base64("Zh==").unwrap_err().0, "base64 has bits left over at the end");
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(encode_hex(&[0x00, 0xab, 0x10]), "00ab10");
+        assert_eq!(encode_hex(&[]), "");
+        assert_eq!(decode_hex("00AB 10").unwrap(), vec![0x00, 0xab, 0x10]);
+        assert_eq!(decode_hex("").unwrap(), Vec::<u8>::new());
+        assert_eq!(decode_hex("0g"), Err(("'g' is not a hex digit".to_string(), 1)));
+        assert_eq!(decode_hex("abc"), Err(("hex has an odd number of digits".to_string(), 2)));
+    }
+}
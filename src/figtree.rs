@@ -1,13 +1,61 @@
 use std::io::{Cursor, Error};
 use std::fs::File;
 use std::io::prelude::*;
+use std::collections::HashMap;
 
-use super::parser::{Parser, ParseEvent, ParseError};
+use std::mem;
+
+use super::parser::{Parser, ParseEvent, ParsedValue, ParseError, KeyStyle};
 use super::lexer::Lexer;
+use super::path::Path;
 use super::position::Position;
+use super::reference::{References, Step, Target};
+use super::tags::TagHandlers;
 
 use super::types::*;
 
+/// Options controlling how a document is parsed.
+///
+/// # Examples
+/// ```
+/// use figtree::{Figtree, KeyStyle, LoadOptions};
+/// let options = LoadOptions { key_style: KeyStyle::Quoted, .. LoadOptions::default() };
+/// assert!(Figtree::from_string("doc { 'key': 1 }").parse_with(&options).is_ok());
+/// assert!(Figtree::from_string("doc { key: 1 }").parse_with(&options).is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LoadOptions {
+    /// How attribute and dict keys must be written.  By default, keys can be written
+    /// either quoted or as bare identifiers.
+    pub key_style: KeyStyle,
+    /// Collect nodes that share a name and labels with an earlier sibling into an
+    /// ordered list of repeated nodes, rather than failing with
+    /// `ParseError::RepeatedNode`.
+    pub repeated_nodes: bool,
+    /// Values for references and string interpolations to fall back on when their
+    /// path is not found in the document being loaded.
+    pub context: Document,
+    /// The active profiles.  A block such as `when(profile == "prod") { ... }` is only
+    /// kept if `prod` is one of them.
+    pub profiles: Vec<String>,
+    /// Values for the other variables used in the conditions of `when` blocks.
+    pub variables: HashMap<String, String>,
+    /// Treat `when` blocks whose conditions use a variable that has not been given a
+    /// value as inactive, rather than failing with `ParseError::UnknownVariable`.
+    pub unknown_variables_inactive: bool,
+    /// The handlers for tagged values such as `!duration "5m"`.  By default, these are
+    /// the built-in handlers.
+    pub tags: TagHandlers,
+    /// Read `${...}` in strings as text, rather than as an interpolation, although `$${`
+    /// is still read as `${`.  Patches are read this way, so that the values they set
+    /// are kept as written.
+    pub literal_strings: bool,
+    /// Read numbers followed by a unit, as in `30s`, `1h30m` or `512MiB`, as durations
+    /// and byte sizes.  Without this, they can be written with the `!duration` and
+    /// `!bytes` tags, as in `!duration "30s"`.
+    pub quantity_literals: bool,
+}
+
 /// Opens, parses, and reads figtree files.
 ///
 /// The `Figtree` struct is essentially a wrapper around the internal pull-parser API
@@ -25,6 +73,31 @@ use super::types::*;
 /// ```
 pub struct Figtree {
     parser: Parser,
+    options: LoadOptions,
+    references: References,
+    // where the node or value being built will be in the document
+    location: Vec<Step>,
+    // an anchor for the next node
+    node_anchor: Option<(String, Position)>,
+    // whether the next node is abstract
+    node_abstract: bool,
+    // annotations of the next attribute or node
+    annotations: Vec<Annotation>,
+    // how the numbers and byte strings in the attribute being built were written, by
+    // their path within it
+    literals: Vec<(Path, SourceForm)>,
+    // whether `when` blocks and tagged values are evaluated, rather than all blocks
+    // being skipped and tags being ignored
+    evaluate: bool,
+}
+
+// Where the contents of a node come from.
+enum Contents {
+    Body,
+    // a copy of the target of an alias or reference
+    Copy(ParsedValue, Position),
+    // the body, merged with the node at a path
+    Extends(Path, Position),
 }
 
 impl Figtree {
@@ -44,7 +117,15 @@ impl Figtree {
     /// ```
     pub fn new<T: Read + 'static>(input: T) -> Self {
         Figtree {
-            parser: Parser::parse(Lexer::lex(input))
+            parser: Parser::parse(Lexer::lex(input)),
+            options: LoadOptions::default(),
+            references: References::new(),
+            location: Vec::new(),
+            node_anchor: None,
+            node_abstract: false,
+            annotations: Vec::new(),
+            literals: Vec::new(),
+            evaluate: true,
         }
     }
 
@@ -119,6 +200,50 @@ impl Figtree {
     ///     Position::at(0, 8));
     /// ```
     pub fn parse(&mut self) -> Result<Document, (ParseError, Position)> {
+        self.parse_with(&LoadOptions::default())
+    }
+
+    /// Parse the document stored in this `Figtree` instance, using the given options.
+    ///
+    /// # Failures
+    /// Fails in the same way as `parse`, and additionally if the document breaks any
+    /// of the rules set by the options.
+    pub fn parse_with(&mut self, options: &LoadOptions)
+        -> Result<Document, (ParseError, Position)> {
+
+        let mut doc = self.parse_unresolved(options)?;
+        // aliases and references can only be resolved once the whole document is known
+        mem::take(&mut self.references).resolve(&mut doc, &options.context)?;
+        Ok(doc)
+    }
+
+    /// Check the syntax of the document stored in this `Figtree` instance, without
+    /// evaluating `when` blocks or tagged values, or resolving references, which can all
+    /// depend on the options the document is loaded with.  Repeated nodes and quantity
+    /// literals are accepted, as they are with `LoadOptions::repeated_nodes` and
+    /// `LoadOptions::quantity_literals`.
+    ///
+    /// # Failures
+    /// Fails in the same way as `parse`, for errors that do not depend on the options.
+    pub fn check_syntax(&mut self) -> Result<(), (ParseError, Position)> {
+        self.evaluate = false;
+        let options = LoadOptions {
+            repeated_nodes: true,
+            quantity_literals: true,
+            .. LoadOptions::default()
+        };
+        self.parse_unresolved(&options).map(|_| ())
+    }
+
+    // Parses a document, leaving placeholders where its aliases, references and
+    // interpolations will go.
+    pub(crate) fn parse_unresolved(&mut self, options: &LoadOptions)
+        -> Result<Document, (ParseError, Position)> {
+
+        self.parser.key_style = options.key_style;
+        self.parser.set_interpolation(!options.literal_strings);
+        self.parser.set_quantities(options.quantity_literals);
+        self.options = options.clone();
         let mut doc = Document::new();
         match self.parser.next() {
             Some(Ok((ParseEvent::FileStart, _))) => {
@@ -137,19 +262,140 @@ impl Figtree {
     fn parse_file(&mut self, doc: &mut Document) -> Option<(ParseError, Position)> {
         loop {
             match self.parser.next() {
-                Some(Ok((ParseEvent::NodeStart(name), _))) => {
-                    if doc.has_node(&name) {
-                        return Some((ParseError::RepeatedNode(name), self.parser.lex_position()));
-                    }
-                    if let Some(err) = self.parse_node(doc.new_node_or_get(name)) {
+                Some(Ok((ParseEvent::FileEnd, _))) => {
+                    return None;
+                },
+                Some(Ok((event, position))) => {
+                    if let Some(err) = self.parse_entry(doc.as_node_mut(), event, position) {
                         return Some(err);
                     }
                 },
-                Some(Ok((ParseEvent::FileEnd, _))) => {
-                    return None;
+                Some(Err(error)) => { return Some(error) },
+                None =>
+                    unreachable!("EOF occurred that cannot happen at this time."),
+            }
+        }
+    }
+
+    // Handles an event that adds an attribute or subnode to a node.
+    fn parse_entry(&mut self, node: &mut Node, event: ParseEvent, position: Position)
+        -> Option<(ParseError, Position)> {
+
+        match event {
+            ParseEvent::NodeStart(name, labels) =>
+                self.parse_subnode(node, name, labels, Contents::Body),
+            ParseEvent::NodeReference(name, labels, target) =>
+                self.parse_subnode(node, name, labels, Contents::Copy(target, position)),
+            ParseEvent::NodeExtends(name, labels, path) =>
+                self.parse_subnode(node, name, labels, Contents::Extends(path, position)),
+            ParseEvent::Key(key) => {
+                let annotations = mem::take(&mut self.annotations);
+                self.location.push(Step::Attr(key.clone()));
+                let value = self.parse_value();
+                self.location.pop();
+                let literals = mem::take(&mut self.literals);
+                match value {
+                    Ok(value) => {
+                        node.set_attr_annotations(key.as_str(), annotations);
+                        node.replace_attr_literals(key.as_str(), literals);
+                        node.insert_attr(key, value);
+                    },
+                    Err(err) => { return Some(err); }
+                }
+                None
+            },
+            ParseEvent::Annotation(name, args) => {
+                let args = args.into_iter().map(Value::from_parsed_value).collect();
+                self.annotations.push(Annotation::with_args(name, args));
+                None
+            },
+            ParseEvent::Anchor(name) => {
+                self.node_anchor = Some((name, position));
+                None
+            },
+            ParseEvent::Abstract => {
+                self.node_abstract = true;
+                None
+            },
+            // the contents of an active block belong to the enclosing node
+            ParseEvent::When(_) if !self.evaluate => self.skip_block(),
+            ParseEvent::When(condition) => match condition.evaluate(&self.options) {
+                Ok(true) => None,
+                Ok(false) => self.skip_block(),
+                Err(ParseError::UnknownVariable(_)) if self.options.unknown_variables_inactive =>
+                    self.skip_block(),
+                Err(err) => Some((err, position)),
+            },
+            ParseEvent::WhenEnd => None,
+            ev => unreachable!("ParseEvent {:?} occurred that cannot happen at this time.", ev),
+        }
+    }
+
+    // Builds a subnode from its body, or records where its contents will come from once
+    // the document is complete.
+    fn parse_subnode(&mut self, parent: &mut Node, name: String, labels: Vec<String>,
+                     contents: Contents)
+        -> Option<(ParseError, Position)> {
+
+        // nodes with different labels are distinct, and never count as repeats
+        let repeated = parent.get_labelled_node(name.as_str(), &labels).is_some();
+        if repeated && !self.options.repeated_nodes {
+            return Some((ParseError::RepeatedNode(name), self.parser.lex_position()));
+        }
+
+        self.location.push(Step::Node(name.clone(), parent.get_nodes(name.as_str()).len()));
+        let mut result = match self.node_anchor.take() {
+            Some((anchor, position)) =>
+                self.references.anchor(anchor, &self.location, position).err(),
+            None => None,
+        };
+        if mem::take(&mut self.node_abstract) {
+            self.references.abstract_node(&self.location);
+        }
+        let mut node = Node::with_labels(labels);
+        node.set_annotations(mem::take(&mut self.annotations));
+        if result.is_none() {
+            result = match contents {
+                Contents::Body => self.parse_node(&mut node),
+                Contents::Copy(target, position) =>
+                    self.add_reference(target, true, position).err(),
+                Contents::Extends(path, position) => {
+                    self.references.reference(Target::Parent(path), &self.location, true, position);
+                    self.parse_node(&mut node)
                 },
-                Some(Ok(ev)) =>
-                    unreachable!("ParseEvent {:?} occurred that cannot happen at this time.", ev),
+            };
+        }
+        self.location.pop();
+        if result.is_none() {
+            parent.push_node(name, node);
+        }
+        result
+    }
+
+    // Records a placeholder at the current location.
+    fn add_reference(&mut self, target: ParsedValue, is_node: bool, position: Position)
+        -> Result<(), (ParseError, Position)> {
+
+        let target = match target {
+            ParsedValue::Alias(name) => Target::Alias(name),
+            ParsedValue::Reference(path) => Target::from_reference(&path)
+                .map_err(|err| (err, position.clone()))?,
+            ParsedValue::Interpolated(parts) => Target::from_parts(parts)?,
+            _ => unreachable!("only aliases, references and interpolations are recorded"),
+        };
+        self.references.reference(target, &self.location, is_node, position);
+        Ok(())
+    }
+
+    // Skips the contents of an inactive `when` block, including any blocks inside it.
+    fn skip_block(&mut self) -> Option<(ParseError, Position)> {
+        let mut depth = 0;
+        loop {
+            match self.parser.next() {
+                Some(Ok((ParseEvent::WhenEnd, _))) if depth == 0 => { return None; },
+                Some(Ok((ParseEvent::WhenEnd, _))) => { depth -= 1; },
+                Some(Ok((ParseEvent::When(_), _))) => { depth += 1; },
+                Some(Ok(_)) => {},
                 Some(Err(error)) => { return Some(error) },
                 None =>
                     unreachable!("EOF occurred that cannot happen at this time."),
@@ -161,22 +407,11 @@ impl Figtree {
         loop {
             match self.parser.next() {
                 Some(Ok((ParseEvent::NodeEnd, _))) => { return None; },
-                Some(Ok((ParseEvent::NodeStart(name), _))) => {
-                    if node.has_node(&name) {
-                        return Some((ParseError::RepeatedNode(name), self.parser.lex_position()));
-                    }
-                    if let Some(err) = self.parse_node(node.new_node_or_get(name)) {
+                Some(Ok((event, position))) => {
+                    if let Some(err) = self.parse_entry(node, event, position) {
                         return Some(err);
                     }
                 },
-                Some(Ok((ParseEvent::Key(key), _))) => {
-                    match self.parse_value() {
-                        Ok(value) => { node.insert_attr(key, value); },
-                        Err(err) => { return Some(err); }
-                    }
-                }
-                Some(Ok(ev)) =>
-                    unreachable!("ParseEvent {:?} occurred that cannot happen at this time.", ev),
                 Some(Err(error)) => { return Some(error) },
                 None =>
                     unreachable!("EOF occurred that cannot happen at this time."),
@@ -186,8 +421,49 @@ impl Figtree {
 
     fn parse_value(&mut self) -> Result<Value, (ParseError, Position)> {
         match self.parser.next() {
-            Some(Ok((ParseEvent::Value(val), _))) =>
-                Ok(Value::from_parsed_value(val)),
+            Some(Ok((ParseEvent::Anchor(name), position))) => {
+                self.references.anchor(name, &self.location, position)?;
+                self.parse_value()
+            },
+            Some(Ok((ParseEvent::Tag(tag), position))) => {
+                // the payload must be known now, so it cannot be a placeholder
+                let placeholders = self.references.count();
+                let literals = self.literals.len();
+                let payload = self.parse_value()?;
+                // the value is whatever the tag makes of the payload, as it was written
+                self.literals.truncate(literals);
+                if self.references.count() > placeholders {
+                    let message = "the payload cannot contain aliases or references";
+                    return Err((ParseError::InvalidTag(tag, message.to_string()), position));
+                }
+                if !self.evaluate {
+                    return Ok(payload);
+                }
+                match self.options.tags.apply(&tag, &payload) {
+                    Some(Ok(value)) => Ok(value),
+                    Some(Err(message)) => Err((ParseError::InvalidTag(tag, message), position)),
+                    None => Err((ParseError::UnknownTag(tag), position)),
+                }
+            },
+            Some(Ok((ParseEvent::Value(val), position))) => match val {
+                ParsedValue::Alias(_) | ParsedValue::Reference(_) | ParsedValue::Interpolated(_) => {
+                    // a placeholder, until the references are resolved
+                    self.add_reference(val, false, position)?;
+                    Ok(Value::new_null())
+                },
+                ParsedValue::Literal(literal) => {
+                    let value = literal.value().clone();
+                    self.literals.push((self.value_path(), SourceForm::Number(literal)));
+                    Ok(value)
+                },
+                ParsedValue::Bytes(bytes, encoding) => {
+                    if encoding != BytesEncoding::default() {
+                        self.literals.push((self.value_path(), SourceForm::Bytes(encoding)));
+                    }
+                    Ok(Value::new_bytes(bytes))
+                },
+                val => Ok(Value::from_parsed_value(val)),
+            },
             Some(Ok((ParseEvent::ListStart, _))) =>
                 self.parse_list(),
             Some(Ok((ParseEvent::DictStart, _))) =>
@@ -201,6 +477,20 @@ impl Figtree {
         }
     }
 
+    // The path of the value being built within the attribute it belongs to.
+    fn value_path(&self) -> Path {
+        let start = self.location.iter().rposition(|step| matches!(step, Step::Attr(_)));
+        let mut path = Path::new();
+        for step in &self.location[start.map_or(0, |start| start + 1)..] {
+            match *step {
+                Step::Key(ref key) => path.push_name(key.as_str()),
+                Step::Index(index) => path.push_index(index),
+                Step::Attr(_) | Step::Node(..) => {},
+            }
+        }
+        path
+    }
+
     fn parse_list(&mut self) -> Result<Value, (ParseError, Position)> {
         let mut list = List::new();
         loop {
@@ -208,7 +498,10 @@ impl Figtree {
                 self.parser.next();
                 return Ok(Value::List(list));
             } else {
-                match self.parse_value() {
+                self.location.push(Step::Index(list.len()));
+                let value = self.parse_value();
+                self.location.pop();
+                match value {
                     Ok(val) => list.push(val),
                     Err(err) => { return Err(err); }
                 }
@@ -221,7 +514,10 @@ impl Figtree {
         loop {
             match self.parser.next() {
                 Some(Ok((ParseEvent::Key(key), _))) => {
-                    match self.parse_value() {
+                    self.location.push(Step::Key(key.clone()));
+                    let value = self.parse_value();
+                    self.location.pop();
+                    match value {
                         Ok(value) => { dict.insert(key, value); },
                         Err(err) => { return Err(err); }
                     }
@@ -241,9 +537,15 @@ impl Figtree {
 
 #[cfg(test)]
 mod tests {
-    use super::Figtree;
+    use super::{Figtree, LoadOptions};
+    use super::super::parser::{KeyStyle, ParseError};
+    use super::super::path::Path;
+    use super::super::position::Position;
+    use super::super::lexer::{LexError, LexToken};
     use super::super::types::*;
     use std::collections::HashMap;
+    use std::env;
+    use std::time::Duration;
 
     #[test]
     fn construct_empty_file() {
@@ -296,4 +598,478 @@ mod tests {
             dict
         }));
     }
+
+    #[test]
+    fn construct_bare_keys() {
+        let config = Figtree::from_string(
+            "node { port: 80, `odd key`: 'x', 'quoted': {nested: []} }").parse().unwrap();
+        let node = config.get_node("node").unwrap();
+        assert_eq!(node.get_attr("port").unwrap(), &Value::new_int(80));
+        assert_eq!(node.get_attr("odd key").unwrap(), &Value::new_string("x"));
+        assert_eq!(node.get_attr("quoted").unwrap()["nested"], Value::List(Vec::new()));
+
+        let options = LoadOptions { key_style: KeyStyle::Quoted, .. LoadOptions::default() };
+        let error = Figtree::from_string("node { 'a': { b: 1 } }").parse_with(&options);
+        assert_eq!(error, Err((ParseError::BareKey("b".to_string()), Position::at(0, 14))));
+    }
+
+    #[test]
+    fn construct_top_level_attributes() {
+        let config = Figtree::from_string(
+            "'version': 2, name: 'app' node { 'key': 1 } 'after': true").parse().unwrap();
+        assert_eq!(config.get_attr("version"), Some(&Value::new_int(2)));
+        assert_eq!(config.get_attr("name"), Some(&Value::new_string("app")));
+        assert_eq!(config.get_attr("after"), Some(&Value::new_bool(true)));
+        assert_eq!(config.attr_count(), 3);
+        assert_eq!(config.node_count(), 1);
+
+        let error = Figtree::from_string("'a': 1 'b': 2").parse();
+        assert_eq!(
+            error,
+            Err((ParseError::UnexpectedToken(LexToken::StringLit("b".to_string())), Position::at(0, 7))));
+    }
+
+    #[test]
+    fn construct_repeated_nodes() {
+        let source = "server { 'port': 80 } server { 'port': 81 } other { sub {} sub { 'x': 1 } }";
+        assert_eq!(
+            Figtree::from_string(source).parse().unwrap_err().0,
+            ParseError::RepeatedNode("server".to_string()));
+
+        let options = LoadOptions { repeated_nodes: true, .. LoadOptions::default() };
+        let config = Figtree::from_string(source).parse_with(&options).unwrap();
+        let ports: Vec<&Value> = config.iter_nodes_named("server")
+            .filter_map(|node| node.get_attr("port"))
+            .collect();
+        assert_eq!(ports, vec![&Value::new_int(80), &Value::new_int(81)]);
+        assert_eq!(config.node_count(), 3);
+        assert_eq!(config.get_node("other").unwrap().get_nodes("sub")[1]["x"], Value::new_int(1));
+    }
+
+    #[test]
+    fn construct_labelled_nodes() {
+        let source = "backend 'api' 'eu-west' { 'port': 80 } backend 'api' 'us' {} backend {}";
+        let config = Figtree::from_string(source).parse().unwrap();
+        assert_eq!(config.get_nodes("backend").len(), 3);
+        assert_eq!(config.get_nodes("backend")[1].labels(), &["api", "us"]);
+        let eu = config.get_labelled_node("backend", &["api", "eu-west"]).unwrap();
+        assert_eq!(eu["port"], Value::new_int(80));
+        assert!(config.get_labelled_node("backend", &[] as &[&str]).unwrap().labels().is_empty());
+        assert!(config.get_labelled_node("backend", &["api"]).is_none());
+
+        let source = "backend 'api' {} backend 'web' {} backend 'api' {}";
+        assert_eq!(
+            Figtree::from_string(source).parse().unwrap_err(),
+            (ParseError::RepeatedNode("backend".to_string()), Position::at(0, 48)));
+        let options = LoadOptions { repeated_nodes: true, .. LoadOptions::default() };
+        let config = Figtree::from_string(source).parse_with(&options).unwrap();
+        assert_eq!(config.get_nodes("backend").len(), 3);
+    }
+
+    #[test]
+    fn construct_references() {
+        let source = "\
+            &base server { 'port': &port 80, 'hosts': ['a', 'b'] }
+            web 'a' *base
+            api ${server}
+            other { 'port': *port, 'host': ${server.hosts[1]}, 'copy': ${other.port} }
+            'first': ${web[\"a\"].hosts[0]}";
+        let config = Figtree::from_string(source).parse().unwrap();
+        let server = config.get_node("server").unwrap();
+        let web = config.get_labelled_node("web", &["a"]).unwrap();
+        assert_eq!((web.labels(), &web["hosts"]), (&["a".to_string()][..], &server["hosts"]));
+        assert_eq!(config.get_node("api"), Some(server));
+        let other = config.get_node("other").unwrap();
+        assert_eq!(other["port"], Value::new_int(80));
+        assert_eq!(other["host"], Value::new_string("b"));
+        assert_eq!(other["copy"], Value::new_int(80));
+        assert_eq!(config.get_attr("first"), Some(&Value::new_string("a")));
+    }
+
+    #[test]
+    fn reference_errors() {
+        let error = |source: &str| Figtree::from_string(source).parse().unwrap_err();
+        assert_eq!(
+            error("a { 'x': ${b.y} } b { 'y': ${a.x} }"),
+            (ParseError::CyclicReference("${b.y}".to_string()), Position::at(0, 9)));
+        assert_eq!(
+            error("a { 'x': &x {'k': *x} }"),
+            (ParseError::CyclicReference("*x".to_string()), Position::at(0, 19)));
+        assert_eq!(
+            error("a { 'x': *nope }"),
+            (ParseError::DanglingReference("*nope".to_string()), Position::at(0, 10)));
+        assert_eq!(
+            error("a { 'x': ${b.y} }"),
+            (ParseError::DanglingReference("${b.y}".to_string()), Position::at(0, 9)));
+        assert_eq!(
+            error("a { 'x': &v 1, 'y': &v 2 }"),
+            (ParseError::DuplicateAnchor("v".to_string()), Position::at(0, 21)));
+        assert_eq!(
+            error("a { 'x': ${b} } b {}"),
+            (ParseError::NotAValue("${b}".to_string()), Position::at(0, 9)));
+        assert_eq!(
+            error("a { 'x': 1 } b ${a.x}"),
+            (ParseError::NotANode("${a.x}".to_string()), Position::at(0, 15)));
+        assert_eq!(
+            error("a { 'x': ${a..x} }"),
+            (ParseError::InvalidReference("${a..x}".to_string()), Position::at(0, 9)));
+    }
+
+    #[test]
+    fn construct_interpolated_strings() {
+        env::set_var("FIGTREE_TEST_USER", "fig");
+        let mut context = Document::new();
+        context.insert_attr("region".to_string(), Value::new_string("eu"));
+        let options = LoadOptions { context, .. LoadOptions::default() };
+        let source = "\
+            server { 'host': 'example.com', 'port': 8080, 'ratio': 0.5, 'tls': false }
+            'url': 'http://${server.host}:${server.port}/' 'api',
+            'mixed': '${server.ratio} ${server.tls} ${url}',
+            'user': '${env:FIGTREE_TEST_USER}@${region}',
+            'home': ${env:FIGTREE_TEST_USER},
+            'escaped': '$${server.host}',
+            'raw': r/${server.host}/";
+        let config = Figtree::from_string(source).parse_with(&options).unwrap();
+        let attr = |key: &str| config.get_attr(key).and_then(Value::get_str).unwrap().to_string();
+        assert_eq!(attr("url"), "http://example.com:8080/api");
+        assert_eq!(attr("mixed"), "0.5 false http://example.com:8080/api");
+        assert_eq!(attr("user"), "fig@eu");
+        assert_eq!(attr("home"), "fig");
+        assert_eq!(attr("escaped"), "${server.host}");
+        assert_eq!(attr("raw"), "${server.host}");
+    }
+
+    #[test]
+    fn interpolation_errors() {
+        // errors in a reference are reported at its `${`, and cycles at the string
+        let error = |source: &str| Figtree::from_string(source).parse().unwrap_err();
+        assert_eq!(
+            error("a { 'x': 1 }\n'y': 'a${a.z}'"),
+            (ParseError::DanglingReference("${a.z}".to_string()), Position::at(1, 7)));
+        assert_eq!(
+            error("a { 'x': 1 }\n'y': '${a.x} ${a.z}'"),
+            (ParseError::DanglingReference("${a.z}".to_string()), Position::at(1, 13)));
+        assert_eq!(
+            error("a { 'x': 1 }\n'y': '''\n    a\n    b${a.z}\n    '''"),
+            (ParseError::DanglingReference("${a.z}".to_string()), Position::at(3, 5)));
+        assert_eq!(
+            error("'y': '${env:FIGTREE_TEST_UNSET}'"),
+            (ParseError::DanglingReference("${env:FIGTREE_TEST_UNSET}".to_string()), Position::at(0, 6)));
+        assert_eq!(
+            error("a { 'x': [1] } 'y': '${a.x}'"),
+            (ParseError::NotInterpolable("${a.x}".to_string()), Position::at(0, 21)));
+        assert_eq!(
+            error("a { 'x': 1 } 'y': '${a}'"),
+            (ParseError::NotInterpolable("${a}".to_string()), Position::at(0, 19)));
+        assert_eq!(
+            error("'x': '${y}', 'y': '${x}'"),
+            (ParseError::CyclicReference("\"${y}\"".to_string()), Position::at(0, 5)));
+        assert_eq!(
+            error("'y': 'x ${a..b}'"),
+            (ParseError::InvalidReference("${a..b}".to_string()), Position::at(0, 8)));
+    }
+
+    #[test]
+    fn construct_inheritance() {
+        let source = "\
+            abstract base { 'port': 80, 'opts': {'a': 1, 'b': 2}, tls { 'on': false } w {} w {} }
+            staging : base { 'opts': {'b': 3} }
+            production : staging { 'port': 443, tls { 'on': true, 'cert': 'x' } }
+            envs { eu : production { w {} } }
+            backend 'api' { 'host': 'a' }
+            web : backend[\"api\"] {}
+            'port': ${production.port}, 'a': ${staging.opts.a}";
+        let options = LoadOptions { repeated_nodes: true, .. LoadOptions::default() };
+        let config = Figtree::from_string(source).parse_with(&options).unwrap();
+        assert!(config.get_node("base").is_none());
+
+        let staging = config.get_node("staging").unwrap();
+        assert_eq!(staging["port"], Value::new_int(80));
+        assert_eq!(staging["opts"]["a"], Value::new_int(1));
+        assert_eq!(staging["opts"]["b"], Value::new_int(3));
+        assert_eq!(staging.get_node("tls").unwrap()["on"], Value::new_bool(false));
+        assert_eq!(staging.get_nodes("w").len(), 2);
+
+        let production = config.get_node("production").unwrap();
+        assert_eq!(production["port"], Value::new_int(443));
+        assert_eq!(production["opts"], staging["opts"]);
+        let tls = production.get_node("tls").unwrap();
+        assert_eq!((&tls["on"], &tls["cert"]), (&Value::new_bool(true), &Value::new_string("x")));
+
+        // repeated nodes are only inherited if the node has none of its own
+        let eu = config.get_node("envs").unwrap().get_node("eu").unwrap();
+        assert_eq!(eu["port"], Value::new_int(443));
+        assert_eq!(eu.get_nodes("w").len(), 1);
+
+        assert_eq!(config.get_node("web").unwrap()["host"], Value::new_string("a"));
+        assert_eq!(config.get_attr("port"), Some(&Value::new_int(443)));
+        assert_eq!(config.get_attr("a"), Some(&Value::new_int(1)));
+    }
+
+    #[test]
+    fn construct_conditions() {
+        let source = "
+            server {
+                port: 8080,
+                when(profile == 'prod') {
+                    port: 80,
+                    tls {}
+                    when(region != 'eu') { mirror: true }
+                }
+                when(profile == 'dev' || region == 'eu') { debug: true }
+            }
+            when(!(profile == 'prod')) { &anchor fallback {} }";
+        let load = |profiles: &[&str], region: &str| {
+            let mut options = LoadOptions {
+                profiles: profiles.iter().map(|profile| profile.to_string()).collect(),
+                ..LoadOptions::default()
+            };
+            options.variables.insert("region".to_string(), region.to_string());
+            Figtree::from_string(source).parse_with(&options).unwrap()
+        };
+
+        let prod = load(&["prod"], "us");
+        let server = prod.get_node("server").unwrap();
+        assert_eq!(server.get_attr("port"), Some(&Value::Int(80)));
+        assert!(server.get_node("tls").is_some());
+        assert_eq!(server.get_attr("mirror"), Some(&Value::Bool(true)));
+        assert_eq!(server.get_attr("debug"), None);
+        assert!(prod.get_node("fallback").is_none());
+
+        let dev = load(&["dev"], "us");
+        let server = dev.get_node("server").unwrap();
+        assert_eq!(server.get_attr("port"), Some(&Value::Int(8080)));
+        assert!(server.get_node("tls").is_none() && server.get_attr("mirror").is_none());
+        assert_eq!(server.get_attr("debug"), Some(&Value::Bool(true)));
+        assert!(dev.get_node("fallback").is_some());
+
+        // blocks are dropped without complaint, whatever they contain
+        let none = load(&[], "eu");
+        assert_eq!(none.get_node("server").unwrap().get_attr("debug"), Some(&Value::Bool(true)));
+
+        assert_eq!(
+            Figtree::from_string("n { when(region == 'eu') {} }").parse().unwrap_err(),
+            (ParseError::UnknownVariable("region".to_string()), Position::at(0, 4)));
+        let options = LoadOptions { unknown_variables_inactive: true, .. LoadOptions::default() };
+        let doc = Figtree::from_string("n { when(region != 'eu') { a: 1 } b: 2 }")
+            .parse_with(&options).unwrap();
+        assert_eq!(doc.get_node("n").unwrap().get_attr("a"), None);
+        assert_eq!(doc.get_node("n").unwrap().get_attr("b"), Some(&Value::Int(2)));
+        assert_eq!(
+            Figtree::from_string("when(profile == 'x') { n {} } n {}").parse().unwrap(),
+            Figtree::from_string("n {}").parse().unwrap());
+    }
+
+    #[test]
+    fn construct_annotations() {
+        let doc = Figtree::from_string("
+            @deprecated('use timeout_ms') 'timeout': 30,
+            @doc('Database settings') abstract db {
+                @secret password: 'hunter2',
+                user: 'fig',
+            }
+            prod : db { @doc('The production user') user: 'prod' }
+            @doc('A copy') backup *prod_db
+            &prod_db other {}").parse().unwrap();
+
+        let deprecated = doc.attr_annotation("timeout", "deprecated").unwrap();
+        assert_eq!(deprecated.message(), Some("use timeout_ms"));
+        assert_eq!(doc.attr_annotations("timeout").len(), 1);
+
+        // annotations of attributes are inherited, but not those of the node itself
+        let prod = doc.get_node("prod").unwrap();
+        assert!(prod.annotations().is_empty());
+        assert_eq!(prod.attr_annotations("password"), &[Annotation::new("secret")]);
+        assert_eq!(
+            prod.attr_annotation("user", "doc").and_then(Annotation::message),
+            Some("The production user"));
+
+        let backup = doc.get_node("backup").unwrap();
+        assert_eq!(backup.annotation("doc").and_then(Annotation::message), Some("A copy"));
+        assert!(doc.get_node("other").unwrap().annotations().is_empty());
+
+        // annotations are not part of the content of a document
+        assert_eq!(
+            Figtree::from_string("@secret 'k': 1 @doc n {}").parse().unwrap(),
+            Figtree::from_string("'k': 1 n {}").parse().unwrap());
+    }
+
+    #[test]
+    fn construct_tags() {
+        env::set_var("FIGTREE_TEST_TAG", "tagged");
+        let mut options = LoadOptions::default();
+        options.tags.register("pair", |payload: &Value| match payload.get_list() {
+            Some(list) if list.len() == 2 => Ok(payload.clone()),
+            _ => Err("expected two items".to_string()),
+        });
+        let load = |source: &str| Figtree::from_string(source).parse_with(&options);
+
+        let doc = load("
+            node {
+                'timeout': !duration '1m30s',
+                'size': !bytes '1MiB',
+                'home': !env 'FIGTREE_TEST_TAG',
+                'both': !pair [1, 2],
+                'symbol': !duration,
+            }").unwrap();
+        let node = doc.get_node("node").unwrap();
+        assert_eq!(node.get_attr("timeout"), Some(&Value::new_duration(Duration::from_secs(90))));
+        assert_eq!(node.get_attr("size"), Some(&Value::new_byte_size(1 << 20)));
+        assert_eq!(node.get_attr("home"), Some(&Value::new_string("tagged")));
+        assert_eq!(node.get_attr("both"), Some(&Value::from(vec![1, 2])));
+        assert_eq!(node.get_attr("symbol"), Some(&Value::new_ident("duration")));
+
+        assert_eq!(
+            load("'a': 1, 'b': !nope 'x'").unwrap_err(),
+            (ParseError::UnknownTag("nope".to_string()), Position::at(0, 13)));
+        assert_eq!(
+            load("'a': !pair [1]").unwrap_err(),
+            (ParseError::InvalidTag("pair".to_string(), "expected two items".to_string()),
+             Position::at(0, 5)));
+        assert_eq!(
+            load("'a': 1, 'b': !env *a").unwrap_err().0,
+            ParseError::InvalidTag(
+                "env".to_string(), "the payload cannot contain aliases or references".to_string()));
+        assert!(matches!(
+            load("'a': !duration '5 minutes'").unwrap_err().0,
+            ParseError::InvalidTag(ref tag, _) if tag == "duration"));
+    }
+
+    #[test]
+    fn construct_quantities() {
+        let options = LoadOptions { quantity_literals: true, .. LoadOptions::default() };
+        let doc = Figtree::from_string("
+            server {
+                timeout: 1.5s,
+                cache: 512MiB,
+                'url': \"http://x/?wait=${server.timeout}\",
+            }").parse_with(&options).unwrap();
+        let server = doc.get_node("server").unwrap();
+        assert_eq!(server.get_attr("timeout"), Some(&Value::new_duration(Duration::from_millis(1500))));
+        assert_eq!(server.get_attr("cache").and_then(Value::as_byte_size), Some(512 << 20));
+        assert_eq!(server.get_attr("url"), Some(&Value::new_string("http://x/?wait=1s500ms")));
+
+        let (error, position) = Figtree::from_string("'a': 1,\n'b': 213503982334602d")
+            .parse_with(&options).unwrap_err();
+        assert!(matches!(error, ParseError::LexError(LexError::InvalidQuantity(_))));
+        assert_eq!(position.line, 1);
+
+        // without the option, quantities are written with tags
+        assert!(Figtree::from_string("'timeout': 1.5s").parse().is_err());
+        let doc = Figtree::from_string("'timeout': !duration \"1.5s\"").parse().unwrap();
+        assert_eq!(
+            doc.get_attr("timeout"), Some(&Value::new_duration(Duration::from_millis(1500))));
+    }
+
+    #[test]
+    fn construct_datetimes() {
+        let doc = Figtree::from_string("
+            maintenance {
+                start: 2026-10-16T22:00:00Z,
+                'notice': \"from ${maintenance.start}\",
+            }").parse().unwrap();
+        let node = doc.get_node("maintenance").unwrap();
+        assert_eq!(
+            node.get_attr("start").and_then(Value::get_datetime),
+            "2026-10-16T22:00:00Z".parse().ok());
+        assert!(node.get_attr("start").and_then(Value::as_system_time).is_some());
+        assert_eq!(node.get_attr("notice"), Some(&Value::new_string("from 2026-10-16T22:00:00Z")));
+
+        assert_eq!(
+            Figtree::from_string("'a': 1,\n'b': 2026-10-16T25:00:00").parse().unwrap_err(),
+            (ParseError::LexError(LexError::InvalidDateTime("hour 25 is out of range".to_string())),
+             Position::at(1, 16)));
+    }
+
+    #[test]
+    fn construct_special_floats() {
+        let doc = Figtree::from_string("
+            filter {
+                'gain': 0x1.8p-1,
+                'limits': [-inf, inf],
+                'missing': nan,
+            }").parse().unwrap();
+        let node = doc.get_node("filter").unwrap();
+        assert_eq!(node.get_attr("gain"), Some(&Value::new_float(0.75)));
+        assert_eq!(
+            node.get_attr("limits"),
+            Some(&Value::List(vec![Value::new_float(f64::NEG_INFINITY), Value::new_float(f64::INFINITY)])));
+        assert!(node.get_attr("missing").and_then(Value::get_float).is_some_and(f64::is_nan));
+        // NaN values are equal to each other, so documents containing them can be compared
+        assert_eq!(node.get_attr("missing"), Some(&Value::new_float(f64::NAN)));
+        assert_eq!(Figtree::from_string("'a': nan").parse().unwrap(), Figtree::from_string("'a': nan").parse().unwrap());
+    }
+
+    #[test]
+    fn construct_numeric_literals() {
+        let doc = Figtree::from_string("
+            'plain': 10,
+            base { 'mask': 0xFF_FF, 'flags': 0b1010, 'count': 1_000_000, 'scale': 8.e4 }
+            derived : base { 'count': 5 }").parse().unwrap();
+        assert_eq!(doc.attr_literal("plain"), None);
+        let base = doc.get_node("base").unwrap();
+        assert_eq!(base.get_attr("mask"), Some(&Value::new_int(65535)));
+        let literals: Vec<_> = ["mask", "flags", "count", "scale"].iter()
+            .map(|key| base.attr_literal(*key).map(|literal| (literal.lexeme(), literal.radix())))
+            .collect();
+        assert_eq!(literals, vec![
+            Some(("0xFF_FF", 16)), Some(("0b1010", 2)), Some(("1_000_000", 10)),
+            Some(("8.e4", 10))]);
+
+        // inherited attributes keep their literals
+        let derived = doc.get_node("derived").unwrap();
+        assert_eq!(derived.attr_literal("mask").map(NumericLiteral::lexeme), Some("0xFF_FF"));
+        assert_eq!(derived.attr_literal("count"), None);
+
+        // numbers in lists and dicts are kept by their path within the attribute
+        // and those in the payload of a tag are not, since the tag gives the value
+        let mut options = LoadOptions::default();
+        options.tags.register("same", |payload: &Value| Ok(payload.clone()));
+        let doc = Figtree::from_string("'l': [1, 0x10], 'd': {'k': [1_0]}, 't': !same 0x1")
+            .parse_with(&options).unwrap();
+        let lexeme = |name: &str, path: Path| {
+            doc.value_literal(name, &path).map(NumericLiteral::lexeme)
+        };
+        assert_eq!(lexeme("l", Path::new().index(1)), Some("0x10"));
+        assert_eq!(lexeme("l", Path::new().index(0)), None);
+        assert_eq!(lexeme("d", Path::new().child("k").index(0)), Some("1_0"));
+        assert_eq!(lexeme("t", Path::new()), None);
+    }
+
+    #[test]
+    fn construct_bytes() {
+        let doc = Figtree::from_string("
+            keys {
+                'public': b64\"3q2+7w==\",
+                'salt': hex'DEAD beef',
+            }").parse().unwrap();
+        let node = doc.get_node("keys").unwrap();
+        assert_eq!(node.get_attr("public").and_then(Value::get_bytes), Some(&[0xde, 0xad, 0xbe, 0xef][..]));
+        assert_eq!(node.get_attr("public"), node.get_attr("salt"));
+
+        assert_eq!(
+            Figtree::from_string("'a': 1,\n'b': hex\"abc\"").parse().unwrap_err(),
+            (ParseError::LexError(LexError::InvalidBytes("hex has an odd number of digits".to_string())),
+             Position::at(1, 11)));
+    }
+
+    #[test]
+    fn inheritance_errors() {
+        let error = |source: &str| Figtree::from_string(source).parse().unwrap_err();
+        assert_eq!(
+            error("a : b {} b : a {}"),
+            (ParseError::CyclicInheritance("b".to_string()), Position::at(0, 6)));
+        assert_eq!(
+            error("a { b : a {} }"),
+            (ParseError::CyclicInheritance("a".to_string()), Position::at(0, 10)));
+        assert_eq!(
+            error("a : missing {}"),
+            (ParseError::DanglingReference("missing".to_string()), Position::at(0, 12)));
+        assert_eq!(
+            error("a { 'x': 1 } b : a.x {}"),
+            (ParseError::NotANode("a.x".to_string()), Position::at(0, 21)));
+        assert_eq!(
+            error("abstract 'x': 1"),
+            (ParseError::UnexpectedToken(LexToken::IntegerLit(1)), Position::at(0, 14)));
+    }
 }
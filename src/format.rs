@@ -0,0 +1,1346 @@
+//! A canonical formatter for figtree source.
+//!
+//! The formatter rewrites a figtree file into a single, consistent layout, without
+//! changing what the file means.  Nodes and attributes are written one per line and
+//! indented by nesting level, every attribute is followed by a comma, and strings are
+//! written with a consistent quote style.  Comments are kept where they were written,
+//! and single blank lines between entries are preserved.  Lists and dicts that were
+//! written on a single line stay on a single line; those that were spread over several
+//! lines (or that contain comments) are written one item per line.
+//!
+//! Literals are otherwise written exactly as they appear in the source, so `0x4f` stays
+//! as `0x4f` and raw strings stay raw.
+//!
+//! A few aspects of the layout can be configured using `FormatOptions`, which can also
+//! be loaded from a `.figtreefmt` file:
+//!
+//! ```text
+//! format {
+//!     "indent_width": 4,
+//!     "quote_style": "double",
+//!     "sort_keys": false,
+//!     "align_colons": false,
+//!     "key_style": "any",
+//! }
+//! ```
+//!
+//! # Examples
+//! ```
+//! use figtree::format::{self, FormatOptions};
+//!
+//! let source = "node{'key' :1 ,  sub{}}";
+//! let formatted = format::format_str(source, &FormatOptions::default())
+//!     .ok().expect("invalid document");
+//! assert_eq!(formatted, "node {\n    \"key\": 1,\n    sub {}\n}\n");
+//! ```
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Cursor};
+use std::path;
+
+use super::figtree::Figtree;
+use super::lexer::{common_indentation, Lexer, LexToken};
+use super::parser::{is_tag_payload, keyword_value, KeyStyle, ParseError};
+use super::position::Position;
+use super::types::*;
+use super::writer::{is_bare_ident, write_string};
+
+/// The name of the file that formatting options are loaded from.
+pub const CONFIG_FILENAME: &str = ".figtreefmt";
+
+/// The quote character used for strings and keys.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuoteStyle {
+    Double,
+    Single,
+}
+
+impl QuoteStyle {
+    fn quote(self) -> char {
+        match self {
+            QuoteStyle::Double => '"',
+            QuoteStyle::Single => '\'',
+        }
+    }
+
+    fn other(self) -> char {
+        match self {
+            QuoteStyle::Double => '\'',
+            QuoteStyle::Single => '"',
+        }
+    }
+}
+
+/// Options controlling the layout produced by the formatter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatOptions {
+    /// The number of spaces used for each level of indentation.
+    pub indent_width: usize,
+    /// The preferred quote character.  Strings that contain the preferred quote but
+    /// not the other quote are written with the other quote to avoid escaping.
+    pub quote_style: QuoteStyle,
+    /// Sort attributes and dict keys within each group of entries, including the keys
+    /// of dicts written on a single line.  Groups are separated by blank lines and
+    /// nodes, and comments move with the entry they are written above.
+    pub sort_keys: bool,
+    /// Pad keys within each group of entries so that their colons line up.
+    pub align_colons: bool,
+    /// Rewrite keys to be quoted or bare.  `KeyStyle::Any` leaves keys as they were
+    /// written, and keys that cannot be written bare are always left quoted.
+    pub key_style: KeyStyle,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            indent_width: 4,
+            quote_style: QuoteStyle::Double,
+            sort_keys: false,
+            align_colons: false,
+            key_style: KeyStyle::Any,
+        }
+    }
+}
+
+/// An enum representing an error that occurs while loading formatting options.
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    ReadError(io::ErrorKind),
+    ParseError(ParseError, Position),
+    InvalidOption(String),
+}
+
+impl FormatOptions {
+    /// Read formatting options from the `format` node of a document.
+    ///
+    /// Any options that are not given keep their default values.
+    ///
+    /// # Failures
+    /// Returns `ConfigError::InvalidOption` if an option is unknown or has a value of
+    /// the wrong type.
+    pub fn from_document(doc: &Document) -> Result<FormatOptions, ConfigError> {
+        let mut options = FormatOptions::default();
+        let node = match doc.get_node("format") {
+            Some(node) => node,
+            None => { return Ok(options); },
+        };
+
+        for (key, value) in node.iter_attrs() {
+            match (key.as_str(), value) {
+                ("indent_width", Value::Int(width)) if *width >= 0 => {
+                    options.indent_width = *width as usize;
+                },
+                ("quote_style", Value::Str(style)) if style == "double" => {
+                    options.quote_style = QuoteStyle::Double;
+                },
+                ("quote_style", Value::Str(style)) if style == "single" => {
+                    options.quote_style = QuoteStyle::Single;
+                },
+                ("sort_keys", Value::Bool(sort)) => {
+                    options.sort_keys = *sort;
+                },
+                ("align_colons", Value::Bool(align)) => {
+                    options.align_colons = *align;
+                },
+                ("key_style", Value::Str(style)) if style == "any" => {
+                    options.key_style = KeyStyle::Any;
+                },
+                ("key_style", Value::Str(style)) if style == "quoted" => {
+                    options.key_style = KeyStyle::Quoted;
+                },
+                ("key_style", Value::Str(style)) if style == "bare" => {
+                    options.key_style = KeyStyle::Bare;
+                },
+                _ => { return Err(ConfigError::InvalidOption(key.clone())); },
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// Load formatting options from a `.figtreefmt` file.
+    ///
+    /// # Failures
+    /// Fails if the file cannot be read or parsed, or if it contains invalid options.
+    pub fn from_file<P: AsRef<path::Path>>(filename: P) -> Result<FormatOptions, ConfigError> {
+        let file = File::open(filename).map_err(|err| ConfigError::ReadError(err.kind()))?;
+        let doc = Figtree::new(file).parse()
+            .map_err(|(err, pos)| ConfigError::ParseError(err, pos))?;
+        FormatOptions::from_document(&doc)
+    }
+
+    /// Find the options that apply to files in a directory.
+    ///
+    /// The directory and each of its parents is searched for a `.figtreefmt` file, and
+    /// the first one found is loaded.  If there is no such file, the default options
+    /// are returned.
+    ///
+    /// # Failures
+    /// Fails if a `.figtreefmt` file is found but cannot be loaded.
+    pub fn discover<P: AsRef<path::Path>>(directory: P) -> Result<FormatOptions, ConfigError> {
+        let directory = directory.as_ref();
+        let directory = directory.canonicalize().unwrap_or_else(|_| directory.to_path_buf());
+        for ancestor in directory.ancestors() {
+            let candidate = ancestor.join(CONFIG_FILENAME);
+            if candidate.is_file() {
+                return FormatOptions::from_file(candidate);
+            }
+        }
+        Ok(FormatOptions::default())
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::ReadError(kind) =>
+                write!(f, "could not read {}: {:?}", CONFIG_FILENAME, kind),
+            ConfigError::ParseError(ref err, ref pos) =>
+                write!(f, "{}:{}: {}", CONFIG_FILENAME, pos, err),
+            ConfigError::InvalidOption(ref key) =>
+                write!(f, "{}: invalid option {:?}", CONFIG_FILENAME, key),
+        }
+    }
+}
+
+/// Format figtree source text.
+///
+/// # Failures
+/// The source is parsed before it is formatted, and any parsing error is returned in
+/// the same form as `Figtree::parse` returns it.  The formatted text is parsed in the
+/// same way, and an error is returned rather than text that cannot be read back.
+pub fn format_str(source: &str, options: &FormatOptions)
+    -> Result<String, (ParseError, Position)> {
+
+    Figtree::from_string(source).check_syntax()?;
+
+    let mut tokens = Tokens::lex(source)?;
+    let mut items = tokens.parse_body();
+    let mut out = String::new();
+    Printer { options }.write_items(&mut items, 0, &mut out);
+
+    Figtree::from_string(&out).check_syntax()?;
+    Ok(out)
+}
+
+struct Token {
+    token: LexToken,
+    text: String,
+    start: usize,
+    end: usize,
+}
+
+enum Literal {
+    Scalar(String),
+    Strings(Vec<String>),
+    Seq(Seq),
+    // a value with an anchor or tag written before it
+    Prefixed(String, Box<Literal>),
+}
+
+struct Seq {
+    open: char,
+    close: char,
+    items: Vec<Item>,
+    multiline: bool,
+}
+
+enum Item {
+    Blank,
+    Comment(String),
+    Entry {
+        // annotations written before the key, such as `@secret`
+        annotations: Vec<String>,
+        key: Option<Key>,
+        value: Literal,
+        trailing: Option<String>,
+    },
+    Node {
+        annotations: Vec<String>,
+        header: Vec<Key>,
+        // an alias or reference written in place of the body
+        source: Option<String>,
+        // the path of the node it inherits from
+        parent: Option<String>,
+        open_comment: Option<String>,
+        body: Vec<Item>,
+        trailing: Option<String>,
+    },
+}
+
+struct Key {
+    text: String,
+    sort_key: String,
+    quoted: bool,
+}
+
+impl Key {
+    fn from_token(token: &Token) -> Key {
+        match token.token {
+            LexToken::StringLit(ref decoded) | LexToken::Identifier(ref decoded) => Key {
+                text: token.text.clone(),
+                sort_key: decoded.clone(),
+                quoted: matches!(token.token, LexToken::StringLit(_)),
+            },
+            _ => Key { text: token.text.clone(), sort_key: token.text.clone(), quoted: false },
+        }
+    }
+}
+
+impl Item {
+    fn trailing_slot(&mut self) -> Option<&mut Option<String>> {
+        match *self {
+            Item::Entry { ref mut trailing, .. } | Item::Node { ref mut trailing, .. }
+                if trailing.is_none() => Some(trailing),
+            _ => None,
+        }
+    }
+
+    fn is_multiline(&self) -> bool {
+        match *self {
+            Item::Entry { ref value, .. } => value.is_multiline(),
+            Item::Comment(ref comment) => comment.contains('\n'),
+            _ => false,
+        }
+    }
+}
+
+impl Literal {
+    fn is_multiline(&self) -> bool {
+        match *self {
+            Literal::Seq(ref seq) => seq.multiline,
+            Literal::Prefixed(_, ref value) => value.is_multiline(),
+            _ => false,
+        }
+    }
+}
+
+struct Tokens {
+    tokens: Vec<Token>,
+    index: usize,
+    last_end: usize,
+    // comments found inside an entry, such as between its key and its value, which are
+    // written above it
+    inner_comments: Vec<String>,
+}
+
+impl Tokens {
+    fn lex(source: &str) -> Result<Tokens, (ParseError, Position)> {
+        let mut lexer = Lexer::lex_preserving(Cursor::new(source.to_string().into_bytes()));
+        lexer.quantities = true;
+        let mut tokens = Vec::new();
+        while let Some(result) = lexer.next() {
+            let token = result.map_err(|err| {
+                (ParseError::LexError(err), lexer.token_start.clone().freeze())
+            })?;
+            let start = lexer.token_start.line;
+            let end = match token {
+                // line comments consume the newline that ends them
+                LexToken::Comment(ref text) if text.starts_with("//") => start,
+                _ => lexer.position.line,
+            };
+            let text = match token {
+                LexToken::Comment(ref text) => text.clone(),
+                _ => lexer.lexeme().unwrap_or("").to_string(),
+            };
+            tokens.push(Token { token, text, start, end });
+        }
+        Ok(Tokens { tokens, index: 0, last_end: 0, inner_comments: Vec::new() })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.index);
+        if let Some(token) = token {
+            self.index += 1;
+            self.last_end = token.end;
+        }
+        token
+    }
+
+    fn next_text(&mut self) -> String {
+        self.next().map(|token| token.text.clone()).unwrap_or_default()
+    }
+
+    fn next_is(&self, expected: &LexToken) -> bool {
+        self.peek().map(|token| &token.token == expected).unwrap_or(false)
+    }
+
+    fn peek_past_comments(&self) -> Option<&Token> {
+        self.tokens[self.index..].iter()
+            .find(|token| !matches!(token.token, LexToken::Comment(_)))
+    }
+
+    // Skips the comments before the next token, keeping them to be written above the
+    // entry they are inside.
+    fn skip_comments(&mut self) {
+        while let Some(&Token { token: LexToken::Comment(_), .. }) = self.peek() {
+            let text = self.next_text();
+            self.inner_comments.push(text.trim_end().to_string());
+        }
+    }
+
+    // Adds an entry to a list of items, after the comments that were found inside it.
+    fn push_entry(&mut self, items: &mut Vec<Item>, entry: Item) {
+        items.extend(self.inner_comments.drain(..).map(Item::Comment));
+        items.push(entry);
+    }
+
+    // Reads the rest of an annotation, having already read the `@`, and writes it out
+    // with its arguments separated by commas.
+    fn annotation_text(&mut self) -> String {
+        let mut text = format!("@{}", self.next_text());
+        if self.next_is(&LexToken::OpenParen) {
+            self.next();
+            let mut args = Vec::new();
+            while let Some(token) = self.next() {
+                match token.token {
+                    LexToken::CloseParen => { break; },
+                    LexToken::Comma => {},
+                    _ => args.push(token.text.clone()),
+                }
+            }
+            text.push('(');
+            text.push_str(&args.join(", "));
+            text.push(')');
+        }
+        text
+    }
+
+    // Reads the rest of a condition, having already read the opening parenthesis, and
+    // writes it out with spaces around its binary operators.
+    fn condition_text(&mut self) -> String {
+        let mut text = String::from("(");
+        let mut depth = 1;
+        while let Some(token) = self.next() {
+            match token.token {
+                LexToken::OpenParen => { depth += 1; },
+                LexToken::CloseParen => { depth -= 1; },
+                _ => {},
+            }
+            match token.token {
+                LexToken::Equals | LexToken::NotEquals | LexToken::And | LexToken::Or => {
+                    text.push(' ');
+                    text.push_str(&token.text);
+                    text.push(' ');
+                },
+                _ => text.push_str(&token.text),
+            }
+            if depth == 0 {
+                break;
+            }
+        }
+        text
+    }
+
+    // A colon followed by a bare identifier starts the parent of a node, as values are
+    // never bare identifiers.
+    fn next_is_parent(&self) -> bool {
+        match self.peek_past_comments().map(|token| &token.token) {
+            Some(LexToken::Identifier(name)) =>
+                keyword_value(name).is_none(),
+            _ => false,
+        }
+    }
+
+    fn blank_before_next(&self) -> bool {
+        self.peek().map(|token| token.start > self.last_end + 1).unwrap_or(false)
+    }
+
+    fn same_line_comment(&mut self) -> Option<String> {
+        let is_comment = match self.peek() {
+            Some(&Token { token: LexToken::Comment(_), start, .. }) => start == self.last_end,
+            _ => false,
+        };
+        if is_comment {
+            self.next().map(|token| token.text.trim_end().to_string())
+        } else {
+            None
+        }
+    }
+
+    // Adds a comment to a list of items, either trailing the previous item if it was
+    // written on the same line, or as a comment on its own line.
+    fn push_comment(&mut self, items: &mut Vec<Item>) {
+        let same_line = self.peek().map(|token| token.start == self.last_end).unwrap_or(false);
+        let blank = self.blank_before_next();
+        let text = self.next().map(|token| token.text.trim_end().to_string()).unwrap_or_default();
+
+        if same_line {
+            if let Some(slot) = items.last_mut().and_then(|item| item.trailing_slot()) {
+                *slot = Some(text);
+                return;
+            }
+        }
+        if blank && !items.is_empty() {
+            items.push(Item::Blank);
+        }
+        items.push(Item::Comment(text));
+    }
+
+    fn parse_body(&mut self) -> Vec<Item> {
+        let mut items = Vec::new();
+        loop {
+            match self.peek().map(|token| token.token.clone()) {
+                None => { break; },
+                Some(LexToken::CloseBrace) => {
+                    self.next();
+                    break;
+                },
+                Some(LexToken::Comment(_)) => {
+                    self.push_comment(&mut items);
+                    continue;
+                },
+                Some(LexToken::Comma) => {
+                    self.next();
+                    continue;
+                },
+                Some(_) => {},
+            }
+
+            if self.blank_before_next() && !items.is_empty() {
+                items.push(Item::Blank);
+            }
+
+            let mut annotations = Vec::new();
+            let mut header = Vec::new();
+            let mut key = None;
+            let mut source = None;
+            let mut parent = None;
+            loop {
+                self.skip_comments();
+                let token = match self.next() {
+                    Some(token) => token,
+                    None => { break; },
+                };
+                match token.token {
+                    LexToken::Colon => {
+                        if self.next_is_parent() {
+                            // the rest of the header is the path of the parent node
+                            let mut path = String::new();
+                            loop {
+                                self.skip_comments();
+                                match self.next() {
+                                    Some(token) if token.token != LexToken::OpenBrace => {
+                                        path.push_str(&token.text);
+                                    },
+                                    _ => { break; },
+                                }
+                            }
+                            parent = Some(path);
+                        } else {
+                            key = header.pop();
+                        }
+                        break;
+                    },
+                    LexToken::OpenBrace => { break; },
+                    LexToken::At => {
+                        let annotation = self.annotation_text();
+                        annotations.push(annotation);
+                    },
+                    LexToken::Ampersand => {
+                        let name = self.next_text();
+                        header.push(Key {
+                            text: format!("&{}", name),
+                            sort_key: name,
+                            quoted: false,
+                        });
+                    },
+                    LexToken::Asterisk => {
+                        let name = self.next_text();
+                        source = Some(format!("*{}", name));
+                        break;
+                    },
+                    LexToken::Reference(_) => {
+                        source = Some(token.text.clone());
+                        break;
+                    },
+                    LexToken::OpenParen => {
+                        // the condition of a `when` block, written straight after `when`
+                        let condition = self.condition_text();
+                        if let Some(when) = header.last_mut() {
+                            when.text.push_str(&condition);
+                        }
+                    },
+                    _ => header.push(Key::from_token(token)),
+                }
+            }
+
+            if key.is_some() {
+                // an attribute
+                let value = self.parse_value();
+                if self.next_is(&LexToken::Comma) {
+                    self.next();
+                }
+                let trailing = self.same_line_comment();
+                self.push_entry(&mut items, Item::Entry { annotations, key, value, trailing });
+            } else {
+                // a node, or a copy of another node
+                let open_comment = self.same_line_comment();
+                let body = if source.is_none() { self.parse_body() } else { Vec::new() };
+                let trailing = self.same_line_comment();
+                self.push_entry(&mut items, Item::Node {
+                    annotations, header, source, parent, open_comment, body, trailing,
+                });
+            }
+        }
+        items
+    }
+
+    fn parse_value(&mut self) -> Literal {
+        self.skip_comments();
+        let (token, text) = match self.next() {
+            Some(token) => (token.token.clone(), token.text.clone()),
+            None => { return Literal::Scalar(String::new()); },
+        };
+
+        match token {
+            LexToken::OpenBracket => Literal::Seq(self.parse_seq('[', ']', false)),
+            LexToken::OpenBrace => Literal::Seq(self.parse_seq('{', '}', true)),
+            LexToken::StringLit(_) | LexToken::InterpolatedString(_) => {
+                let mut pieces = vec![text];
+                while let Some(token) = self.peek_past_comments() {
+                    match token.token {
+                        LexToken::StringLit(_) | LexToken::InterpolatedString(_) => {},
+                        _ => { break; },
+                    }
+                    self.skip_comments();
+                    pieces.push(self.next_text());
+                }
+                Literal::Strings(pieces)
+            },
+            LexToken::Bang => {
+                let ident = self.next_text();
+                match self.peek_past_comments() {
+                    Some(token) if is_tag_payload(&token.token) =>
+                        Literal::Prefixed(format!("!{}", ident), Box::new(self.parse_value())),
+                    _ => Literal::Scalar(format!("!{}", ident)),
+                }
+            },
+            LexToken::Asterisk => {
+                let name = self.next_text();
+                Literal::Scalar(format!("*{}", name))
+            },
+            LexToken::Ampersand => {
+                let name = self.next_text();
+                Literal::Prefixed(format!("&{}", name), Box::new(self.parse_value()))
+            },
+            _ => Literal::Scalar(text),
+        }
+    }
+
+    fn parse_seq(&mut self, open: char, close: char, keyed: bool) -> Seq {
+        let open_line = self.last_end;
+        let mut items = Vec::new();
+        let mut has_comments = false;
+
+        loop {
+            match self.peek().map(|token| token.token.clone()) {
+                None => { break; },
+                Some(LexToken::CloseBrace) | Some(LexToken::CloseBracket) => {
+                    self.next();
+                    break;
+                },
+                Some(LexToken::Comment(_)) => {
+                    has_comments = true;
+                    self.push_comment(&mut items);
+                    continue;
+                },
+                Some(LexToken::Comma) => {
+                    self.next();
+                    continue;
+                },
+                Some(_) => {},
+            }
+
+            if self.blank_before_next() && !items.is_empty() {
+                items.push(Item::Blank);
+            }
+
+            let key = if keyed {
+                let key = self.next().map(Key::from_token);
+                self.skip_comments();
+                self.next(); // the colon
+                key
+            } else {
+                None
+            };
+
+            let value = self.parse_value();
+            if self.next_is(&LexToken::Comma) {
+                self.next();
+            }
+            let trailing = self.same_line_comment();
+            has_comments |= !self.inner_comments.is_empty();
+            let entry = Item::Entry { annotations: Vec::new(), key, value, trailing };
+            self.push_entry(&mut items, entry);
+        }
+
+        let multiline = self.last_end != open_line
+            || has_comments
+            || items.iter().any(|item| item.is_multiline());
+        Seq { open, close, items, multiline }
+    }
+}
+
+struct Printer<'a> {
+    options: &'a FormatOptions,
+}
+
+impl<'a> Printer<'a> {
+    fn indent(&self, level: usize, out: &mut String) {
+        for _ in 0..(level * self.options.indent_width) {
+            out.push(' ');
+        }
+    }
+
+    // Moves the lines of a triple-quoted string after the first to `level`, keeping their
+    // indentation relative to each other and to the closing quotes, so that the string
+    // has the same value.
+    fn reindent(&self, text: &str, level: usize) -> String {
+        let mut lines = text.split('\n');
+        let mut reindented = lines.next().unwrap_or("").to_string();
+        let rest: Vec<&str> = lines.collect();
+        let indented = rest.iter().cloned().filter(|line| !line.trim().is_empty());
+        let common = common_indentation(indented);
+        for line in rest {
+            reindented.push('\n');
+            match line.strip_prefix(common) {
+                Some(unindented) if !unindented.is_empty() => {
+                    self.indent(level, &mut reindented);
+                    reindented.push_str(unindented);
+                },
+                _ => {},
+            }
+        }
+        reindented
+    }
+
+    fn requote(&self, text: &str, decoded: &str) -> String {
+        let old = match text.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => quote,
+            // raw strings and anything else are written exactly as they were
+            _ => { return text.to_string(); },
+        };
+
+        let preferred = self.options.quote_style.quote();
+        let other = self.options.quote_style.other();
+        let new = if decoded.contains(preferred) && !decoded.contains(other) {
+            other
+        } else {
+            preferred
+        };
+        if new == old {
+            return text.to_string();
+        }
+
+        let mut requoted = String::new();
+        requoted.push(new);
+        let mut chars = text[1..text.len() - 1].chars();
+        while let Some(ch) = chars.next() {
+            if ch == '\\' {
+                match chars.next() {
+                    Some(escaped) if escaped == old => requoted.push(escaped),
+                    Some(escaped) => {
+                        requoted.push('\\');
+                        requoted.push(escaped);
+                    },
+                    None => requoted.push('\\'),
+                }
+            } else if ch == new {
+                requoted.push('\\');
+                requoted.push(ch);
+            } else {
+                requoted.push(ch);
+            }
+        }
+        requoted.push(new);
+        requoted
+    }
+
+    fn key_text(&self, key: &Key) -> String {
+        match self.options.key_style {
+            KeyStyle::Bare if key.quoted && is_bare_ident(&key.sort_key) =>
+                key.sort_key.clone(),
+            KeyStyle::Quoted if !key.quoted => {
+                let mut quoted = String::new();
+                write_string(&key.sort_key, &mut quoted);
+                self.requote(&quoted, &key.sort_key)
+            },
+            _ if key.quoted => self.requote(&key.text, &key.sort_key),
+            _ => key.text.clone(),
+        }
+    }
+
+    fn write_value(&self, value: &Literal, level: usize, out: &mut String) {
+        match *value {
+            Literal::Scalar(ref text) => out.push_str(text),
+            Literal::Strings(ref pieces) => {
+                for (index, piece) in pieces.iter().enumerate() {
+                    if index > 0 {
+                        out.push(' ');
+                    }
+                    if is_block_string(piece) {
+                        out.push_str(&self.reindent(piece, level + 1));
+                        continue;
+                    }
+                    let decoded = strip_quotes(piece);
+                    out.push_str(&self.requote(piece, &decoded));
+                }
+            },
+            Literal::Seq(ref seq) => self.write_seq(seq, level, out),
+            Literal::Prefixed(ref prefix, ref value) => {
+                out.push_str(prefix);
+                out.push(' ');
+                self.write_value(value, level, out);
+            },
+        }
+    }
+
+    fn write_seq(&self, seq: &Seq, level: usize, out: &mut String) {
+        out.push(seq.open);
+        if seq.items.is_empty() {
+            out.push(seq.close);
+            return;
+        }
+
+        if !seq.multiline {
+            for (index, item) in self.arrange(&seq.items).into_iter().enumerate() {
+                if let Item::Entry { ref key, ref value, .. } = *item {
+                    if index > 0 {
+                        out.push_str(", ");
+                    }
+                    if let Some(ref key) = *key {
+                        out.push_str(&self.key_text(key));
+                        out.push_str(": ");
+                    }
+                    self.write_value(value, level, out);
+                }
+            }
+            out.push(seq.close);
+            return;
+        }
+
+        out.push('\n');
+        let mut items = self.arrange(&seq.items);
+        self.write_arranged(&mut items, level + 1, out);
+        self.indent(level, out);
+        out.push(seq.close);
+    }
+
+    fn write_items(&self, items: &mut [Item], level: usize, out: &mut String) {
+        let mut arranged = self.arrange(items);
+        self.write_arranged(&mut arranged, level, out);
+    }
+
+    // Removes leading, trailing and repeated blank lines, and sorts keys if requested.
+    fn arrange<'i>(&self, items: &'i [Item]) -> Vec<&'i Item> {
+        let mut arranged: Vec<&Item> = Vec::new();
+        for item in items {
+            if let Item::Blank = *item {
+                match arranged.last() {
+                    None | Some(&&Item::Blank) => { continue; },
+                    _ => {},
+                }
+            }
+            arranged.push(item);
+        }
+        while let Some(&&Item::Blank) = arranged.last() {
+            arranged.pop();
+        }
+
+        if self.options.sort_keys {
+            arranged = sort_groups(arranged);
+        }
+        arranged
+    }
+
+    fn write_arranged(&self, items: &mut [&Item], level: usize, out: &mut String) {
+        let widths = if self.options.align_colons {
+            self.key_widths(items)
+        } else {
+            vec![0; items.len()]
+        };
+
+        for (index, item) in items.iter().enumerate() {
+            match **item {
+                Item::Blank => out.push('\n'),
+                Item::Comment(ref text) => {
+                    self.indent(level, out);
+                    out.push_str(text);
+                    out.push('\n');
+                },
+                Item::Entry { ref annotations, ref key, ref value, ref trailing } => {
+                    self.indent(level, out);
+                    if let Some(ref key) = *key {
+                        let text = annotated(annotations, self.key_text(key));
+                        let padding = widths[index].saturating_sub(text.chars().count());
+                        out.push_str(&text);
+                        for _ in 0..padding {
+                            out.push(' ');
+                        }
+                        out.push_str(": ");
+                    }
+                    self.write_value(value, level, out);
+                    out.push(',');
+                    if let Some(ref comment) = *trailing {
+                        out.push(' ');
+                        out.push_str(comment);
+                    }
+                    out.push('\n');
+                },
+                Item::Node {
+                    ref annotations, ref header, ref source, ref parent, ref open_comment,
+                    ref body, ref trailing,
+                } => {
+                    self.indent(level, out);
+                    out.push_str(&annotated(annotations, String::new()));
+                    // the name of the node is written as it was, and labels are
+                    // requoted like any other string
+                    let header: Vec<String> = header.iter()
+                        .map(|part| if part.quoted {
+                            self.requote(&part.text, &part.sort_key)
+                        } else {
+                            part.text.clone()
+                        })
+                        .collect();
+                    out.push_str(&header.join(" "));
+                    if let Some(ref parent) = *parent {
+                        out.push_str(" : ");
+                        out.push_str(parent);
+                    }
+                    let mut body = self.arrange(body);
+                    if let Some(ref source) = *source {
+                        out.push(' ');
+                        out.push_str(source);
+                    } else if body.is_empty() && open_comment.is_none() {
+                        out.push_str(" {}");
+                    } else {
+                        out.push_str(" {");
+                        if let Some(ref comment) = *open_comment {
+                            out.push(' ');
+                            out.push_str(comment);
+                        }
+                        out.push('\n');
+                        self.write_arranged(&mut body, level + 1, out);
+                        self.indent(level, out);
+                        out.push('}');
+                    }
+                    if let Some(ref comment) = *trailing {
+                        out.push(' ');
+                        out.push_str(comment);
+                    }
+                    out.push('\n');
+                },
+            }
+        }
+    }
+
+    // Computes the width that each key should be padded to, so that keys in the same
+    // group line up.  Groups are broken by blank lines and nodes.
+    fn key_widths(&self, items: &[&Item]) -> Vec<usize> {
+        let mut widths = vec![0; items.len()];
+        let mut group_start = 0;
+        for index in 0..=items.len() {
+            if !matches!(items.get(index), None | Some(&&Item::Blank) | Some(&&Item::Node { .. })) {
+                continue;
+            }
+
+            let width = items[group_start..index].iter()
+                .filter_map(|item| match **item {
+                    Item::Entry { ref annotations, key: Some(ref key), .. } =>
+                        Some(annotated(annotations, self.key_text(key)).chars().count()),
+                    _ => None,
+                })
+                .max()
+                .unwrap_or(0);
+            for slot in &mut widths[group_start..index] {
+                *slot = width;
+            }
+            group_start = index + 1;
+        }
+        widths
+    }
+}
+
+// Writes annotations before the text of a key or node header.
+fn annotated(annotations: &[String], text: String) -> String {
+    let mut prefixed = String::new();
+    for annotation in annotations {
+        prefixed.push_str(annotation);
+        prefixed.push(' ');
+    }
+    prefixed + &text
+}
+
+// Decodes a string literal lexeme just enough to decide which quote to use for it.
+fn is_block_string(text: &str) -> bool {
+    text.starts_with("\"\"\"") || text.starts_with("'''")
+}
+
+fn strip_quotes(text: &str) -> String {
+    if text.len() >= 2 && (text.starts_with('"') || text.starts_with('\'')) {
+        text[1..text.len() - 1].to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+// Sorts keyed entries within each group, keeping comments attached to the entry that
+// follows them.
+fn sort_groups(items: Vec<&Item>) -> Vec<&Item> {
+    let mut sorted = Vec::new();
+    let mut group: Vec<(String, Vec<&Item>)> = Vec::new();
+    let mut pending: Vec<&Item> = Vec::new();
+
+    fn flush<'i>(group: &mut Vec<(String, Vec<&'i Item>)>, sorted: &mut Vec<&'i Item>) {
+        group.sort_by(|a, b| a.0.cmp(&b.0));
+        for (_, items) in group.drain(..) {
+            sorted.extend(items);
+        }
+    }
+
+    for item in items {
+        match *item {
+            Item::Entry { key: Some(ref key), .. } => {
+                pending.push(item);
+                group.push((key.sort_key.clone(), pending.split_off(0)));
+            },
+            Item::Comment(_) => pending.push(item),
+            _ => {
+                flush(&mut group, &mut sorted);
+                sorted.append(&mut pending);
+                sorted.push(item);
+            },
+        }
+    }
+    flush(&mut group, &mut sorted);
+    sorted.append(&mut pending);
+    sorted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::parser::ParseError;
+    use super::super::position::Position;
+
+    fn fmt(source: &str) -> String {
+        format_str(source, &FormatOptions::default()).unwrap()
+    }
+
+    fn fmt_with(source: &str, options: FormatOptions) -> String {
+        format_str(source, &options).unwrap()
+    }
+
+    #[test]
+    fn normalizes_layout() {
+        assert_eq!(fmt(""), "");
+        assert_eq!(fmt("a{}b{c{}}"), "a {}\nb {\n    c {}\n}\n");
+        assert_eq!(fmt("'v':1 a{}"), "\"v\": 1,\na {}\n");
+        assert_eq!(
+            fmt("node { 'a': 1, 'b': [1,2 ,3], 'c': {'x':0x4f}, 'd': true }"),
+            concat!(
+                "node {\n",
+                "    \"a\": 1,\n",
+                "    \"b\": [1, 2, 3],\n",
+                "    \"c\": {\"x\": 0x4f},\n",
+                "    \"d\": true,\n",
+                "}\n"));
+    }
+
+    #[test]
+    fn keeps_comments_and_blank_lines() {
+        let source = "
+// leading comment
+node { // after brace
+    'a': 1, // trailing
+
+
+    /* block */ 'b': r/raw 'string'/
+    sub {} // after node
+}
+// final";
+        assert_eq!(fmt(source), concat!(
+            "// leading comment\n",
+            "node { // after brace\n",
+            "    \"a\": 1, // trailing\n",
+            "\n",
+            "    /* block */\n",
+            "    \"b\": r/raw 'string'/,\n",
+            "    sub {} // after node\n",
+            "}\n",
+            "// final\n"));
+
+        // comments inside an entry are written above it
+        assert_eq!(fmt("a { 'x': /* why */ 1 }"), "a {\n    /* why */\n    \"x\": 1,\n}\n");
+        assert_eq!(fmt("a { 'x' /* c */ : 1 }"), "a {\n    /* c */\n    \"x\": 1,\n}\n");
+        assert_eq!(
+            fmt("a { 'x': // why\n    'one' /* and */ 'two', 'd': {'k' /* c */: 1} }"),
+            concat!(
+                "a {\n",
+                "    // why\n",
+                "    /* and */\n",
+                "    \"x\": \"one\" \"two\",\n",
+                "    \"d\": {\n",
+                "        /* c */\n",
+                "        \"k\": 1,\n",
+                "    },\n",
+                "}\n"));
+        assert_eq!(fmt("b : /* base */ a {}"), "/* base */\nb : a {}\n");
+    }
+
+    #[test]
+    fn expands_multiline_containers() {
+        let source = "node { 'list': [1,
+            // comment
+            2], 'nested': [[1], {'a': [
+                true]}] }";
+        assert_eq!(fmt(source), concat!(
+            "node {\n",
+            "    \"list\": [\n",
+            "        1,\n",
+            "        // comment\n",
+            "        2,\n",
+            "    ],\n",
+            "    \"nested\": [\n",
+            "        [1],\n",
+            "        {\n",
+            "            \"a\": [\n",
+            "                true,\n",
+            "            ],\n",
+            "        },\n",
+            "    ],\n",
+            "}\n"));
+    }
+
+    #[test]
+    fn quote_styles() {
+        let source = "n { 'a': 'it\\'s', \"b\": \"say \\\"hi\\\"\", 'c': 'x' \"y\" }";
+        assert_eq!(fmt(source), concat!(
+            "n {\n",
+            "    \"a\": \"it's\",\n",
+            "    \"b\": 'say \"hi\"',\n",
+            "    \"c\": \"x\" \"y\",\n",
+            "}\n"));
+
+        let options = FormatOptions { quote_style: QuoteStyle::Single, .. FormatOptions::default() };
+        assert_eq!(fmt_with("n { \"a\": \"x'y\\\"z\" }", options), "n {\n    'a': 'x\\'y\"z',\n}\n");
+
+        // node labels are strings too
+        assert_eq!(fmt("backend 'api'   \"eu\" {}"), "backend \"api\" \"eu\" {}\n");
+    }
+
+    #[test]
+    fn sorting_and_alignment() {
+        let source = "node {
+            'zebra': 1,
+            // about apple
+            'apple': 2,
+
+            'b': 3,
+            'aa': 4,
+            sub {}
+        }";
+        let options = FormatOptions {
+            sort_keys: true,
+            align_colons: true,
+            indent_width: 2,
+            .. FormatOptions::default()
+        };
+        assert_eq!(fmt_with(source, options), concat!(
+            "node {\n",
+            "  // about apple\n",
+            "  \"apple\": 2,\n",
+            "  \"zebra\": 1,\n",
+            "\n",
+            "  \"aa\": 4,\n",
+            "  \"b\" : 3,\n",
+            "  sub {}\n",
+            "}\n"));
+    }
+
+    #[test]
+    fn sorting_single_line_dicts() {
+        let source = "'d': {'z': 1, 'b': {'y': 2, 'a': [3, 1]}}, 'l': [{'n': 1, 'm': 2}]";
+        let options = FormatOptions { sort_keys: true, .. FormatOptions::default() };
+        assert_eq!(fmt_with(source, options), concat!(
+            "\"d\": {\"b\": {\"a\": [3, 1], \"y\": 2}, \"z\": 1},\n",
+            "\"l\": [{\"m\": 2, \"n\": 1}],\n"));
+        assert_eq!(fmt(source), concat!(
+            "\"d\": {\"z\": 1, \"b\": {\"y\": 2, \"a\": [3, 1]}},\n",
+            "\"l\": [{\"n\": 1, \"m\": 2}],\n"));
+    }
+
+    #[test]
+    fn key_styles() {
+        let source = "n { bare: 1, 'quoted': {inner: 2, `odd key`: 3}, 'has space': 4 }";
+        assert_eq!(fmt(source), concat!(
+            "n {\n",
+            "    bare: 1,\n",
+            "    \"quoted\": {inner: 2, `odd key`: 3},\n",
+            "    \"has space\": 4,\n",
+            "}\n"));
+
+        let options = FormatOptions { key_style: KeyStyle::Quoted, .. FormatOptions::default() };
+        assert_eq!(fmt_with(source, options), concat!(
+            "n {\n",
+            "    \"bare\": 1,\n",
+            "    \"quoted\": {\"inner\": 2, \"odd key\": 3},\n",
+            "    \"has space\": 4,\n",
+            "}\n"));
+
+        let options = FormatOptions { key_style: KeyStyle::Bare, .. FormatOptions::default() };
+        assert_eq!(fmt_with(source, options), concat!(
+            "n {\n",
+            "    bare: 1,\n",
+            "    quoted: {inner: 2, `odd key`: 3},\n",
+            "    \"has space\": 4,\n",
+            "}\n"));
+    }
+
+    #[test]
+    fn anchors_and_references() {
+        let source = "&base  server { 'port': &p 80, 'hosts': &h [1,
+            2] } web 'a'  *base api ${server} other { 'p': *p, 'q': ${server.port} }";
+        assert_eq!(fmt(source), concat!(
+            "&base server {\n",
+            "    \"port\": &p 80,\n",
+            "    \"hosts\": &h [\n",
+            "        1,\n",
+            "        2,\n",
+            "    ],\n",
+            "}\n",
+            "web \"a\" *base\n",
+            "api ${server}\n",
+            "other {\n",
+            "    \"p\": *p,\n",
+            "    \"q\": ${server.port},\n",
+            "}\n"));
+    }
+
+    #[test]
+    fn inheritance() {
+        let source = "abstract  base { 'port': 80 } web:base { 'host': 'a' }
+            api  \"v1\" : servers [ \"eu\" ] . main {}";
+        assert_eq!(fmt(source), concat!(
+            "abstract base {\n",
+            "    \"port\": 80,\n",
+            "}\n",
+            "web : base {\n",
+            "    \"host\": \"a\",\n",
+            "}\n",
+            "api \"v1\" : servers[\"eu\"].main {}\n"));
+    }
+
+    #[test]
+    fn annotations() {
+        let source = "@secret 'password' : 'x', @deprecated( 'old' ,1 ) 'old':1 @doc n{}";
+        assert_eq!(fmt(source), concat!(
+            "@secret \"password\": \"x\",\n",
+            "@deprecated('old', 1) \"old\": 1,\n",
+            "@doc n {}\n"));
+    }
+
+    #[test]
+    fn tags() {
+        // tags are not applied, since handlers are given when a file is loaded
+        let source = "'a': !env   'FIGTREE_TEST_UNSET', 'b': [!ident, !custom  {'k':1}]";
+        assert_eq!(
+            fmt(source),
+            "\"a\": !env \"FIGTREE_TEST_UNSET\",\n\"b\": [!ident, !custom {\"k\": 1}],\n");
+    }
+
+    #[test]
+    fn numeric_literals() {
+        let source = "'mask':0xFF_FF,'list':[ 0b1010 , 1_000_000 , 8.e4 ]";
+        assert_eq!(fmt(source), "\"mask\": 0xFF_FF,\n\"list\": [0b1010, 1_000_000, 8.e4],\n");
+    }
+
+    #[test]
+    fn special_floats() {
+        let source = "'gain':0x1.8p-1 , 'limits':[ -inf,inf ], 'missing' : nan";
+        assert_eq!(fmt(source), "\"gain\": 0x1.8p-1,\n\"limits\": [-inf, inf],\n\"missing\": nan,\n");
+    }
+
+    #[test]
+    fn bytes_literals() {
+        // the encoding is kept as written, since it may have been chosen for a reason
+        let source = "'key':hex'DEAD beef' , 'salt': b64\"3q2+7w==\"";
+        assert_eq!(fmt(source), "\"key\": hex'DEAD beef',\n\"salt\": b64\"3q2+7w==\",\n");
+    }
+
+    #[test]
+    fn block_strings() {
+        let source = "q {\n'sql': '''\n  SELECT *\n\n    FROM t\n  ''', 'one': '''x'''}";
+        let formatted = fmt(source);
+        assert_eq!(formatted, concat!(
+            "q {\n",
+            "    \"sql\": '''\n",
+            "        SELECT *\n",
+            "\n",
+            "          FROM t\n",
+            "        ''',\n",
+            "    \"one\": '''x''',\n",
+            "}\n"));
+        assert_eq!(
+            Figtree::from_string(formatted).parse().unwrap(),
+            Figtree::from_string(source).parse().unwrap());
+    }
+
+    #[test]
+    fn conditions() {
+        let source = "when ( profile=='prod'||!( region != \"eu\" ) ) { 'k': 1 } when(a=='b'){}";
+        assert_eq!(fmt(source), concat!(
+            "when(profile == 'prod' || !(region != \"eu\")) {\n",
+            "    \"k\": 1,\n",
+            "}\n",
+            "when(a == 'b') {}\n"));
+    }
+
+    #[test]
+    fn interpolated_strings() {
+        // references are left unresolved, since they may depend on how the file is loaded
+        let source = "'url': 'http://${host}' '/${env:FIGTREE_TEST_UNSET}', 'raw': r/${x}/";
+        assert_eq!(
+            fmt(source),
+            "\"url\": \"http://${host}\" \"/${env:FIGTREE_TEST_UNSET}\",\n\"raw\": r/${x}/,\n");
+    }
+
+    #[test]
+    fn formatting_is_stable() {
+        let source = "node { 'a': [1,
+            2], /* c */ sub { 'x': {} } }";
+        let once = fmt(source);
+        assert_eq!(fmt(&once), once);
+    }
+
+    #[test]
+    fn reports_parse_errors() {
+        assert_eq!(
+            format_str("node {", &FormatOptions::default()),
+            Err((ParseError::UnexpectedEndOfFile, Position::at(0, 6))));
+    }
+
+    #[test]
+    fn options_from_document() {
+        let doc = Figtree::from_string("format {
+            'indent_width': 2, 'quote_style': 'single', 'sort_keys': true }").parse().unwrap();
+        assert_eq!(FormatOptions::from_document(&doc), Ok(FormatOptions {
+            indent_width: 2,
+            quote_style: QuoteStyle::Single,
+            sort_keys: true,
+            align_colons: false,
+            key_style: KeyStyle::Any,
+        }));
+
+        let doc = Figtree::from_string("format { 'indent': 2 }").parse().unwrap();
+        assert_eq!(
+            FormatOptions::from_document(&doc),
+            Err(ConfigError::InvalidOption("indent".to_string())));
+    }
+}
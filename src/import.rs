@@ -0,0 +1,607 @@
+//! Importers for other, simpler configuration formats.
+//!
+//! Ini files and Java `.properties` files are both essentially flat lists of key-value
+//! pairs with some form of grouping, which means they can be translated directly into
+//! figtree nodes.  In ini files, each `[section]` becomes a node, with dotted section
+//! names (`[a.b]`) becoming nested nodes.  In properties files, the dotted prefix of
+//! each key (`a.b.key=value`) is used to build the nodes, and the final part of the key
+//! becomes the attribute name.
+//!
+//! Both formats are string-only, so by default every value is imported as a
+//! `Value::Str`.  `ImportOptions` can be used to turn on type inference for integers,
+//! floats and booleans.
+//!
+//! A `Document` has nowhere to store comments, so converting an import into a document
+//! will discard them.  Converting an import into figtree text with `Import::to_figtree`
+//! keeps each comment attached to the section or key that follows it.
+//!
+//! # Examples
+//! ```
+//! use figtree::import::{self, ImportOptions};
+//! use figtree::types::Value;
+//!
+//! let ini = "[server.http]\nport = 8080\n";
+//! let mut options = ImportOptions::default();
+//! options.infer_ints = true;
+//!
+//! let imported = import::ini(ini.as_bytes(), &options).ok().expect("invalid ini");
+//! let doc = imported.to_document();
+//! let port = doc.get_node("server")
+//!     .and_then(|node| node.get_node("http"))
+//!     .and_then(|node| node.get_attr("port"));
+//! assert_eq!(port, Some(&Value::new_int(8080)));
+//! ```
+
+use std::io::{self, Read};
+use std::char::from_u32;
+
+use super::position::Position;
+use super::types::*;
+use super::writer;
+
+/// Options controlling how ini and properties files are imported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportOptions {
+    /// Import values that look like integers (`8080`, `-3`) as `Value::Int`.
+    pub infer_ints: bool,
+    /// Import values that look like floats (`3.5`, `1e4`) as `Value::Float`.
+    pub infer_floats: bool,
+    /// Import the values `true` and `false` as `Value::Bool`.
+    pub infer_bools: bool,
+    /// The node that keys outside of any section are placed in.
+    ///
+    /// If this is `None`, keys outside of a section become top-level attributes of the
+    /// document.  Defaults to `Some("global")`.
+    pub root_node: Option<String>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            infer_ints: false,
+            infer_floats: false,
+            infer_bools: false,
+            root_node: Some("global".to_string()),
+        }
+    }
+}
+
+/// An enum representing an error that occurs while importing a file.
+#[derive(Debug, PartialEq)]
+pub enum ImportError {
+    ReadError(io::ErrorKind),
+    UnclosedSection,
+    EmptyName,
+    MissingSeparator,
+    InvalidUnicodeEscape(String),
+}
+
+type ImportResult<T> = Result<T, (ImportError, Position)>;
+
+#[derive(Debug)]
+struct ImportAttr {
+    comments: Vec<String>,
+    key: String,
+    value: Value,
+}
+
+#[derive(Debug)]
+struct ImportNode {
+    comments: Vec<String>,
+    attrs: Vec<ImportAttr>,
+    nodes: Vec<(String, ImportNode)>,
+}
+
+impl ImportNode {
+    fn new() -> Self {
+        ImportNode { comments: Vec::new(), attrs: Vec::new(), nodes: Vec::new() }
+    }
+
+    fn node_or_insert(&mut self, name: &str) -> &mut ImportNode {
+        let index = match self.nodes.iter().position(|(n, _)| n == name) {
+            Some(index) => index,
+            None => {
+                self.nodes.push((name.to_string(), ImportNode::new()));
+                self.nodes.len() - 1
+            }
+        };
+        &mut self.nodes[index].1
+    }
+
+    fn path_or_insert(&mut self, path: &[String]) -> &mut ImportNode {
+        path.iter().fold(self, |node, name| node.node_or_insert(name))
+    }
+
+    fn set_attr(&mut self, key: String, value: Value, comments: Vec<String>) {
+        if let Some(attr) = self.attrs.iter_mut().find(|attr| attr.key == key) {
+            attr.value = value;
+            attr.comments.extend(comments);
+            return;
+        }
+        self.attrs.push(ImportAttr { comments, key, value });
+    }
+
+    fn fill_node(&self, node: &mut Node) {
+        for attr in &self.attrs {
+            node.insert_attr(attr.key.clone(), attr.value.clone());
+        }
+        for (name, subnode) in &self.nodes {
+            subnode.fill_node(node.new_node_or_get(name.clone()));
+        }
+    }
+
+    fn write(&self, name: &str, indent: usize, out: &mut String) {
+        write_comments(&self.comments, indent, out);
+        push_indent(indent, out);
+        writer::write_name(name, out);
+        if self.attrs.is_empty() && self.nodes.is_empty() {
+            out.push_str(" {}\n");
+            return;
+        }
+
+        out.push_str(" {\n");
+        self.write_body(indent + 1, out);
+        push_indent(indent, out);
+        out.push_str("}\n");
+    }
+
+    fn write_body(&self, indent: usize, out: &mut String) {
+        for attr in &self.attrs {
+            write_comments(&attr.comments, indent, out);
+            push_indent(indent, out);
+            writer::write_string(&attr.key, out);
+            out.push_str(": ");
+            writer::write_value(&attr.value, out);
+            out.push_str(",\n");
+        }
+        for (index, (name, subnode)) in self.nodes.iter().enumerate() {
+            if index > 0 || !self.attrs.is_empty() {
+                out.push('\n');
+            }
+            subnode.write(name, indent, out);
+        }
+    }
+}
+
+fn push_indent(indent: usize, out: &mut String) {
+    for _ in 0..indent {
+        out.push_str("    ");
+    }
+}
+
+fn write_comments(comments: &[String], indent: usize, out: &mut String) {
+    for comment in comments {
+        push_indent(indent, out);
+        out.push_str("//");
+        out.push_str(comment);
+        out.push('\n');
+    }
+}
+
+/// The result of importing an ini or properties file.
+///
+/// This can be turned into a `Document` for use as configuration, or into figtree
+/// source text when converting a file permanently.
+#[derive(Debug)]
+pub struct Import {
+    root: ImportNode,
+    trailing_comments: Vec<String>,
+}
+
+impl Import {
+    /// Build a `Document` from the imported sections and keys.
+    ///
+    /// Comments are discarded.
+    pub fn to_document(&self) -> Document {
+        let mut doc = Document::new();
+        self.root.fill_node(doc.as_node_mut());
+        doc
+    }
+
+    /// Write the imported sections and keys out as figtree source.
+    ///
+    /// Sections and keys are written in the order they first appeared in the imported
+    /// file, and comments are written above the section or key they preceded.
+    pub fn to_figtree(&self) -> String {
+        let mut out = String::new();
+        self.root.write_body(0, &mut out);
+        if !self.trailing_comments.is_empty() && !out.is_empty() {
+            out.push('\n');
+        }
+        write_comments(&self.trailing_comments, 0, &mut out);
+        out
+    }
+}
+
+struct Importer<'a> {
+    options: &'a ImportOptions,
+    root: ImportNode,
+    section: Option<Vec<String>>,
+    comments: Vec<String>,
+}
+
+impl<'a> Importer<'a> {
+    fn new(options: &'a ImportOptions) -> Self {
+        Importer {
+            options,
+            root: ImportNode::new(),
+            section: None,
+            comments: Vec::new(),
+        }
+    }
+
+    fn start_section(&mut self, path: Vec<String>) {
+        let comments = self.comments.split_off(0);
+        self.root.path_or_insert(&path).comments.extend(comments);
+        self.section = Some(path);
+    }
+
+    fn insert(&mut self, mut path: Vec<String>, key: String, value: Value) {
+        if path.is_empty() {
+            if let Some(ref root) = self.options.root_node {
+                path.push(root.clone());
+            }
+        }
+
+        let comments = self.comments.split_off(0);
+        self.root.path_or_insert(&path).set_attr(key, value, comments);
+    }
+
+    fn finish(self) -> Import {
+        Import { root: self.root, trailing_comments: self.comments }
+    }
+
+    fn infer(&self, raw: &str) -> Value {
+        if self.options.infer_bools {
+            match raw {
+                "true" => { return Value::new_bool(true); },
+                "false" => { return Value::new_bool(false); },
+                _ => {},
+            }
+        }
+        if self.options.infer_ints {
+            if let Ok(i) = raw.parse::<i64>() {
+                return Value::new_int(i);
+            }
+        }
+        if self.options.infer_floats && looks_like_float(raw) {
+            if let Ok(f) = raw.parse::<f64>() {
+                return Value::new_float(f);
+            }
+        }
+        Value::new_string(raw)
+    }
+}
+
+// `f64::from_str` also accepts words like "inf" and "NaN", which are much more likely
+// to be strings in a config file than special float values.
+fn looks_like_float(raw: &str) -> bool {
+    raw.chars().any(|ch| ch.is_ascii_digit())
+        && raw.chars().all(|ch| ch.is_ascii_digit() || "+-.eE".contains(ch))
+}
+
+fn read_input<R: Read>(mut input: R) -> ImportResult<String> {
+    let mut text = String::new();
+    match input.read_to_string(&mut text) {
+        Ok(_) => Ok(text),
+        Err(err) => Err((ImportError::ReadError(err.kind()), Position::new())),
+    }
+}
+
+fn leading_whitespace(line: &str) -> usize {
+    line.chars().take_while(|ch| ch.is_whitespace()).count()
+}
+
+/// Import an ini file.
+///
+/// Sections are written `[name]`, and dotted section names are imported as nested
+/// nodes.  Keys and values are separated by either `=` or `:`, and values may be
+/// wrapped in single or double quotes, in which case they are never type-inferred.
+/// Lines beginning with `;` or `#` are comments.  If a section or key appears more than
+/// once, the later definitions are merged into the earlier ones.
+///
+/// # Failures
+/// If the file cannot be read, or contains an invalid line, an `(ImportError,
+/// Position)` tuple is returned, where the position points at the offending line.
+pub fn ini<R: Read>(input: R, options: &ImportOptions) -> ImportResult<Import> {
+    let text = read_input(input)?;
+    let mut importer = Importer::new(options);
+
+    for (line_no, line) in text.lines().enumerate() {
+        let indent = leading_whitespace(line);
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        if trimmed.starts_with(';') || trimmed.starts_with('#') {
+            importer.comments.push(trimmed[1..].trim_end().to_string());
+        } else if trimmed.starts_with('[') {
+            if !trimmed.ends_with(']') {
+                return Err((ImportError::UnclosedSection, Position::at(line_no, indent)));
+            }
+            let mut path = Vec::new();
+            for name in trimmed[1..trimmed.len() - 1].split('.') {
+                let name = name.trim();
+                if name.is_empty() {
+                    return Err((ImportError::EmptyName, Position::at(line_no, indent)));
+                }
+                path.push(name.to_string());
+            }
+            importer.start_section(path);
+        } else {
+            let separator = match trimmed.find(['=', ':']) {
+                Some(separator) => separator,
+                None => {
+                    return Err((ImportError::MissingSeparator, Position::at(line_no, indent)));
+                },
+            };
+            let key = trimmed[..separator].trim();
+            if key.is_empty() {
+                return Err((ImportError::EmptyName, Position::at(line_no, indent)));
+            }
+
+            let raw = trimmed[separator + 1..].trim();
+            let quoted = raw.len() >= 2
+                && ((raw.starts_with('"') && raw.ends_with('"'))
+                    || (raw.starts_with('\'') && raw.ends_with('\'')));
+            let value = if quoted {
+                Value::new_string(&raw[1..raw.len() - 1])
+            } else {
+                importer.infer(raw)
+            };
+
+            let path = importer.section.clone().unwrap_or_default();
+            importer.insert(path, key.to_string(), value);
+        }
+    }
+
+    Ok(importer.finish())
+}
+
+// Joins continuation lines (lines ending in an unescaped backslash) into single logical
+// lines, keeping track of the physical line each logical line started on.
+fn logical_lines(text: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+
+    for (line_no, line) in text.lines().enumerate() {
+        let (start, mut buffer) = match current.take() {
+            Some((start, buffer)) => (start, buffer + line.trim_start()),
+            None => (line_no, line.to_string()),
+        };
+
+        let trailing_slashes = buffer.chars().rev().take_while(|&ch| ch == '\\').count();
+        let is_comment = buffer.trim_start().starts_with('#')
+            || buffer.trim_start().starts_with('!');
+        if trailing_slashes % 2 == 1 && !is_comment {
+            buffer.pop();
+            current = Some((start, buffer));
+        } else {
+            lines.push((start, buffer));
+        }
+    }
+
+    if let Some(line) = current {
+        lines.push(line);
+    }
+    lines
+}
+
+fn unescape_properties(raw: &str, line_no: usize) -> ImportResult<String> {
+    let mut buffer = String::new();
+    let mut chars = raw.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            buffer.push(ch);
+            continue;
+        }
+
+        match chars.next() {
+            Some('t') => buffer.push('\t'),
+            Some('n') => buffer.push('\n'),
+            Some('r') => buffer.push('\r'),
+            Some('f') => buffer.push('\x0c'),
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                match u32::from_str_radix(&hex, 16).ok().and_then(from_u32) {
+                    Some(ch) if hex.len() == 4 => buffer.push(ch),
+                    _ => {
+                        return Err((ImportError::InvalidUnicodeEscape(hex),
+                                    Position::at(line_no, 0)));
+                    },
+                }
+            },
+            Some(ch) => buffer.push(ch),
+            None => {},
+        }
+    }
+    Ok(buffer)
+}
+
+/// Import a Java `.properties` file.
+///
+/// Keys are separated from values by `=`, `:` or whitespace.  Each dotted part of a key
+/// except the last becomes a node, so `server.http.port=8080` is imported as the
+/// attribute `port` in the node `http`, inside the node `server`.  Lines beginning with
+/// `#` or `!` are comments, lines ending in a backslash are continued onto the next
+/// line, and the standard properties escapes (including `\uXXXX`) are understood.
+///
+/// # Failures
+/// If the file cannot be read, or contains an invalid line, an `(ImportError,
+/// Position)` tuple is returned, where the position points at the offending line.
+pub fn properties<R: Read>(input: R, options: &ImportOptions) -> ImportResult<Import> {
+    let text = read_input(input)?;
+    let mut importer = Importer::new(options);
+
+    for (line_no, line) in logical_lines(&text) {
+        let indent = leading_whitespace(&line);
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        if trimmed.starts_with('#') || trimmed.starts_with('!') {
+            importer.comments.push(trimmed[1..].trim_end().to_string());
+            continue;
+        }
+
+        // find the end of the key, skipping over escaped characters
+        let mut key_end = trimmed.len();
+        let mut escaped = false;
+        for (index, ch) in trimmed.char_indices() {
+            if escaped {
+                escaped = false;
+            } else if ch == '\\' {
+                escaped = true;
+            } else if ch == '=' || ch == ':' || ch.is_whitespace() {
+                key_end = index;
+                break;
+            }
+        }
+
+        let mut rest = trimmed[key_end..].trim_start();
+        if rest.starts_with('=') || rest.starts_with(':') {
+            rest = rest[1..].trim_start();
+        }
+
+        let key = unescape_properties(&trimmed[..key_end], line_no)?;
+        let raw = unescape_properties(rest, line_no)?;
+
+        let mut path: Vec<String> = key.split('.').map(|part| part.to_string()).collect();
+        let key = path.pop().unwrap_or_default();
+        if key.is_empty() || path.iter().any(|part| part.is_empty()) {
+            return Err((ImportError::EmptyName, Position::at(line_no, indent)));
+        }
+
+        let value = importer.infer(&raw);
+        importer.insert(path, key, value);
+    }
+
+    Ok(importer.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::position::Position;
+
+    fn inferring() -> ImportOptions {
+        ImportOptions {
+            infer_ints: true,
+            infer_floats: true,
+            infer_bools: true,
+            .. ImportOptions::default()
+        }
+    }
+
+    #[test]
+    fn ini_sections() {
+        let text = "
+            top = 1
+            [server]
+            host = example.com
+            [server.http]
+            port: 8080
+            [server]
+            name = \"quoted value\"";
+
+        let doc = ini(text.as_bytes(), &ImportOptions::default()).unwrap().to_document();
+        assert_eq!(doc.node_count(), 2);
+        assert_eq!(
+            doc.get_node("global").and_then(|node| node.get_attr("top")),
+            Some(&Value::new_string("1")));
+
+        let server = doc.get_node("server").unwrap();
+        assert_eq!(server.get_attr("host"), Some(&Value::new_string("example.com")));
+        assert_eq!(server.get_attr("name"), Some(&Value::new_string("quoted value")));
+        assert_eq!(
+            server.get_node("http").and_then(|node| node.get_attr("port")),
+            Some(&Value::new_string("8080")));
+    }
+
+    #[test]
+    fn ini_type_inference() {
+        let text = "[types]\nint = 8080\nfloat = 3.5\nbool = true\nstr = inf\nquoted = '4'";
+        let doc = ini(text.as_bytes(), &inferring()).unwrap().to_document();
+        let node = doc.get_node("types").unwrap();
+        assert_eq!(node.get_attr("int"), Some(&Value::new_int(8080)));
+        assert_eq!(node.get_attr("float"), Some(&Value::new_float(3.5)));
+        assert_eq!(node.get_attr("bool"), Some(&Value::new_bool(true)));
+        assert_eq!(node.get_attr("str"), Some(&Value::new_string("inf")));
+        assert_eq!(node.get_attr("quoted"), Some(&Value::new_string("4")));
+    }
+
+    #[test]
+    fn ini_errors() {
+        let err = ini("[ok]\n  [broken".as_bytes(), &ImportOptions::default()).unwrap_err();
+        assert_eq!(err, (ImportError::UnclosedSection, Position::at(1, 2)));
+
+        let err = ini("[a..b]".as_bytes(), &ImportOptions::default()).unwrap_err();
+        assert_eq!(err.0, ImportError::EmptyName);
+
+        let err = ini("[a]\nno separator".as_bytes(), &ImportOptions::default()).unwrap_err();
+        assert_eq!(err, (ImportError::MissingSeparator, Position::at(1, 0)));
+
+    }
+
+    #[test]
+    fn keys_outside_sections() {
+        let options = ImportOptions { root_node: None, .. ImportOptions::default() };
+        let import = ini("; version\nversion = 2\n[a]\nkey = value".as_bytes(), &options).unwrap();
+        let doc = import.to_document();
+        assert_eq!(doc.get_attr("version"), Some(&Value::new_string("2")));
+        assert_eq!(import.to_figtree(), concat!(
+            "// version\n",
+            "\"version\": \"2\",\n",
+            "\n",
+            "a {\n",
+            "    \"key\": \"value\",\n",
+            "}\n"));
+    }
+
+    #[test]
+    fn ini_comments_to_figtree() {
+        let text = "; the server\n[server]\n# the port\nport = 80\n; trailing";
+        let figtree = ini(text.as_bytes(), &inferring()).unwrap().to_figtree();
+        assert_eq!(figtree, concat!(
+            "// the server\n",
+            "server {\n",
+            "    // the port\n",
+            "    \"port\": 80,\n",
+            "}\n",
+            "\n",
+            "// trailing\n"));
+    }
+
+    #[test]
+    fn properties_dotted_keys() {
+        let text = "
+            # comment
+            server.http.port = 8080
+            server.name: web\\u0031
+            server.motd   hello \\
+                          world
+            timeout=30";
+        let doc = properties(text.as_bytes(), &inferring()).unwrap().to_document();
+
+        let server = doc.get_node("server").unwrap();
+        assert_eq!(server.get_attr("name"), Some(&Value::new_string("web1")));
+        assert_eq!(server.get_attr("motd"), Some(&Value::new_string("hello world")));
+        assert_eq!(
+            server.get_node("http").and_then(|node| node.get_attr("port")),
+            Some(&Value::new_int(8080)));
+        assert_eq!(
+            doc.get_node("global").and_then(|node| node.get_attr("timeout")),
+            Some(&Value::new_int(30)));
+    }
+
+    #[test]
+    fn properties_errors() {
+        let err = properties("a..b = 1".as_bytes(), &ImportOptions::default()).unwrap_err();
+        assert_eq!(err, (ImportError::EmptyName, Position::at(0, 0)));
+
+        let err = properties("a = \\u00zz".as_bytes(), &ImportOptions::default()).unwrap_err();
+        assert_eq!(err.0, ImportError::InvalidUnicodeEscape("00zz".to_string()));
+    }
+}
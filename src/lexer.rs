@@ -1,10 +1,18 @@
 use std::io::prelude::*;
 use std::io;
+use std::iter;
+use std::ops::Range;
+use std::fmt;
 use std::char::from_u32;
 use std::str::FromStr;
+use std::time::Duration;
 
+use datetime::{self, DateTime};
+use encoding;
+use units;
 use utils::{CharReader, ident_head, ident_body};
-use position::MutablePosition;
+use position::{MutablePosition, Position};
+use types::BytesEncoding;
 
 type LexResult = Result<LexToken, LexError>;
 
@@ -14,26 +22,167 @@ pub enum LexToken {
     OpenBrace, CloseBrace,
     OpenBracket, CloseBracket,
     Comma, Colon, Bang,
+    Ampersand, Asterisk, Dot, At,
+    OpenParen, CloseParen,
+    Equals, NotEquals, And, Or,
     Identifier(String),
     StringLit(String),
     IntegerLit(i64),
     FloatLit(f64),
+    /// A number of a unit of time, as in `30s` or `1h30m`.
+    DurationLit(Duration),
+    /// A number of a unit of size, as in `512MiB`.
+    ByteSizeLit(u64),
+    /// An RFC 3339 date, time, or date and time, as in `2026-10-16T12:00:00Z`.
+    DateTimeLit(DateTime),
+    /// A base64 or hex byte string, as in `b64"AAEC"` or `hex"000102"`.
+    BytesLit(Vec<u8>, BytesEncoding),
+    Comment(String),
+    Reference(String),
+    InterpolatedString(Vec<StringPart>),
+}
+
+/// A piece of a string that contains interpolations.
+#[derive(Debug, PartialEq, Clone)]
+pub enum StringPart {
+    Text(String),
+    /// The text between `${` and `}`, and the position of the `${`.
+    Reference(String, Position),
 }
 
 /// An enum representing different kinds of lexing errors
 ///
 /// May be referenced in a `ParseError` if the parsing failed due to a lexical error
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum LexError {
     UnclosedCommentError,
     UnclosedStringError,
     UnclosedIdentError,
+    UnclosedReferenceError,
     NewlineInIdentifier,
     InvalidEscape(char),
     InvalidUnicodeEscape(u32),
     FloatParseError(<f64 as FromStr>::Err),
     IntegerParseError(<i64 as FromStr>::Err),
     UnrecognisedCharError(char),
+    InvalidQuantity(String),
+    InvalidDateTime(String),
+    InvalidBytes(String),
+    InvalidFloat(String),
+}
+
+impl fmt::Display for LexToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LexToken::OpenBrace => write!(f, "'{{'"),
+            LexToken::CloseBrace => write!(f, "'}}'"),
+            LexToken::OpenBracket => write!(f, "'['"),
+            LexToken::CloseBracket => write!(f, "']'"),
+            LexToken::Comma => write!(f, "','"),
+            LexToken::Colon => write!(f, "':'"),
+            LexToken::Bang => write!(f, "'!'"),
+            LexToken::Ampersand => write!(f, "'&'"),
+            LexToken::Asterisk => write!(f, "'*'"),
+            LexToken::Dot => write!(f, "'.'"),
+            LexToken::At => write!(f, "'@'"),
+            LexToken::OpenParen => write!(f, "'('"),
+            LexToken::CloseParen => write!(f, "')'"),
+            LexToken::Equals => write!(f, "'=='"),
+            LexToken::NotEquals => write!(f, "'!='"),
+            LexToken::And => write!(f, "'&&'"),
+            LexToken::Or => write!(f, "'||'"),
+            LexToken::Identifier(ref ident) => write!(f, "identifier `{}`", ident),
+            LexToken::StringLit(ref string) => write!(f, "string {:?}", string),
+            LexToken::IntegerLit(integer) => write!(f, "integer {}", integer),
+            LexToken::FloatLit(flt) => write!(f, "float {:?}", flt),
+            LexToken::DurationLit(duration) => write!(f, "duration {:?}", duration),
+            LexToken::ByteSizeLit(bytes) => write!(f, "byte size {}B", bytes),
+            LexToken::DateTimeLit(datetime) => write!(f, "date and time {}", datetime),
+            LexToken::BytesLit(ref bytes, _) => write!(f, "{} bytes", bytes.len()),
+            LexToken::Comment(_) => write!(f, "comment"),
+            LexToken::Reference(ref path) => write!(f, "reference `${{{}}}`", path),
+            LexToken::InterpolatedString(_) => write!(f, "interpolated string"),
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LexError::UnclosedCommentError => write!(f, "unclosed block comment"),
+            LexError::UnclosedStringError => write!(f, "unclosed string"),
+            LexError::UnclosedIdentError => write!(f, "unclosed quoted identifier"),
+            LexError::UnclosedReferenceError => write!(f, "unclosed reference"),
+            LexError::NewlineInIdentifier => write!(f, "newline in quoted identifier"),
+            LexError::InvalidEscape(ch) => write!(f, "invalid escape sequence '\\{}'", ch),
+            LexError::InvalidUnicodeEscape(code) =>
+                write!(f, "invalid unicode escape (0x{:x})", code),
+            LexError::FloatParseError(ref err) => write!(f, "invalid float: {}", err),
+            LexError::IntegerParseError(ref err) => write!(f, "invalid integer: {}", err),
+            LexError::UnrecognisedCharError(ch) => write!(f, "unrecognised character {:?}", ch),
+            LexError::InvalidQuantity(ref message) => write!(f, "invalid quantity: {}", message),
+            LexError::InvalidDateTime(ref message) => write!(f, "invalid date or time: {}", message),
+            LexError::InvalidBytes(ref message) => write!(f, "invalid bytes: {}", message),
+            LexError::InvalidFloat(ref message) => write!(f, "invalid float: {}", message),
+        }
+    }
+}
+
+/// The leading whitespace that all of a set of lines share.
+pub(crate) fn common_indentation<'a, I>(lines: I) -> &'a str where I: Iterator<Item=&'a str> {
+    lines
+        .map(|line| &line[..line.len() - line.trim_start_matches([' ', '\t']).len()])
+        .fold(None, |common: Option<&str>, indent| match common {
+            Some(common) => {
+                let shared = common.bytes().zip(indent.bytes()).take_while(|&(a, b)| a == b);
+                Some(&common[..shared.count()])
+            },
+            None => Some(indent),
+        })
+        .unwrap_or("")
+}
+
+/// Find the lines of a triple-quoted string that are left once its layout is removed,
+/// as ranges of its text.
+///
+/// A first line that is blank is the rest of the line the string opens on, and is
+/// removed along with its newline, as is a last line that is blank, which holds the
+/// closing quotes.  The indentation that all of the remaining lines after the first
+/// share with the closing line is then removed from each of them, so the contents of
+/// the string can be indented along with the document.
+pub(crate) fn dedented_lines(text: &str) -> Vec<Range<usize>> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (end, _) in text.match_indices('\n') {
+        lines.push(start..end);
+        start = end + 1;
+    }
+    lines.push(start..text.len());
+    if lines.len() == 1 {
+        return lines;
+    }
+    let line = |range: &Range<usize>| &text[range.clone()];
+    let first = lines.remove(0);
+    let closing = if lines.last().is_some_and(|range| line(range).trim().is_empty()) {
+        lines.pop()
+    } else {
+        None
+    };
+
+    let indented = lines.iter().map(line).filter(|line| !line.trim().is_empty());
+    let common = common_indentation(indented.chain(closing.as_ref().map(line)));
+
+    let mut dedented = Vec::new();
+    if !line(&first).trim().is_empty() {
+        dedented.push(first);
+    }
+    // blank lines may be less indented than the rest
+    dedented.extend(lines.into_iter().map(|range| if line(&range).starts_with(common) {
+        range.start + common.len()..range.end
+    } else {
+        range.start..range.start
+    }));
+    dedented
 }
 
 pub struct Lexer {
@@ -43,6 +192,17 @@ pub struct Lexer {
     stored_next: Vec<char>,
     errored: bool,
     peeked_next: Option<LexResult>,
+    keep_comments: bool,
+    /// Whether `${...}` in a string is an interpolation, rather than part of its text.
+    /// `$${` is read as `${` either way.
+    pub interpolate: bool,
+    /// Whether a number followed by a unit, as in `30s` or `512MiB`, is a duration or
+    /// byte size, rather than an error.
+    pub quantities: bool,
+    lexeme: Option<String>,
+    // the source text of the most recent number, which is kept even when other
+    // lexemes are not
+    numeric_lexeme: String,
 }
 
 impl Lexer {
@@ -54,6 +214,44 @@ impl Lexer {
             stored_next: Vec::new(),
             errored: false,
             peeked_next: None,
+            keep_comments: false,
+            interpolate: true,
+            quantities: false,
+            lexeme: None,
+            numeric_lexeme: String::new(),
+        }
+    }
+
+    /// Construct a lexer that also produces comment tokens, and records the source
+    /// text of each token so that it can be retrieved using `lexeme`.
+    pub fn lex_preserving<R: Read + 'static>(reader: R) -> Self {
+        let mut lexer = Self::lex(reader);
+        lexer.keep_comments = true;
+        lexer.lexeme = Some(String::new());
+        lexer
+    }
+
+    /// The source text of the most recently lexed token, if this lexer is recording
+    /// source text.
+    pub fn lexeme(&self) -> Option<&str> {
+        self.lexeme.as_deref()
+    }
+
+    /// The source text of the most recently lexed number, such as `0xFF_FF` or `8.e4`.
+    pub(crate) fn numeric_lexeme(&self) -> &str {
+        &self.numeric_lexeme
+    }
+
+    fn record_lexeme(&mut self, text: &str) {
+        if let Some(ref mut lexeme) = self.lexeme {
+            lexeme.push_str(text);
+        }
+    }
+
+    fn start_token(&mut self, start: MutablePosition) {
+        self.token_start = start;
+        if let Some(ref mut lexeme) = self.lexeme {
+            lexeme.clear();
         }
     }
 
@@ -80,6 +278,9 @@ impl Lexer {
             } else {
                 self.position.push(1);
             }
+            if let Some(ref mut lexeme) = self.lexeme {
+                lexeme.push(next);
+            }
 
             Some(next)
         } else {
@@ -90,6 +291,9 @@ impl Lexer {
     fn ret_next(&mut self, returned: char) {
         self.position.unpush(1);
         self.stored_next.push(returned);
+        if let Some(ref mut lexeme) = self.lexeme {
+            lexeme.pop();
+        }
     }
 
     fn parse_ident(&mut self) -> Option<LexResult> {
@@ -113,12 +317,55 @@ impl Lexer {
                 }
             }
 
+            if ident == "b64" || ident == "hex" {
+                let after = self.pop_next();
+                if let Some(after) = after {
+                    self.ret_next(after);
+                }
+                if after == Some('"') || after == Some('\'') {
+                    return Some(self.parse_bytes(&ident));
+                }
+            }
             Some(Ok(LexToken::Identifier(ident)))
         } else {
             None
         }
     }
 
+    // Reads the quoted text of a `b64"..."` or `hex"..."` literal, having already read
+    // the name of the encoding.  Errors are reported at the character that is wrong.
+    fn parse_bytes(&mut self, encoding: &str) -> LexResult {
+        let quote_char = self.pop_next().expect("bytes literals start with a quote");
+        let start = self.position.clone();
+        let mut text = String::new();
+        loop {
+            match self.pop_next() {
+                Some(next_char) if next_char == quote_char => { break; },
+                Some(next_char) => { text.push(next_char); },
+                None => { return Err(LexError::UnclosedStringError); },
+            }
+        }
+
+        let decoded = if encoding == "hex" {
+            encoding::decode_hex(&text).map(|bytes| LexToken::BytesLit(bytes, BytesEncoding::Hex))
+        } else {
+            encoding::decode_base64(&text)
+                .map(|bytes| LexToken::BytesLit(bytes, BytesEncoding::Base64))
+        };
+        decoded.map_err(|(message, index)| {
+            let mut at = start;
+            for ch in text[..index].chars() {
+                if ch == '\n' {
+                    at.new_line();
+                } else {
+                    at.push(1);
+                }
+            }
+            self.token_start = at;
+            LexError::InvalidBytes(message)
+        })
+    }
+
     fn parse_int(&mut self, base: u32) -> Option<LexResult> {
         let mut buffer = String::new();
 
@@ -127,6 +374,9 @@ impl Lexer {
                 buffer.push(next_char);
             } else if next_char == '_' {
                 continue; // accepted but ignored
+            } else if base == 16 && (next_char == '.' || next_char == 'p' || next_char == 'P') {
+                self.ret_next(next_char);
+                return Some(self.parse_hex_float(buffer));
             } else {
                 self.ret_next(next_char);
                 break;
@@ -148,6 +398,86 @@ impl Lexer {
         Some(Ok(LexToken::IntegerLit(i64::from_str_radix(&buffer, base).unwrap())))
     }
 
+    // Reads the fraction and binary exponent of a hex float such as `0x1.8p3`, whose
+    // whole part has already been read.  The exponent is required, as in C, and the
+    // digits must fit in 64 bits, so that the value is exact wherever it can be.
+    fn parse_hex_float(&mut self, whole: String) -> LexResult {
+        let mut text = format!("0x{}", whole);
+        let mut digits = whole;
+        let mut fraction_digits = 0;
+        if let Some(next_char) = self.pop_next() {
+            if next_char == '.' {
+                text.push(next_char);
+                while let Some(next_char) = self.pop_next() {
+                    if next_char.is_ascii_hexdigit() {
+                        text.push(next_char);
+                        digits.push(next_char);
+                        fraction_digits += 1;
+                    } else if next_char == '_' {
+                        text.push(next_char);
+                    } else {
+                        self.ret_next(next_char);
+                        break;
+                    }
+                }
+            } else {
+                self.ret_next(next_char);
+            }
+        }
+
+        let mut exponent = String::new();
+        match self.pop_next() {
+            Some(next_char) if next_char == 'p' || next_char == 'P' => {
+                text.push(next_char);
+                if let Some(next_char) = self.pop_next() {
+                    if next_char == '+' || next_char == '-' {
+                        exponent.push(next_char);
+                    } else {
+                        self.ret_next(next_char);
+                    }
+                }
+                while let Some(next_char) = self.pop_next() {
+                    if next_char.is_ascii_digit() {
+                        exponent.push(next_char);
+                    } else {
+                        self.ret_next(next_char);
+                        break;
+                    }
+                }
+                text.push_str(&exponent);
+            },
+            next_char => {
+                if let Some(next_char) = next_char {
+                    self.ret_next(next_char);
+                }
+                return Err(LexError::InvalidFloat(
+                    format!("`{}` needs a binary exponent, as in `{}p0`", text, text)));
+            },
+        }
+
+        if digits.is_empty() {
+            return Err(LexError::InvalidFloat(format!("`{}` has no digits", text)));
+        }
+        let digits = digits.trim_start_matches('0');
+        let significant = digits.trim_end_matches('0');
+        let shift = 4 * (digits.len() - significant.len()) as i64 - 4 * fraction_digits as i64;
+        let mantissa = if significant.is_empty() { 0 } else {
+            u64::from_str_radix(significant, 16).map_err(|_| LexError::InvalidFloat(
+                format!("`{}` has more digits than a float can hold", text)))?
+        };
+        let exponent: i64 = exponent.parse().map_err(|_| LexError::InvalidFloat(
+            format!("`{}` has an invalid exponent", text)))?;
+
+        let value = scale_by_power_of_two(mantissa as f64, exponent.saturating_add(shift));
+        if value.is_infinite() {
+            Err(LexError::InvalidFloat(format!("`{}` is too large for a float", text)))
+        } else if value == 0.0 && mantissa != 0 {
+            Err(LexError::InvalidFloat(format!("`{}` is too small for a float", text)))
+        } else {
+            Ok(LexToken::FloatLit(value))
+        }
+    }
+
     fn parse_exponent(&mut self) -> String {
         let mut exponent = String::from("e");
         if let Some(next_char) = self.pop_next() {
@@ -172,6 +502,8 @@ impl Lexer {
 
     fn parse_float_int(&mut self) -> Option<LexResult> {
         let mut sign = '+';
+        let mut is_signed = false;
+        let mut is_underscored = false;
         let mut is_float = false;
         let mut buffer = String::new();
         let mut exponent = String::new();
@@ -179,6 +511,7 @@ impl Lexer {
         if let Some(next_char) = self.pop_next() {
             if next_char == '+' || next_char == '-' {
                 sign = next_char;
+                is_signed = true;
             } else {
                 self.ret_next(next_char);
             }
@@ -197,11 +530,31 @@ impl Lexer {
             } else if next_char.is_digit(10) {
                 buffer.push(next_char);
             } else if next_char == '_' {
+                is_underscored = true;
                 continue;  // accepted, but ignored
-            } else if next_char == 'e' || next_char == 'E' {
+            } else if (next_char == 'e' || next_char == 'E') && self.exponent_follows() {
                 exponent = self.parse_exponent();
                 is_float = true;
                 break;
+            } else if next_char.is_alphabetic() && buffer.is_empty() && is_signed {
+                // a signed keyword, as in `-inf`
+                self.ret_next(next_char);
+                return Some(self.parse_signed_keyword(sign));
+            } else if next_char.is_alphabetic() && !buffer.is_empty() {
+                self.ret_next(next_char);
+                if self.quantities && self.unit_follows() {
+                    return Some(self.parse_quantity(sign, buffer));
+                }
+                // a number with letters in it, as in `-0x10` or `5x`
+                if is_signed {
+                    buffer.insert(0, sign);
+                }
+                return Some(Err(self.parse_bad_number(buffer, is_float)));
+            } else if (next_char == '-' && buffer.len() == 4 || next_char == ':' && buffer.len() == 2)
+                    && !is_signed && !is_underscored && !is_float {
+                // the year of a date, or the hour of a time
+                self.ret_next(next_char);
+                return Some(self.parse_datetime(buffer));
             } else {
                 self.ret_next(next_char);
                 break;
@@ -232,7 +585,128 @@ impl Lexer {
         }
     }
 
+    // Reads the word after a sign, which must be `inf`, since `+` and `-` are not
+    // otherwise allowed before a name.
+    fn parse_signed_keyword(&mut self, sign: char) -> LexResult {
+        let mut word = String::new();
+        while let Some(next_char) = self.pop_next() {
+            if next_char.is_alphanumeric() || next_char == '_' {
+                word.push(next_char);
+            } else {
+                self.ret_next(next_char);
+                break;
+            }
+        }
+        match &*word {
+            "inf" if sign == '-' => Ok(LexToken::FloatLit(f64::NEG_INFINITY)),
+            "inf" => Ok(LexToken::FloatLit(f64::INFINITY)),
+            _ => Err(LexError::InvalidFloat(format!("`{}{}` is not a number", sign, word))),
+        }
+    }
+
+    // Tests whether an `e` that has just been read is followed by an exponent, rather
+    // than being the start of a unit such as `EiB`.
+    fn exponent_follows(&mut self) -> bool {
+        let after = self.pop_next();
+        if let Some(after) = after {
+            self.ret_next(after);
+        }
+        matches!(after, Some(ch) if ch.is_ascii_digit() || ch == '+' || ch == '-')
+    }
+
+    // Tests whether the letters that follow are a unit of time or size, as in `30s` or
+    // `512MiB`, leaving them to be read again.
+    fn unit_follows(&mut self) -> bool {
+        let mut unit = String::new();
+        while let Some(next_char) = self.pop_next() {
+            if next_char.is_alphabetic() {
+                unit.push(next_char);
+            } else {
+                self.ret_next(next_char);
+                break;
+            }
+        }
+        for returned in unit.chars().rev() {
+            self.ret_next(returned);
+        }
+        units::DURATION_UNITS.iter().chain(units::BYTE_UNITS).any(|&(name, _)| name == unit)
+    }
+
+    // Reads the rest of a number that has letters in it, and reports the error that
+    // parsing it as a number gives.
+    fn parse_bad_number(&mut self, number: String, is_float: bool) -> LexError {
+        let mut text = number;
+        while let Some(next_char) = self.pop_next() {
+            if next_char.is_alphanumeric() || next_char == '_' || next_char == '.' {
+                text.push(next_char);
+            } else {
+                self.ret_next(next_char);
+                break;
+            }
+        }
+        let err = if is_float {
+            text.parse::<f64>().err().map(LexError::FloatParseError)
+        } else {
+            text.parse::<i64>().err().map(LexError::IntegerParseError)
+        };
+        err.unwrap_or_else(|| LexError::InvalidFloat(format!("`{}` is not a number", text)))
+    }
+
+    // Reads the units of a quantity such as `30s`, `1h30m` or `512MiB`, whose number has
+    // already been read.  Units ending in `B` are sizes, and all others are durations.
+    fn parse_quantity(&mut self, sign: char, number: String) -> LexResult {
+        let mut text = number;
+        while let Some(next_char) = self.pop_next() {
+            if next_char.is_alphanumeric() || next_char == '.' {
+                text.push(next_char);
+            } else if next_char != '_' {
+                self.ret_next(next_char);
+                break;
+            }
+        }
+
+        if sign == '-' {
+            return Err(LexError::InvalidQuantity(format!("`-{}` is negative", text)));
+        }
+        let is_size = text.trim_end_matches(|ch: char| ch.is_ascii_digit()).ends_with('B');
+        if is_size {
+            units::parse_byte_size(&text).map(LexToken::ByteSizeLit)
+        } else {
+            units::parse_duration(&text).map(LexToken::DurationLit)
+        }.map_err(LexError::InvalidQuantity)
+    }
+
+    // Reads the rest of a date or time, whose first field has already been read.
+    // Errors are reported at the field that is wrong, rather than at the start of the
+    // literal.
+    fn parse_datetime(&mut self, first_field: String) -> LexResult {
+        let mut text = first_field;
+        while let Some(next_char) = self.pop_next() {
+            if next_char.is_ascii_digit() || "-:.+TtZz".contains(next_char) {
+                text.push(next_char);
+            } else {
+                self.ret_next(next_char);
+                break;
+            }
+        }
+
+        datetime::parse(&text).map(LexToken::DateTimeLit).map_err(|(message, index)| {
+            self.token_start.push(index);
+            LexError::InvalidDateTime(message)
+        })
+    }
+
     fn parse_numeric(&mut self) -> Option<LexResult> {
+        // the text of a number is recorded in the same way as a lexeme, and added to the
+        // lexeme afterwards if this lexer is recording them
+        let recorded = self.lexeme.replace(String::new());
+        let result = self.parse_number();
+        self.numeric_lexeme = self.lexeme.take().unwrap_or_default();
+        self.lexeme = recorded.map(|lexeme| lexeme + &self.numeric_lexeme);
+        result
+    }
+
+    fn parse_number(&mut self) -> Option<LexResult> {
         if let Some(next_char) = self.pop_next() {
             if let Some(after) = self.pop_next() {
                 if next_char == '0' && after == 'd' {
@@ -285,6 +759,7 @@ impl Lexer {
 
     fn parse_string(&mut self) -> Option<LexResult> {
         let mut buffer = String::new();
+        let mut parts = Vec::new();
         let mut quote_closed = false;
         let quote_char = match self.pop_next() {
             Some('\'') => '\'',
@@ -292,6 +767,9 @@ impl Lexer {
             Some(ch) => unreachable!("{:?} should not be a quote char", ch),
             None => { return None; },
         };
+        if self.opens_block_string(quote_char) {
+            return self.parse_block_string(quote_char);
+        }
 
         while let Some(next_char) = self.pop_next() {
             if next_char == '\\' {
@@ -322,18 +800,202 @@ impl Lexer {
             } else if next_char == quote_char {
                 quote_closed = true;
                 break;
+            } else if next_char == '$' {
+                // `${` starts an interpolation, and `$${` is a literal `${`
+                let start = Position::at(self.position.line, self.position.pos - 1);
+                match (self.pop_next(), self.pop_next()) {
+                    (Some('{'), after) if self.interpolate => {
+                        if let Some(after) = after {
+                            self.ret_next(after);
+                        }
+                        if !buffer.is_empty() {
+                            parts.push(StringPart::Text(buffer.split_off(0)));
+                        }
+                        match self.parse_interpolation(quote_char) {
+                            Ok(path) => parts.push(StringPart::Reference(path, start)),
+                            Err(err) => { return self.err(err); },
+                        }
+                    },
+                    (Some('$'), Some('{')) => { buffer.push_str("${"); },
+                    (first, second) => {
+                        for returned in second.into_iter().chain(first) {
+                            self.ret_next(returned);
+                        }
+                        buffer.push('$');
+                    },
+                }
             } else {
                 buffer.push(next_char);
             }
         }
 
-        if quote_closed {
+        if !quote_closed {
+            self.err(LexError::UnclosedStringError)
+        } else if parts.is_empty() {
             Some(Ok(LexToken::StringLit(buffer)))
         } else {
-            self.err(LexError::UnclosedStringError)
+            if !buffer.is_empty() {
+                parts.push(StringPart::Text(buffer));
+            }
+            Some(Ok(LexToken::InterpolatedString(parts)))
+        }
+    }
+
+    // Tests whether the quote that has just been read is the first of three, and reads
+    // the other two if it is.
+    fn opens_block_string(&mut self, quote_char: char) -> bool {
+        match (self.pop_next(), self.pop_next()) {
+            (Some(first), Some(second)) if first == quote_char && second == quote_char => true,
+            (first, second) => {
+                for returned in second.into_iter().chain(first) {
+                    self.ret_next(returned);
+                }
+                false
+            },
+        }
+    }
+
+    // Reads a triple-quoted string, having already read the opening quotes.  The text is
+    // dedented as it is written in the source, and escapes and interpolations are then
+    // read from it exactly as they are from an ordinary string.
+    fn parse_block_string(&mut self, quote_char: char) -> Option<LexResult> {
+        let mut raw = String::new();
+        // the position in the source of each byte read, including the closing quotes
+        let mut positions = Vec::new();
+        let mut quotes = 0;
+        loop {
+            let position = (self.position.line, self.position.pos);
+            let next_char = self.pop_next();
+            if let Some(next_char) = next_char {
+                positions.extend(iter::repeat_n(position, next_char.len_utf8()));
+            }
+            if next_char == Some(quote_char) {
+                quotes += 1;
+                continue;
+            }
+            if quotes >= 3 {
+                // the last three quotes close the string, and any before them are part of it
+                if let Some(next_char) = next_char {
+                    self.ret_next(next_char);
+                }
+                raw.extend(iter::repeat_n(quote_char, quotes - 3));
+                break;
+            }
+            raw.extend(iter::repeat_n(quote_char, quotes));
+            quotes = 0;
+            match next_char {
+                Some('\\') => {
+                    raw.push('\\');
+                    let position = (self.position.line, self.position.pos);
+                    match self.pop_next() {
+                        Some(escaped) => {
+                            positions.extend(iter::repeat_n(position, escaped.len_utf8()));
+                            raw.push(escaped);
+                        },
+                        None => { return self.err(LexError::UnclosedStringError); },
+                    }
+                },
+                Some(next_char) => { raw.push(next_char); },
+                None => { return self.err(LexError::UnclosedStringError); },
+            }
+        }
+
+        // quotes in the text are escaped, so that it can be read as an ordinary string,
+        // and the offset in `raw` that each character comes from is kept
+        let mut quoted = quote_char.to_string();
+        let mut origins = vec![0];
+        let mut line_end = None;
+        for range in dedented_lines(&raw) {
+            if let Some(line_end) = line_end {
+                quoted.push('\n');
+                origins.push(line_end);
+            }
+            line_end = Some(raw[range.end..].find('\n').map_or(raw.len(), |n| range.end + n));
+            let mut chars = raw[range.clone()].char_indices();
+            while let Some((offset, next_char)) = chars.next() {
+                if next_char == '\\' {
+                    // escapes are kept as they are, including escaped quotes
+                    quoted.push(next_char);
+                    origins.push(range.start + offset);
+                    if let Some((offset, escaped)) = chars.next() {
+                        quoted.push(escaped);
+                        origins.push(range.start + offset);
+                    }
+                } else {
+                    if next_char == quote_char {
+                        quoted.push('\\');
+                        origins.push(range.start + offset);
+                    }
+                    quoted.push(next_char);
+                    origins.push(range.start + offset);
+                }
+            }
+        }
+        quoted.push(quote_char);
+        origins.push(raw.len());
+
+        // the number of characters of the quoted string before a position in it
+        let offset = |line: usize, pos: usize| quoted.split('\n').take(line)
+            .map(|line| line.chars().count() + 1)
+            .sum::<usize>() + pos;
+        let mut inner = Lexer::lex(io::Cursor::new(quoted.clone().into_bytes()));
+        inner.interpolate = self.interpolate;
+        match inner.parse_string() {
+            Some(Err(err)) => {
+                // the error is reported at the last character the inner lexer read
+                let read = offset(inner.position.line, inner.position.pos);
+                let origin = origins[read.saturating_sub(1).min(origins.len() - 1)];
+                let (line, pos) = positions[origin];
+                self.token_start = MutablePosition::at(line, pos);
+                self.err(err)
+            },
+            Some(Ok(LexToken::InterpolatedString(parts))) => {
+                // references are moved to where they are in the source
+                let parts = parts.into_iter().map(|part| match part {
+                    StringPart::Reference(path, start) => {
+                        let (line, pos) = positions[origins[offset(start.line, start.pos)]];
+                        StringPart::Reference(path, Position::at(line, pos))
+                    },
+                    text => text,
+                }).collect();
+                Some(Ok(LexToken::InterpolatedString(parts)))
+            },
+            result => result,
         }
     }
 
+    // Reads the path of an interpolation in a string, having already read the `${`.
+    // The quote that surrounds the string can be escaped with a backslash inside the
+    // path, and other escapes are left for the path to interpret.
+    fn parse_interpolation(&mut self, quote_char: char) -> Result<String, LexError> {
+        let mut path = String::new();
+        let mut quote = None;
+        while let Some(mut next_char) = self.pop_next() {
+            if next_char == '\\' {
+                match self.pop_next() {
+                    Some(escaped) if escaped == quote_char => { next_char = escaped; },
+                    Some(escaped) if escaped != '\n' => {
+                        path.push(next_char);
+                        path.push(escaped);
+                        continue;
+                    },
+                    _ => { break; },
+                }
+            } else if next_char == quote_char || next_char == '\n' {
+                break;
+            }
+
+            match (quote, next_char) {
+                (None, '}') => { return Ok(path); },
+                (None, '"') | (None, '`') => { quote = Some(next_char); },
+                (Some(open), close) if open == close => { quote = None; },
+                _ => {},
+            }
+            path.push(next_char);
+        }
+        Err(LexError::UnclosedReferenceError)
+    }
+
     fn parse_raw_string(&mut self) -> Option<LexResult> {
         let mut buffer = String::new();
         let mut quote_closed = false;
@@ -363,33 +1025,64 @@ impl Lexer {
         while let Some(next_char) = self.pop_next() {
             if next_char == quote_char {
                 let mut close_quote_length = 1;
-                while let Some(next_char) = self.pop_next() {
-                    if next_char == quote_char {
-                        close_quote_length += 1;
-                        if close_quote_length == quote_length {
-                            quote_closed = true;
+                while close_quote_length < quote_length {
+                    match self.pop_next() {
+                        Some(next_char) if next_char == quote_char => {
+                            close_quote_length += 1;
+                        },
+                        Some(next_char) => {
+                            self.ret_next(next_char);
                             break;
-                        }
-                    } else {
-                        for _ in 0..(close_quote_length) {
-                            buffer.push(quote_char);
-                        }
-                        buffer.push(next_char);
-                        break;
+                        },
+                        None => { break; },
                     }
                 }
 
-                if quote_closed {
+                if close_quote_length == quote_length {
+                    quote_closed = true;
                     break;
                 }
+                for _ in 0..close_quote_length {
+                    buffer.push(quote_char);
+                }
             } else {
                 buffer.push(next_char);
             }
         }
 
+        if !quote_closed {
+            return self.err(LexError::UnclosedStringError);
+        }
         Some(Ok(LexToken::StringLit(buffer)))
     }
 
+    // Reads the path of a `${path}` reference, having already read the opening `${`.
+    // The path is kept as it was written, to be parsed as a `Path` later; quoted names
+    // in the path may contain braces.
+    fn parse_reference(&mut self) -> Option<LexResult> {
+        let mut path = String::new();
+        let mut quote = None;
+        while let Some(next_char) = self.pop_next() {
+            match (quote, next_char) {
+                (_, '\n') => { break; },
+                (None, '}') => { return Some(Ok(LexToken::Reference(path))); },
+                (None, '"') | (None, '`') => { quote = Some(next_char); },
+                (Some(_), '\\') => {
+                    path.push(next_char);
+                    match self.pop_next() {
+                        Some('\n') | None => { break; },
+                        Some(escaped) => { path.push(escaped); },
+                    }
+                    continue;
+                },
+                (Some(open), close) if open == close => { quote = None; },
+                _ => {},
+            }
+            path.push(next_char);
+        }
+        self.err(LexError::UnclosedReferenceError)
+    }
+
     fn parse_ident_escaped(&mut self) -> Option<LexResult> {
         let mut buffer = String::new();
         let mut quote_closed = false;
@@ -441,22 +1134,28 @@ impl Lexer {
         }
     }
 
-    fn remove_line_comment(&mut self) -> Option<LexError> {
+    fn remove_line_comment(&mut self) -> String {
+        let mut comment = String::from("//");
         while let Some(ch) = self.pop_next() {
             if ch == '\r' || ch == '\n' {
                 break;
             }
+            comment.push(ch);
         }
 
-        None
+        comment
     }
-    fn remove_multiline_comment(&mut self) -> Option<LexError> {
+
+    fn remove_multiline_comment(&mut self) -> Result<String, LexError> {
+        let mut comment = String::from("/*");
         let mut comment_level = 1;
         loop {
             if let Some(ch) = self.pop_next() {
+                comment.push(ch);
                 if ch == '/' {
                     if let Some(ch) = self.pop_next() {
                         if ch == '*' {
+                            comment.push(ch);
                             comment_level += 1;
                         } else {
                             self.ret_next(ch);
@@ -465,6 +1164,7 @@ impl Lexer {
                 } else if ch == '*' {
                     if let Some(ch) = self.pop_next() {
                         if ch == '/' {
+                            comment.push(ch);
                             comment_level -= 1;
                         } else {
                             self.ret_next(ch);
@@ -476,11 +1176,11 @@ impl Lexer {
                     break;
                 }
             } else {
-                return Some(LexError::UnclosedCommentError);
+                return Err(LexError::UnclosedCommentError);
             }
         }
 
-        None
+        Ok(comment)
     }
 }
 
@@ -496,6 +1196,7 @@ impl Iterator for Lexer {
         // loop continuously until told to break
         loop {
             // take first character, test if it's either whitespace or '/'
+            let comment_start = self.position.clone();
             if let Some(ch) = self.pop_next() {
                 if ch.is_whitespace() {
                     continue;
@@ -504,16 +1205,24 @@ impl Iterator for Lexer {
                         Some('/') => {
                             // single line comment ("// hello")
                             // remove, and continue cycle to find next ignorable
-                            if let Some(err) = self.remove_line_comment() {
-                                return self.err(err);
+                            self.start_token(comment_start);
+                            self.record_lexeme("//");
+                            let comment = self.remove_line_comment();
+                            if self.keep_comments {
+                                return Some(Ok(LexToken::Comment(comment)));
                             }
                             continue;
                         },
                         Some('*') => {
                             // multiline comment ("/* hello */")
                             // remove and continue cycle
-                            if let Some(err) = self.remove_multiline_comment() {
-                                return self.err(err);
+                            self.start_token(comment_start);
+                            self.record_lexeme("/*");
+                            match self.remove_multiline_comment() {
+                                Ok(comment) => if self.keep_comments {
+                                    return Some(Ok(LexToken::Comment(comment)));
+                                },
+                                Err(err) => { return self.err(err); },
                             }
                             continue;
                         },
@@ -544,7 +1253,8 @@ impl Iterator for Lexer {
             }
         }
 
-        self.token_start = self.position.clone();
+        let token_start = self.position.clone();
+        self.start_token(token_start);
 
         if let Some(next_char) = self.pop_next() {
             if next_char == 'r' {
@@ -556,12 +1266,10 @@ impl Iterator for Lexer {
                             return self.parse_raw_string();
                         },
                         _ => {
+                            // an identifier, which is handled below
                             self.ret_next(after);
-                            self.ret_next(next_char);
                         }
                     }
-                } else {
-                    self.ret_next(next_char);
                 }
             }
             if next_char == '{' {
@@ -579,9 +1287,44 @@ impl Iterator for Lexer {
             if next_char == ',' {
                 return Some(Ok(LexToken::Comma));
             }
+            if next_char == '@' {
+                return Some(Ok(LexToken::At));
+            }
+            if next_char == '(' {
+                return Some(Ok(LexToken::OpenParen));
+            }
+            if next_char == ')' {
+                return Some(Ok(LexToken::CloseParen));
+            }
+            // the operators of conditions are made of two characters, and `!` and `&`
+            // are tokens in their own right
+            if ['!', '&', '|', '='].contains(&next_char) {
+                let after = self.pop_next();
+                match (next_char, after) {
+                    ('!', Some('=')) => { return Some(Ok(LexToken::NotEquals)); },
+                    ('&', Some('&')) => { return Some(Ok(LexToken::And)); },
+                    ('|', Some('|')) => { return Some(Ok(LexToken::Or)); },
+                    ('=', Some('=')) => { return Some(Ok(LexToken::Equals)); },
+                    (_, Some(after)) => { self.ret_next(after); },
+                    (_, None) => {},
+                }
+            }
             if next_char == '!' {
                 return Some(Ok(LexToken::Bang));
             }
+            if next_char == '&' {
+                return Some(Ok(LexToken::Ampersand));
+            }
+            if next_char == '*' {
+                return Some(Ok(LexToken::Asterisk));
+            }
+            if next_char == '$' {
+                match self.pop_next() {
+                    Some('{') => { return self.parse_reference(); },
+                    Some(after) => { self.ret_next(after); },
+                    None => {},
+                }
+            }
             if next_char == ':' {
                 return Some(Ok(LexToken::Colon));
             }
@@ -593,6 +1336,16 @@ impl Iterator for Lexer {
                 self.ret_next(next_char);
                 return self.parse_ident();
             }
+            if next_char == '.' {
+                // a dot followed by a digit starts a float
+                let after = self.pop_next();
+                if let Some(after) = after {
+                    self.ret_next(after);
+                }
+                if !matches!(after, Some(digit) if digit.is_ascii_digit()) {
+                    return Some(Ok(LexToken::Dot));
+                }
+            }
             if next_char.is_digit(10) || ['+', '-', '.'].contains(&next_char) {
                 self.ret_next(next_char);
                 return self.parse_numeric();
@@ -609,10 +1362,24 @@ impl Iterator for Lexer {
     }
 }
 
+// Multiplies by a power of two, in steps small enough that each factor is a normal
+// float, so that the result is exact unless it overflows or is subnormal.
+fn scale_by_power_of_two(mut value: f64, mut exponent: i64) -> f64 {
+    while exponent > 1000 && value.is_finite() && value != 0.0 {
+        value *= 2f64.powi(1000);
+        exponent -= 1000;
+    }
+    while exponent < -1000 && value != 0.0 {
+        value *= 2f64.powi(-1000);
+        exponent += 1000;
+    }
+    value * 2f64.powi(exponent.clamp(-1000, 1000) as i32)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use super::super::position::MutablePosition;
+    use super::super::position::{MutablePosition, Position};
     use std::io::Cursor;
 
     #[test]
@@ -711,11 +1478,11 @@ mod tests {
 
     #[test]
     fn parse_unrecognised_char() {
-        let mut lexer = Lexer::lex(Cursor::new("&".as_bytes()));
+        let mut lexer = Lexer::lex(Cursor::new("%".as_bytes()));
         match lexer.next() {
             None => panic!("Should return some"),
             Some(Ok(tok)) => panic!(format!("Should return err, returned Ok({:?})", tok)),
-            Some(Err(LexError::UnrecognisedCharError(c))) => assert_eq!(c, '&'),
+            Some(Err(LexError::UnrecognisedCharError(c))) => assert_eq!(c, '%'),
             Some(Err(err)) => panic!(format!("Should return char error, returned {:?}", err)),
         }
     }
@@ -824,6 +1591,152 @@ mod tests {
             LexToken::IntegerLit(0));
     }
 
+    #[test]
+    fn record_numeric_lexemes() {
+        let mut lexer = Lexer::lex(Cursor::new("[0xFF_FF, 8.e4, 'x', -inf]".as_bytes()));
+        let mut lexemes = Vec::new();
+        while let Some(token) = lexer.next() {
+            if let Ok(LexToken::IntegerLit(_)) | Ok(LexToken::FloatLit(_)) = token {
+                lexemes.push(lexer.numeric_lexeme().to_string());
+            }
+        }
+        assert_eq!(lexemes, vec!["0xFF_FF", "8.e4", "-inf"]);
+
+        // numbers are also part of the lexemes when they are being recorded
+        let mut lexer = Lexer::lex_preserving(Cursor::new("[ 1_0 ]".as_bytes()));
+        lexer.next();
+        assert_eq!(lexer.next(), Some(Ok(LexToken::IntegerLit(10))));
+        assert_eq!((lexer.lexeme(), lexer.numeric_lexeme()), (Some("1_0"), "1_0"));
+    }
+
+    #[test]
+    fn parse_special_floats() {
+        let lex = |source: &str| Lexer::lex(Cursor::new(source.to_string().into_bytes()))
+            .collect::<Vec<_>>();
+        let float = |flt: f64| Ok(LexToken::FloatLit(flt));
+        assert_eq!(lex("0x1.8p3"), vec![float(12.0)]);
+        assert_eq!(lex("0x.8P-1"), vec![float(0.25)]);
+        assert_eq!(lex("0x1_0p+0"), vec![float(16.0)]);
+        assert_eq!(lex("0x1.fffffffffffffp1023"), vec![float(f64::MAX)]);
+        assert_eq!(lex("0x1p-1074"), vec![float(f64::from_bits(1))]);
+        assert_eq!(lex("0x0.000p99999"), vec![float(0.0)]);
+        assert_eq!(lex("[-inf, +inf]"), vec![
+            Ok(LexToken::OpenBracket), float(f64::NEG_INFINITY), Ok(LexToken::Comma),
+            float(f64::INFINITY), Ok(LexToken::CloseBracket)]);
+        assert_eq!(lex("inf"), vec![Ok(LexToken::Identifier("inf".to_string()))]);
+
+        let error = |source: &str| match lex(source).remove(0) {
+            Err(LexError::InvalidFloat(message)) => message,
+            other => panic!("{:?} lexed as {:?}", source, other),
+        };
+        assert_eq!(error("0x1.8"), "`0x1.8` needs a binary exponent, as in `0x1.8p0`");
+        assert_eq!(error("0xp1"), "`0xp1` has no digits");
+        assert_eq!(error("0x1p"), "`0x1p` has an invalid exponent");
+        assert_eq!(error("0x1p1024"), "`0x1p1024` is too large for a float");
+        assert_eq!(error("0x1p-1075"), "`0x1p-1075` is too small for a float");
+        assert_eq!(
+            error("0x1.00000000000000001p0"),
+            "`0x1.00000000000000001p0` has more digits than a float can hold");
+        assert_eq!(error("-nan"), "`-nan` is not a number");
+    }
+
+    #[test]
+    fn parse_quantities() {
+        let lex = |source: &str| {
+            let mut lexer = Lexer::lex(Cursor::new(source.to_string().into_bytes()));
+            lexer.quantities = true;
+            lexer.parse_numeric().unwrap()
+        };
+        assert_eq!(lex("30s"), Ok(LexToken::DurationLit(Duration::from_secs(30))));
+        assert_eq!(lex("1.5h"), Ok(LexToken::DurationLit(Duration::from_secs(5400))));
+        assert_eq!(lex("1h30m"), Ok(LexToken::DurationLit(Duration::from_secs(5400))));
+        assert_eq!(lex("+250ms"), Ok(LexToken::DurationLit(Duration::from_millis(250))));
+        assert_eq!(lex("1_000µs"), Ok(LexToken::DurationLit(Duration::from_millis(1))));
+        assert_eq!(lex("512MiB"), Ok(LexToken::ByteSizeLit(512 << 20)));
+        assert_eq!(lex("2EB"), Ok(LexToken::ByteSizeLit(2_000_000_000_000_000_000)));
+        assert_eq!(lex("0B"), Ok(LexToken::ByteSizeLit(0)));
+        assert_eq!(lex("2e3"), Ok(LexToken::FloatLit(2e3)));
+
+        assert_eq!(
+            lex("-5s"),
+            Err(LexError::InvalidQuantity("`-5s` is negative".to_string())));
+        assert_eq!(
+            lex("1h30x"),
+            Err(LexError::InvalidQuantity("`x` is not a unit of time".to_string())));
+
+        // numbers followed by anything but a unit are not quantities
+        let bad_int = "x".parse::<i64>().unwrap_err();
+        assert_eq!(lex("-0x10"), Err(LexError::IntegerParseError(bad_int.clone())));
+        assert_eq!(lex("5x"), Err(LexError::IntegerParseError(bad_int)));
+        assert_eq!(lex("1.5sec"), Err(LexError::FloatParseError("x".parse::<f64>().unwrap_err())));
+        let mut lexer = Lexer::lex(Cursor::new("-0x10, 1".as_bytes()));
+        assert_eq!(lexer.next().unwrap().unwrap_err().to_string(),
+            "invalid integer: invalid digit found in string");
+        assert_eq!(lexer.next().unwrap(), Ok(LexToken::Comma));
+        assert_eq!(lex("16EiB"), Err(LexError::InvalidQuantity("`16EiB` is too large".to_string())));
+        assert!(lex("1.5ns").is_err());
+
+        // without quantities, units are not read
+        let mut lexer = Lexer::lex(Cursor::new("30s".as_bytes()));
+        assert_eq!(
+            lexer.parse_numeric().unwrap(),
+            Err(LexError::IntegerParseError("x".parse::<i64>().unwrap_err())));
+        assert!(lex("1B1B").is_err());
+
+        let mut lexer = Lexer::lex(Cursor::new("[1m, 2KiB]".as_bytes()));
+        lexer.quantities = true;
+        let tokens: Vec<_> = lexer.by_ref().map(Result::unwrap).collect();
+        assert_eq!(tokens, vec![
+            LexToken::OpenBracket, LexToken::DurationLit(Duration::from_secs(60)), LexToken::Comma,
+            LexToken::ByteSizeLit(2048), LexToken::CloseBracket]);
+    }
+
+    #[test]
+    fn parse_datetimes() {
+        let lex = |source: &str| Lexer::lex(Cursor::new(source.to_string().into_bytes()))
+            .map(Result::unwrap).collect::<Vec<_>>();
+        let datetime = |text: &str| LexToken::DateTimeLit(text.parse().unwrap());
+        assert_eq!(
+            lex("[2026-10-16, 12:30:00, 2026-10-16T12:00:00Z]"),
+            vec![LexToken::OpenBracket, datetime("2026-10-16"), LexToken::Comma,
+                 datetime("12:30:00"), LexToken::Comma, datetime("2026-10-16T12:00:00Z"),
+                 LexToken::CloseBracket]);
+        assert_eq!(lex("-2026"), vec![LexToken::IntegerLit(-2026)]);
+        assert_eq!(lex("20_26"), vec![LexToken::IntegerLit(2026)]);
+
+        let mut lexer = Lexer::lex(Cursor::new("'a': 2026-02-30".as_bytes()));
+        lexer.next();
+        lexer.next();
+        assert_eq!(
+            lexer.next(),
+            Some(Err(LexError::InvalidDateTime("day 30 is out of range for 2026-02".to_string()))));
+        assert_eq!(lexer.token_start.clone().freeze(), Position::at(0, 13));
+    }
+
+    #[test]
+    fn parse_bytes() {
+        let lex = |source: &str| Lexer::lex(Cursor::new(source.to_string().into_bytes()))
+            .map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(
+            lex("[b64\"AAEC\", hex'00 ff', b64, hex]"),
+            vec![LexToken::OpenBracket, LexToken::BytesLit(vec![0, 1, 2], BytesEncoding::Base64),
+                 LexToken::Comma, LexToken::BytesLit(vec![0, 255], BytesEncoding::Hex),
+                 LexToken::Comma,
+                 LexToken::Identifier("b64".to_string()), LexToken::Comma,
+                 LexToken::Identifier("hex".to_string()), LexToken::CloseBracket]);
+
+        let mut lexer = Lexer::lex(Cursor::new("'a': b64\"AAEC\n  AA!A\"".as_bytes()));
+        lexer.next();
+        lexer.next();
+        assert_eq!(
+            lexer.next(),
+            Some(Err(LexError::InvalidBytes("'!' is not a base64 digit".to_string()))));
+        assert_eq!(lexer.token_start.clone().freeze(), Position::at(1, 4));
+
+        let mut lexer = Lexer::lex(Cursor::new("hex'0".as_bytes()));
+        assert_eq!(lexer.next(), Some(Err(LexError::UnclosedStringError)));
+    }
+
     #[test]
     fn parse_string() {
         let mut lexer = Lexer::lex(Cursor::new("'string'".as_bytes()));
@@ -862,6 +1775,95 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_block_string() {
+        let lex = |source: &str| Lexer::lex(Cursor::new(source.to_string().into_bytes()))
+            .parse_string().unwrap();
+        let string = |text: &str| Ok(LexToken::StringLit(text.to_string()));
+
+        assert_eq!(lex("'''one line'''"), string("one line"));
+        assert_eq!(
+            lex("\"\"\"\n    SELECT *\n      FROM t\n    \"\"\""), string("SELECT *\n  FROM t"));
+        assert_eq!(lex("\"\"\"\n    kept\n\n  \"\"\""), string("  kept\n"));
+        assert_eq!(lex("\"\"\"first\n    second\"\"\""), string("first\nsecond"));
+        assert_eq!(lex("'''\n    it's \\t'quoted'\n    '''"), string("it's \t'quoted'"));
+        assert_eq!(lex("\"\"\"a \\\"\"\" b\"\"\"\""), string("a \"\"\" b\""));
+        assert_eq!(lex("''''''"), string(""));
+        assert_eq!(
+            lex("'''\n    ${a.b}\n    '''"),
+            Ok(LexToken::InterpolatedString(vec![
+                StringPart::Reference("a.b".to_string(), Position::at(1, 4))])));
+
+        assert_eq!(lex("'''unclosed''"), Err(LexError::UnclosedStringError));
+        assert_eq!(lex("'''\\q'''"), Err(LexError::InvalidEscape('q')));
+
+        // an empty string is not the start of a triple-quoted string
+        let mut lexer = Lexer::lex(Cursor::new("'' 'x'".as_bytes()));
+        assert_eq!(lexer.next(), Some(string("")));
+        assert_eq!(lexer.next(), Some(string("x")));
+
+        // errors are reported where they are in the source, not in the dedented text
+        let source = "'a': '''\n    ok\n    bad \\q\n    '''";
+        let mut lexer = Lexer::lex(Cursor::new(source.as_bytes()));
+        lexer.next();
+        lexer.next();
+        assert_eq!(lexer.next(), Some(Err(LexError::InvalidEscape('q'))));
+        assert_eq!(lexer.token_start.clone().freeze(), Position::at(2, 9));
+
+        let mut lexer = Lexer::lex(Cursor::new("'''\n      x ${a\n      b\n      '''".as_bytes()));
+        assert_eq!(lexer.next(), Some(Err(LexError::UnclosedReferenceError)));
+        assert_eq!(lexer.token_start.clone().freeze(), Position::at(1, 11));
+    }
+
+    #[test]
+    fn dedent_text() {
+        let dedent = |text: &str| dedented_lines(text).into_iter()
+            .map(|range| &text[range]).collect::<Vec<_>>().join("\n");
+        assert_eq!(dedent("\n  a\n    b\n  "), "a\n  b");
+        assert_eq!(dedent("\n\ta\n\t\n\tb\n\t"), "a\n\nb");
+        assert_eq!(dedent("\n    a\n  "), "  a");
+        assert_eq!(dedent("  a  "), "  a  ");
+        assert_eq!(dedent("\n"), "");
+        assert_eq!(dedent("\n  a\n\n"), "  a\n");
+        assert_eq!(dedent("\n  a\n\n  "), "a\n");
+    }
+
+    #[test]
+    fn parse_interpolated_string() {
+        let mut lexer = Lexer::lex(Cursor::new("'a ${b.c} $${d} $e $$f ${g}'".as_bytes()));
+        assert_eq!(
+            lexer.parse_string().unwrap().unwrap(),
+            LexToken::InterpolatedString(vec![
+                StringPart::Text("a ".to_string()),
+                StringPart::Reference("b.c".to_string(), Position::at(0, 3)),
+                StringPart::Text(" ${d} $e $$f ".to_string()),
+                StringPart::Reference("g".to_string(), Position::at(0, 23)),
+            ]));
+
+        // quotes in paths are escaped if they would end the string
+        let mut lexer = Lexer::lex(Cursor::new("\"${a[\\\"}\\\"]}\"".as_bytes()));
+        assert_eq!(
+            lexer.parse_string().unwrap().unwrap(),
+            LexToken::InterpolatedString(vec![
+                StringPart::Reference("a[\"}\"]".to_string(), Position::at(0, 1))]));
+
+        let mut lexer = Lexer::lex(Cursor::new("'only $${escapes}'".as_bytes()));
+        assert_eq!(
+            lexer.parse_string().unwrap().unwrap(),
+            LexToken::StringLit("only ${escapes}".to_string()));
+
+        let mut lexer = Lexer::lex(Cursor::new("'${a' 1".as_bytes()));
+        assert_eq!(lexer.parse_string().unwrap(), Err(LexError::UnclosedReferenceError));
+
+        let mut lexer = Lexer::lex(Cursor::new("'${a} $${b}'".as_bytes()));
+        lexer.interpolate = false;
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::StringLit("${a} ${b}".to_string()));
+
+        // raw strings are never interpolated
+        let mut lexer = Lexer::lex(Cursor::new("r#${a}#".as_bytes()));
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::StringLit("${a}".to_string()));
+    }
+
     #[test]
     fn parse_raw_string() {
         let mut lexer = Lexer::lex(Cursor::new("r/hello/".as_bytes()));
@@ -876,4 +1878,119 @@ mod tests {
         assert_eq!(lexer.parse_raw_string().unwrap().unwrap(),
             LexToken::StringLit("hel///lo".to_string()));
     }
+
+    #[test]
+    fn raw_strings_with_one_delimiter() {
+        // the string ends at the first delimiter, rather than running on to the next
+        let mut lexer = Lexer::lex(Cursor::new("r/one/ r/two/".as_bytes()));
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::StringLit("one".to_string()));
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::StringLit("two".to_string()));
+        assert!(lexer.next().is_none());
+
+        let mut lexer = Lexer::lex(Cursor::new("r|it's \"quoted\"|, 1".as_bytes()));
+        assert_eq!(lexer.next().unwrap().unwrap(),
+            LexToken::StringLit("it's \"quoted\"".to_string()));
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::Comma);
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::IntegerLit(1));
+    }
+
+    #[test]
+    fn unclosed_raw_strings() {
+        let mut lexer = Lexer::lex(Cursor::new("r//unclosed/".as_bytes()));
+        assert_eq!(lexer.parse_raw_string().unwrap(), Err(LexError::UnclosedStringError));
+
+        for source in &["r/unclosed", "r///a//", "r#"] {
+            let mut lexer = Lexer::lex(Cursor::new(source.as_bytes()));
+            assert_eq!(lexer.next().unwrap(), Err(LexError::UnclosedStringError), "{}", source);
+            assert!(lexer.next().is_none());
+        }
+    }
+
+    #[test]
+    fn parse_anchors_and_references() {
+        let mut lexer = Lexer::lex(Cursor::new("&a *a ${b.c[\"d}e\"][0]} $x".as_bytes()));
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::Ampersand);
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::Identifier("a".to_string()));
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::Asterisk);
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::Identifier("a".to_string()));
+        assert_eq!(
+            lexer.next().unwrap().unwrap(),
+            LexToken::Reference("b.c[\"d}e\"][0]".to_string()));
+        assert_eq!(lexer.next().unwrap().unwrap_err(), LexError::UnrecognisedCharError('$'));
+
+        let mut lexer = Lexer::lex(Cursor::new("${a.b\n}".as_bytes()));
+        assert_eq!(lexer.next().unwrap().unwrap_err(), LexError::UnclosedReferenceError);
+        let mut lexer = Lexer::lex(Cursor::new("${a[\"}\"".as_bytes()));
+        assert_eq!(lexer.next().unwrap().unwrap_err(), LexError::UnclosedReferenceError);
+    }
+
+    #[test]
+    fn parse_dots() {
+        let mut lexer = Lexer::lex(Cursor::new("a.b .5 c. 1.5".as_bytes()));
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::Identifier("a".to_string()));
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::Dot);
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::Identifier("b".to_string()));
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::FloatLit(0.5));
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::Identifier("c".to_string()));
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::Dot);
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::FloatLit(1.5));
+        assert!(lexer.next().is_none());
+    }
+
+    #[test]
+    fn parse_r_idents() {
+        let mut lexer = Lexer::lex(Cursor::new("region r r'raw'".as_bytes()));
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::Identifier("region".to_string()));
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::Identifier("r".to_string()));
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::StringLit("raw".to_string()));
+        assert!(lexer.next().is_none());
+    }
+
+    #[test]
+    fn parse_operators() {
+        let mut lexer = Lexer::lex(Cursor::new("@(a == 'b') != && || ! & !c = |".as_bytes()));
+        let expected = vec![
+            LexToken::At,
+            LexToken::OpenParen,
+            LexToken::Identifier("a".to_string()),
+            LexToken::Equals,
+            LexToken::StringLit("b".to_string()),
+            LexToken::CloseParen,
+            LexToken::NotEquals,
+            LexToken::And,
+            LexToken::Or,
+            LexToken::Bang,
+            LexToken::Ampersand,
+            LexToken::Bang,
+            LexToken::Identifier("c".to_string()),
+        ];
+        for token in expected {
+            assert_eq!(lexer.next().unwrap().unwrap(), token);
+        }
+        assert_eq!(lexer.next().unwrap(), Err(LexError::UnrecognisedCharError('=')));
+    }
+
+    #[test]
+    fn lex_preserving() {
+        let mut lexer = Lexer::lex_preserving(Cursor::new(
+            "// line\nnode { /* block */ 'key': 0x1f }".as_bytes()));
+        let mut tokens = Vec::new();
+        while let Some(token) = lexer.next() {
+            tokens.push((token.unwrap(), lexer.lexeme().unwrap().to_string()));
+        }
+        assert_eq!(tokens, vec![
+            (LexToken::Comment("// line".to_string()), "// line\n".to_string()),
+            (LexToken::Identifier("node".to_string()), "node".to_string()),
+            (LexToken::OpenBrace, "{".to_string()),
+            (LexToken::Comment("/* block */".to_string()), "/* block */".to_string()),
+            (LexToken::StringLit("key".to_string()), "'key'".to_string()),
+            (LexToken::Colon, ":".to_string()),
+            (LexToken::IntegerLit(31), "0x1f".to_string()),
+            (LexToken::CloseBrace, "}".to_string()),
+        ]);
+
+        let mut lexer = Lexer::lex(Cursor::new("// line\nnode".as_bytes()));
+        assert_eq!(lexer.next().unwrap().unwrap(), LexToken::Identifier("node".to_string()));
+        assert_eq!(lexer.lexeme(), None);
+    }
 }
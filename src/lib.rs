@@ -15,6 +15,66 @@
 //! }
 //! ```
 //!
+//! Keys that are valid identifiers can also be written without quotes, as in
+//! `key: "value"`, and attributes can be written at the top level of a document, outside
+//! of any node.  Nodes can be given string labels after their name, as in
+//! `backend "api" { ... }`, to tell apart several nodes of the same kind.
+//!
+//! To avoid repeating values, a node or value can be named with an anchor such as
+//! `&defaults`, and copied elsewhere with the alias `*defaults`.  A reference such as
+//! `${server.port}` copies whatever a path points to in the document, so nodes can be
+//! copied with `backup ${server}` or `copy *defaults` in place of a body.
+//!
+//! References can also be interpolated into strings, as in
+//! `"http://${server.host}:${server.port}/"`, where strings, numbers and booleans are
+//! written as text.  Paths that are not in the document are looked up in
+//! `LoadOptions::context`, and `${env:NAME}` reads the environment variable `NAME`.
+//! A literal `${` is written as `$${`, and raw strings are never interpolated.
+//!
+//! Long text can be written in triple quotes, as `"""..."""` or `'''...'''`.  A newline
+//! straight after the opening quotes is dropped, as is the last line if it only holds
+//! the closing quotes, and the indentation shared by the lines and the closing quotes is
+//! removed.  Otherwise, triple-quoted strings are read like any other string.
+//!
+//! A node can extend another node, as in `production : base.server { ... }`, to inherit
+//! its attributes and subnodes.  Dicts and subnodes are merged with those of the
+//! parent, and anything the node sets itself takes precedence.  Nodes written as
+//! `abstract base { ... }` can be extended, but are left out of the loaded document.
+//!
+//! Settings that only apply to some environments can be written in blocks such as
+//! `when(profile == "prod") { ... }`.  Conditions are evaluated against the profiles
+//! and variables in `LoadOptions`, and the contents of a block are only kept in the
+//! enclosing node if its condition holds.
+//!
+//! Attributes and nodes can be annotated with metadata that is not part of their value,
+//! as in `@secret "password": "..."` or `@deprecated("use timeout_ms") "timeout": 30`.
+//! Annotations are kept on each `Node`, for tools and applications to act on.
+//!
+//! Floats can be written in hex with a binary exponent, as in `0x1.8p3`, for values
+//! that must be exact, and the keywords `inf`, `-inf` and `nan` are the special float
+//! values.  Unlike `f64`, a NaN value is equal to itself.
+//!
+//! Durations and byte sizes are written with the `!duration` and `!bytes` tags, as in
+//! `!duration "1h30m"`, and are read as `Value::Duration` and `Value::ByteSize`.  With
+//! `LoadOptions::quantity_literals`, they can also be written as numbers with a unit, as
+//! in `30s`, `1.5h`, `1h30m` or `512MiB`.  Units ending in `B` are sizes, with decimal
+//! (`kB`, `MB`) and binary (`KiB`, `MiB`) prefixes, and durations go from `ns` to `d`.
+//! Since `0d` is the prefix of a decimal integer, no days are written `0s`.
+//!
+//! Dates and times are written in RFC 3339 format, as in `2026-10-16`, `12:00:00` or
+//! `2026-10-16T12:00:00Z`, and are read as `Value::DateTime`.  They are checked as the
+//! document is read, so a date that does not exist is an error; see the `datetime`
+//! module.
+//!
+//! Binary values such as keys and salts can be written in base64 or hex, as in
+//! `b64"3q2+7w=="` or `hex"dead beef"`, and are read as `Value::Bytes`.  Whitespace
+//! inside them is ignored, and a character that is not part of the encoding is reported
+//! where it appears.
+//!
+//! A value can be tagged to say how it should be read, as in `!duration "5m"` or
+//! `!env "HOME"`.  Tags are converted by the handlers in `LoadOptions::tags`, and
+//! applications can register handlers of their own; see the `tags` module.
+//!
 //! The figtree library parses structures like this into documents that can be
 //! manipulated to use as an efficient configuration system.
 //!
@@ -40,14 +100,52 @@ mod position;
 pub use position::Position;
 
 mod lexer;
-pub use lexer::LexToken;
+pub use lexer::{LexToken, StringPart};
 pub use lexer::LexError;
 
 mod parser;
-pub use parser::ParseError;
+pub use parser::{ParseError, KeyStyle};
 
 pub mod types;
 pub use types::*;
 
+mod reference;
+
+mod condition;
+
+mod units;
+
+pub mod datetime;
+
+mod encoding;
+
+#[cfg(feature = "serde")]
+mod serialize;
+
+pub mod tags;
+pub use tags::TagHandlers;
+
 mod figtree;
-pub use figtree::Figtree;
+pub use figtree::{Figtree, LoadOptions};
+
+pub mod writer;
+
+pub mod path;
+pub use path::{Path, PathTarget};
+
+pub mod import;
+
+pub mod format;
+
+pub mod diff;
+pub use diff::Change;
+
+pub mod patch;
+pub use patch::Patch;
+
+pub mod merge;
+
+pub mod watch;
+
+pub mod shared;
+pub use shared::SharedConfig;
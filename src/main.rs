@@ -0,0 +1,339 @@
+//! The `figtree` command-line tool.
+//!
+//! Provides a way to validate and query figtree files from the shell:
+//!
+//! ```text
+//! figtree check <file>...      parse files and report any errors
+//! figtree get <path> <file>    print the node or value at a path
+//! figtree dump <file>          print the parsed document
+//! figtree diff <old> <new>     print the structural differences between two files
+//! figtree patch <patch> <file> print a file with a patch applied
+//! figtree merge <base> <ours> <theirs>
+//!                              print a three-way merge, with conflict markers
+//! figtree fmt [--check] <file>...
+//!                              rewrite files in the canonical layout
+//! ```
+//!
+//! Files are loaded with the profiles and variables given by `--profile <name>` and
+//! `--var <name>=<value>`, which can be repeated, for the conditions of `when` blocks.
+//! With `--repeated`, nodes that share a name are loaded as repeated nodes, which paths
+//! address by index, as in `server[1]`.
+//! Without either, `check` treats blocks whose conditions use a variable as inactive,
+//! rather than reporting the variable as unknown.
+//!
+//! Errors are written to stderr as `file:line:pos: message`.  The tool exits with 0 on
+//! success, 1 if a file could not be read, parsed, or did not contain the requested
+//! path (or, for `diff` and `fmt --check`, if there were differences, and for `merge`,
+//! if there were conflicts), and 2 if it was called incorrectly.
+
+extern crate figtree;
+
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path;
+use std::process;
+
+use figtree::{Document, Figtree, LoadOptions, Path, PathTarget};
+use figtree::diff;
+use figtree::format::{self, FormatOptions};
+use figtree::merge;
+use figtree::patch::Patch;
+use figtree::path::Segment;
+use figtree::types::Value;
+use figtree::writer;
+
+const USAGE: &str = "\
+usage: figtree <command> [<args>]
+
+commands:
+    check <file>...      parse files and report any errors
+    get <path> <file>    print the node or value at a path
+    dump <file>          print the parsed document
+    diff <old> <new>     print the structural differences between two files
+    patch <patch> <file> print a file with a patch applied
+    merge <base> <ours> <theirs>
+                         print a three-way merge, with conflict markers
+    fmt [--check] <file>...
+                         rewrite files in the canonical layout, or with --check,
+                         list the files that are not formatted
+    help                 print this message
+
+options, for all commands but fmt:
+    --profile <name>     make a profile active, for `when(profile == \"...\")` blocks
+    --var <name>=<value> give a variable a value, for the conditions of `when` blocks
+    --repeated           load nodes that share a name as repeated nodes";
+
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+
+// Takes the `--profile`, `--var` and `--repeated` options out of the arguments,
+// wherever they are, returning the options to load files with and the rest of the
+// arguments.
+fn load_options(args: &[String]) -> Result<(LoadOptions, Vec<String>), String> {
+    let mut options = LoadOptions::default();
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--profile" => {
+                let profile = args.next().ok_or("--profile requires a name")?;
+                options.profiles.push(profile.clone());
+            },
+            "--var" => {
+                let variable = args.next().ok_or("--var requires a name and a value")?;
+                let (name, value) = variable.split_once('=')
+                    .ok_or_else(|| format!("`{}` is not of the form <name>=<value>", variable))?;
+                options.variables.insert(name.to_string(), value.to_string());
+            },
+            "--repeated" => { options.repeated_nodes = true; },
+            _ => rest.push(arg.clone()),
+        }
+    }
+    Ok((options, rest))
+}
+
+fn load(filename: &str, options: &LoadOptions) -> Result<Document, String> {
+    let mut figgy = Figtree::from_filename(filename)
+        .map_err(|err| format!("{}: {}", filename, err))?;
+    figgy.parse_with(options)
+        .map_err(|(err, pos)| format!("{}:{}: {}", filename, pos, err))
+}
+
+fn check(filenames: &[String], options: &LoadOptions) -> i32 {
+    if filenames.is_empty() {
+        return usage_error("check requires at least one file");
+    }
+
+    // without any profiles or variables, files are checked with the blocks that depend
+    // on variables left out
+    let mut options = options.clone();
+    if options.profiles.is_empty() && options.variables.is_empty() {
+        options.unknown_variables_inactive = true;
+    }
+    let mut status = 0;
+    for filename in filenames {
+        if let Err(message) = load(filename, &options) {
+            eprintln!("{}", message);
+            status = EXIT_FAILURE;
+        }
+    }
+    status
+}
+
+fn get(args: &[String], options: &LoadOptions) -> i32 {
+    if args.len() != 2 {
+        return usage_error("get requires a path and a file");
+    }
+
+    let path = match Path::parse(&args[0]) {
+        Ok(path) => path,
+        Err(err) => { return usage_error(&format!("invalid path `{}`: {}", args[0], err)); },
+    };
+    let doc = match load(&args[1], options) {
+        Ok(doc) => doc,
+        Err(message) => {
+            eprintln!("{}", message);
+            return EXIT_FAILURE;
+        },
+    };
+
+    let output = match path.lookup(&doc) {
+        // strings are printed without quotes so that scripts can use them directly
+        Some(PathTarget::Value(Value::Str(s))) => format!("{}\n", s),
+        Some(PathTarget::Value(value)) => format!("{}\n", writer::value_to_string(value)),
+        Some(PathTarget::Node(node)) => {
+            // paths can end in the labels or index of a node after its name, and the
+            // labels are written from the node itself
+            let name = path.segments().iter().rev()
+                .find_map(|segment| match segment {
+                    Segment::Name(name) => Some(name.as_str()),
+                    _ => None,
+                })
+                .unwrap_or_default();
+            writer::node_to_string(name, node)
+        },
+        None => {
+            eprintln!("{}: no such path `{}`", args[1], path);
+            return EXIT_FAILURE;
+        },
+    };
+    print(&output)
+}
+
+fn dump(args: &[String], options: &LoadOptions) -> i32 {
+    if args.len() != 1 {
+        return usage_error("dump requires exactly one file");
+    }
+
+    match load(&args[0], options) {
+        Ok(doc) => print(&writer::to_string(&doc)),
+        Err(message) => {
+            eprintln!("{}", message);
+            EXIT_FAILURE
+        },
+    }
+}
+
+fn diff(args: &[String], options: &LoadOptions) -> i32 {
+    if args.len() != 2 {
+        return usage_error("diff requires exactly two files");
+    }
+
+    let docs = load(&args[0], options)
+        .and_then(|old| load(&args[1], options).map(|new| (old, new)));
+    let (old, new) = match docs {
+        Ok(docs) => docs,
+        Err(message) => {
+            eprintln!("{}", message);
+            return EXIT_FAILURE;
+        },
+    };
+
+    // like diff(1), exit with 1 when the files differ
+    let changes = old.diff(&new);
+    match print(&diff::render(&changes)) {
+        0 if changes.is_empty() => 0,
+        _ => EXIT_FAILURE,
+    }
+}
+
+fn patch(args: &[String], options: &LoadOptions) -> i32 {
+    if args.len() != 2 {
+        return usage_error("patch requires a patch and a file");
+    }
+
+    let patch = fs::read_to_string(&args[0])
+        .map_err(|err| format!("{}: {}", args[0], err))
+        .and_then(|source| Patch::parse(&source)
+            .map_err(|err| format!("{}: {}", args[0], err)));
+    let result = patch.and_then(|patch| load(&args[1], options).and_then(|mut doc| {
+        doc.apply_patch(&patch)
+            .map(|_| doc)
+            .map_err(|(err, index)| format!("{}: operation {}: {}", args[0], index, err))
+    }));
+
+    match result {
+        Ok(doc) => print(&writer::to_string(&doc)),
+        Err(message) => {
+            eprintln!("{}", message);
+            EXIT_FAILURE
+        },
+    }
+}
+
+fn merge(args: &[String], options: &LoadOptions) -> i32 {
+    if args.len() != 3 {
+        return usage_error("merge requires a base, our and their file");
+    }
+
+    let mut docs = Vec::new();
+    for filename in args {
+        match load(filename, options) {
+            Ok(doc) => docs.push(doc),
+            Err(message) => {
+                eprintln!("{}", message);
+                return EXIT_FAILURE;
+            },
+        }
+    }
+
+    let merged = merge::merge(&docs[0], &docs[1], &docs[2]);
+    for conflict in &merged.conflicts {
+        eprintln!("{}", conflict);
+    }
+    match print(&merged.to_string_with_markers()) {
+        0 if merged.is_clean() => 0,
+        _ => EXIT_FAILURE,
+    }
+}
+
+fn fmt(args: &[String]) -> i32 {
+    let check = args.first().map(|arg| arg == "--check").unwrap_or(false);
+    let filenames = if check { &args[1..] } else { args };
+    if filenames.is_empty() {
+        return usage_error("fmt requires at least one file");
+    }
+
+    let mut status = 0;
+    for filename in filenames {
+        match format_file(filename, check) {
+            Ok(true) => {},
+            Ok(false) => {
+                println!("{}", filename);
+                status = EXIT_FAILURE;
+            },
+            Err(message) => {
+                eprintln!("{}", message);
+                status = EXIT_FAILURE;
+            },
+        }
+    }
+    status
+}
+
+// Formats a file, returning false if it needs formatting and `check` is set.  Without
+// `check`, files that are not formatted are rewritten in place.
+fn format_file(filename: &str, check: bool) -> Result<bool, String> {
+    let source = fs::read_to_string(filename)
+        .map_err(|err| format!("{}: {}", filename, err))?;
+
+    let directory = match path::Path::new(filename).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => path::Path::new("."),
+    };
+    let options = FormatOptions::discover(directory)
+        .map_err(|err| format!("{}: {}", filename, err))?;
+
+    let formatted = format::format_str(&source, &options)
+        .map_err(|(err, pos)| format!("{}:{}: {}", filename, pos, err))?;
+    if formatted == source {
+        return Ok(true);
+    }
+    if !check {
+        fs::write(filename, formatted).map_err(|err| format!("{}: {}", filename, err))?;
+    }
+    Ok(!check)
+}
+
+fn print(output: &str) -> i32 {
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    match handle.write_all(output.as_bytes()).and_then(|_| handle.flush()) {
+        Ok(_) => 0,
+        Err(_) => EXIT_FAILURE,
+    }
+}
+
+fn usage_error(message: &str) -> i32 {
+    eprintln!("figtree: {}\n\n{}", message, USAGE);
+    EXIT_USAGE
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = args.first().map(|arg| arg.as_str());
+    let (options, rest) = match command {
+        Some("fmt") | None => (LoadOptions::default(), Vec::new()),
+        Some(_) => match load_options(&args[1..]) {
+            Ok(parsed) => parsed,
+            Err(message) => process::exit(usage_error(&message)),
+        },
+    };
+    let status = match command {
+        Some("check") => check(&rest, &options),
+        Some("get") => get(&rest, &options),
+        Some("dump") => dump(&rest, &options),
+        Some("diff") => diff(&rest, &options),
+        Some("patch") => patch(&rest, &options),
+        Some("merge") => merge(&rest, &options),
+        Some("fmt") => fmt(&args[1..]),
+        Some("help") | Some("--help") | Some("-h") => {
+            println!("{}", USAGE);
+            0
+        },
+        Some(command) => usage_error(&format!("unknown command `{}`", command)),
+        None => usage_error("no command given"),
+    };
+    process::exit(status);
+}
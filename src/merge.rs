@@ -0,0 +1,599 @@
+//! Three-way merges of documents.
+//!
+//! A three-way merge combines two documents that were both derived from a common base,
+//! such as a user's edited copy of a config file ("ours") and a new version of the
+//! defaults it was copied from ("theirs").  Changes made on only one side are taken as
+//! they are, and changes made identically on both sides are taken once.  Anything that
+//! was changed differently on each side is a conflict.
+//!
+//! Nodes are merged recursively, so edits to different attributes of the same node
+//! never conflict.  Attribute values are merged as a whole: a dict or list that was
+//! changed differently on each side is a conflict, even if the changes touch different
+//! keys or elements.  A node that was removed on one side and changed on the other is
+//! also a conflict.  Repeated nodes are merged by position, like list elements would be
+//! if they were merged recursively; the index in the path of a conflict between
+//! repeated nodes is the position of the conflict in the merged document.
+//!
+//! The merged document keeps our side of each conflict.  `Merge::to_string_with_markers`
+//! writes the merged document out with each conflict shown between conflict markers,
+//! like the `diff3` style used by `git`:
+//!
+//! ```text
+//! server {
+//! <<<<<<< ours
+//!     "port": 8080,
+//! ||||||| base
+//!     "port": 80,
+//! =======
+//!     "port": 8000,
+//! >>>>>>> theirs
+//! }
+//! ```
+//!
+//! # Examples
+//! ```
+//! use figtree::Figtree;
+//! use figtree::merge;
+//!
+//! let parse = |source: &str| Figtree::from_string(source).parse().ok().expect("invalid");
+//! let base = parse("server { 'port': 80, 'workers': 4 }");
+//! let ours = parse("server { 'port': 8080, 'workers': 4 }");
+//! let theirs = parse("server { 'port': 80, 'workers': 8 }");
+//!
+//! let merged = merge::merge(&base, &ours, &theirs);
+//! assert!(merged.is_clean());
+//! assert_eq!(merged.document, parse("server { 'port': 8080, 'workers': 8 }"));
+//! ```
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ptr;
+
+use super::patch::PatchValue;
+use super::path::{Path, Segment};
+use super::types::*;
+use super::writer;
+
+/// A node or attribute that was changed differently in each merged document.
+///
+/// Each side is `None` if the node or attribute is not present in that document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub path: Path,
+    pub base: Option<PatchValue>,
+    pub ours: Option<PatchValue>,
+    pub theirs: Option<PatchValue>,
+}
+
+/// The result of a three-way merge.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Merge {
+    /// The merged document, which contains our side of any conflicts.
+    pub document: Document,
+    /// The conflicts found during the merge, in the order they appear when the merged
+    /// document is written out.
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Merge two documents that were both derived from a common base.
+pub fn merge(base: &Document, ours: &Document, theirs: &Document) -> Merge {
+    let mut conflicts = Vec::new();
+    let root = merge_node(
+        &Path::new(), Some(base.as_node()), ours.as_node(), theirs.as_node(), &mut conflicts);
+    Merge { document: Document::from_node(root), conflicts }
+}
+
+impl Conflict {
+    fn is_node(&self) -> bool {
+        [&self.base, &self.ours, &self.theirs].iter()
+            .any(|side| matches!(side, Some(PatchValue::Node(_))))
+    }
+
+    // The position of the name of the conflicting node or attribute in the path, which
+    // is followed by any labels, and an index for repeated nodes.
+    fn name_position(&self) -> usize {
+        self.path.segments().iter()
+            .rposition(|segment| matches!(segment, Segment::Name(_)))
+            .expect("conflicts are always at named paths")
+    }
+
+    fn name(&self) -> &str {
+        match self.path.segments()[self.name_position()] {
+            Segment::Name(ref name) => name,
+            _ => unreachable!("conflicts are always at named paths"),
+        }
+    }
+
+    fn has_labels(&self, labels: &[String]) -> bool {
+        let mut own = self.path.segments()[self.name_position() + 1..].iter()
+            .filter_map(|segment| match segment {
+                Segment::Label(label) => Some(label),
+                _ => None,
+            });
+        labels.iter().all(|label| own.next() == Some(label)) && own.next().is_none()
+    }
+
+    fn index(&self) -> Option<usize> {
+        match self.path.segments().last() {
+            Some(&Segment::Index(index)) => Some(index),
+            _ => None,
+        }
+    }
+
+    fn parent(&self) -> &[Segment] {
+        &self.path.segments()[..self.name_position()]
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match (&self.base, &self.ours, &self.theirs) {
+            (_, None, _) => "removed in ours, changed in theirs",
+            (_, _, None) => "changed in ours, removed in theirs",
+            (None, _, _) => "added differently in both",
+            _ => "changed differently in both",
+        };
+        write!(f, "conflict at `{}`: {}", self.path, description)
+    }
+}
+
+impl Merge {
+    /// Test if the merge completed without any conflicts.
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+
+    /// Write the merged document out as figtree source, with conflict markers around
+    /// each conflict.
+    ///
+    /// The output has the same layout as `writer::to_string`, and is the same as its
+    /// output if there are no conflicts.
+    pub fn to_string_with_markers(&self) -> String {
+        let mut out = String::new();
+        self.write_body(&Path::new(), self.document.as_node(), 0, &mut out);
+        out
+    }
+
+    fn conflicts_in<'a>(&'a self, path: &'a Path) -> impl Iterator<Item=&'a Conflict> + 'a {
+        self.conflicts.iter().filter(move |conflict| conflict.parent() == path.segments())
+    }
+
+    fn write_body(&self, path: &Path, node: &Node, indent: usize, out: &mut String) {
+        let attrs = writer::sorted(node.iter_attrs());
+        let mut names: BTreeSet<&str> = attrs.iter().map(|&(name, _)| name.as_str()).collect();
+        let conflicts: Vec<&Conflict> = self.conflicts_in(path)
+            .filter(|conflict| !conflict.is_node())
+            .collect();
+        names.extend(conflicts.iter().map(|conflict| conflict.name()));
+
+        for &name in &names {
+            match conflicts.iter().find(|conflict| conflict.name() == name) {
+                Some(conflict) => write_conflict(conflict, indent, out),
+                None => if let Some(value) = node.get_attr(name) {
+                    writer::write_attr(node, name, value, indent, out);
+                },
+            }
+        }
+
+        let nodes = writer::sorted(node.iter_nodes());
+        self.write_nodes(path, &nodes, !names.is_empty(), indent, out);
+    }
+
+    fn write_nodes(&self, path: &Path, nodes: &[(&String, &Node)], after_attrs: bool,
+                   indent: usize, out: &mut String) {
+
+        let mut names: BTreeSet<&str> = nodes.iter().map(|&(name, _)| name.as_str()).collect();
+        let conflicts: Vec<&Conflict> = self.conflicts_in(path)
+            .filter(|conflict| conflict.is_node())
+            .collect();
+        names.extend(conflicts.iter().map(|conflict| conflict.name()));
+
+        let mut first = !after_attrs;
+        let mut separate = |out: &mut String| {
+            if !first {
+                out.push('\n');
+            }
+            first = false;
+        };
+
+        for name in names {
+            let named: Vec<&Node> = nodes.iter()
+                .filter(|&&(node_name, _)| node_name == name)
+                .map(|&(_, node)| node)
+                .collect();
+            let mut pending: Vec<&Conflict> = conflicts.iter()
+                .filter(|conflict| conflict.name() == name)
+                .cloned()
+                .collect();
+
+            // the merged document keeps our side of each conflict, which is replaced by
+            // the conflict markers
+            let mut counts: Vec<(&[String], usize)> = Vec::new();
+            for node in named {
+                let labels = node.labels();
+                let index = match counts.iter_mut().find(|&&mut (other, _)| other == labels) {
+                    Some(&mut (_, ref mut count)) => { *count += 1; *count - 1 },
+                    None => { counts.push((labels, 1)); 0 },
+                };
+                let mut replaced = false;
+                while let Some(at) = pending.iter()
+                    .position(|conflict| {
+                        conflict.has_labels(labels) && conflict.index().unwrap_or(0) == index
+                    }) {
+
+                    let conflict = pending.remove(at);
+                    separate(out);
+                    write_conflict(conflict, indent, out);
+                    replaced |= conflict.ours.is_some();
+                }
+                if !replaced {
+                    separate(out);
+                    let path = self.node_path(path, name, labels, index);
+                    self.write_node(&path, name, node, indent, out);
+                }
+            }
+            for conflict in pending {
+                separate(out);
+                write_conflict(conflict, indent, out);
+            }
+        }
+    }
+
+    // The path of a merged node, which includes its index if any conflicts among the
+    // nodes with the same name and labels are addressed by index.
+    fn node_path(&self, parent: &Path, name: &str, labels: &[String], index: usize) -> Path {
+        let path = parent.node_child(name, labels);
+        let indexed = self.conflicts.iter().any(|conflict| {
+            let segments = conflict.path.segments();
+            segments.starts_with(path.segments())
+                && matches!(segments.get(path.segments().len()), Some(Segment::Index(_)))
+        });
+        if indexed { path.index(index) } else { path }
+    }
+
+    fn write_node(&self, path: &Path, name: &str, node: &Node, indent: usize, out: &mut String) {
+        let has_conflicts = self.conflicts.iter()
+            .any(|conflict| conflict.path.segments().starts_with(path.segments()));
+
+        writer::push_indent(indent, out);
+        writer::write_annotations(node.annotations(), out);
+        writer::write_header(name, node, out);
+        if node.is_empty() && !has_conflicts {
+            out.push_str("{}\n");
+        } else {
+            out.push_str("{\n");
+            self.write_body(path, node, indent + 1, out);
+            writer::push_indent(indent, out);
+            out.push_str("}\n");
+        }
+    }
+}
+
+fn write_conflict(conflict: &Conflict, indent: usize, out: &mut String) {
+    let write_side = |side: &Option<PatchValue>, out: &mut String| match *side {
+        Some(PatchValue::Value(ref value)) =>
+            writer::write_attr(&Node::new(), conflict.name(), value, indent, out),
+        Some(PatchValue::Node(ref node)) => writer::write_node(conflict.name(), node, indent, out),
+        None => {},
+    };
+
+    out.push_str("<<<<<<< ours\n");
+    write_side(&conflict.ours, out);
+    out.push_str("||||||| base\n");
+    write_side(&conflict.base, out);
+    out.push_str("=======\n");
+    write_side(&conflict.theirs, out);
+    out.push_str(">>>>>>> theirs\n");
+}
+
+// Picks the side of a merge to keep, or returns `None` if both sides were changed
+// differently.
+fn pick<'a, T: PartialEq>(base: Option<&'a T>, ours: Option<&'a T>, theirs: Option<&'a T>)
+    -> Option<Option<&'a T>> {
+
+    if ours == theirs || theirs == base {
+        Some(ours)
+    } else if ours == base {
+        Some(theirs)
+    } else {
+        None
+    }
+}
+
+// Merges one node, returning the node to keep, if any.
+fn merge_entry(path: Path, base: Option<&Node>, ours: Option<&Node>, theirs: Option<&Node>,
+               conflicts: &mut Vec<Conflict>) -> Option<Node> {
+
+    match (base, ours, theirs) {
+        (base, Some(ours), Some(theirs)) => Some(merge_node(&path, base, ours, theirs, conflicts)),
+        (base, ours, theirs) => match pick(base, ours, theirs) {
+            Some(node) => node.cloned(),
+            None => {
+                conflicts.push(Conflict {
+                    path,
+                    base: base.cloned().map(PatchValue::Node),
+                    ours: ours.cloned().map(PatchValue::Node),
+                    theirs: theirs.cloned().map(PatchValue::Node),
+                });
+                ours.cloned()
+            },
+        },
+    }
+}
+
+fn merge_node(path: &Path, base: Option<&Node>, ours: &Node, theirs: &Node,
+              conflicts: &mut Vec<Conflict>) -> Node {
+
+    let mut merged = Node::with_labels(ours.labels().iter().cloned());
+    // the annotations of the node are merged like a value, keeping ours if they were
+    // changed differently
+    let annotations = (base.map(Node::annotations), ours.annotations(), theirs.annotations());
+    let picked = pick(annotations.0.as_ref(), Some(&annotations.1), Some(&annotations.2))
+        .flatten()
+        .unwrap_or(&annotations.1);
+    merged.set_annotations(picked.to_vec());
+
+    let sides: Vec<&Node> = vec![ours, theirs].into_iter().chain(base).collect();
+    let base_attrs = base.into_iter().flat_map(|node| node.iter_attrs());
+    let keys: BTreeSet<&String> = base_attrs.chain(ours.iter_attrs()).chain(theirs.iter_attrs())
+        .map(|(key, _)| key)
+        .collect();
+    for key in keys {
+        let base = base.and_then(|node| node.get_attr(key.as_str()));
+        let (ours, theirs) = (ours.get_attr(key.as_str()), theirs.get_attr(key.as_str()));
+        let picked = pick(base, ours, theirs).unwrap_or_else(|| {
+            conflicts.push(Conflict {
+                path: path.child(key.as_str()),
+                base: base.cloned().map(PatchValue::Value),
+                ours: ours.cloned().map(PatchValue::Value),
+                theirs: theirs.cloned().map(PatchValue::Value),
+            });
+            ours
+        });
+        if let Some(value) = picked {
+            // the annotations and literal forms of the value come from the side it was
+            // picked from
+            let side = sides.iter()
+                .find(|side| side.get_attr(key.as_str()).is_some_and(|v| ptr::eq(v, value)))
+                .expect("picked values come from one of the sides");
+            merged.insert_attr(key.as_str(), value.clone());
+            merged.set_attr_annotations(key.as_str(), side.attr_annotations(key.as_str()).to_vec());
+            merged.copy_attr_literals(key, side);
+        }
+    }
+
+    let base_nodes = base.into_iter().flat_map(|node| node.iter_nodes());
+    let names: BTreeSet<&String> = base_nodes.chain(ours.iter_nodes()).chain(theirs.iter_nodes())
+        .map(|(name, _)| name)
+        .collect();
+    for name in names {
+        let lists = [
+            base.map(|node| node.get_nodes(name.as_str())).unwrap_or(&[]),
+            ours.get_nodes(name.as_str()),
+            theirs.get_nodes(name.as_str()),
+        ];
+        // only nodes with the same labels are merged with each other
+        for labels in label_sets(&lists) {
+            let path = path.node_child(name, labels);
+            let sets: Vec<Vec<&Node>> = lists.iter().map(|nodes| labelled(nodes, labels)).collect();
+            let longest = sets.iter().map(Vec::len).max().unwrap_or(0);
+            let entry = |index: usize| (
+                sets[0].get(index).cloned(), sets[1].get(index).cloned(), sets[2].get(index).cloned());
+            if longest <= 1 {
+                let (base, ours, theirs) = entry(0);
+                if let Some(node) = merge_entry(path, base, ours, theirs, conflicts) {
+                    merged.push_node(name.as_str(), node);
+                }
+                continue;
+            }
+
+            // repeated nodes are merged by position
+            for index in 0..longest {
+                let path = path.index(labelled(merged.get_nodes(name.as_str()), labels).len());
+                let (base, ours, theirs) = entry(index);
+                if let Some(node) = merge_entry(path, base, ours, theirs, conflicts) {
+                    merged.push_node(name.as_str(), node);
+                }
+            }
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::figtree::{Figtree, LoadOptions};
+
+    fn parse(source: &str) -> Document {
+        Figtree::from_string(source).parse().unwrap()
+    }
+
+    fn parse_repeated(source: &str) -> Document {
+        let options = LoadOptions { repeated_nodes: true, ..LoadOptions::default() };
+        Figtree::from_string(source).parse_with(&options).unwrap()
+    }
+
+    #[test]
+    fn clean_merges() {
+        let base = parse("a { 'x': 1, 'y': 2, 'z': 3 } b { 'list': [1] } c {}");
+        let ours = parse("a { 'x': 10, 'y': 2, 'z': 3, 'new': true } b { 'list': [1] }");
+        let theirs = parse("a { 'x': 1, 'y': 20, 'new': true } b { 'list': [1, 2] } c {} d {}");
+
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(merged.conflicts, vec![]);
+        assert_eq!(
+            merged.document,
+            parse("a { 'x': 10, 'y': 20, 'new': true } b { 'list': [1, 2] } d {}"));
+        assert_eq!(merged.to_string_with_markers(), writer::to_string(&merged.document));
+    }
+
+    #[test]
+    fn keeps_annotations_and_literals() {
+        let source = "@doc('db') db { @secret 'pw': 'x', 'mask': 0xFF, 'key': hex'00ff', \
+                      'query': '''\n    a\n    b\n    ''' }";
+        let base = parse(source);
+        let merged = merge(&base, &base, &base);
+        assert_eq!(merged.document, base);
+        assert_eq!(merged.to_string_with_markers(), writer::to_string(&base));
+        assert!(merged.to_string_with_markers().contains("@secret \"pw\": \"x\""));
+        assert!(merged.to_string_with_markers().contains("\"mask\": 0xFF"));
+
+        // the annotations of a value come from the side it was picked from
+        let theirs = parse(&source.replace("@secret 'pw': 'x'", "@secret @new 'pw': 'y'"));
+        let merged = merge(&base, &base, &theirs);
+        let db = merged.document.get_node("db").unwrap();
+        assert_eq!(db.attr_annotations("pw").len(), 2);
+        assert_eq!(db.annotations(), base.get_node("db").unwrap().annotations());
+        assert_eq!(merged.to_string_with_markers(), writer::to_string(&theirs));
+    }
+
+    #[test]
+    fn conflicting_merges() {
+        let base = parse("a { 'x': 1, 'd': {'k': 1} } b { 'y': 1 } c { 'z': 1 }");
+        let ours = parse("a { 'x': 2, 'd': {'k': 1, 'l': 2}, 'n': 1 } c { 'z': 2 }");
+        let theirs = parse("a { 'x': 3, 'd': {'k': 2}, 'n': 2 } b { 'y': 2 } c { 'z': 1 }");
+        let merged = merge(&base, &ours, &theirs);
+
+        let conflicts: Vec<String> = merged.conflicts.iter()
+            .map(|conflict| conflict.to_string())
+            .collect();
+        assert_eq!(conflicts, vec![
+            "conflict at `a.d`: changed differently in both",
+            "conflict at `a.n`: added differently in both",
+            "conflict at `a.x`: changed differently in both",
+            "conflict at `b`: removed in ours, changed in theirs",
+        ]);
+        assert_eq!(merged.conflicts[3], Conflict {
+            path: Path::parse("b").unwrap(),
+            base: Some(PatchValue::Node(base.get_node("b").unwrap().clone())),
+            ours: None,
+            theirs: Some(PatchValue::Node(theirs.get_node("b").unwrap().clone())),
+        });
+        assert_eq!(
+            merged.document,
+            parse("a { 'x': 2, 'd': {'k': 1, 'l': 2}, 'n': 1 } c { 'z': 2 }"));
+    }
+
+    #[test]
+    fn conflict_markers() {
+        let base = parse("a { 'x': 1, 'y': 1 } b { 'z': 1 }");
+        let ours = parse("a { 'x': 2, 'y': 1 } b { 'z': 2 }");
+        let theirs = parse("a { 'y': 1 }");
+        let merged = merge(&base, &ours, &theirs);
+
+        assert_eq!(merged.to_string_with_markers(), concat!(
+            "a {\n",
+            "<<<<<<< ours\n",
+            "    \"x\": 2,\n",
+            "||||||| base\n",
+            "    \"x\": 1,\n",
+            "=======\n",
+            ">>>>>>> theirs\n",
+            "    \"y\": 1,\n",
+            "}\n",
+            "\n",
+            "<<<<<<< ours\n",
+            "b {\n",
+            "    \"z\": 2,\n",
+            "}\n",
+            "||||||| base\n",
+            "b {\n",
+            "    \"z\": 1,\n",
+            "}\n",
+            "=======\n",
+            ">>>>>>> theirs\n"));
+    }
+
+    #[test]
+    fn top_level_attributes() {
+        let base = parse("'version': 1, 'name': 'x' a {}");
+        let ours = parse("'version': 2, 'name': 'x' a {}");
+        let theirs = parse("'version': 3, 'name': 'y' a {}");
+        let merged = merge(&base, &ours, &theirs);
+
+        assert_eq!(merged.conflicts.len(), 1);
+        assert_eq!(merged.to_string_with_markers(), concat!(
+            "\"name\": \"y\",\n",
+            "<<<<<<< ours\n",
+            "\"version\": 2,\n",
+            "||||||| base\n",
+            "\"version\": 1,\n",
+            "=======\n",
+            "\"version\": 3,\n",
+            ">>>>>>> theirs\n",
+            "\n",
+            "a {}\n"));
+    }
+
+    #[test]
+    fn repeated_nodes() {
+        let base = parse_repeated("s { 'p': 1 } s { 'p': 2 }");
+        let ours = parse_repeated("s { 'p': 1 } s { 'p': 3 } s { 'p': 4 }");
+        let theirs = parse_repeated("s { 'p': 5 } s { 'p': 2 }");
+        let merged = merge(&base, &ours, &theirs);
+        assert!(merged.is_clean());
+        let ports: Vec<&Value> = merged.document.iter_nodes_named("s")
+            .map(|node| &node["p"])
+            .collect();
+        assert_eq!(ports, [&Value::new_int(5), &Value::new_int(3), &Value::new_int(4)]);
+
+        let theirs = parse_repeated("s { 'p': 1 } s { 'p': 6 }");
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(merged.conflicts.len(), 1);
+        assert_eq!(merged.conflicts[0].path.to_string(), "s[1].p");
+        assert_eq!(merged.to_string_with_markers(), concat!(
+            "s {\n",
+            "    \"p\": 1,\n",
+            "}\n",
+            "\n",
+            "s {\n",
+            "<<<<<<< ours\n",
+            "    \"p\": 3,\n",
+            "||||||| base\n",
+            "    \"p\": 2,\n",
+            "=======\n",
+            "    \"p\": 6,\n",
+            ">>>>>>> theirs\n",
+            "}\n",
+            "\n",
+            "s {\n",
+            "    \"p\": 4,\n",
+            "}\n"));
+
+        let base = parse_repeated("s { 'p': 1 } s { 'p': 2 }");
+        let ours = parse_repeated("s { 'p': 1 }");
+        let theirs = parse_repeated("s { 'p': 1 } s { 'p': 7 }");
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(merged.conflicts.len(), 1);
+        assert_eq!(merged.conflicts[0].path.to_string(), "s[1]");
+        assert_eq!(merged.document.get_nodes("s").len(), 1);
+    }
+
+    #[test]
+    fn labelled_nodes() {
+        let base = parse("b 'x' { 'p': 1 } b 'y' { 'p': 1 }");
+        let ours = parse("b 'x' { 'p': 2 } b 'y' { 'p': 2 }");
+        let theirs = parse("b 'y' { 'p': 1 } b 'x' { 'p': 3 } b 'z' {}");
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(merged.conflicts.len(), 1);
+        assert_eq!(merged.conflicts[0].path.to_string(), "b[\"x\"].p");
+        assert_eq!(merged.document, parse("b 'x' { 'p': 2 } b 'y' { 'p': 2 } b 'z' {}"));
+        assert_eq!(merged.to_string_with_markers(), concat!(
+            "b \"x\" {\n",
+            "<<<<<<< ours\n",
+            "    \"p\": 2,\n",
+            "||||||| base\n",
+            "    \"p\": 1,\n",
+            "=======\n",
+            "    \"p\": 3,\n",
+            ">>>>>>> theirs\n",
+            "}\n",
+            "\n",
+            "b \"y\" {\n",
+            "    \"p\": 2,\n",
+            "}\n",
+            "\n",
+            "b \"z\" {}\n"));
+    }
+}
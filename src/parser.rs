@@ -1,22 +1,54 @@
-use super::lexer::{Lexer, LexToken, LexError};
+use super::condition::Condition;
+use super::datetime::DateTime;
+use super::lexer::{Lexer, LexToken, LexError, StringPart};
+use super::path::Path;
 use super::position::Position;
+use super::types::{BytesEncoding, NumericLiteral, Value};
+use super::writer::is_bare_ident;
+
+use std::fmt;
+use std::time::Duration;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum ParsedValue {
     Str(String),
     Int(i64),
     Float(f64),
+    Duration(Duration),
+    ByteSize(u64),
+    DateTime(DateTime),
+    Bytes(Vec<u8>, BytesEncoding),
     Bool(bool),
     Ident(String),
     Null,
+    Alias(String),
+    Reference(String),
+    Interpolated(Vec<StringPart>),
+    // an int or float along with the text it was written as
+    Literal(NumericLiteral),
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum ParseEvent {
     FileStart,
     FileEnd,
-    NodeStart(String),
+    NodeStart(String, Vec<String>),
     NodeEnd,
+    // a node whose contents are copied from an alias or reference
+    NodeReference(String, Vec<String>, ParsedValue),
+    // a node that extends the node at a path, followed by its contents like `NodeStart`
+    NodeExtends(String, Vec<String>, Path),
+    Anchor(String),
+    // the next node is an abstract template
+    Abstract,
+    // the start of a `when` block, whose contents belong to the enclosing node if its
+    // condition holds, followed by those contents and `WhenEnd`
+    When(Condition),
+    WhenEnd,
+    // an annotation of the next attribute or node, with its literal arguments
+    Annotation(String, Vec<ParsedValue>),
+    // a tag for the value that follows, its payload
+    Tag(String),
     Key(String),
     Value(ParsedValue),
     ListStart,
@@ -26,23 +58,107 @@ pub enum ParseEvent {
 }
 
 /// An enum representing an error that occurs during parsing.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum ParseError {
     LexError(LexError),
     UnexpectedEndOfFile,
     UnexpectedToken(LexToken),
     RepeatedNode(String),
+    BareKey(String),
+    QuotedKey(String),
+    DuplicateAnchor(String),
+    InvalidReference(String),
+    DanglingReference(String),
+    CyclicReference(String),
+    NotAValue(String),
+    NotANode(String),
+    NotInterpolable(String),
+    CyclicInheritance(String),
+    UnknownVariable(String),
+    UnknownTag(String),
+    InvalidTag(String, String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::LexError(ref err) => write!(f, "{}", err),
+            ParseError::UnexpectedEndOfFile => write!(f, "unexpected end of file"),
+            ParseError::UnexpectedToken(ref tok) => write!(f, "unexpected {}", tok),
+            ParseError::RepeatedNode(ref name) => write!(f, "repeated node `{}`", name),
+            ParseError::BareKey(ref key) => write!(f, "key `{}` must be quoted", key),
+            ParseError::QuotedKey(ref key) => write!(f, "key `{}` must not be quoted", key),
+            ParseError::DuplicateAnchor(ref name) => write!(f, "anchor `&{}` is defined twice", name),
+            ParseError::InvalidReference(ref reference) =>
+                write!(f, "`{}` is not a valid reference", reference),
+            ParseError::DanglingReference(ref reference) =>
+                write!(f, "`{}` does not refer to anything", reference),
+            ParseError::CyclicReference(ref reference) =>
+                write!(f, "`{}` refers to itself", reference),
+            ParseError::NotAValue(ref reference) =>
+                write!(f, "`{}` refers to a node where a value is expected", reference),
+            ParseError::NotANode(ref reference) =>
+                write!(f, "`{}` refers to a value where a node is expected", reference),
+            ParseError::NotInterpolable(ref reference) =>
+                write!(f, "`{}` cannot be interpolated into a string", reference),
+            ParseError::CyclicInheritance(ref parent) =>
+                write!(f, "`{}` inherits from the node that extends it", parent),
+            ParseError::UnknownVariable(ref name) =>
+                write!(f, "variable `{}` has no value", name),
+            ParseError::UnknownTag(ref tag) => write!(f, "unknown tag `!{}`", tag),
+            ParseError::InvalidTag(ref tag, ref message) =>
+                write!(f, "invalid `!{}` value: {}", tag, message),
+        }
+    }
+}
+
+/// The value of a bare name that is a keyword: `true`, `false`, `null`, `inf` or `nan`.
+/// A negative infinity is written `-inf`, which is read as a float by the lexer.
+pub(crate) fn keyword_value(name: &str) -> Option<ParsedValue> {
+    match name {
+        "true" => Some(ParsedValue::Bool(true)),
+        "false" => Some(ParsedValue::Bool(false)),
+        "null" => Some(ParsedValue::Null),
+        "inf" => Some(ParsedValue::Float(f64::INFINITY)),
+        "nan" => Some(ParsedValue::Float(f64::NAN)),
+        _ => None,
+    }
+}
+
+/// Test whether a token after `!name` starts the payload of a tag, rather than `!name`
+/// being an identifier value on its own.
+pub(crate) fn is_tag_payload(token: &LexToken) -> bool {
+    matches!(*token,
+        LexToken::StringLit(_) | LexToken::InterpolatedString(_) | LexToken::IntegerLit(_) |
+        LexToken::FloatLit(_) | LexToken::DurationLit(_) | LexToken::ByteSizeLit(_) |
+        LexToken::DateTimeLit(_) | LexToken::BytesLit(..) | LexToken::OpenBracket | LexToken::OpenBrace |
+        LexToken::Asterisk | LexToken::Reference(_))
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum ParseContext {
-    Basefile,
+    Basefile(bool),
     Node(bool),
+    // the contents of a `when` block
+    Conditional(bool),
     Value,
     List(bool),
     Dict(bool),
 }
 
+/// How attribute and dict keys may be written.
+///
+/// Keys can be written either as string literals (`"key": value`) or as bare
+/// identifiers (`key: value`).  A house style can require one or the other; keys that
+/// cannot be written as bare identifiers may always be quoted.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum KeyStyle {
+    #[default]
+    Any,
+    Quoted,
+    Bare,
+}
+
 type ContextStack = Vec<ParseContext>;
 pub type ParseResult = Result<(ParseEvent, Position), (ParseError, Position)>;
 
@@ -51,6 +167,13 @@ pub struct Parser {
     ended: bool,
     lexer: Lexer,
     peeked_result: Option<ParseResult>,
+    // whether the last event was an anchor or `abstract`, which must be followed by a
+    // node or value
+    prefixed: bool,
+    // whether the last event was an annotation, which must be followed by an attribute
+    // or node
+    annotated: bool,
+    pub key_style: KeyStyle,
 }
 
 impl Parser {
@@ -60,9 +183,23 @@ impl Parser {
             ended: false,
             lexer: lexer,
             peeked_result: None,
+            prefixed: false,
+            annotated: false,
+            key_style: KeyStyle::Any,
         }
     }
 
+    /// Set whether `${...}` in strings is read as an interpolation, or as text.
+    pub fn set_interpolation(&mut self, interpolate: bool) {
+        self.lexer.interpolate = interpolate;
+    }
+
+    /// Set whether numbers followed by units, as in `30s`, are read as durations and
+    /// byte sizes.
+    pub fn set_quantities(&mut self, quantities: bool) {
+        self.lexer.quantities = quantities;
+    }
+
     pub fn peek(&mut self) -> Option<&ParseResult> {
         if self.peeked_result.is_some() {
             self.peeked_result.as_ref()
@@ -76,6 +213,13 @@ impl Parser {
         self.lexer.token_start.clone().freeze()
     }
 
+    // Keeps the text of the number that was just lexed if writing its value would lose
+    // something, such as the hex in `0xFF_FF` or the precision of `0.1`.
+    fn number(&self, plain: ParsedValue, value: Value) -> ParsedValue {
+        let literal = NumericLiteral::new(self.lexer.numeric_lexeme(), value);
+        if literal.would_be_lost() { ParsedValue::Literal(literal) } else { plain }
+    }
+
     fn lex_error(&mut self, error: LexError) -> Option<ParseResult> {
         self.yield_error(ParseError::LexError(error))
     }
@@ -83,9 +227,10 @@ impl Parser {
     fn has_comma(&mut self) -> bool {
         match self.context.last() {
             None => false,
-            Some(&ParseContext::Basefile) => false,
+            Some(&ParseContext::Basefile(has_comma)) => has_comma,
             Some(&ParseContext::Value) => false,
             Some(&ParseContext::Node(has_comma)) => has_comma,
+            Some(&ParseContext::Conditional(has_comma)) => has_comma,
             Some(&ParseContext::List(has_comma)) => has_comma,
             Some(&ParseContext::Dict(has_comma)) => has_comma,
         }
@@ -94,7 +239,9 @@ impl Parser {
     fn set_comma(&mut self, state: bool) {
         let pushable = match self.context.pop() {
             None => None,
+            Some(ParseContext::Basefile(_)) => Some(ParseContext::Basefile(state)),
             Some(ParseContext::Node(_)) => Some(ParseContext::Node(state)),
+            Some(ParseContext::Conditional(_)) => Some(ParseContext::Conditional(state)),
             Some(ParseContext::List(_)) => Some(ParseContext::List(state)),
             Some(ParseContext::Dict(_)) => Some(ParseContext::Dict(state)),
             Some(ctx) => Some(ctx),
@@ -107,24 +254,24 @@ impl Parser {
 
     fn parse_context_file(&mut self) -> Option<ParseResult> {
         let next = self.lexer.next();
-        if let Some(Ok(LexToken::Identifier(ident))) = next {
-            let next = self.lexer.next();
-            match next {
-                Some(Ok(LexToken::OpenBrace)) => {
-                    self.context.push(ParseContext::Node(true));
-                    self.yield_state(ParseEvent::NodeStart(ident))
-                }
-                Some(Ok(tok)) =>
-                    self.yield_error(ParseError::UnexpectedToken(tok)),
-                Some(Err(err)) =>
-                    self.yield_error(ParseError::LexError(err)),
-                None =>
-                    self.yield_error(ParseError::UnexpectedEndOfFile),
-            }
+        if let Some(error) = self.check_prefixed(&next) {
+            return self.yield_error(error);
+        }
+        if let Some(Ok(LexToken::Ampersand)) = next {
+            self.parse_anchor()
+        } else if let Some(Ok(LexToken::At)) = next {
+            self.parse_annotation()
+        } else if let Some(Ok(LexToken::StringLit(key))) = next {
+            let start = self.lex_position();
+            self.parse_key(key, true, start)
+        } else if let Some(Ok(LexToken::Identifier(ident))) = next {
+            self.parse_name(ident)
         } else if let Some(Ok(tok)) = next {
             self.yield_error(ParseError::UnexpectedToken(tok))
         } else if let Some(Err(next)) = next {
             self.lex_error(next)
+        } else if self.annotated {
+            self.yield_error(ParseError::UnexpectedEndOfFile)
         } else {
             self.ended = true;
             self.yield_state(ParseEvent::FileEnd)
@@ -133,43 +280,22 @@ impl Parser {
 
     fn parse_context_node(&mut self) -> Option<ParseResult> {
         let next = self.lexer.next();
+        if let Some(error) = self.check_prefixed(&next) {
+            return self.yield_error(error);
+        }
         match next {
-            Some(Ok(LexToken::CloseBrace)) => {
-                self.context.pop();
-                self.yield_state(ParseEvent::NodeEnd)
-            },
-            Some(Ok(LexToken::Identifier(ident))) => {
-                self.set_comma(true);
-                match self.lexer.next() {
-                    Some(Ok(LexToken::OpenBrace)) => {
-                        self.context.push(ParseContext::Node(true));
-                        self.yield_state(ParseEvent::NodeStart(ident))
-                    },
-                    Some(Ok(tok)) =>
-                        self.yield_error(ParseError::UnexpectedToken(tok)),
-                    Some(Err(err)) =>
-                        self.lex_error(err),
-                    None =>
-                        self.yield_error(ParseError::UnexpectedEndOfFile),
-                }
+            Some(Ok(LexToken::Ampersand)) => self.parse_anchor(),
+            Some(Ok(LexToken::At)) => self.parse_annotation(),
+            Some(Ok(LexToken::CloseBrace)) if self.annotated =>
+                self.yield_error(ParseError::UnexpectedToken(LexToken::CloseBrace)),
+            Some(Ok(LexToken::CloseBrace)) => match self.context.pop() {
+                Some(ParseContext::Conditional(_)) => self.yield_state(ParseEvent::WhenEnd),
+                _ => self.yield_state(ParseEvent::NodeEnd),
             },
+            Some(Ok(LexToken::Identifier(ident))) => self.parse_name(ident),
             Some(Ok(LexToken::StringLit(key))) => {
-                if !self.has_comma() {
-                    return self.yield_error(ParseError::UnexpectedToken(LexToken::StringLit(key)));
-                }
-                self.set_comma(false);
-                match self.lexer.next() {
-                    Some(Ok(LexToken::Colon)) => {
-                        self.context.push(ParseContext::Value);
-                        self.yield_state(ParseEvent::Key(key))
-                    },
-                    Some(Ok(tok)) =>
-                        self.yield_error(ParseError::UnexpectedToken(tok)),
-                    Some(Err(err)) =>
-                        self.lex_error(err),
-                    None =>
-                        self.yield_error(ParseError::UnexpectedEndOfFile),
-                }
+                let start = self.lex_position();
+                self.parse_key(key, true, start)
             },
             Some(Ok(tok)) => {
                 self.yield_error(ParseError::UnexpectedToken(tok))
@@ -183,51 +309,397 @@ impl Parser {
         }
     }
 
+    // Parses a name in a file or node, which is either a bare key, the name of a node,
+    // `abstract` followed by the name of a node, or the start of a `when` block.
+    fn parse_name(&mut self, ident: String) -> Option<ParseResult> {
+        let start = self.lex_position();
+        match self.lexer.peek() {
+            Some(&Ok(LexToken::Colon)) => self.parse_key(ident, false, start),
+            Some(&Ok(LexToken::Identifier(_))) if ident == "abstract" =>
+                self.yield_state(ParseEvent::Abstract),
+            Some(&Ok(LexToken::OpenParen)) if ident == "when" => self.parse_when(start),
+            _ => self.parse_node_header(ident),
+        }
+    }
+
+    // Parses the condition of a `when` block and the opening brace after it, having
+    // already read `when`, which started at `start`.
+    fn parse_when(&mut self, start: Position) -> Option<ParseResult> {
+        self.lexer.next();
+        if self.prefixed || self.annotated {
+            // only nodes can follow an anchor or `abstract`, and blocks are never
+            // annotated
+            return self.yield_error(ParseError::UnexpectedToken(LexToken::OpenParen));
+        }
+        let condition = match self.parse_condition() {
+            Ok(condition) => condition,
+            Err(error) => { return self.yield_error(error); },
+        };
+        match self.next_token() {
+            Ok(LexToken::CloseParen) => {},
+            Ok(tok) => { return self.yield_error(ParseError::UnexpectedToken(tok)); },
+            Err(error) => { return self.yield_error(error); },
+        }
+        match self.next_token() {
+            Ok(LexToken::OpenBrace) => {},
+            Ok(tok) => { return self.yield_error(ParseError::UnexpectedToken(tok)); },
+            Err(error) => { return self.yield_error(error); },
+        }
+        self.set_comma(true);
+        self.context.push(ParseContext::Conditional(true));
+        self.yield_state_at(ParseEvent::When(condition), start)
+    }
+
+    // Parses conditions joined by `||`.
+    fn parse_condition(&mut self) -> Result<Condition, ParseError> {
+        let mut condition = self.parse_conjunction()?;
+        while matches!(self.lexer.peek(), Some(&Ok(LexToken::Or))) {
+            self.lexer.next();
+            condition = Condition::Or(Box::new(condition), Box::new(self.parse_conjunction()?));
+        }
+        Ok(condition)
+    }
+
+    // Parses conditions joined by `&&`, which binds more tightly than `||`.
+    fn parse_conjunction(&mut self) -> Result<Condition, ParseError> {
+        let mut condition = self.parse_comparison()?;
+        while matches!(self.lexer.peek(), Some(&Ok(LexToken::And))) {
+            self.lexer.next();
+            condition = Condition::And(Box::new(condition), Box::new(self.parse_comparison()?));
+        }
+        Ok(condition)
+    }
+
+    // Parses a comparison of a variable with a string, a negated condition, or a
+    // condition in parentheses.
+    fn parse_comparison(&mut self) -> Result<Condition, ParseError> {
+        match self.next_token()? {
+            LexToken::Bang => Ok(Condition::Not(Box::new(self.parse_comparison()?))),
+            LexToken::OpenParen => {
+                let condition = self.parse_condition()?;
+                match self.next_token()? {
+                    LexToken::CloseParen => Ok(condition),
+                    tok => Err(ParseError::UnexpectedToken(tok)),
+                }
+            },
+            LexToken::Identifier(name) => {
+                let equal = match self.next_token()? {
+                    LexToken::Equals => true,
+                    LexToken::NotEquals => false,
+                    tok => { return Err(ParseError::UnexpectedToken(tok)); },
+                };
+                match self.next_token()? {
+                    LexToken::StringLit(value) if equal => Ok(Condition::Equals(name, value)),
+                    LexToken::StringLit(value) => Ok(Condition::NotEquals(name, value)),
+                    tok => Err(ParseError::UnexpectedToken(tok)),
+                }
+            },
+            tok => Err(ParseError::UnexpectedToken(tok)),
+        }
+    }
+
+    fn next_token(&mut self) -> Result<LexToken, ParseError> {
+        match self.lexer.next() {
+            Some(Ok(tok)) => Ok(tok),
+            Some(Err(err)) => Err(ParseError::LexError(err)),
+            None => Err(ParseError::UnexpectedEndOfFile),
+        }
+    }
+
+    // Checks that the token after an anchor or `abstract` in a file or node can start a
+    // node, since only nodes and values can be anchored.  A name followed by a colon is
+    // checked by `parse_key`.
+    fn check_prefixed(&mut self, next: &Option<Result<LexToken, LexError>>) -> Option<ParseError> {
+        if !self.prefixed {
+            return None;
+        }
+        match *next {
+            Some(Ok(LexToken::Identifier(_))) => None,
+            Some(Ok(ref tok)) => Some(ParseError::UnexpectedToken(tok.clone())),
+            Some(Err(ref err)) => Some(ParseError::LexError(err.clone())),
+            None => Some(ParseError::UnexpectedEndOfFile),
+        }
+    }
+
+    // Parses the name of an anchor, having already read the `&`.
+    fn parse_anchor(&mut self) -> Option<ParseResult> {
+        if self.prefixed {
+            return self.yield_error(ParseError::UnexpectedToken(LexToken::Ampersand));
+        }
+        match self.lexer.next() {
+            Some(Ok(LexToken::Identifier(name))) => self.yield_state(ParseEvent::Anchor(name)),
+            Some(Ok(tok)) => self.yield_error(ParseError::UnexpectedToken(tok)),
+            Some(Err(err)) => self.lex_error(err),
+            None => self.yield_error(ParseError::UnexpectedEndOfFile),
+        }
+    }
+
+    // Parses an annotation, having already read the `@`: a name, optionally followed by
+    // literal arguments in parentheses.
+    fn parse_annotation(&mut self) -> Option<ParseResult> {
+        let start = self.lex_position();
+        let name = match self.next_token() {
+            Ok(LexToken::Identifier(name)) => name,
+            Ok(tok) => { return self.yield_error(ParseError::UnexpectedToken(tok)); },
+            Err(error) => { return self.yield_error(error); },
+        };
+        let mut args = Vec::new();
+        if matches!(self.lexer.peek(), Some(&Ok(LexToken::OpenParen))) {
+            self.lexer.next();
+            if let Err(error) = self.parse_annotation_args(&mut args) {
+                return self.yield_error(error);
+            }
+        }
+        self.yield_state_at(ParseEvent::Annotation(name, args), start)
+    }
+
+    // Parses the comma-separated arguments of an annotation and the closing parenthesis,
+    // having already read the opening parenthesis.
+    fn parse_annotation_args(&mut self, args: &mut Vec<ParsedValue>) -> Result<(), ParseError> {
+        loop {
+            let arg = match self.next_token()? {
+                LexToken::CloseParen if args.is_empty() => { return Ok(()); },
+                LexToken::StringLit(string) => ParsedValue::Str(string),
+                LexToken::IntegerLit(integer) => ParsedValue::Int(integer),
+                LexToken::FloatLit(flt) => ParsedValue::Float(flt),
+                LexToken::DurationLit(duration) => ParsedValue::Duration(duration),
+                LexToken::ByteSizeLit(bytes) => ParsedValue::ByteSize(bytes),
+                LexToken::DateTimeLit(datetime) => ParsedValue::DateTime(datetime),
+                LexToken::BytesLit(bytes, encoding) => ParsedValue::Bytes(bytes, encoding),
+                LexToken::Identifier(ident) => match keyword_value(&ident) {
+                    Some(value) => value,
+                    None => { return Err(ParseError::UnexpectedToken(LexToken::Identifier(ident))); },
+                },
+                tok => { return Err(ParseError::UnexpectedToken(tok)); },
+            };
+            args.push(arg);
+            match self.next_token()? {
+                LexToken::Comma => {},
+                LexToken::CloseParen => { return Ok(()); },
+                tok => { return Err(ParseError::UnexpectedToken(tok)); },
+            }
+        }
+    }
+
+    // Parses the name of an alias, having already read the `*`.
+    fn parse_alias(&mut self) -> Result<String, ParseError> {
+        match self.lexer.next() {
+            Some(Ok(LexToken::Identifier(name))) => Ok(name),
+            Some(Ok(tok)) => Err(ParseError::UnexpectedToken(tok)),
+            Some(Err(err)) => Err(ParseError::LexError(err)),
+            None => Err(ParseError::UnexpectedEndOfFile),
+        }
+    }
+
+    // Parses the rest of a node header, having already read the name: any number of
+    // string labels followed by the opening brace, or by an alias or reference to the
+    // node to copy.
+    fn parse_node_header(&mut self, name: String) -> Option<ParseResult> {
+        self.set_comma(true);
+        let mut labels = Vec::new();
+        loop {
+            match self.lexer.next() {
+                Some(Ok(LexToken::StringLit(label))) => labels.push(label),
+                Some(Ok(LexToken::OpenBrace)) => {
+                    self.context.push(ParseContext::Node(true));
+                    return self.yield_state(ParseEvent::NodeStart(name, labels));
+                },
+                Some(Ok(LexToken::Asterisk)) => {
+                    let target = match self.parse_alias() {
+                        Ok(alias) => ParsedValue::Alias(alias),
+                        Err(error) => { return self.yield_error(error); },
+                    };
+                    return self.yield_state(ParseEvent::NodeReference(name, labels, target));
+                },
+                Some(Ok(LexToken::Reference(path))) => {
+                    let target = ParsedValue::Reference(path);
+                    return self.yield_state(ParseEvent::NodeReference(name, labels, target));
+                },
+                Some(Ok(LexToken::Colon)) => { return self.parse_extends(name, labels); },
+                Some(Ok(tok)) =>
+                    return self.yield_error(ParseError::UnexpectedToken(tok)),
+                Some(Err(err)) =>
+                    return self.lex_error(err),
+                None =>
+                    return self.yield_error(ParseError::UnexpectedEndOfFile),
+            }
+        }
+    }
+
+    // Parses the path of the node that a node extends, and the opening brace after it,
+    // having already read the colon.
+    fn parse_extends(&mut self, name: String, labels: Vec<String>) -> Option<ParseResult> {
+        self.set_comma(true);
+        let mut path = Path::new();
+        let mut after_segment = false;
+        loop {
+            match self.lexer.next() {
+                Some(Ok(LexToken::Identifier(segment))) if !after_segment => {
+                    path.push_name(segment);
+                    after_segment = true;
+                },
+                Some(Ok(LexToken::Dot)) if after_segment => { after_segment = false; },
+                Some(Ok(LexToken::OpenBracket)) if after_segment => {
+                    let segment = (self.lexer.next(), self.lexer.next());
+                    match segment {
+                        (Some(Ok(LexToken::StringLit(label))), Some(Ok(LexToken::CloseBracket))) =>
+                            path.push_label(label),
+                        (Some(Ok(LexToken::IntegerLit(index))), Some(Ok(LexToken::CloseBracket)))
+                            if index >= 0 => path.push_index(index as usize),
+                        (Some(Err(err)), _) | (_, Some(Err(err))) => return self.lex_error(err),
+                        (None, _) | (_, None) =>
+                            return self.yield_error(ParseError::UnexpectedEndOfFile),
+                        (Some(Ok(LexToken::StringLit(_))), Some(Ok(tok))) |
+                        (Some(Ok(LexToken::IntegerLit(_))), Some(Ok(tok))) |
+                        (Some(Ok(tok)), _) =>
+                            return self.yield_error(ParseError::UnexpectedToken(tok)),
+                    }
+                },
+                Some(Ok(LexToken::OpenBrace)) if after_segment => {
+                    self.context.push(ParseContext::Node(true));
+                    return self.yield_state(ParseEvent::NodeExtends(name, labels, path));
+                },
+                Some(Ok(tok)) =>
+                    return self.yield_error(ParseError::UnexpectedToken(tok)),
+                Some(Err(err)) =>
+                    return self.lex_error(err),
+                None =>
+                    return self.yield_error(ParseError::UnexpectedEndOfFile),
+            }
+        }
+    }
+
+    // Parses the colon after a key in a node or dict, having already read the key,
+    // which started at `start`.  In a file or node, a name followed by a colon and
+    // another name starts a node that extends another instead.
+    fn parse_key(&mut self, key: String, quoted: bool, start: Position) -> Option<ParseResult> {
+        let next = self.lexer.next();
+        let in_dict = matches!(self.context.last(), Some(&ParseContext::Dict(_)));
+        if !quoted && !in_dict && matches!(next, Some(Ok(LexToken::Colon))) {
+            let extends = match self.lexer.peek() {
+                Some(&Ok(LexToken::Identifier(ref parent))) =>
+                    keyword_value(parent).is_none(),
+                _ => false,
+            };
+            if extends {
+                return self.parse_extends(key, Vec::new());
+            }
+        }
+
+        let error = if self.prefixed {
+            // only nodes can follow an anchor or `abstract`
+            Some(ParseError::UnexpectedToken(LexToken::Colon))
+        } else if !self.has_comma() {
+            let token = if quoted { LexToken::StringLit(key.clone()) } else { LexToken::Identifier(key.clone()) };
+            Some(ParseError::UnexpectedToken(token))
+        } else {
+            match self.key_style {
+                KeyStyle::Quoted if !quoted => Some(ParseError::BareKey(key.clone())),
+                KeyStyle::Bare if quoted && is_bare_ident(&key) =>
+                    Some(ParseError::QuotedKey(key.clone())),
+                _ => None,
+            }
+        };
+        if let Some(error) = error {
+            self.ended = true;
+            return Some(Err((error, start)));
+        }
+        self.set_comma(false);
+        match next {
+            Some(Ok(LexToken::Colon)) => {
+                self.context.push(ParseContext::Value);
+                self.yield_state(ParseEvent::Key(key))
+            },
+            Some(Ok(tok)) =>
+                self.yield_error(ParseError::UnexpectedToken(tok)),
+            Some(Err(err)) =>
+                self.lex_error(err),
+            None =>
+                self.yield_error(ParseError::UnexpectedEndOfFile),
+        }
+    }
+
+    // Joins adjacent strings into a single value, having already read the first.
+    fn parse_strings(&mut self, mut parts: Vec<StringPart>) -> Option<ParseResult> {
+        let start = self.lex_position();
+        loop {
+            // I think this hack is necessary
+            match self.lexer.peek() {
+                Some(&Ok(LexToken::StringLit(_))) | Some(&Ok(LexToken::InterpolatedString(_))) => {},
+                _ => { break; }
+            }
+
+            match self.lexer.next().unwrap().unwrap() {
+                LexToken::StringLit(s) => parts.push(StringPart::Text(s)),
+                LexToken::InterpolatedString(more) => parts.extend(more),
+                _ => unreachable!(),
+            }
+        }
+
+        let mut joined: Vec<StringPart> = Vec::new();
+        for part in parts {
+            match (joined.last_mut(), part) {
+                (Some(&mut StringPart::Text(ref mut text)), StringPart::Text(more)) => {
+                    text.push_str(&more);
+                },
+                (_, part) => joined.push(part),
+            }
+        }
+        let value = match joined.first() {
+            Some(StringPart::Text(string)) if joined.len() == 1 => ParsedValue::Str(string.clone()),
+            _ => ParsedValue::Interpolated(joined),
+        };
+        self.yield_state_at(ParseEvent::Value(value), start)
+    }
+
     fn parse_context_value(&mut self) -> Option<ParseResult> {
         self.context.pop();
+        if matches!(self.lexer.peek(), Some(&Ok(LexToken::Ampersand))) {
+            // the anchored value follows the anchor
+            self.lexer.next();
+            self.context.push(ParseContext::Value);
+            return self.parse_anchor();
+        }
        let response = match self.lexer.next() {
             None => self.yield_error(ParseError::UnexpectedEndOfFile),
             Some(Err(err)) => self.yield_error(ParseError::LexError(err)),
-            Some(Ok(LexToken::StringLit(string))) => {
-                let mut val_string = String::new();
-                val_string.push_str(&string);
-                loop {
-                    // I think this hack is necessary
-                    match self.lexer.peek() {
-                        Some(&Ok(LexToken::StringLit(_))) => {},
-                        _ => { break; }
-                    }
-
-                    match self.lexer.next().unwrap().unwrap() {
-                        LexToken::StringLit(s) => {
-                            val_string.push_str(&s);
-                        },
-                        _ => unreachable!(),
-                    }
-                }
-                self.yield_state(ParseEvent::Value(ParsedValue::Str(val_string)))
-            },
+            Some(Ok(LexToken::StringLit(string))) => self.parse_strings(vec![StringPart::Text(string)]),
+            Some(Ok(LexToken::InterpolatedString(parts))) => self.parse_strings(parts),
             Some(Ok(LexToken::IntegerLit(integer))) => {
-                self.yield_state(ParseEvent::Value(ParsedValue::Int(integer)))
+                let value = self.number(ParsedValue::Int(integer), Value::new_int(integer));
+                self.yield_state(ParseEvent::Value(value))
             }
             Some(Ok(LexToken::FloatLit(flt))) => {
-                self.yield_state(ParseEvent::Value(ParsedValue::Float(flt)))
+                let value = self.number(ParsedValue::Float(flt), Value::new_float(flt));
+                self.yield_state(ParseEvent::Value(value))
+            }
+            Some(Ok(LexToken::DurationLit(duration))) => {
+                self.yield_state(ParseEvent::Value(ParsedValue::Duration(duration)))
+            }
+            Some(Ok(LexToken::ByteSizeLit(bytes))) => {
+                self.yield_state(ParseEvent::Value(ParsedValue::ByteSize(bytes)))
+            }
+            Some(Ok(LexToken::DateTimeLit(datetime))) => {
+                self.yield_state(ParseEvent::Value(ParsedValue::DateTime(datetime)))
+            }
+            Some(Ok(LexToken::BytesLit(bytes, encoding))) => {
+                self.yield_state(ParseEvent::Value(ParsedValue::Bytes(bytes, encoding)))
             }
             Some(Ok(LexToken::Identifier(ident))) => {
-                match &*ident {
-                    "true" =>
-                        self.yield_state(ParseEvent::Value(ParsedValue::Bool(true))),
-                    "false" =>
-                        self.yield_state(ParseEvent::Value(ParsedValue::Bool(false))),
-                    "null" =>
-                        self.yield_state(ParseEvent::Value(ParsedValue::Null)),
-                    _ =>
-                        self.yield_error(ParseError::UnexpectedToken(LexToken::Identifier(ident))),
+                match keyword_value(&ident) {
+                    Some(value) => self.yield_state(ParseEvent::Value(value)),
+                    None => self.yield_error(ParseError::UnexpectedToken(LexToken::Identifier(ident))),
                 }
             },
             Some(Ok(LexToken::Bang)) => {
+                let start = self.lex_position();
                 match self.lexer.next() {
                     Some(Ok(LexToken::Identifier(s))) => {
+                        if matches!(self.lexer.peek(), Some(Ok(next)) if is_tag_payload(next)) {
+                            // the payload follows the tag
+                            self.context.push(ParseContext::Value);
+                            return self.yield_state_at(ParseEvent::Tag(s), start);
+                        }
                         self.yield_state(ParseEvent::Value(ParsedValue::Ident(s)))
                     },
                     Some(Ok(tok)) => self.yield_error(ParseError::UnexpectedToken(tok)),
@@ -235,6 +707,12 @@ impl Parser {
                     None => self.yield_error(ParseError::UnexpectedEndOfFile),
                 }
             },
+            Some(Ok(LexToken::Asterisk)) => match self.parse_alias() {
+                Ok(alias) => self.yield_state(ParseEvent::Value(ParsedValue::Alias(alias))),
+                Err(error) => self.yield_error(error),
+            },
+            Some(Ok(LexToken::Reference(path))) =>
+                self.yield_state(ParseEvent::Value(ParsedValue::Reference(path))),
             Some(Ok(LexToken::OpenBracket)) => {
                 self.context.push(ParseContext::List(true));
                 self.yield_state(ParseEvent::ListStart)
@@ -275,25 +753,19 @@ impl Parser {
         match self.lexer.next() {
             Some(Ok(LexToken::CloseBrace)) => {
                 self.context.pop();
+                if matches!(self.lexer.peek(), Some(&Ok(LexToken::Comma))) {
+                    self.set_comma(true);
+                    self.lexer.next();
+                }
                 self.yield_state(ParseEvent::DictEnd)
             },
             Some(Ok(LexToken::StringLit(key))) => {
-                if !self.has_comma() {
-                    return self.yield_error(ParseError::UnexpectedToken(LexToken::StringLit(key)));
-                }
-                self.set_comma(false);
-                match self.lexer.next() {
-                    Some(Ok(LexToken::Colon)) => {
-                        self.context.push(ParseContext::Value);
-                        self.yield_state(ParseEvent::Key(key))
-                    },
-                    Some(Ok(tok)) =>
-                        self.yield_error(ParseError::UnexpectedToken(tok)),
-                    Some(Err(err)) =>
-                        self.lex_error(err),
-                    None =>
-                        self.yield_error(ParseError::UnexpectedEndOfFile),
-                }
+                let start = self.lex_position();
+                self.parse_key(key, true, start)
+            },
+            Some(Ok(LexToken::Identifier(key))) => {
+                let start = self.lex_position();
+                self.parse_key(key, false, start)
             },
             Some(Ok(tok)) => {
                 self.yield_error(ParseError::UnexpectedToken(tok))
@@ -308,7 +780,14 @@ impl Parser {
     }
 
     fn yield_state(&mut self, state: ParseEvent) -> Option<ParseResult> {
-        Some(Ok((state, self.lex_position())))
+        let position = self.lex_position();
+        self.yield_state_at(state, position)
+    }
+
+    fn yield_state_at(&mut self, state: ParseEvent, position: Position) -> Option<ParseResult> {
+        self.prefixed = matches!(state, ParseEvent::Anchor(_) | ParseEvent::Abstract);
+        self.annotated = matches!(state, ParseEvent::Annotation(..));
+        Some(Ok((state, position)))
     }
 
     fn yield_error(&mut self, error: ParseError) -> Option<ParseResult> {
@@ -321,20 +800,21 @@ impl Iterator for Parser {
     type Item = ParseResult;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.ended { return None; }
+        // a peeked error has already ended the parse, but must still be returned
         if self.peeked_result.is_some() { return self.peeked_result.take(); }
+        if self.ended { return None; }
 
         let current_state = self.context.pop();
         match current_state {
             None => {
-                self.context.push(ParseContext::Basefile);
+                self.context.push(ParseContext::Basefile(true));
                 self.yield_state(ParseEvent::FileStart)
             }
-            Some(ParseContext::Basefile) => {
+            Some(ParseContext::Basefile(_)) => {
                 self.context.push(current_state.unwrap());
                 self.parse_context_file()
             },
-            Some(ParseContext::Node(_)) => {
+            Some(ParseContext::Node(_)) | Some(ParseContext::Conditional(_)) => {
                 self.context.push(current_state.unwrap());
                 self.parse_context_node()
             },
@@ -374,7 +854,7 @@ mod tests {
         let file = Cursor::new("node { }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileEnd);
         assert!(parser.next().is_none());
@@ -385,8 +865,8 @@ mod tests {
         let file = Cursor::new("node { subnode {} }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("subnode".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("subnode".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileEnd);
@@ -395,11 +875,11 @@ mod tests {
         let file = Cursor::new("node { subnode { sub { sub { sub {} } } } }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("subnode".to_string()));
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("sub".to_string()));
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("sub".to_string()));
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("sub".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("subnode".to_string(), vec![]));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("sub".to_string(), vec![]));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("sub".to_string(), vec![]));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("sub".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
@@ -413,7 +893,7 @@ mod tests {
         let file = Cursor::new("node { 'key': 'value' }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("key".to_string()));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Str("value".to_string())));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
@@ -423,7 +903,7 @@ mod tests {
         let file = Cursor::new("node { 'key': 3 }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("key".to_string()));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Int(3)));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
@@ -433,7 +913,7 @@ mod tests {
         let file = Cursor::new("node { 'key': 3.5 }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("key".to_string()));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Float(3.5)));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
@@ -443,7 +923,7 @@ mod tests {
         let file = Cursor::new("node { 'key': true }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("key".to_string()));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Bool(true)));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
@@ -453,7 +933,7 @@ mod tests {
         let file = Cursor::new("node { 'key': false }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("key".to_string()));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Bool(false)));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
@@ -463,7 +943,7 @@ mod tests {
         let file = Cursor::new("node { 'key': !my_ident }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("key".to_string()));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Ident("my_ident".to_string())));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
@@ -473,7 +953,7 @@ mod tests {
         let file = Cursor::new("node { 'key': null }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("key".to_string()));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Null));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
@@ -486,7 +966,7 @@ mod tests {
         let file = Cursor::new("node { 'key': 'value 1' 'value 2' }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("key".to_string()));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Str("value 1value 2".to_string())));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
@@ -500,7 +980,7 @@ mod tests {
         let file = Cursor::new("node { 'key1': true, 'key2': 'val' }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("key1".to_string()));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Bool(true)));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("key2".to_string()));
@@ -513,7 +993,7 @@ mod tests {
         let file = Cursor::new("node { 'key1': true 'key2': 'val' }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("key1".to_string()));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Bool(true)));
         assert_eq!(parser.next().unwrap().unwrap_err().0, ParseError::UnexpectedToken(LexToken::StringLit("key2".to_string())));
@@ -521,19 +1001,31 @@ mod tests {
         let file = Cursor::new("node { 'key1': 'true' 'key2': 'val' }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("key1".to_string()));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Str("truekey2".to_string())));
         assert_eq!(parser.next().unwrap().unwrap_err().0, ParseError::UnexpectedToken(LexToken::Colon));
         assert!(parser.next().is_none());
     }
 
+    #[test]
+    fn returns_peeked_errors() {
+        let file = Cursor::new("node { 'key': }".as_bytes());
+        let mut parser = Parser::parse(Lexer::lex(file));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("key".to_string()));
+        assert!(parser.peek().unwrap().is_err());
+        assert_eq!(parser.next().unwrap().unwrap_err().0, ParseError::UnexpectedToken(LexToken::CloseBrace));
+        assert!(parser.next().is_none());
+    }
+
     #[test]
     fn handle_list_values() {
         let file = Cursor::new("node { 'key': ['val1', 2, 3.4, false, !ident] }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("key".to_string()));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::ListStart);
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Str("val1".to_string())));
@@ -552,7 +1044,7 @@ mod tests {
         let file = Cursor::new("node { 'key': ['lista', ['listb', []]] }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("key".to_string()));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::ListStart);
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Str("lista".to_string())));
@@ -572,13 +1064,13 @@ mod tests {
         let file = Cursor::new("node { 'key': [1, 2,], subnode {} }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("key".to_string()));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::ListStart);
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Int(1)));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Int(2)));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::ListEnd);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("subnode".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("subnode".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileEnd);
@@ -587,7 +1079,7 @@ mod tests {
         let file = Cursor::new("node { , }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap_err().0, ParseError::UnexpectedToken(LexToken::Comma));
         assert!(parser.next().is_none());
     }
@@ -597,7 +1089,7 @@ mod tests {
         let file = Cursor::new("node { 'key': {'1': 2, '3': 4} }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("key".to_string()));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::DictStart);
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("1".to_string()));
@@ -615,7 +1107,7 @@ mod tests {
         let file = Cursor::new("node { 'key': {'1': {'b': {} } } }".as_bytes());
         let mut parser = Parser::parse(Lexer::lex(file));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
-        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("key".to_string()));
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::DictStart);
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("1".to_string()));
@@ -629,4 +1121,277 @@ mod tests {
         assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileEnd);
         assert!(parser.next().is_none());
     }
+
+    #[test]
+    fn handle_bare_keys() {
+        let file = Cursor::new("node { key: 1, sub { } other: {inner: true} }".as_bytes());
+        let mut parser = Parser::parse(Lexer::lex(file));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("node".to_string(), vec![]));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("key".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Int(1)));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeStart("sub".to_string(), vec![]));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("other".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::DictStart);
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("inner".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Bool(true)));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::DictEnd);
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileEnd);
+        assert!(parser.next().is_none());
+    }
+
+    #[test]
+    fn handle_node_labels() {
+        let file = Cursor::new("backend \"api\" 'eu-west' { sub \"a\" {} }".as_bytes());
+        let mut parser = Parser::parse(Lexer::lex(file));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
+        assert_eq!(
+            parser.next().unwrap().unwrap().0,
+            ParseEvent::NodeStart("backend".to_string(), vec!["api".to_string(), "eu-west".to_string()]));
+        assert_eq!(
+            parser.next().unwrap().unwrap().0,
+            ParseEvent::NodeStart("sub".to_string(), vec!["a".to_string()]));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileEnd);
+
+        // labels must be strings
+        let file = Cursor::new("backend api {}".as_bytes());
+        let mut parser = Parser::parse(Lexer::lex(file));
+        assert_eq!(
+            parser.nth(1).unwrap().unwrap_err().0,
+            ParseError::UnexpectedToken(LexToken::Identifier("api".to_string())));
+    }
+
+    #[test]
+    fn handle_anchors_and_references() {
+        let file = Cursor::new("&base node { 'a': &v [*v], 'b': ${node.a} } copy 'x' *base other ${node}"
+            .as_bytes());
+        let mut parser = Parser::parse(Lexer::lex(file));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileStart);
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Anchor("base".to_string()));
+        assert_eq!(
+            parser.next().unwrap().unwrap().0,
+            ParseEvent::NodeStart("node".to_string(), vec![]));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("a".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Anchor("v".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::ListStart);
+        assert_eq!(
+            parser.next().unwrap().unwrap().0,
+            ParseEvent::Value(ParsedValue::Alias("v".to_string())));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::ListEnd);
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("b".to_string()));
+        assert_eq!(
+            parser.next().unwrap().unwrap().0,
+            ParseEvent::Value(ParsedValue::Reference("node.a".to_string())));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
+        assert_eq!(
+            parser.next().unwrap().unwrap().0,
+            ParseEvent::NodeReference(
+                "copy".to_string(), vec!["x".to_string()], ParsedValue::Alias("base".to_string())));
+        assert_eq!(
+            parser.next().unwrap().unwrap().0,
+            ParseEvent::NodeReference(
+                "other".to_string(), vec![], ParsedValue::Reference("node".to_string())));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileEnd);
+
+        // anchors can only be followed by a node or a value
+        let file = Cursor::new("node { &a 'key': 1 }".as_bytes());
+        let mut parser = Parser::parse(Lexer::lex(file));
+        assert_eq!(
+            parser.nth(3).unwrap().unwrap_err(),
+            (ParseError::UnexpectedToken(LexToken::StringLit("key".to_string())), Position::at(0, 10)));
+    }
+
+    #[test]
+    fn handle_interpolated_strings() {
+        let file = Cursor::new("node { 'a': 'x' '${y}' r/${z}/ 'w', 'b': 'x' r/y/ }".as_bytes());
+        let mut parser = Parser::parse(Lexer::lex(file));
+        assert_eq!(
+            parser.nth(3).unwrap().unwrap().0,
+            ParseEvent::Value(ParsedValue::Interpolated(vec![
+                StringPart::Text("x".to_string()),
+                StringPart::Reference("y".to_string(), Position::at(0, 17)),
+                StringPart::Text("${z}w".to_string()),
+            ])));
+        assert_eq!(
+            parser.nth(1).unwrap().unwrap().0,
+            ParseEvent::Value(ParsedValue::Str("xy".to_string())));
+    }
+
+    #[test]
+    fn handle_inheritance() {
+        let file = Cursor::new(
+            "abstract base {} a : base { b 'x' : a.c[\"l\"][1] {} } 'k': {v: 1}".as_bytes());
+        let mut parser = Parser::parse(Lexer::lex(file));
+        assert_eq!(parser.nth(1).unwrap().unwrap().0, ParseEvent::Abstract);
+        assert_eq!(
+            parser.next().unwrap().unwrap().0,
+            ParseEvent::NodeStart("base".to_string(), vec![]));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
+        assert_eq!(
+            parser.next().unwrap().unwrap().0,
+            ParseEvent::NodeExtends("a".to_string(), vec![], Path::parse("base").unwrap()));
+        assert_eq!(
+            parser.next().unwrap().unwrap().0,
+            ParseEvent::NodeExtends(
+                "b".to_string(), vec!["x".to_string()], Path::parse("a.c[\"l\"][1]").unwrap()));
+        assert_eq!(parser.nth(2).unwrap().unwrap().0, ParseEvent::Key("k".to_string()));
+        assert_eq!(parser.nth(1).unwrap().unwrap().0, ParseEvent::Key("v".to_string()));
+
+        // keys in dicts never start nodes
+        let file = Cursor::new("'k': {v: w}".as_bytes());
+        let mut parser = Parser::parse(Lexer::lex(file));
+        assert_eq!(
+            parser.nth(4).unwrap().unwrap_err().0,
+            ParseError::UnexpectedToken(LexToken::Identifier("w".to_string())));
+
+        // only nodes can be abstract
+        let file = Cursor::new("abstract key: 1".as_bytes());
+        let mut parser = Parser::parse(Lexer::lex(file));
+        assert_eq!(parser.nth(1).unwrap().unwrap().0, ParseEvent::Abstract);
+        assert_eq!(
+            parser.next().unwrap().unwrap_err().0,
+            ParseError::UnexpectedToken(LexToken::Colon));
+    }
+
+    #[test]
+    fn handle_conditions() {
+        let file = Cursor::new(
+            "when(a == 'x' || !(b != 'y') && c == 'z') { k: 1 } n { when (p == 'q') {} }"
+            .as_bytes());
+        let mut parser = Parser::parse(Lexer::lex(file));
+        let equals = |name: &str, value: &str| Condition::Equals(name.to_string(), value.to_string());
+        let expected = Condition::Or(
+            Box::new(equals("a", "x")),
+            Box::new(Condition::And(
+                Box::new(Condition::Not(
+                    Box::new(Condition::NotEquals("b".to_string(), "y".to_string())))),
+                Box::new(equals("c", "z")))));
+        assert_eq!(
+            parser.nth(1).unwrap().unwrap(),
+            (ParseEvent::When(expected), Position::at(0, 0)));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("k".to_string()));
+        assert_eq!(parser.nth(1).unwrap().unwrap().0, ParseEvent::WhenEnd);
+        assert_eq!(
+            parser.nth(1).unwrap().unwrap(),
+            (ParseEvent::When(equals("p", "q")), Position::at(0, 55)));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::WhenEnd);
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::NodeEnd);
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileEnd);
+
+        // `when` is still a valid node name
+        let file = Cursor::new("when {}".as_bytes());
+        let mut parser = Parser::parse(Lexer::lex(file));
+        assert_eq!(
+            parser.nth(1).unwrap().unwrap().0,
+            ParseEvent::NodeStart("when".to_string(), vec![]));
+
+        let error = |source: &'static str| {
+            let mut parser = Parser::parse(Lexer::lex(Cursor::new(source.as_bytes())));
+            parser.find(|result| result.is_err()).unwrap().unwrap_err()
+        };
+        assert_eq!(
+            error("when(a 'x') {}"),
+            (ParseError::UnexpectedToken(LexToken::StringLit("x".to_string())), Position::at(0, 7)));
+        assert_eq!(
+            error("when(a == b) {}"),
+            (ParseError::UnexpectedToken(LexToken::Identifier("b".to_string())), Position::at(0, 10)));
+        assert_eq!(
+            error("when(a == 'b' {}"),
+            (ParseError::UnexpectedToken(LexToken::OpenBrace), Position::at(0, 14)));
+        assert_eq!(
+            error("when(a == 'b') 'k': 1"),
+            (ParseError::UnexpectedToken(LexToken::StringLit("k".to_string())), Position::at(0, 15)));
+        assert_eq!(
+            error("&a when(a == 'b') {}"),
+            (ParseError::UnexpectedToken(LexToken::OpenParen), Position::at(0, 7)));
+    }
+
+    #[test]
+    fn handle_annotations() {
+        let file = Cursor::new(
+            "@secret 'k': 1 @doc('a', 2, true) @empty() &a n { @x k2: 2 }".as_bytes());
+        let mut parser = Parser::parse(Lexer::lex(file));
+        assert_eq!(
+            parser.nth(1).unwrap().unwrap(),
+            (ParseEvent::Annotation("secret".to_string(), vec![]), Position::at(0, 0)));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("k".to_string()));
+        assert_eq!(
+            parser.nth(1).unwrap().unwrap(),
+            (ParseEvent::Annotation("doc".to_string(), vec![
+                ParsedValue::Str("a".to_string()), ParsedValue::Int(2), ParsedValue::Bool(true),
+            ]), Position::at(0, 15)));
+        assert_eq!(
+            parser.next().unwrap().unwrap().0,
+            ParseEvent::Annotation("empty".to_string(), vec![]));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Anchor("a".to_string()));
+        assert_eq!(
+            parser.next().unwrap().unwrap().0,
+            ParseEvent::NodeStart("n".to_string(), vec![]));
+        assert_eq!(
+            parser.next().unwrap().unwrap().0,
+            ParseEvent::Annotation("x".to_string(), vec![]));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Key("k2".to_string()));
+
+        let error = |source: &'static str| {
+            let mut parser = Parser::parse(Lexer::lex(Cursor::new(source.as_bytes())));
+            parser.find(|result| result.is_err()).unwrap().unwrap_err()
+        };
+        assert_eq!(
+            error("n { @secret }"),
+            (ParseError::UnexpectedToken(LexToken::CloseBrace), Position::at(0, 12)));
+        assert_eq!(error("@secret"), (ParseError::UnexpectedEndOfFile, Position::at(0, 7)));
+        assert_eq!(
+            error("@doc(a) 'k': 1"),
+            (ParseError::UnexpectedToken(LexToken::Identifier("a".to_string())), Position::at(0, 5)));
+        assert_eq!(
+            error("@doc('a' 'b') 'k': 1"),
+            (ParseError::UnexpectedToken(LexToken::StringLit("b".to_string())), Position::at(0, 9)));
+        assert_eq!(
+            error("&a @doc n {}"),
+            (ParseError::UnexpectedToken(LexToken::At), Position::at(0, 3)));
+        assert_eq!(
+            error("@doc when(a == 'b') {}"),
+            (ParseError::UnexpectedToken(LexToken::OpenParen), Position::at(0, 9)));
+    }
+
+    #[test]
+    fn handle_tags() {
+        let file = Cursor::new("'a': !t 'x', 'b': [!i, !t [1]], 'c': !i".as_bytes());
+        let mut parser = Parser::parse(Lexer::lex(file));
+        assert_eq!(parser.nth(2).unwrap().unwrap(), (ParseEvent::Tag("t".to_string()), Position::at(0, 5)));
+        assert_eq!(
+            parser.next().unwrap().unwrap().0,
+            ParseEvent::Value(ParsedValue::Str("x".to_string())));
+        assert_eq!(parser.nth(2).unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Ident("i".to_string())));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Tag("t".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::ListStart);
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Int(1)));
+        assert_eq!(parser.nth(2).unwrap().unwrap().0, ParseEvent::Key("c".to_string()));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::Value(ParsedValue::Ident("i".to_string())));
+        assert_eq!(parser.next().unwrap().unwrap().0, ParseEvent::FileEnd);
+    }
+
+    #[test]
+    fn enforce_key_style() {
+        let file = Cursor::new("node {\n  'quoted': 1,\n  bare: 2 }".as_bytes());
+        let mut parser = Parser::parse(Lexer::lex(file));
+        parser.key_style = KeyStyle::Quoted;
+        assert_eq!(parser.nth(2).unwrap().unwrap().0, ParseEvent::Key("quoted".to_string()));
+        assert_eq!(
+            parser.nth(1).unwrap().unwrap_err(),
+            (ParseError::BareKey("bare".to_string()), Position::at(2, 2)));
+
+        let file = Cursor::new("node { bare: 1, 'has space': 2, 'quoted': 3 }".as_bytes());
+        let mut parser = Parser::parse(Lexer::lex(file));
+        parser.key_style = KeyStyle::Bare;
+        assert_eq!(parser.nth(2).unwrap().unwrap().0, ParseEvent::Key("bare".to_string()));
+        assert_eq!(parser.nth(1).unwrap().unwrap().0, ParseEvent::Key("has space".to_string()));
+        assert_eq!(
+            parser.nth(1).unwrap().unwrap_err(),
+            (ParseError::QuotedKey("quoted".to_string()), Position::at(0, 32)));
+    }
 }
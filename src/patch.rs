@@ -0,0 +1,808 @@
+//! Patches that describe changes to a document.
+//!
+//! A patch is an ordered list of operations, modelled on JSON Patch (RFC 6902), that
+//! are applied to a document in turn.  Each operation is addressed by a `Path`:
+//!
+//! - `add` inserts a node or value.  Adding to a node or dict replaces any existing
+//!   entry with the same name, and adding to a list inserts before the given index, or
+//!   at the end if the index is the length of the list.
+//! - `remove` removes a node or value.
+//! - `replace` replaces an existing node or value, keeping the annotations of an
+//!   attribute.
+//! - `move` removes a node or value from one path and adds it at another.
+//! - `test` checks that the node or value at a path is equal to the given one.
+//!
+//! `Document::apply_patch` applies a patch atomically: if any operation fails,
+//! including a `test`, the document is left unchanged.
+//!
+//! Patches are written as a list of dicts, either as the `operations` attribute of a
+//! `patch` node in a figtree document, or as a bare list, in which case the syntax is
+//! the same as JSON Patch:
+//!
+//! ```text
+//! patch {
+//!     "operations": [
+//!         {"op": "test", "path": "server.env", "value": "staging"},
+//!         {"op": "replace", "path": "server.port", "value": 8080},
+//!         {"op": "add", "path": "server.hosts[0]", "value": "localhost"},
+//!         {"op": "move", "from": "server.old_name", "path": "server.name"},
+//!         {"op": "add", "path": "tls", "node": {"attrs": {"cert": "a.pem"}}},
+//!         {"op": "remove", "path": "debug"},
+//!     ],
+//! }
+//! ```
+//!
+//! Nodes are given using the `node` key rather than `value`, as a dict with optional
+//! `attrs` and `nodes` dicts and an optional `labels` list.  Repeated nodes are given in
+//! `nodes` as a list of node dicts, and are addressed by index, as in `server[1]`.
+//! Labelled nodes are addressed by their labels, as in `backend["api"]`, and adding or
+//! removing a node only affects the nodes with the same name and labels.
+//!
+//! # Examples
+//! ```
+//! use figtree::Figtree;
+//! use figtree::patch::Patch;
+//!
+//! let mut config = Figtree::from_string("server { 'env': 'staging', 'port': 80 }")
+//!     .parse().ok().expect("invalid document");
+//! let patch = Patch::parse(r#"[
+//!     {"op": "test", "path": "server.env", "value": "staging"},
+//!     {"op": "replace", "path": "server.port", "value": 8080}
+//! ]"#).ok().expect("invalid patch");
+//!
+//! config.apply_patch(&patch).ok().expect("patch failed");
+//! assert_eq!(
+//!     config.get_node("server").and_then(|node| node.get_attr("port")),
+//!     Some(&figtree::Value::new_int(8080)));
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::diff::Change;
+use super::figtree::{Figtree, LoadOptions};
+use super::parser::ParseError;
+use super::path::{Path, PathTarget, Segment};
+use super::position::Position;
+use super::types::*;
+use super::writer;
+
+/// A node or value that is added, replaced, or tested by a patch.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatchValue {
+    Node(Node),
+    Value(Value),
+}
+
+/// A single operation in a `Patch`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    Add(Path, PatchValue),
+    Remove(Path),
+    Replace(Path, PatchValue),
+    Move { from: Path, to: Path },
+    Test(Path, PatchValue),
+}
+
+/// An enum representing an error that occurs while reading or applying a patch.
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+    /// The patch could not be parsed.
+    ParseError(ParseError, Position),
+    /// The operation at the given index is malformed.
+    InvalidOperation(usize, String),
+    /// There is no node or value at the path.
+    NoSuchPath(Path),
+    /// The path does not point somewhere that the node or value can be added, such as a
+    /// list index past the end of the list, or a value at the top level of a document.
+    InvalidTarget(Path),
+    /// A `test` operation did not match.
+    TestFailed(Path),
+}
+
+/// An ordered list of operations to apply to a document.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Patch {
+    operations: Vec<Operation>,
+}
+
+impl Patch {
+    /// Construct a new, empty patch.
+    pub fn new() -> Self {
+        Patch { operations: Vec::new() }
+    }
+
+    /// Append an operation to the patch.
+    pub fn push(&mut self, operation: Operation) {
+        self.operations.push(operation);
+    }
+
+    /// Get the operations in this patch.
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    /// Test if this patch has no operations.
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// Parse a patch from figtree source, or from a bare list of operations.
+    ///
+    /// # Failures
+    /// Returns `PatchError::ParseError` if the source cannot be parsed, and
+    /// `PatchError::InvalidOperation` if it does not describe a valid patch.
+    pub fn parse(source: &str) -> Result<Patch, PatchError> {
+        // the values a patch sets are data, so `${...}` in them is not an interpolation
+        let options = LoadOptions { literal_strings: true, .. LoadOptions::default() };
+        if source.trim_start().starts_with('[') {
+            // wrap the list in a document, on lines of its own so that error positions
+            // only need their line adjusting
+            let wrapped = format!("patch {{ \"operations\":\n{}\n}}", source);
+            let doc = Figtree::from_string(wrapped).parse_with(&options)
+                .map_err(|(err, pos)| PatchError::ParseError(
+                    err, Position::at(pos.line.saturating_sub(1), pos.pos)))?;
+            Patch::from_document(&doc)
+        } else {
+            let doc = Figtree::from_string(source).parse_with(&options)
+                .map_err(|(err, pos)| PatchError::ParseError(err, pos))?;
+            Patch::from_document(&doc)
+        }
+    }
+
+    /// Read a patch from the `operations` attribute of the `patch` node of a document.
+    ///
+    /// # Failures
+    /// Returns `PatchError::InvalidOperation` if the document does not describe a valid
+    /// patch.
+    pub fn from_document(doc: &Document) -> Result<Patch, PatchError> {
+        let operations = doc.get_node("patch")
+            .and_then(|node| node.get_attr("operations"))
+            .ok_or_else(|| PatchError::InvalidOperation(
+                0, "missing `patch` node with an `operations` attribute".to_string()))?;
+        Patch::from_value(operations)
+    }
+
+    /// Read a patch from a list of operation dicts.
+    ///
+    /// # Failures
+    /// Returns `PatchError::InvalidOperation` if the value does not describe a valid
+    /// patch.
+    pub fn from_value(value: &Value) -> Result<Patch, PatchError> {
+        let list = value.get_list().ok_or_else(|| PatchError::InvalidOperation(
+            0, "operations must be a list".to_string()))?;
+
+        let mut patch = Patch::new();
+        for (index, operation) in list.iter().enumerate() {
+            let operation = operation.get_dict()
+                .ok_or_else(|| invalid(index, "operation must be a dict"))
+                .and_then(|dict| read_operation(index, dict))?;
+            patch.push(operation);
+        }
+        Ok(patch)
+    }
+
+    /// Construct a patch that applies a list of changes from `Document::diff`.
+    ///
+    /// Applying the patch from `old.diff(&new)` to `old` produces `new`.
+    pub fn from_diff(changes: &[Change]) -> Patch {
+        let mut patch = Patch::new();
+        let mut index = 0;
+        while index < changes.len() {
+            // removals from the end of a list or a set of repeated nodes must happen last
+            // element first, so that the indices of the remaining removals stay valid
+            let run = changes[index..].iter()
+                .take_while(|change| match **change {
+                    Change::ValueRemoved(ref path, _) | Change::NodeRemoved(ref path, _) =>
+                        is_element(path) && parent(path) == parent(changes[index].path()),
+                    _ => false,
+                })
+                .count();
+            if run > 1 {
+                for change in changes[index..index + run].iter().rev() {
+                    patch.push(Operation::Remove(change.path().clone()));
+                }
+                index += run;
+                continue;
+            }
+
+            patch.push(match changes[index] {
+                Change::NodeAdded(ref path, node) =>
+                    Operation::Add(path.clone(), PatchValue::Node(node.clone())),
+                Change::ValueAdded(ref path, value) =>
+                    Operation::Add(path.clone(), PatchValue::Value(value.clone())),
+                Change::NodeRemoved(ref path, _) | Change::ValueRemoved(ref path, _) =>
+                    Operation::Remove(path.clone()),
+                Change::ValueChanged(ref path, _, value) =>
+                    Operation::Replace(path.clone(), PatchValue::Value(value.clone())),
+            });
+            index += 1;
+        }
+        patch
+    }
+
+    /// Convert this patch to the list of operation dicts that describes it.
+    pub fn to_value(&self) -> Value {
+        let mut list = Vec::new();
+        for operation in &self.operations {
+            let mut dict = HashMap::new();
+            let (op, path, content) = match *operation {
+                Operation::Add(ref path, ref content) => ("add", path, Some(content)),
+                Operation::Remove(ref path) => ("remove", path, None),
+                Operation::Replace(ref path, ref content) => ("replace", path, Some(content)),
+                Operation::Move { ref from, ref to } => {
+                    dict.insert("from".to_string(), Value::new_string(from.to_string()));
+                    ("move", to, None)
+                },
+                Operation::Test(ref path, ref content) => ("test", path, Some(content)),
+            };
+            dict.insert("op".to_string(), Value::new_string(op));
+            dict.insert("path".to_string(), Value::new_string(path.to_string()));
+            match content {
+                Some(PatchValue::Value(value)) => {
+                    dict.insert("value".to_string(), value.clone());
+                },
+                Some(PatchValue::Node(node)) => {
+                    dict.insert("node".to_string(), node_to_value(node));
+                },
+                None => {},
+            }
+            list.push(Value::Dict(dict));
+        }
+        Value::List(list)
+    }
+}
+
+impl fmt::Display for Patch {
+    /// Writes the patch as a figtree document with a `patch` node.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.operations.is_empty() {
+            return f.write_str("patch {\n    \"operations\": [],\n}\n");
+        }
+        f.write_str("patch {\n    \"operations\": [\n")?;
+        if let Value::List(list) = self.to_value() {
+            for operation in &list {
+                writeln!(f, "        {},", writer::value_to_string(operation))?;
+            }
+        }
+        f.write_str("    ],\n}\n")
+    }
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PatchError::ParseError(ref err, ref pos) => write!(f, "{}: {}", pos, err),
+            PatchError::InvalidOperation(index, ref message) =>
+                write!(f, "invalid operation {}: {}", index, message),
+            PatchError::NoSuchPath(ref path) => write!(f, "no such path `{}`", path),
+            PatchError::InvalidTarget(ref path) => write!(f, "cannot add at `{}`", path),
+            PatchError::TestFailed(ref path) => write!(f, "test failed at `{}`", path),
+        }
+    }
+}
+
+impl Document {
+    /// Apply a patch to this document.
+    ///
+    /// The operations are applied in order.  If any of them fails, the document is left
+    /// as it was before the patch was applied.
+    ///
+    /// # Failures
+    /// Returns the error and the index of the operation that failed.
+    pub fn apply_patch(&mut self, patch: &Patch) -> Result<(), (PatchError, usize)> {
+        let mut patched = self.clone();
+        for (index, operation) in patch.operations.iter().enumerate() {
+            apply_operation(&mut patched, operation).map_err(|err| (err, index))?;
+        }
+        *self = patched;
+        Ok(())
+    }
+}
+
+fn invalid(index: usize, message: &str) -> PatchError {
+    PatchError::InvalidOperation(index, message.to_string())
+}
+
+fn read_operation(index: usize, dict: &Dict) -> Result<Operation, PatchError> {
+    let read_path = |key: &str| -> Result<Path, PatchError> {
+        let path = dict.get(key).and_then(|value| value.get_str())
+            .ok_or_else(|| invalid(index, &format!("`{}` must be a string", key)))?;
+        Path::parse(path)
+            .map_err(|err| invalid(index, &format!("invalid path `{}`: {}", path, err)))
+    };
+    let read_content = || -> Result<PatchValue, PatchError> {
+        match (dict.get("value"), dict.get("node")) {
+            (Some(value), None) => Ok(PatchValue::Value(value.clone())),
+            (None, Some(node)) => value_to_node(node)
+                .map(PatchValue::Node)
+                .ok_or_else(|| invalid(index, "`node` must be a dict of `attrs` and `nodes`")),
+            _ => Err(invalid(index, "exactly one of `value` or `node` is required")),
+        }
+    };
+
+    match dict.get("op").and_then(|op| op.get_str()) {
+        Some("add") => Ok(Operation::Add(read_path("path")?, read_content()?)),
+        Some("remove") => Ok(Operation::Remove(read_path("path")?)),
+        Some("replace") => Ok(Operation::Replace(read_path("path")?, read_content()?)),
+        Some("move") => Ok(Operation::Move { from: read_path("from")?, to: read_path("path")? }),
+        Some("test") => Ok(Operation::Test(read_path("path")?, read_content()?)),
+        Some(op) => Err(invalid(index, &format!("unknown op `{}`", op))),
+        None => Err(invalid(index, "`op` must be a string")),
+    }
+}
+
+fn value_to_node(value: &Value) -> Option<Node> {
+    let dict = value.get_dict()?;
+    let mut node = Node::new();
+    for (key, value) in dict {
+        match key.as_str() {
+            "labels" => {
+                let labels = value.get_list()?.iter()
+                    .map(|label| label.get_str().map(|label| label.to_string()))
+                    .collect::<Option<Vec<String>>>()?;
+                node.set_labels(labels);
+            },
+            "attrs" => for (name, attr) in value.get_dict()? {
+                node.insert_attr(name.clone(), attr.clone());
+            },
+            "nodes" => for (name, subnode) in value.get_dict()? {
+                match *subnode {
+                    Value::List(ref repeated) => for subnode in repeated {
+                        node.push_node(name.clone(), value_to_node(subnode)?);
+                    },
+                    _ => node.push_node(name.clone(), value_to_node(subnode)?),
+                }
+            },
+            _ => { return None; },
+        }
+    }
+    Some(node)
+}
+
+fn node_to_value(node: &Node) -> Value {
+    let mut dict = HashMap::new();
+    if !node.labels().is_empty() {
+        let labels = node.labels().iter().map(|label| Value::new_string(label.as_str()));
+        dict.insert("labels".to_string(), Value::List(labels.collect()));
+    }
+    if node.has_attrs() {
+        let attrs = node.iter_attrs()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        dict.insert("attrs".to_string(), Value::Dict(attrs));
+    }
+    if node.has_nodes() {
+        let mut nodes = HashMap::new();
+        for (name, _) in node.iter_nodes() {
+            let repeated = node.get_nodes(name.as_str());
+            let value = match repeated.len() {
+                1 => node_to_value(&repeated[0]),
+                _ => Value::List(repeated.iter().map(node_to_value).collect()),
+            };
+            nodes.insert(name.clone(), value);
+        }
+        dict.insert("nodes".to_string(), Value::Dict(nodes));
+    }
+    Value::Dict(dict)
+}
+
+fn is_element(path: &Path) -> bool {
+    matches!(path.segments().last(), Some(Segment::Index(_)))
+}
+
+fn parent(path: &Path) -> &[Segment] {
+    let segments = path.segments();
+    &segments[..segments.len().saturating_sub(1)]
+}
+
+const NO_LABELS: &[&str] = &[];
+
+// The container that holds the target of a path.  The top level of the document is
+// treated as a node.
+enum Parent<'a> {
+    Node(&'a mut Node),
+    // a node, and the name and labels of some of its subnodes
+    Nodes(&'a mut Node, &'a str, Vec<&'a str>),
+    Value(&'a mut Value),
+}
+
+fn find_parent<'a>(doc: &'a mut Document, path: &'a Path)
+    -> Result<(Parent<'a>, &'a Segment), PatchError> {
+
+    let (last, parents) = path.segments().split_last()
+        .ok_or_else(|| PatchError::NoSuchPath(path.clone()))?;
+    let mut parent = Parent::Node(doc.as_node_mut());
+    for (index, segment) in parents.iter().enumerate() {
+        let next = parents.get(index + 1).unwrap_or(last);
+        parent = descend(parent, segment, next)
+            .ok_or_else(|| PatchError::NoSuchPath(path.clone()))?;
+    }
+    Ok((parent, last))
+}
+
+// Follows one segment of a path, using the same rules as `Path::lookup` does for names
+// that are not the last in the path.  The segment after it is needed to tell whether
+// an index selects one of a set of repeated nodes.
+fn descend<'a>(parent: Parent<'a>, segment: &'a Segment, next: &Segment)
+    -> Option<Parent<'a>> {
+
+    let selects_nodes = matches!(*next, Segment::Index(_) | Segment::Label(_));
+    match (parent, segment) {
+        (Parent::Node(node), Segment::Name(name)) => match *next {
+            _ if selects_nodes && (node.has_node(name) || !node.has_attr(name)) =>
+                Some(Parent::Nodes(node, name, Vec::new())),
+            _ if node.has_node(name) =>
+                node.get_labelled_node_mut(name.as_str(), NO_LABELS).map(Parent::Node),
+            _ => node.get_attr_mut(name.as_str()).map(Parent::Value),
+        },
+        (Parent::Nodes(node, name, mut labels), Segment::Label(label)) => {
+            labels.push(label);
+            if selects_nodes {
+                Some(Parent::Nodes(node, name, labels))
+            } else {
+                node.get_labelled_node_mut(name, &labels).map(Parent::Node)
+            }
+        },
+        (Parent::Nodes(node, name, labels), &Segment::Index(index)) =>
+            node.get_nodes_mut(name).iter_mut()
+                .filter(|node| node.has_labels(&labels))
+                .nth(index)
+                .map(Parent::Node),
+        (Parent::Value(Value::Dict(dict)), Segment::Name(name)) =>
+            dict.get_mut(name).map(Parent::Value),
+        (Parent::Value(Value::List(list)), &Segment::Index(index)) =>
+            list.get_mut(index).map(Parent::Value),
+        _ => None,
+    }
+}
+
+fn insert(doc: &mut Document, path: &Path, content: PatchValue) -> Result<(), PatchError> {
+    let (parent, last) = find_parent(doc, path)?;
+    match (parent, last, content) {
+        (Parent::Node(parent), Segment::Name(name), PatchValue::Node(node)) => {
+            replace_nodes(parent, name, node);
+        },
+        (Parent::Nodes(parent, name, mut labels), Segment::Label(label),
+         PatchValue::Node(mut node)) => {
+            labels.push(label);
+            node.set_labels(labels);
+            replace_nodes(parent, name, node);
+        },
+        (Parent::Node(parent), Segment::Name(name), PatchValue::Value(value)) => {
+            parent.insert_attr(name.as_str(), value);
+        },
+        (Parent::Nodes(parent, name, labels), &Segment::Index(index),
+         PatchValue::Node(mut node)) => {
+            // insert before the node with the same labels at the index, or after the
+            // last of them
+            let positions = positions(parent, name, &labels);
+            let position = match positions.get(index) {
+                Some(&position) => position,
+                None if index == positions.len() =>
+                    positions.last().map_or(parent.get_nodes(name).len(), |last| last + 1),
+                None => { return Err(PatchError::InvalidTarget(path.clone())); },
+            };
+            node.set_labels(labels);
+            parent.insert_node_at(name, position, node);
+        },
+        (Parent::Value(Value::Dict(dict)), Segment::Name(name), PatchValue::Value(value)) => {
+            dict.insert(name.clone(), value);
+        },
+        (Parent::Value(Value::List(list)), &Segment::Index(index), PatchValue::Value(value))
+            if index <= list.len() => {
+            list.insert(index, value);
+        },
+        _ => { return Err(PatchError::InvalidTarget(path.clone())); },
+    }
+    Ok(())
+}
+
+// Removes the target of a path, preferring attributes over nodes like `Path::lookup`.
+fn take(doc: &mut Document, path: &Path) -> Result<PatchValue, PatchError> {
+    let (parent, last) = find_parent(doc, path)?;
+    let taken = match (parent, last) {
+        (Parent::Node(node), Segment::Name(name)) => node.delete_attr(name.as_str())
+            .map(PatchValue::Value)
+            .or_else(|| remove_nodes(node, name, NO_LABELS).map(PatchValue::Node)),
+        (Parent::Nodes(node, name, mut labels), Segment::Label(label)) => {
+            labels.push(label);
+            remove_nodes(node, name, &labels).map(PatchValue::Node)
+        },
+        (Parent::Nodes(node, name, labels), &Segment::Index(index)) =>
+            positions(node, name, &labels).get(index)
+                .and_then(|&position| node.remove_node_at(name, position))
+                .map(PatchValue::Node),
+        (Parent::Value(Value::Dict(dict)), Segment::Name(name)) =>
+            dict.remove(name).map(PatchValue::Value),
+        (Parent::Value(Value::List(list)), &Segment::Index(index)) if index < list.len() =>
+            Some(PatchValue::Value(list.remove(index))),
+        _ => None,
+    };
+    taken.ok_or_else(|| PatchError::NoSuchPath(path.clone()))
+}
+
+// The positions of the subnodes with the given name and labels among all of the
+// subnodes with that name.
+fn positions(node: &Node, name: &str, labels: &[&str]) -> Vec<usize> {
+    node.iter_nodes_named(name).enumerate()
+        .filter(|&(_, node)| node.has_labels(labels))
+        .map(|(position, _)| position)
+        .collect()
+}
+
+// Replaces the subnodes with the same name and labels as a node, keeping its place
+// among any subnodes with other labels.
+fn replace_nodes(parent: &mut Node, name: &str, node: Node) {
+    let nodes = parent.delete_nodes(name);
+    let position = nodes.iter().position(|other| other.labels() == node.labels())
+        .unwrap_or(nodes.len());
+    let mut kept: Vec<Node> = nodes.into_iter()
+        .filter(|other| other.labels() != node.labels())
+        .collect();
+    kept.insert(position, node);
+    for node in kept {
+        parent.push_node(name, node);
+    }
+}
+
+// Removes the subnodes with the given name and labels, returning the first of them.
+fn remove_nodes(parent: &mut Node, name: &str, labels: &[&str]) -> Option<Node> {
+    let (removed, kept): (Vec<Node>, Vec<Node>) = parent.delete_nodes(name).into_iter()
+        .partition(|node| node.has_labels(labels));
+    for node in kept {
+        parent.push_node(name, node);
+    }
+    removed.into_iter().next()
+}
+
+fn apply_operation(doc: &mut Document, operation: &Operation) -> Result<(), PatchError> {
+    match *operation {
+        Operation::Add(ref path, ref content) => insert(doc, path, content.clone()),
+        Operation::Remove(ref path) => take(doc, path).map(|_| ()),
+        Operation::Replace(ref path, ref content) => {
+            // the annotations of an attribute, such as `@secret`, are kept with its new
+            // value
+            let annotations = match find_parent(doc, path)? {
+                (Parent::Node(node), Segment::Name(name)) =>
+                    node.attr_annotations(name.as_str()).to_vec(),
+                _ => Vec::new(),
+            };
+            take(doc, path)?;
+            insert(doc, path, content.clone())?;
+            if let (Parent::Node(node), Segment::Name(name)) = find_parent(doc, path)? {
+                if node.get_attr(name.as_str()).is_some() {
+                    node.set_attr_annotations(name.as_str(), annotations);
+                }
+            }
+            Ok(())
+        },
+        Operation::Move { ref from, ref to } => {
+            if to.segments().starts_with(from.segments()) && to != from {
+                return Err(PatchError::InvalidTarget(to.clone()));
+            }
+            let content = take(doc, from)?;
+            insert(doc, to, content)
+        },
+        Operation::Test(ref path, ref content) => {
+            let matches = match (path.lookup(doc), content) {
+                (None, _) => { return Err(PatchError::NoSuchPath(path.clone())); },
+                (Some(PathTarget::Node(node)), PatchValue::Node(expected)) => node == expected,
+                (Some(PathTarget::Value(value)), PatchValue::Value(expected)) =>
+                    value == expected,
+                _ => false,
+            };
+            if matches {
+                Ok(())
+            } else {
+                Err(PatchError::TestFailed(path.clone()))
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::figtree::LoadOptions;
+    use super::super::lexer::LexToken;
+
+    fn parse(source: &str) -> Document {
+        Figtree::from_string(source).parse().unwrap()
+    }
+
+    fn path(path: &str) -> Path {
+        Path::parse(path).unwrap()
+    }
+
+    #[test]
+    fn parse_patches() {
+        let patch = Patch::parse("patch { 'operations': [
+            {'op': 'add', 'path': 'a.b[0]', 'value': 1},
+            {'op': 'remove', 'path': 'a.c'},
+            {'op': 'replace', 'path': 'a.d', 'node': {'attrs': {'x': true}, 'nodes': {'e': {}}}},
+            {'op': 'move', 'from': 'a.f', 'path': 'a.g'},
+            {'op': 'test', 'path': 'h', 'node': {}},
+        ] }").unwrap();
+
+        let mut node = Node::new();
+        node.insert_attr("x", Value::new_bool(true));
+        node.new_node_or_get("e");
+        assert_eq!(patch.operations(), &[
+            Operation::Add(path("a.b[0]"), PatchValue::Value(Value::new_int(1))),
+            Operation::Remove(path("a.c")),
+            Operation::Replace(path("a.d"), PatchValue::Node(node)),
+            Operation::Move { from: path("a.f"), to: path("a.g") },
+            Operation::Test(path("h"), PatchValue::Node(Node::new())),
+        ]);
+
+        let json = Patch::parse("[{\"op\": \"remove\", \"path\": \"a\"}]").unwrap();
+        assert_eq!(json.operations(), &[Operation::Remove(path("a"))]);
+
+        // values are data, so they are not interpolated
+        let json = Patch::parse("[{\"op\": \"add\", \"path\": \"a\", \"value\": \"${HOME}/x\"}]");
+        let value = PatchValue::Value(Value::new_string("${HOME}/x"));
+        assert_eq!(json.unwrap().operations(), &[Operation::Add(path("a"), value)]);
+
+        assert_eq!(
+            Patch::parse("[{\"op\": \"remove\"}]"),
+            Err(PatchError::InvalidOperation(0, "`path` must be a string".to_string())));
+        assert_eq!(
+            Patch::parse("[{\"op\": \"remove\", \"path\": \"a\"},\n {\"op\": \"copy\"}]"),
+            Err(PatchError::InvalidOperation(1, "unknown op `copy`".to_string())));
+        assert_eq!(
+            Patch::parse("[{\"op\": \"add\", \"path\": \"a\"}]"),
+            Err(PatchError::InvalidOperation(
+                0, "exactly one of `value` or `node` is required".to_string())));
+        assert_eq!(
+            Patch::parse("[\n{\"op\" \"add\"}]"),
+            Err(PatchError::ParseError(
+                ParseError::UnexpectedToken(LexToken::StringLit("add".to_string())),
+                Position::at(1, 6))));
+    }
+
+    #[test]
+    fn write_patches() {
+        let patch = Patch::parse("[
+            {'op': 'add', 'path': 'a.\"b c\"', 'node': {'attrs': {'x': 1}}},
+            {'op': 'move', 'from': 'a.f', 'path': 'a.g'},
+        ]").unwrap();
+        let written = patch.to_string();
+        assert_eq!(written, concat!(
+            "patch {\n",
+            "    \"operations\": [\n",
+            "        {\"node\": {\"attrs\": {\"x\": 1}}, \"op\": \"add\", \"path\": \"a.\\\"b c\\\"\"},\n",
+            "        {\"from\": \"a.f\", \"op\": \"move\", \"path\": \"a.g\"},\n",
+            "    ],\n",
+            "}\n"));
+        assert_eq!(Patch::parse(&written), Ok(patch));
+        assert_eq!(Patch::parse(&Patch::new().to_string()), Ok(Patch::new()));
+
+        let mut patch = Patch::new();
+        patch.push(Operation::Add(path("a"), PatchValue::Value(Value::new_string("${a} $${b}"))));
+        assert_eq!(Patch::parse(&patch.to_string()), Ok(patch));
+    }
+
+    #[test]
+    fn apply_operations() {
+        let mut doc = parse("
+            a { 'list': [1, 2], 'dict': {'k': 1}, 'x': 'attr', x { 'y': 1 } }
+            b { 'old': true }");
+        let patch = Patch::parse("[
+            {'op': 'add', 'path': 'a.list[1]', 'value': 5},
+            {'op': 'add', 'path': 'a.list[3]', 'value': 6},
+            {'op': 'add', 'path': 'a.dict.j', 'value': 0},
+            {'op': 'replace', 'path': 'a.x', 'value': 'replaced'},
+            {'op': 'remove', 'path': 'a.x.y'},
+            {'op': 'move', 'from': 'b.old', 'path': 'a.new'},
+            {'op': 'add', 'path': 'c', 'node': {'nodes': {'d': {}}}},
+            {'op': 'test', 'path': 'a.new', 'value': true},
+            {'op': 'remove', 'path': 'b'},
+            {'op': 'add', 'path': 'version', 'value': 2},
+        ]").unwrap();
+        doc.apply_patch(&patch).unwrap();
+        assert_eq!(doc, parse("
+            'version': 2
+            a { 'list': [1, 5, 2, 6], 'dict': {'j': 0, 'k': 1}, 'x': 'replaced', 'new': true,
+                x {} }
+            c { d {} }"));
+
+        let mut doc = parse("db { @secret 'password': 'old' }");
+        let patch = Patch::parse("[{'op': 'replace', 'path': 'db.password', 'value': 'new'}]");
+        doc.apply_patch(&patch.unwrap()).unwrap();
+        assert_eq!(doc, parse("db { @secret 'password': 'new' }"));
+        assert!(writer::to_string(&doc).contains("@secret \"password\": \"new\""));
+    }
+
+    #[test]
+    fn failed_patches_are_atomic() {
+        let original = parse("a { 'list': [1], 'env': 'staging' }");
+        let failing = |patch: &str| {
+            let mut doc = original.clone();
+            let result = doc.apply_patch(&Patch::parse(patch).unwrap());
+            assert_eq!(doc, original);
+            result
+        };
+
+        assert_eq!(
+            failing("[{'op': 'remove', 'path': 'a.env'}, {'op': 'test', 'path': 'a.env', 'value': 'prod'}]"),
+            Err((PatchError::NoSuchPath(path("a.env")), 1)));
+        assert_eq!(
+            failing("[{'op': 'remove', 'path': 'a.list'}, {'op': 'test', 'path': 'a', 'node': {}}]"),
+            Err((PatchError::TestFailed(path("a")), 1)));
+        assert_eq!(
+            failing("[{'op': 'test', 'path': 'a.env', 'value': 'prod'}]"),
+            Err((PatchError::TestFailed(path("a.env")), 0)));
+        assert_eq!(
+            failing("[{'op': 'add', 'path': 'a.list[2]', 'value': 1}]"),
+            Err((PatchError::InvalidTarget(path("a.list[2]")), 0)));
+        assert_eq!(
+            failing("[{'op': 'add', 'path': 'a[0]', 'value': 1}]"),
+            Err((PatchError::InvalidTarget(path("a[0]")), 0)));
+        assert_eq!(
+            failing("[{'op': 'add', 'path': 'missing.x', 'value': 1}]"),
+            Err((PatchError::NoSuchPath(path("missing.x")), 0)));
+        assert_eq!(
+            failing("[{'op': 'move', 'from': 'a', 'path': 'a.b'}]"),
+            Err((PatchError::InvalidTarget(path("a.b")), 0)));
+    }
+
+    #[test]
+    fn patches_from_diffs() {
+        let old = parse("a { 'list': [1, 2, 3, 4], 'd': {'k': 1}, gone {} } b {}");
+        let new = parse("a { 'list': [0, 2], 'd': {'j': 1}, 'n': null, added { 'x': 1 } }");
+        let patch = Patch::from_diff(&old.diff(&new));
+
+        let mut patched = old.clone();
+        patched.apply_patch(&patch).unwrap();
+        assert_eq!(patched, new);
+    }
+
+    #[test]
+    fn repeated_nodes() {
+        let options = LoadOptions { repeated_nodes: true, .. LoadOptions::default() };
+        let parse = |source: &str| Figtree::from_string(source).parse_with(&options).unwrap();
+
+        let mut doc = parse("a { 'x': 1 } a { 'x': 2 } a { 'x': 3 }");
+        let patch = Patch::parse("[
+            {'op': 'replace', 'path': 'a[1].x', 'value': 20},
+            {'op': 'remove', 'path': 'a[2]'},
+            {'op': 'add', 'path': 'a[0]', 'node': {'attrs': {'x': 0}}},
+            {'op': 'add', 'path': 'b', 'node': {'nodes': {'c': [{}, {'attrs': {'y': 1}}]}}},
+        ]").unwrap();
+        doc.apply_patch(&patch).unwrap();
+        assert_eq!(doc, parse("a { 'x': 0 } a { 'x': 1 } a { 'x': 20 } b { c {} c { 'y': 1 } }"));
+        assert_eq!(Patch::parse(&patch.to_string()).unwrap(), patch);
+
+        let old = parse("a { 'x': 1 } a {} a {} a {} b {}");
+        let new = parse("a { 'x': 2 } b {} b { 'y': 1 } c {} c {}");
+        for &(old, new) in &[(&old, &new), (&new, &old)] {
+            let mut patched = old.clone();
+            patched.apply_patch(&Patch::from_diff(&old.diff(new))).unwrap();
+            assert_eq!(&patched, new);
+        }
+    }
+
+    #[test]
+    fn labelled_nodes() {
+        let mut doc = parse("b 'x' { 'p': 1 } b 'y' {} b {}");
+        let patch = Patch::parse(r#"[
+            {"op": "replace", "path": "b[\"x\"].p", "value": 2},
+            {"op": "remove", "path": "b"},
+            {"op": "add", "path": "b[\"z\"]", "node": {}},
+            {"op": "add", "path": "c", "node": {"nodes": {"d": [{"labels": ["1"]}, {"labels": ["2"]}]}}},
+            {"op": "test", "path": "b[\"y\"]", "node": {"labels": ["y"]}},
+        ]"#).unwrap();
+        doc.apply_patch(&patch).unwrap();
+        assert_eq!(doc, parse("b 'x' { 'p': 2 } b 'y' {} b 'z' {} c { d '1' {} d '2' {} }"));
+        assert_eq!(Patch::parse(&patch.to_string()).unwrap(), patch);
+
+        let old = parse("b 'x' { 'p': 1 } b 'y' {} b {}");
+        let new = parse("b 'y' { 'q': 1 } b 'z' {}");
+        for &(old, new) in &[(&old, &new), (&new, &old)] {
+            let mut patched = old.clone();
+            patched.apply_patch(&Patch::from_diff(&old.diff(new))).unwrap();
+            assert_eq!(&patched, new);
+        }
+    }
+}
@@ -0,0 +1,452 @@
+//! Paths that address nodes and values within a document.
+//!
+//! A path is a series of names separated by dots, optionally followed by list indices
+//! in square brackets.  Names that are not valid identifiers can be quoted using either
+//! double quotes or backticks.
+//!
+//! ```text
+//! server.http.port
+//! server.allowed_hosts[0]
+//! backend["api"].port
+//! test.subtest.dict."an identifier"
+//! ```
+//!
+//! When a path is looked up in a document, each name is first matched against the
+//! subnodes of the current node, and then against its attributes.  The final name in a
+//! path is matched the other way around, so that a path will select an attribute over a
+//! node of the same name.  Once a path has selected a value, any further names select
+//! keys from dicts, and indices select items from lists.  An index directly after the
+//! name of a node selects one of a set of repeated nodes, so `server[1].port` is the
+//! port of the second `server` node.  Quoted strings in square brackets after the name
+//! of a node select a node by its labels, so `backend["api"]["eu-west"]` selects the
+//! node written as `backend "api" "eu-west" {}`.  A name without labels only selects
+//! nodes without labels, and an index can follow the labels to select one of several
+//! repeated nodes with the same labels.  The top level of a
+//! document is treated as a node, so a path with a single name can select either a
+//! node or a top-level attribute.
+//!
+//! # Examples
+//! ```
+//! use figtree::{Figtree, Path, PathTarget};
+//! use figtree::types::Value;
+//!
+//! let mut figgy = Figtree::from_string("server { 'ports': [80, 443] }");
+//! let config = figgy.parse().ok().expect("invalid document");
+//! let path = Path::parse("server.ports[1]").ok().expect("invalid path");
+//! match path.lookup(&config) {
+//!     Some(PathTarget::Value(value)) => assert_eq!(value, &Value::new_int(443)),
+//!     _ => panic!("value not found"),
+//! }
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::types::*;
+use super::writer;
+
+/// A single step in a `Path`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Segment {
+    Name(String),
+    Index(usize),
+    Label(String),
+}
+
+/// An enum representing an error that occurs while parsing a path.
+#[derive(Debug, PartialEq)]
+pub enum PathError {
+    Empty,
+    EmptySegment(usize),
+    UnclosedQuote(usize),
+    InvalidIndex(usize),
+    UnexpectedChar(char, usize),
+}
+
+/// A path to a node or value within a document.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Path {
+    segments: Vec<Segment>,
+}
+
+/// The node or value that a `Path` points to.
+#[derive(Debug, PartialEq)]
+pub enum PathTarget<'a> {
+    Node(&'a Node),
+    Value(&'a Value),
+}
+
+impl Path {
+    /// Construct a new, empty path.
+    pub fn new() -> Self {
+        Path { segments: Vec::new() }
+    }
+
+    /// Parse a path from its textual form.
+    ///
+    /// # Failures
+    /// Returns a `PathError` if the path is empty or malformed.  Errors that refer to
+    /// a particular part of the path contain the character offset of that part.
+    pub fn parse(input: &str) -> Result<Path, PathError> {
+        let chars: Vec<char> = input.chars().collect();
+        let mut segments = Vec::new();
+        let mut index = 0;
+
+        if chars.is_empty() {
+            return Err(PathError::Empty);
+        }
+
+        loop {
+            // a name, either bare or quoted
+            let start = index;
+            let name = match chars.get(index) {
+                Some(&quote) if quote == '"' || quote == '`' => parse_quoted(&chars, &mut index)?,
+                _ => {
+                    let mut name = String::new();
+                    while let Some(&ch) = chars.get(index) {
+                        if ch == '.' || ch == '[' {
+                            break;
+                        }
+                        name.push(ch);
+                        index += 1;
+                    }
+                    if name.is_empty() {
+                        return Err(PathError::EmptySegment(start));
+                    }
+                    name
+                },
+            };
+            segments.push(Segment::Name(name));
+
+            // any number of indices and labels
+            while chars.get(index) == Some(&'[') {
+                let start = index;
+                let mut digits = String::new();
+                index += 1;
+                if matches!(chars.get(index), Some(&'"') | Some(&'`')) {
+                    let label = parse_quoted(&chars, &mut index)?;
+                    match chars.get(index) {
+                        Some(&']') => { index += 1; },
+                        Some(&ch) => { return Err(PathError::UnexpectedChar(ch, index)); },
+                        None => { return Err(PathError::InvalidIndex(start)); },
+                    }
+                    segments.push(Segment::Label(label));
+                    continue;
+                }
+                while let Some(&ch) = chars.get(index) {
+                    if ch == ']' {
+                        break;
+                    }
+                    digits.push(ch);
+                    index += 1;
+                }
+                if chars.get(index) != Some(&']') {
+                    return Err(PathError::InvalidIndex(start));
+                }
+                index += 1;
+                match digits.trim().parse::<usize>() {
+                    Ok(n) => segments.push(Segment::Index(n)),
+                    Err(_) => { return Err(PathError::InvalidIndex(start)); },
+                }
+            }
+
+            match chars.get(index) {
+                None => break,
+                Some(&'.') => { index += 1; },
+                Some(&ch) => { return Err(PathError::UnexpectedChar(ch, index)); },
+            }
+        }
+
+        Ok(Path { segments })
+    }
+
+    /// Get the segments that make up this path.
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Test if this path has no segments.
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Append a name to the end of this path.
+    pub fn push_name<S>(&mut self, name: S) where S: Into<String> {
+        self.segments.push(Segment::Name(name.into()));
+    }
+
+    /// Append a list index to the end of this path.
+    pub fn push_index(&mut self, index: usize) {
+        self.segments.push(Segment::Index(index));
+    }
+
+    /// Construct a new path with a name appended to this one.
+    pub fn child<S>(&self, name: S) -> Path where S: Into<String> {
+        let mut path = self.clone();
+        path.push_name(name);
+        path
+    }
+
+    /// Construct a new path with a list index appended to this one.
+    pub fn index(&self, index: usize) -> Path {
+        let mut path = self.clone();
+        path.push_index(index);
+        path
+    }
+
+    /// Append a node label to the end of this path.
+    pub fn push_label<S>(&mut self, label: S) where S: Into<String> {
+        self.segments.push(Segment::Label(label.into()));
+    }
+
+    /// Construct a new path with a node label appended to this one.
+    pub fn label<S>(&self, label: S) -> Path where S: Into<String> {
+        let mut path = self.clone();
+        path.push_label(label);
+        path
+    }
+
+    /// Construct the path to a subnode of the node at this path, selecting it by its
+    /// name and labels.
+    pub(crate) fn node_child(&self, name: &str, labels: &[String]) -> Path {
+        let mut path = self.child(name);
+        for label in labels {
+            path.push_label(label.as_str());
+        }
+        path
+    }
+
+    /// Find the node or value this path points to in a document.
+    pub fn lookup<'a>(&self, doc: &'a Document) -> Option<PathTarget<'a>> {
+        if self.segments.is_empty() {
+            return None;
+        }
+        lookup_in_node(doc.as_node(), &self.segments)
+    }
+
+    /// Find the value this path points to inside of another value, where an empty path
+    /// points to the value itself.
+    pub(crate) fn lookup_in<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        match lookup_in_value(value, &self.segments) {
+            Some(PathTarget::Value(value)) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+// Parses a name or label quoted with the quote character at `index`, leaving `index`
+// after the closing quote.
+fn parse_quoted(chars: &[char], index: &mut usize) -> Result<String, PathError> {
+    let start = *index;
+    let quote = chars[start];
+    let mut name = String::new();
+    *index += 1;
+    loop {
+        match chars.get(*index) {
+            Some(&'\\') if *index + 1 < chars.len() => {
+                name.push(chars[*index + 1]);
+                *index += 2;
+            },
+            Some(&ch) if ch == quote => {
+                *index += 1;
+                return Ok(name);
+            },
+            Some(&ch) => {
+                name.push(ch);
+                *index += 1;
+            },
+            None => { return Err(PathError::UnclosedQuote(start)); },
+        }
+    }
+}
+
+fn lookup_in_node<'a>(node: &'a Node, segments: &[Segment]) -> Option<PathTarget<'a>> {
+    let (name, rest) = match segments.split_first() {
+        None => { return Some(PathTarget::Node(node)); },
+        Some((Segment::Name(name), rest)) => (name, rest),
+        Some(_) => { return None; },
+    };
+
+    let as_node = || {
+        // labels after the name of a node select the nodes with exactly those labels,
+        // or no labels if there are none, and an index then selects one of them
+        let labels: Vec<&String> = rest.iter()
+            .map_while(|segment| match segment {
+                Segment::Label(label) => Some(label),
+                _ => None,
+            })
+            .collect();
+        let rest = &rest[labels.len()..];
+        let mut nodes = node.iter_nodes_named(name.as_str())
+            .filter(|node| node.has_labels(&labels));
+        match rest.split_first() {
+            Some((&Segment::Index(index), rest)) if node.has_node(name) =>
+                nodes.nth(index).and_then(|node| lookup_in_node(node, rest)),
+            _ => nodes.next().and_then(|node| lookup_in_node(node, rest)),
+        }
+    };
+    let as_attr = || node.get_attr(name.as_str())
+        .and_then(|value| lookup_in_value(value, rest));
+
+    if rest.is_empty() {
+        as_attr().or_else(as_node)
+    } else {
+        as_node().or_else(as_attr)
+    }
+}
+
+fn lookup_in_value<'a>(value: &'a Value, segments: &[Segment]) -> Option<PathTarget<'a>> {
+    match (segments.split_first(), value) {
+        (None, value) =>
+            Some(PathTarget::Value(value)),
+        (Some((Segment::Name(name), rest)), Value::Dict(dict)) =>
+            dict.get(name).and_then(|value| lookup_in_value(value, rest)),
+        (Some((&Segment::Index(index), rest)), Value::List(list)) =>
+            list.get(index).and_then(|value| lookup_in_value(value, rest)),
+        _ => None,
+    }
+}
+
+impl FromStr for Path {
+    type Err = PathError;
+
+    fn from_str(s: &str) -> Result<Path, PathError> {
+        Path::parse(s)
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, segment) in self.segments.iter().enumerate() {
+            match *segment {
+                Segment::Name(ref name) => {
+                    if index > 0 {
+                        f.write_str(".")?;
+                    }
+                    if writer::is_bare_ident(name) {
+                        f.write_str(name)?;
+                    } else {
+                        let mut quoted = String::new();
+                        writer::write_string(name, &mut quoted);
+                        f.write_str(&quoted)?;
+                    }
+                },
+                Segment::Index(n) => write!(f, "[{}]", n)?,
+                Segment::Label(ref label) => {
+                    let mut quoted = String::new();
+                    writer::write_string(label, &mut quoted);
+                    write!(f, "[{}]", quoted)?;
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PathError::Empty => write!(f, "empty path"),
+            PathError::EmptySegment(pos) => write!(f, "empty name at offset {}", pos),
+            PathError::UnclosedQuote(pos) => write!(f, "unclosed quote at offset {}", pos),
+            PathError::InvalidIndex(pos) => write!(f, "invalid index at offset {}", pos),
+            PathError::UnexpectedChar(ch, pos) =>
+                write!(f, "unexpected character {:?} at offset {}", ch, pos),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::figtree::{Figtree, LoadOptions};
+
+    #[test]
+    fn parse_paths() {
+        assert_eq!(Path::parse("a.b").unwrap().segments(), &[
+            Segment::Name("a".to_string()), Segment::Name("b".to_string())]);
+        assert_eq!(Path::parse("a[1][2].`b.c`.\"d\\\"\"").unwrap().segments(), &[
+            Segment::Name("a".to_string()), Segment::Index(1), Segment::Index(2),
+            Segment::Name("b.c".to_string()), Segment::Name("d\"".to_string())]);
+
+        assert_eq!(Path::parse(""), Err(PathError::Empty));
+        assert_eq!(Path::parse("a..b"), Err(PathError::EmptySegment(2)));
+        assert_eq!(Path::parse("a.\"b"), Err(PathError::UnclosedQuote(2)));
+        assert_eq!(Path::parse("a[x]"), Err(PathError::InvalidIndex(1)));
+        assert_eq!(Path::parse("a[1"), Err(PathError::InvalidIndex(1)));
+        assert_eq!(Path::parse("`a`b"), Err(PathError::UnexpectedChar('b', 3)));
+    }
+
+    #[test]
+    fn display_paths() {
+        let path = Path::parse("a[1].`b c`.d").unwrap();
+        assert_eq!(path.to_string(), "a[1].\"b c\".d");
+        assert_eq!(Path::new().child("x").index(0).child("y").to_string(), "x[0].y");
+    }
+
+    #[test]
+    fn lookup_paths() {
+        let mut figgy = Figtree::from_string("
+            'version': 2,
+            node {
+                'list': [1, {'key': 'two'}],
+                'both': 'attr',
+                both { 'inner': true }
+            }");
+        let doc = figgy.parse().unwrap();
+        let lookup = |path: &str| Path::parse(path).unwrap().lookup(&doc);
+
+        assert_eq!(lookup("node.list[0]"), Some(PathTarget::Value(&Value::new_int(1))));
+        assert_eq!(
+            lookup("node.list[1].key"),
+            Some(PathTarget::Value(&Value::new_string("two"))));
+        assert_eq!(lookup("node.both"), Some(PathTarget::Value(&Value::new_string("attr"))));
+        assert_eq!(
+            lookup("node.both.inner"),
+            Some(PathTarget::Value(&Value::new_bool(true))));
+        assert!(matches!(lookup("node"), Some(PathTarget::Node(_))));
+        assert_eq!(lookup("node.list[2]"), None);
+        assert_eq!(lookup("node.missing"), None);
+        assert_eq!(lookup("missing"), None);
+        assert_eq!(lookup("version"), Some(PathTarget::Value(&Value::new_int(2))));
+    }
+
+    #[test]
+    fn lookup_repeated_nodes() {
+        let options = LoadOptions { repeated_nodes: true, .. LoadOptions::default() };
+        let doc = Figtree::from_string("a { 'x': 1 } a { 'x': 2 } b { 'l': [3] }")
+            .parse_with(&options).unwrap();
+        let lookup = |path: &str| Path::parse(path).unwrap().lookup(&doc);
+
+        assert_eq!(lookup("a.x"), Some(PathTarget::Value(&Value::new_int(1))));
+        assert_eq!(lookup("a[1].x"), Some(PathTarget::Value(&Value::new_int(2))));
+        assert_eq!(lookup("a[1]"), Some(PathTarget::Node(&doc.get_nodes("a")[1])));
+        assert_eq!(lookup("a[2]"), None);
+        assert_eq!(lookup("b[0].l"), Some(PathTarget::Value(&Value::List(vec![Value::new_int(3)]))));
+        assert_eq!(lookup("b.l[0]"), Some(PathTarget::Value(&Value::new_int(3))));
+    }
+
+    #[test]
+    fn lookup_labelled_nodes() {
+        let path = Path::parse("a[\"x\"][`y z`][1].b").unwrap();
+        assert_eq!(path.segments(), &[
+            Segment::Name("a".to_string()), Segment::Label("x".to_string()),
+            Segment::Label("y z".to_string()), Segment::Index(1), Segment::Name("b".to_string())]);
+        assert_eq!(path.to_string(), "a[\"x\"][\"y z\"][1].b");
+        assert_eq!(Path::parse("a[\"x\"y]"), Err(PathError::UnexpectedChar('y', 5)));
+        assert_eq!(Path::parse("a[\"x\""), Err(PathError::InvalidIndex(1)));
+
+        let doc = Figtree::from_string("
+            backend 'api' 'eu' { 'port': 1 }
+            backend 'api' { 'port': 2 }
+            backend { 'port': 3 }
+        ").parse().unwrap();
+        let lookup = |path: &str| Path::parse(path).unwrap().lookup(&doc);
+
+        assert_eq!(lookup("backend[\"api\"][\"eu\"].port"), Some(PathTarget::Value(&Value::new_int(1))));
+        assert_eq!(lookup("backend[\"api\"].port"), Some(PathTarget::Value(&Value::new_int(2))));
+        assert_eq!(lookup("backend.port"), Some(PathTarget::Value(&Value::new_int(3))));
+        assert_eq!(lookup("backend[\"api\"][0].port"), Some(PathTarget::Value(&Value::new_int(2))));
+        assert_eq!(lookup("backend[\"eu\"]"), None);
+    }
+}
@@ -1,5 +1,7 @@
+use std::fmt;
+
 /// Represents a position in the file
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Position {
     /// line of the file
     pub line: usize,
@@ -19,6 +21,13 @@ impl Position {
     }
 }
 
+impl fmt::Display for Position {
+    /// Displays the position as `line:pos`, counting both from one rather than zero.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line + 1, self.pos + 1)
+    }
+}
+
 #[derive(Debug)]
 pub struct MutablePosition {
     pub line: usize,
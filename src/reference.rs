@@ -0,0 +1,530 @@
+//! Resolution of anchors, aliases and references.
+//!
+//! An anchor (`&name`) names the node or value that follows it, and an alias (`*name`)
+//! stands for a copy of the anchored node or value.  A reference (`${path}`) stands for
+//! a copy of whatever the path points to in the document.  While a document is being
+//! built, aliases and references are left as placeholders and recorded along with
+//! their locations; once the whole document has been read, they are resolved by
+//! copying their targets into place.
+//!
+//! Strings that contain interpolations, such as `"http://${server.host}/"`, are
+//! placeholders too, and are resolved by writing the text of each target into the
+//! string.  Paths that are not found in the document are looked up in the context given
+//! by `LoadOptions`, and `${env:NAME}` stands for the environment variable `NAME`.
+//!
+//! A node that extends another (`child : parent { ... }`) inherits the attributes and
+//! subnodes of its parent, and is treated as a placeholder until it has done so.
+//! Abstract nodes are removed once everything else has been resolved.
+//!
+//! Targets may themselves contain aliases and references, so resolution happens in
+//! rounds: a reference is only resolved once its target no longer contains any
+//! placeholders, and is not reached through one.  References that are still unresolved
+//! when a round makes no progress must refer to themselves, directly or indirectly.
+
+use std::collections::HashMap;
+use std::env;
+
+use super::lexer::StringPart;
+use super::parser::ParseError;
+use super::path::{Path, PathTarget, Segment};
+use super::patch::PatchValue;
+use super::position::Position;
+use super::types::*;
+use super::units;
+
+/// A single step from a node or value to one of its children.
+///
+/// Unlike the segments of a `Path`, steps are never ambiguous, so they are used to
+/// record where anchors and placeholders are while a document is being built.
+#[derive(Debug, Clone)]
+pub(crate) enum Step {
+    Attr(String),
+    // the index of a subnode among all of the subnodes with the name
+    Node(String, usize),
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Debug)]
+pub(crate) enum Target {
+    Alias(String),
+    Path(Path),
+    Env(String),
+    // a string with targets to interpolate into it
+    Interpolation(Vec<Piece>),
+    // the node that a node extends
+    Parent(Path),
+}
+
+#[derive(Debug)]
+pub(crate) enum Piece {
+    Text(String),
+    // a target, and the position of its `${`
+    Target(Target, Position),
+}
+
+impl Target {
+    /// Parse the text of a `${...}` reference.
+    pub fn from_reference(text: &str) -> Result<Target, ParseError> {
+        if let Some(name) = text.strip_prefix("env:") {
+            if !name.is_empty() {
+                return Ok(Target::Env(name.to_string()));
+            }
+        } else if let Ok(path) = Path::parse(text) {
+            return Ok(Target::Path(path));
+        }
+        Err(ParseError::InvalidReference(format!("${{{}}}", text)))
+    }
+
+    /// Parse the pieces of an interpolated string.
+    ///
+    /// # Failures
+    /// Fails at the `${` of the first reference that is not well-formed.
+    pub fn from_parts(parts: Vec<StringPart>) -> Result<Target, (ParseError, Position)> {
+        let pieces = parts.into_iter()
+            .map(|part| match part {
+                StringPart::Text(text) => Ok(Piece::Text(text)),
+                StringPart::Reference(text, position) => match Target::from_reference(&text) {
+                    Ok(target) => Ok(Piece::Target(target, position)),
+                    Err(err) => Err((err, position)),
+                },
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Target::Interpolation(pieces))
+    }
+
+    // The target as it was written, for error messages.
+    fn written(&self) -> String {
+        match *self {
+            Target::Alias(ref name) => format!("*{}", name),
+            Target::Path(ref path) => format!("${{{}}}", path),
+            Target::Env(ref name) => format!("${{env:{}}}", name),
+            Target::Parent(ref path) => path.to_string(),
+            Target::Interpolation(ref pieces) => {
+                let mut written = String::new();
+                for piece in pieces {
+                    match *piece {
+                        Piece::Text(ref text) => written.push_str(text),
+                        Piece::Target(ref target, _) => written.push_str(&target.written()),
+                    }
+                }
+                format!("{:?}", written)
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Reference {
+    location: Vec<Step>,
+    target: Target,
+    position: Position,
+    is_node: bool,
+}
+
+/// The anchors and references found while building a document.
+#[derive(Debug, Default)]
+pub(crate) struct References {
+    anchors: HashMap<String, Vec<Step>>,
+    references: Vec<Reference>,
+    abstract_nodes: Vec<Vec<Step>>,
+}
+
+// What resolving a reference found.
+enum Outcome {
+    Resolved(PatchValue),
+    Inherited(Node),
+    // the target contains a placeholder, or is reached through one
+    Blocked,
+    // the error, and where it is reported
+    Failed(ParseError, Position),
+}
+
+// What looking up a target found.
+enum Found<'a> {
+    Target(PathTarget<'a>),
+    Env(String),
+    Blocked,
+    Missing,
+}
+
+type Address = *const ();
+
+// Where targets are looked up, apart from the document itself.
+struct Sources<'a> {
+    anchors: &'a HashMap<String, Vec<Step>>,
+    context: &'a Document,
+    placeholders: Vec<Address>,
+}
+
+impl References {
+    pub fn new() -> Self {
+        References::default()
+    }
+
+    /// Record an anchor for the node or value at a location.
+    pub fn anchor(&mut self, name: String, location: &[Step], position: Position)
+        -> Result<(), (ParseError, Position)> {
+
+        if self.anchors.contains_key(&name) {
+            return Err((ParseError::DuplicateAnchor(name), position));
+        }
+        self.anchors.insert(name, location.to_vec());
+        Ok(())
+    }
+
+    /// Record a placeholder at a location, which is resolved by copying its target.
+    pub fn reference(&mut self, target: Target, location: &[Step], is_node: bool,
+                     position: Position) {
+
+        self.references.push(Reference {
+            location: location.to_vec(),
+            target,
+            position,
+            is_node,
+        });
+    }
+
+    /// The number of placeholders recorded so far.
+    pub fn count(&self) -> usize {
+        self.references.len()
+    }
+
+    /// Record an abstract node at a location, which is removed once the document has
+    /// been resolved.
+    pub fn abstract_node(&mut self, location: &[Step]) {
+        self.abstract_nodes.push(location.to_vec());
+    }
+
+    /// Replace every placeholder in a document with a copy of its target, merge nodes
+    /// with the nodes they extend, and remove abstract nodes.
+    ///
+    /// Paths that are not found in the document are looked up in the context.
+    ///
+    /// # Failures
+    /// Fails if a reference has no target, refers to a node where a value is expected
+    /// or the other way around, or refers to itself.
+    pub fn resolve(self, doc: &mut Document, context: &Document)
+        -> Result<(), (ParseError, Position)> {
+
+        let mut sources = Sources { anchors: &self.anchors, context, placeholders: Vec::new() };
+        let mut pending = self.references;
+        while !pending.is_empty() {
+            let mut progress = false;
+            let mut index = 0;
+            while index < pending.len() {
+                // resolving a reference can move the others, so their addresses are
+                // found again each time
+                sources.placeholders = pending.iter()
+                    .filter_map(|reference| find(doc.as_node(), &reference.location))
+                    .map(|target| address(&target))
+                    .collect();
+
+                let outcome = sources.outcome(doc, &pending[index]);
+                match outcome {
+                    Outcome::Blocked => {
+                        index += 1;
+                        continue;
+                    },
+                    Outcome::Failed(error, position) => {
+                        return Err((error, position));
+                    },
+                    Outcome::Resolved(copy) => place(doc, &pending[index].location, copy),
+                    Outcome::Inherited(parent) => {
+                        if let Some(Place::Node(node)) = locate(doc, &pending[index].location) {
+                            inherit(node, &parent);
+                        }
+                    },
+                }
+                pending.remove(index);
+                progress = true;
+            }
+
+            if !progress {
+                let reference = pending.remove(0);
+                let error = match reference.target {
+                    Target::Parent(ref path) => ParseError::CyclicInheritance(path.to_string()),
+                    ref target => ParseError::CyclicReference(target.written()),
+                };
+                return Err((error, reference.position));
+            }
+        }
+
+        // removing later nodes first keeps the locations of earlier nodes the same
+        for location in self.abstract_nodes.iter().rev() {
+            if let Some((&Step::Node(ref name, index), parent)) = location.split_last() {
+                if let Some(Place::Node(parent)) = locate(doc, parent) {
+                    parent.remove_node_at(name.as_str(), index);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Sources<'a> {
+    fn outcome(&self, doc: &Document, reference: &Reference) -> Outcome {
+        let failed = |error| Outcome::Failed(error, reference.position.clone());
+        let target = &reference.target;
+        let pieces = match *target {
+            Target::Interpolation(ref pieces) => pieces,
+            Target::Parent(_) => {
+                return match self.lookup(doc, target) {
+                    Found::Target(PathTarget::Node(node)) => Outcome::Inherited(node.clone()),
+                    Found::Blocked => Outcome::Blocked,
+                    Found::Missing => failed(ParseError::DanglingReference(target.written())),
+                    _ => failed(ParseError::NotANode(target.written())),
+                };
+            },
+            _ => {
+                return match self.lookup(doc, target) {
+                    Found::Target(PathTarget::Node(node)) if reference.is_node =>
+                        Outcome::Resolved(PatchValue::Node(node.clone())),
+                    Found::Target(PathTarget::Value(value)) if !reference.is_node =>
+                        Outcome::Resolved(PatchValue::Value(value.clone())),
+                    Found::Env(value) if !reference.is_node =>
+                        Outcome::Resolved(PatchValue::Value(Value::Str(value))),
+                    Found::Blocked => Outcome::Blocked,
+                    Found::Missing => failed(ParseError::DanglingReference(target.written())),
+                    _ if reference.is_node => failed(ParseError::NotANode(target.written())),
+                    _ => failed(ParseError::NotAValue(target.written())),
+                };
+            },
+        };
+
+        let mut string = String::new();
+        // failures are reported at the `${` of the target that failed
+        for piece in pieces {
+            let (target, position) = match *piece {
+                Piece::Text(ref text) => {
+                    string.push_str(text);
+                    continue;
+                },
+                Piece::Target(ref target, ref position) => (target, position.clone()),
+            };
+            match self.lookup(doc, target) {
+                Found::Target(PathTarget::Value(value)) => match interpolated(value) {
+                    Some(text) => string.push_str(&text),
+                    None => {
+                        let error = ParseError::NotInterpolable(target.written());
+                        return Outcome::Failed(error, position);
+                    },
+                },
+                Found::Target(PathTarget::Node(_)) => {
+                    let error = ParseError::NotInterpolable(target.written());
+                    return Outcome::Failed(error, position);
+                },
+                Found::Env(value) => string.push_str(&value),
+                Found::Blocked => { return Outcome::Blocked; },
+                Found::Missing => {
+                    let error = ParseError::DanglingReference(target.written());
+                    return Outcome::Failed(error, position);
+                },
+            }
+        }
+        Outcome::Resolved(PatchValue::Value(Value::Str(string)))
+    }
+
+    fn lookup<'d>(&'d self, doc: &'d Document, target: &Target) -> Found<'d> {
+        let found = match *target {
+            Target::Alias(ref name) => match self.anchors.get(name) {
+                Some(location) => {
+                    let through = (1..location.len())
+                        .filter_map(|length| find(doc.as_node(), &location[..length]))
+                        .any(|target| self.placeholders.contains(&address(&target)));
+                    if through {
+                        return Found::Blocked;
+                    }
+                    find(doc.as_node(), location)
+                },
+                None => None,
+            },
+            Target::Path(ref path) | Target::Parent(ref path) => {
+                if through_placeholder(doc, path, &self.placeholders) {
+                    return Found::Blocked;
+                }
+                path.lookup(doc).or_else(|| path.lookup(self.context))
+            },
+            Target::Env(ref name) => {
+                return env::var(name).map(Found::Env).unwrap_or(Found::Missing);
+            },
+            Target::Interpolation(_) => unreachable!("interpolations are never nested"),
+        };
+
+        match found {
+            None => Found::Missing,
+            Some(ref target) if contains(target, &self.placeholders) => Found::Blocked,
+            Some(target) => Found::Target(target),
+        }
+    }
+}
+
+// The text that a value is written as when it is interpolated into a string.  Only
+// strings, numbers, quantities, dates and times, and booleans can be interpolated.
+fn interpolated(value: &Value) -> Option<String> {
+    match *value {
+        Value::Str(ref string) => Some(string.clone()),
+        Value::Duration(duration) => Some(units::format_duration(duration)),
+        Value::ByteSize(size) => Some(units::format_byte_size(size)),
+        Value::Int(_) | Value::Float(_) | Value::DateTime(_) | Value::Bool(_) =>
+            Some(value.to_string()),
+        _ => None,
+    }
+}
+
+// Tests whether a path passes through a placeholder, so that what it leads to may
+// change once the placeholder has been resolved.
+fn through_placeholder(doc: &Document, path: &Path, placeholders: &[Address]) -> bool {
+    let mut prefix = Path::new();
+    for segment in path.segments() {
+        match *segment {
+            Segment::Name(ref name) => prefix.push_name(name.as_str()),
+            Segment::Index(index) => prefix.push_index(index),
+            Segment::Label(ref label) => prefix.push_label(label.as_str()),
+        }
+        match prefix.lookup(doc) {
+            Some(ref target) if placeholders.contains(&address(target)) => { return true; },
+            _ => {},
+        }
+    }
+    false
+}
+
+fn address(target: &PathTarget) -> Address {
+    match *target {
+        PathTarget::Node(node) => node as *const Node as Address,
+        PathTarget::Value(value) => value as *const Value as Address,
+    }
+}
+
+// Tests whether a node or value is, or contains, any of the placeholders.
+fn contains(target: &PathTarget, placeholders: &[Address]) -> bool {
+    if placeholders.contains(&address(target)) {
+        return true;
+    }
+    match *target {
+        PathTarget::Node(node) => {
+            node.iter_attrs()
+                .any(|(_, value)| contains(&PathTarget::Value(value), placeholders))
+                || node.iter_nodes()
+                    .any(|(_, node)| contains(&PathTarget::Node(node), placeholders))
+        },
+        PathTarget::Value(Value::List(list)) =>
+            list.iter().any(|value| contains(&PathTarget::Value(value), placeholders)),
+        PathTarget::Value(Value::Dict(dict)) =>
+            dict.values().any(|value| contains(&PathTarget::Value(value), placeholders)),
+        PathTarget::Value(_) => false,
+    }
+}
+
+fn find<'a>(node: &'a Node, location: &[Step]) -> Option<PathTarget<'a>> {
+    let mut target = PathTarget::Node(node);
+    for step in location {
+        target = match (target, step) {
+            (PathTarget::Node(node), Step::Attr(name)) =>
+                PathTarget::Value(node.get_attr(name.as_str())?),
+            (PathTarget::Node(node), &Step::Node(ref name, index)) =>
+                PathTarget::Node(node.get_nodes(name.as_str()).get(index)?),
+            (PathTarget::Value(Value::Dict(dict)), Step::Key(key)) =>
+                PathTarget::Value(dict.get(key)?),
+            (PathTarget::Value(Value::List(list)), &Step::Index(index)) =>
+                PathTarget::Value(list.get(index)?),
+            _ => { return None; },
+        };
+    }
+    Some(target)
+}
+
+enum Place<'a> {
+    Node(&'a mut Node),
+    Value(&'a mut Value),
+}
+
+fn locate<'a>(doc: &'a mut Document, location: &[Step]) -> Option<Place<'a>> {
+    let mut place = Place::Node(doc.as_node_mut());
+    for step in location {
+        place = match (place, step) {
+            (Place::Node(node), Step::Attr(name)) =>
+                node.get_attr_mut(name.as_str()).map(Place::Value),
+            (Place::Node(node), &Step::Node(ref name, index)) =>
+                node.get_nodes_mut(name.as_str()).get_mut(index).map(Place::Node),
+            (Place::Value(Value::Dict(dict)), Step::Key(key)) =>
+                dict.get_mut(key).map(Place::Value),
+            (Place::Value(Value::List(list)), &Step::Index(index)) =>
+                list.get_mut(index).map(Place::Value),
+            _ => None,
+        }?;
+    }
+    Some(place)
+}
+
+// Replaces the placeholder at a location with a copy of its target.  Copied nodes keep
+// the labels and annotations of the placeholder.
+fn place(doc: &mut Document, location: &[Step], copy: PatchValue) {
+    let place = locate(doc, location).expect("placeholders can always be found");
+    match (place, copy) {
+        (Place::Node(placeholder), PatchValue::Node(mut copy)) => {
+            copy.set_labels(placeholder.labels().to_vec());
+            copy.set_annotations(placeholder.annotations().to_vec());
+            *placeholder = copy;
+        },
+        (Place::Value(placeholder), PatchValue::Value(copy)) => { *placeholder = copy; },
+        _ => unreachable!("placeholders are only replaced by the same kind of thing"),
+    }
+}
+
+// Merges a parent into a node that extends it.  The node keeps its own attributes,
+// except that dicts that both have are merged key by key.  Subnodes that both have with
+// the same name and labels are merged in the same way as the nodes themselves, and
+// subnodes that only the parent has are copied, unless the node has its own repeated
+// nodes with that name and those labels.  Attributes keep the annotations of the
+// parent's attributes unless they have annotations of their own.
+fn inherit(node: &mut Node, parent: &Node) {
+    for (key, inherited) in parent.iter_attrs() {
+        match node.get_attr_mut(key.as_str()) {
+            Some(own) => inherit_value(own, inherited),
+            None => {
+                node.insert_attr(key.clone(), inherited.clone());
+                node.copy_attr_literals(key.as_str(), parent);
+            },
+        }
+        if node.attr_annotations(key.as_str()).is_empty() {
+            node.set_attr_annotations(key.as_str(), parent.attr_annotations(key.as_str()).to_vec());
+        }
+    }
+
+    let names: Vec<&String> = parent.iter_nodes().map(|(name, _)| name).collect();
+    for (index, name) in names.iter().enumerate() {
+        if names[..index].contains(name) {
+            continue;
+        }
+        let inherited_nodes = parent.get_nodes(name.as_str());
+        for labels in label_sets(&[inherited_nodes]) {
+            let inherited = labelled(inherited_nodes, labels);
+            let own_count = labelled(node.get_nodes(name.as_str()), labels).len();
+            match (own_count, inherited.len()) {
+                (0, _) => for copy in inherited {
+                    node.push_node(name.as_str(), copy.clone());
+                },
+                (1, 1) => {
+                    let own = node.get_nodes_mut(name.as_str()).iter_mut()
+                        .find(|own| own.labels() == labels)
+                        .expect("the node has a subnode with these labels");
+                    inherit(own, inherited[0]);
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+fn inherit_value(own: &mut Value, inherited: &Value) {
+    if let (Value::Dict(own), Value::Dict(inherited)) = (own, inherited) {
+        for (key, inherited) in inherited {
+            match own.get_mut(key) {
+                Some(own) => inherit_value(own, inherited),
+                None => { own.insert(key.clone(), inherited.clone()); },
+            }
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! `Serialize` and `Deserialize` for `Value`, with the `serde` feature.
+//!
+//! Bytes are serialized with `serialize_bytes`, so formats with a binary type keep them
+//! as binary, and bytes read from such formats become `Value::Bytes`.  Durations and
+//! dates and times, which serde has no types for, are written as strings in figtree
+//! syntax, byte sizes as their number of bytes, and identifiers as strings.  Dict
+//! entries are written in order of their keys.
+
+extern crate serde;
+
+use std::fmt;
+
+use self::serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use self::serde::ser::{Serialize, SerializeMap, Serializer};
+
+use super::types::{Dict, List, Value};
+use super::units;
+
+impl Serialize for Value {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        match *self {
+            Value::Str(ref s) | Value::Ident(ref s) => serializer.serialize_str(s),
+            Value::Int(i) => serializer.serialize_i64(i),
+            Value::Float(f) => serializer.serialize_f64(f),
+            Value::Duration(d) => serializer.serialize_str(&units::format_duration(d)),
+            Value::ByteSize(b) => serializer.serialize_u64(b),
+            Value::DateTime(d) => serializer.collect_str(&d),
+            Value::Bytes(ref b) => serializer.serialize_bytes(b),
+            Value::Bool(b) => serializer.serialize_bool(b),
+            Value::List(ref list) => serializer.collect_seq(list),
+            Value::Dict(ref dict) => {
+                let mut entries: Vec<_> = dict.iter().collect();
+                entries.sort_by(|a, b| a.0.cmp(b.0));
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            },
+            Value::Null => serializer.serialize_unit(),
+        }
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a figtree value")
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<Value, E> {
+        Ok(Value::new_bool(b))
+    }
+
+    fn visit_i64<E>(self, i: i64) -> Result<Value, E> {
+        Ok(Value::new_int(i))
+    }
+
+    fn visit_u64<E>(self, u: u64) -> Result<Value, E> where E: de::Error {
+        if u > i64::MAX as u64 {
+            return Err(E::invalid_value(de::Unexpected::Unsigned(u), &"an integer that fits in 64 bits"));
+        }
+        Ok(Value::new_int(u as i64))
+    }
+
+    fn visit_f64<E>(self, f: f64) -> Result<Value, E> {
+        Ok(Value::new_float(f))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Value, E> {
+        Ok(Value::new_string(s))
+    }
+
+    fn visit_string<E>(self, s: String) -> Result<Value, E> {
+        Ok(Value::new_string(s))
+    }
+
+    fn visit_bytes<E>(self, b: &[u8]) -> Result<Value, E> {
+        Ok(Value::new_bytes(b))
+    }
+
+    fn visit_byte_buf<E>(self, b: Vec<u8>) -> Result<Value, E> {
+        Ok(Value::new_bytes(b))
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::new_null())
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::new_null())
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Value, D::Error> where D: Deserializer<'de> {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Value, A::Error> where A: SeqAccess<'de> {
+        let mut list = List::new();
+        while let Some(value) = seq.next_element()? {
+            list.push(value);
+        }
+        Ok(Value::List(list))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Value, A::Error> where A: MapAccess<'de> {
+        let mut dict = Dict::new();
+        while let Some((key, value)) = map.next_entry()? {
+            dict.insert(key, value);
+        }
+        Ok(Value::Dict(dict))
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D>(deserializer: D) -> Result<Value, D::Error> where D: Deserializer<'de> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_test;
+
+    use self::serde_test::{assert_de_tokens, assert_ser_tokens, assert_tokens, Token};
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn bytes() {
+        assert_tokens(&Value::new_bytes(vec![0xde, 0xad]), &[Token::Bytes(&[0xde, 0xad])]);
+        assert_de_tokens(&Value::new_bytes(vec![1]), &[Token::ByteBuf(&[1])]);
+    }
+
+    #[test]
+    fn values() {
+        assert_tokens(&Value::new_string("a"), &[Token::Str("a")]);
+        assert_tokens(&Value::new_int(-1), &[Token::I64(-1)]);
+        assert_tokens(&Value::new_float(0.5), &[Token::F64(0.5)]);
+        assert_tokens(&Value::new_bool(true), &[Token::Bool(true)]);
+        assert_tokens(&Value::new_null(), &[Token::Unit]);
+        assert_de_tokens(&Value::new_int(7), &[Token::U64(7)]);
+        assert_de_tokens(&Value::new_null(), &[Token::None]);
+        assert_de_tokens(&Value::new_int(7), &[Token::Some, Token::U8(7)]);
+
+        assert_ser_tokens(&Value::new_duration(Duration::from_secs(90)), &[Token::Str("1m30s")]);
+        assert_ser_tokens(&Value::new_byte_size(1024), &[Token::U64(1024)]);
+        assert_ser_tokens(
+            &Value::new_datetime("2026-10-16T12:00:00Z".parse().unwrap()),
+            &[Token::Str("2026-10-16T12:00:00Z")]);
+        assert_ser_tokens(&Value::new_ident("x"), &[Token::Str("x")]);
+    }
+
+    #[test]
+    fn containers() {
+        let mut dict = Dict::new();
+        dict.insert("b".to_string(), Value::List(vec![Value::new_int(1)]));
+        dict.insert("a".to_string(), Value::new_bytes(Vec::new()));
+        assert_tokens(&Value::Dict(dict), &[
+            Token::Map { len: Some(2) },
+            Token::Str("a"), Token::Bytes(&[]),
+            Token::Str("b"), Token::Seq { len: Some(1) }, Token::I64(1), Token::SeqEnd,
+            Token::MapEnd,
+        ]);
+    }
+}
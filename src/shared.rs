@@ -0,0 +1,376 @@
+//! A config document shared between threads.
+//!
+//! `SharedConfig` is a cheaply cloneable handle to a document that many threads can
+//! read while another swaps in new versions.  Reading takes a snapshot of the current
+//! version as an `Arc<Document>`, so readers never see a half-updated document and are
+//! never blocked for longer than it takes to clone an `Arc`.
+//!
+//! Threads that only care about part of the config can subscribe to a path with
+//! `watch_path`, and are only notified when the node or value at that path changes.
+//! `watch_value` does the same for a value of a known type, such as an `i64`.
+//!
+//! # Examples
+//! ```
+//! use figtree::Figtree;
+//! use figtree::shared::SharedConfig;
+//! use figtree::patch::PatchValue;
+//! use figtree::types::Value;
+//!
+//! let parse = |source: &str| Figtree::from_string(source).parse().ok().expect("invalid");
+//! let config = SharedConfig::new(parse("server { 'port': 80, 'workers': 4 }"));
+//! let port_changes = config.watch_path("server.port").ok().expect("invalid path");
+//!
+//! config.store(parse("server { 'port': 80, 'workers': 8 }"));
+//! assert!(port_changes.try_recv().is_err());
+//!
+//! config.store(parse("server { 'port': 8080, 'workers': 8 }"));
+//! let change = port_changes.try_recv().ok().expect("no change");
+//! assert_eq!(change.new, Some(PatchValue::Value(Value::new_int(8080))));
+//! ```
+
+use std::convert::TryFrom;
+use std::mem;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+
+use super::patch::PatchValue;
+use super::path::{Path, PathError, PathTarget};
+use super::types::{Document, Value};
+use super::watch::WatchEvent;
+
+/// A change to the node or value at a watched path.
+///
+/// Each side is `None` if there was nothing at the path in that version.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathChange {
+    pub path: Path,
+    pub old: Option<PatchValue>,
+    pub new: Option<PatchValue>,
+}
+
+/// A change to the value at a path watched with `watch_value`.
+///
+/// Each side is `None` if there was no value of type `T` at the path in that version.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueChange<T> {
+    pub path: Path,
+    pub old: Option<T>,
+    pub new: Option<T>,
+}
+
+// sends a change between two versions of the target of a path, if it is one the
+// subscriber cares about, and returns false once the subscriber has gone
+type Notify = Box<dyn Fn(&Path, Option<PathTarget>, Option<PathTarget>) -> bool + Send>;
+
+struct PathWatch {
+    path: Path,
+    notify: Notify,
+}
+
+struct Inner {
+    current: RwLock<Arc<Document>>,
+    // held while swapping in a new document, so that notifications are sent in the
+    // same order as the swaps they describe
+    watches: Mutex<Vec<PathWatch>>,
+}
+
+/// A handle to a document shared between threads.
+///
+/// Cloning the handle is cheap, and all clones refer to the same document.
+#[derive(Clone)]
+pub struct SharedConfig {
+    inner: Arc<Inner>,
+}
+
+impl SharedConfig {
+    /// Construct a shared config holding a document.
+    pub fn new<D: Into<Arc<Document>>>(doc: D) -> Self {
+        SharedConfig {
+            inner: Arc::new(Inner {
+                current: RwLock::new(doc.into()),
+                watches: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Get the current version of the document.
+    ///
+    /// The snapshot is unaffected by later swaps.
+    pub fn snapshot(&self) -> Arc<Document> {
+        self.inner.current.read().unwrap().clone()
+    }
+
+    /// Replace the document, notifying any watched paths that changed.
+    ///
+    /// Returns the previous version.
+    pub fn store<D: Into<Arc<Document>>>(&self, doc: D) -> Arc<Document> {
+        let mut watches = self.inner.watches.lock().unwrap();
+        let new = doc.into();
+        let old = {
+            let mut current = self.inner.current.write().unwrap();
+            mem::replace(&mut *current, new.clone())
+        };
+        notify(&mut watches, &old, &new);
+        old
+    }
+
+    /// Modify a copy of the current document and swap it in.
+    ///
+    /// No lock is held while `modify` runs, so it can use this config.  If another
+    /// thread swaps in a document first, `modify` is run again on a copy of that
+    /// document, so no update is lost.  Returns the previous version.
+    pub fn update<F>(&self, mut modify: F) -> Arc<Document> where F: FnMut(&mut Document) {
+        loop {
+            let old = self.snapshot();
+            let mut doc = (*old).clone();
+            modify(&mut doc);
+            let new = Arc::new(doc);
+
+            let mut watches = self.inner.watches.lock().unwrap();
+            {
+                let mut current = self.inner.current.write().unwrap();
+                if !Arc::ptr_eq(&current, &old) {
+                    continue;
+                }
+                *current = new.clone();
+            }
+            notify(&mut watches, &old, &new);
+            return old;
+        }
+    }
+
+    /// Subscribe to changes to the node or value at a path.
+    ///
+    /// A `PathChange` is sent each time a new document is swapped in where the target
+    /// of the path differs from the previous version.  The subscription ends when the
+    /// receiver is dropped.
+    ///
+    /// # Failures
+    /// Returns a `PathError` if the path cannot be parsed.
+    pub fn watch_path(&self, path: &str) -> Result<Receiver<PathChange>, PathError> {
+        let (sender, receiver) = mpsc::channel();
+        self.watch(path, Box::new(move |path, before, after| {
+            sender.send(PathChange {
+                path: path.clone(),
+                old: to_owned(before),
+                new: to_owned(after),
+            }).is_ok()
+        }))?;
+        Ok(receiver)
+    }
+
+    /// Subscribe to changes to the value at a path, converted to `T`.
+    ///
+    /// A `ValueChange` is sent each time a new document is swapped in where the
+    /// converted value differs from the previous version.  Anything at the path that
+    /// cannot be converted, such as a node or a value of another type, counts as no
+    /// value.
+    ///
+    /// # Failures
+    /// Returns a `PathError` if the path cannot be parsed.
+    ///
+    /// # Examples
+    /// ```
+    /// # use figtree::Figtree;
+    /// # use figtree::shared::SharedConfig;
+    /// let parse = |source: &str| Figtree::from_string(source).parse().ok().expect("invalid");
+    /// let config = SharedConfig::new(parse("server { 'port': 80 }"));
+    /// let ports = config.watch_value::<i64>("server.port").ok().expect("invalid path");
+    ///
+    /// config.store(parse("server { 'port': 8080 }"));
+    /// assert_eq!(ports.try_recv().ok().expect("no change").new, Some(8080));
+    /// ```
+    pub fn watch_value<T>(&self, path: &str) -> Result<Receiver<ValueChange<T>>, PathError>
+        where T: for<'a> TryFrom<&'a Value> + PartialEq + Send + 'static {
+
+        let (sender, receiver) = mpsc::channel();
+        self.watch(path, Box::new(move |path, before, after| {
+            let (old, new) = (convert::<T>(before), convert::<T>(after));
+            old == new || sender.send(ValueChange { path: path.clone(), old, new }).is_ok()
+        }))?;
+        Ok(receiver)
+    }
+
+    fn watch(&self, path: &str, notify: Notify) -> Result<(), PathError> {
+        let path = Path::parse(path)?;
+        self.inner.watches.lock().unwrap().push(PathWatch { path, notify });
+        Ok(())
+    }
+
+    /// Store each document reloaded by a `Watcher` in this config.
+    ///
+    /// Takes a receiver from `Watcher::subscribe`, and returns the thread that reads
+    /// from it, which finishes when the watcher is dropped.  Errors from the watcher
+    /// are ignored, since the watcher keeps the last good document.
+    pub fn follow(&self, events: Receiver<WatchEvent>) -> JoinHandle<()> {
+        let config = self.clone();
+        thread::spawn(move || {
+            for event in events {
+                if let WatchEvent::Reloaded(doc) = event {
+                    config.store(doc);
+                }
+            }
+        })
+    }
+}
+
+fn to_owned(target: Option<PathTarget>) -> Option<PatchValue> {
+    target.map(|target| match target {
+        PathTarget::Node(node) => PatchValue::Node(node.clone()),
+        PathTarget::Value(value) => PatchValue::Value(value.clone()),
+    })
+}
+
+fn convert<T>(target: Option<PathTarget>) -> Option<T> where T: for<'a> TryFrom<&'a Value> {
+    match target {
+        Some(PathTarget::Value(value)) => T::try_from(value).ok(),
+        _ => None,
+    }
+}
+
+fn notify(watches: &mut Vec<PathWatch>, old: &Document, new: &Document) {
+    watches.retain(|watch| {
+        let (before, after) = (watch.path.lookup(old), watch.path.lookup(new));
+        before == after || (watch.notify)(&watch.path, before, after)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::figtree::Figtree;
+    use super::super::types::*;
+
+    fn parse(source: &str) -> Document {
+        Figtree::from_string(source).parse().unwrap()
+    }
+
+    #[test]
+    fn snapshots_and_swaps() {
+        let config = SharedConfig::new(parse("a { 'x': 1 }"));
+        let before = config.snapshot();
+        let old = config.store(parse("a { 'x': 2 }"));
+
+        assert!(Arc::ptr_eq(&before, &old));
+        assert_eq!(*before, parse("a { 'x': 1 }"));
+        assert_eq!(*config.clone().snapshot(), parse("a { 'x': 2 }"));
+    }
+
+    #[test]
+    fn concurrent_updates() {
+        let config = SharedConfig::new(parse("counter { 'n': 0 }"));
+        let threads: Vec<_> = (0..8).map(|_| {
+            let config = config.clone();
+            thread::spawn(move || for _ in 0..50 {
+                config.update(|doc| {
+                    let value = doc.get_node_mut("counter").unwrap().get_attr_mut("n").unwrap();
+                    *value = Value::new_int(value.get_int().unwrap() + 1);
+                });
+                let _ = config.snapshot();
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(*config.snapshot(), parse("counter { 'n': 400 }"));
+    }
+
+    #[test]
+    fn watching_paths() {
+        let config = SharedConfig::new(parse("server { 'port': 80 } logging {}"));
+        let port = config.watch_path("server.port").unwrap();
+        let logging = config.watch_path("logging").unwrap();
+        assert!(config.watch_path("server..port").is_err());
+
+        config.store(parse("server { 'port': 80 } logging { 'level': 'debug' }"));
+        assert!(port.try_recv().is_err());
+        let mut node = Node::new();
+        node.insert_attr("level", Value::new_string("debug"));
+        assert_eq!(logging.try_recv(), Ok(PathChange {
+            path: Path::parse("logging").unwrap(),
+            old: Some(PatchValue::Node(Node::new())),
+            new: Some(PatchValue::Node(node)),
+        }));
+
+        drop(logging);
+        config.update(|doc| { doc.delete_node("server"); doc.delete_node("logging"); });
+        assert_eq!(port.try_recv(), Ok(PathChange {
+            path: Path::parse("server.port").unwrap(),
+            old: Some(PatchValue::Value(Value::new_int(80))),
+            new: None,
+        }));
+        assert_eq!(config.inner.watches.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn updates_can_use_the_config() {
+        let config = SharedConfig::new(parse("a { 'x': 1 }"));
+        let mut runs = 0;
+        let mut changes = None;
+        config.update(|doc| {
+            runs += 1;
+            changes = Some(config.watch_path("a.x").unwrap());
+            if runs == 1 {
+                // swapping in another document first makes the update run again
+                config.store(parse("a { 'x': 5 }"));
+            }
+            let value = doc.get_node_mut("a").unwrap().get_attr_mut("x").unwrap();
+            *value = Value::new_int(value.get_int().unwrap() + 1);
+        });
+        assert_eq!(runs, 2);
+        assert_eq!(*config.snapshot(), parse("a { 'x': 6 }"));
+        let change = changes.unwrap().try_recv().unwrap();
+        assert_eq!(change.new, Some(PatchValue::Value(Value::new_int(6))));
+    }
+
+    #[test]
+    fn watching_values() {
+        let config = SharedConfig::new(parse("server { 'port': 80, 'name': 'a' }"));
+        let port = config.watch_value::<i64>("server.port").unwrap();
+        let name = config.watch_value::<String>("server.name").unwrap();
+        assert!(config.watch_value::<i64>("server..port").is_err());
+
+        config.store(parse("server { 'port': 8080, 'name': 'a' }"));
+        assert_eq!(port.try_recv(), Ok(ValueChange {
+            path: Path::parse("server.port").unwrap(),
+            old: Some(80),
+            new: Some(8080),
+        }));
+        assert!(name.try_recv().is_err());
+
+        // values of another type count as no value
+        config.store(parse("server { 'port': '8080', 'name': 'b' }"));
+        assert_eq!(port.try_recv().unwrap().new, None);
+        assert_eq!(name.try_recv().unwrap().new, Some("b".to_string()));
+    }
+
+    #[test]
+    fn following_watchers() {
+        use std::env;
+        use std::fs;
+        use std::process;
+        use std::time::Duration;
+        use super::super::watch::{Watcher, WatchOptions};
+
+        let file = env::temp_dir().join(format!("figtree-shared-{}.ft", process::id()));
+        fs::write(&file, "server { 'port': 80 }").unwrap();
+        let options = WatchOptions {
+            poll_interval: Duration::from_millis(10),
+            debounce: Duration::from_millis(30),
+            .. WatchOptions::default()
+        };
+        let watcher = Watcher::new(file.clone(), options).unwrap();
+        let config = SharedConfig::new(watcher.current());
+        let port = config.watch_path("server.port").unwrap();
+        let follower = config.follow(watcher.subscribe());
+
+        fs::write(&file, "server { 'port': 8080 }").unwrap();
+        let change = port.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(change.new, Some(PatchValue::Value(Value::new_int(8080))));
+
+        drop(watcher);
+        follower.join().unwrap();
+        fs::remove_file(file).unwrap();
+    }
+}
@@ -0,0 +1,280 @@
+//! Tagged values, such as `!duration "5m"` or `!env "HOME"`.
+//!
+//! A tag is written as an identifier value followed directly by the literal it applies
+//! to, its payload.  While a document is loaded, the payload is handed to the handler
+//! registered for the tag, which either converts it into the value that ends up in the
+//! document, or rejects it with a message that is reported along with the position of
+//! the tag.  Tags without a handler are an error, so a tagged value is never silently
+//! read as something else.
+//!
+//! `!name` on its own, or followed by a comma or the end of a list, is still an
+//! identifier value.
+//!
+//! The handlers used for a load are given by `LoadOptions::tags`, which has the built-in
+//! handlers unless it is replaced:
+//!
+//! - `!duration "1h30m"` is a duration, the same as the literal `1h30m` when
+//!   `LoadOptions::quantity_literals` is set.
+//! - `!bytes "512MiB"` is a byte size, the same as the literal `512MiB` when
+//!   `LoadOptions::quantity_literals` is set.
+//! - `!regex "^a.*"` is a string, after checking that it is a well-formed pattern.
+//! - `!path "~/x"` is a string, with a leading `~` replaced by the home directory.
+//! - `!env "HOME"` is the value of an environment variable.
+//!
+//! # Examples
+//! ```
+//! use std::time::Duration;
+//! use figtree::{Figtree, LoadOptions, Value};
+//! let mut options = LoadOptions::default();
+//! options.tags.register("upper", |payload: &Value| match payload.get_str() {
+//!     Some(text) => Ok(Value::new_string(text.to_uppercase())),
+//!     None => Err("expected a string".to_string()),
+//! });
+//! let doc = Figtree::from_string("'name': !upper 'fig', 'wait': !duration '1m'")
+//!     .parse_with(&options).ok().expect("invalid document");
+//! assert_eq!(doc.get_attr("name"), Some(&Value::new_string("FIG")));
+//! assert_eq!(doc.get_attr("wait"), Some(&Value::new_duration(Duration::from_secs(60))));
+//! ```
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+
+use super::types::Value;
+use super::units;
+
+/// A function that converts the payload of a tagged value, or rejects it with a message.
+pub type TagHandler = Arc<dyn Fn(&Value) -> Result<Value, String> + Send + Sync>;
+
+/// The handlers for the tags that can be used in a document.
+#[derive(Clone)]
+pub struct TagHandlers {
+    handlers: HashMap<String, TagHandler>,
+}
+
+impl TagHandlers {
+    /// Construct a set of handlers without any tags at all.
+    pub fn new() -> Self {
+        TagHandlers { handlers: HashMap::new() }
+    }
+
+    /// Construct a set of handlers for the built-in tags: `duration`, `bytes`, `regex`,
+    /// `path` and `env`.
+    pub fn builtin() -> Self {
+        let mut handlers = TagHandlers::new();
+        handlers.register("duration", duration);
+        handlers.register("bytes", bytes);
+        handlers.register("regex", regex);
+        handlers.register("path", path);
+        handlers.register("env", env_var);
+        handlers
+    }
+
+    /// Register the handler for a tag, replacing any handler it already had.
+    pub fn register<S, F>(&mut self, tag: S, handler: F)
+        where S: Into<String>, F: Fn(&Value) -> Result<Value, String> + Send + Sync + 'static {
+
+        self.handlers.insert(tag.into(), Arc::new(handler));
+    }
+
+    /// Remove the handler for a tag, so that using the tag is an error.
+    pub fn remove(&mut self, tag: &str) -> Option<TagHandler> {
+        self.handlers.remove(tag)
+    }
+
+    /// Test whether a tag has a handler.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.handlers.contains_key(tag)
+    }
+
+    /// Convert the payload of a tagged value, returning `None` if the tag has no
+    /// handler.
+    pub fn apply(&self, tag: &str, payload: &Value) -> Option<Result<Value, String>> {
+        self.handlers.get(tag).map(|handler| handler(payload))
+    }
+}
+
+impl Default for TagHandlers {
+    fn default() -> Self {
+        TagHandlers::builtin()
+    }
+}
+
+impl fmt::Debug for TagHandlers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut tags: Vec<&String> = self.handlers.keys().collect();
+        tags.sort();
+        f.debug_set().entries(tags).finish()
+    }
+}
+
+/// Handlers are equal if they handle the same tags with the same functions.
+impl PartialEq for TagHandlers {
+    fn eq(&self, other: &TagHandlers) -> bool {
+        self.handlers.len() == other.handlers.len()
+            && self.handlers.iter().all(|(tag, handler)| match other.handlers.get(tag) {
+                Some(other) => Arc::ptr_eq(handler, other),
+                None => false,
+            })
+    }
+}
+
+fn expect_str(payload: &Value) -> Result<&str, String> {
+    payload.get_str()
+        .ok_or_else(|| format!("expected a string, found {}", payload.type_name()))
+}
+
+fn duration(payload: &Value) -> Result<Value, String> {
+    units::parse_duration(expect_str(payload)?).map(Value::new_duration)
+}
+
+fn bytes(payload: &Value) -> Result<Value, String> {
+    units::parse_byte_size(expect_str(payload)?).map(Value::new_byte_size)
+}
+
+fn regex(payload: &Value) -> Result<Value, String> {
+    let pattern = expect_str(payload)?;
+    check_pattern(pattern)?;
+    Ok(Value::new_string(pattern))
+}
+
+fn path(payload: &Value) -> Result<Value, String> {
+    let path = expect_str(payload)?;
+    let rest = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+        _ => { return Ok(Value::new_string(path)); },
+    };
+    match env::var("HOME") {
+        Ok(home) => Ok(Value::new_string(home + rest)),
+        Err(_) => Err(format!("cannot expand `{}` without a home directory", path)),
+    }
+}
+
+fn env_var(payload: &Value) -> Result<Value, String> {
+    let name = expect_str(payload)?;
+    env::var(name)
+        .map(Value::new_string)
+        .map_err(|_| format!("environment variable `{}` is not set", name))
+}
+
+// Checks the structure of a regular expression: escapes are complete, groups and
+// character classes are closed, and repetitions have something to repeat.  The
+// pattern is not compiled, so applications that need more than this can register a
+// handler that uses their own regex engine.
+fn check_pattern(pattern: &str) -> Result<(), String> {
+    let mut chars = pattern.chars().peekable();
+    let mut depth = 0;
+    let mut can_repeat = false;
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => {
+                if chars.next().is_none() {
+                    return Err("pattern ends with an unfinished escape".to_string());
+                }
+                can_repeat = true;
+            },
+            '[' => {
+                // a `]` straight after the opening bracket is part of the class
+                chars.next_if_eq(&'^');
+                chars.next_if_eq(&']');
+                loop {
+                    match chars.next() {
+                        Some(']') => { break; },
+                        Some('\\') => { chars.next(); },
+                        Some(_) => {},
+                        None => { return Err("unclosed character class".to_string()); },
+                    }
+                }
+                can_repeat = true;
+            },
+            '(' => {
+                depth += 1;
+                can_repeat = false;
+                // group flags and names, as in `(?:...)`, are not repetitions
+                chars.next_if_eq(&'?');
+            },
+            ')' => {
+                if depth == 0 {
+                    return Err("unmatched `)`".to_string());
+                }
+                depth -= 1;
+                can_repeat = true;
+            },
+            '|' => { can_repeat = false; },
+            '*' | '+' | '?' | '{' if !can_repeat => {
+                return Err(format!("`{}` has nothing to repeat", ch));
+            },
+            '*' | '+' | '?' => {
+                // a lazy or possessive repetition, as in `a*?`
+                chars.next_if(|&next| next == '?' || next == '+');
+                can_repeat = false;
+            },
+            _ => { can_repeat = true; },
+        }
+    }
+    if depth > 0 {
+        return Err("unclosed group".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn apply(tag: &str, payload: Value) -> Result<Value, String> {
+        TagHandlers::builtin().apply(tag, &payload).unwrap()
+    }
+
+    #[test]
+    fn builtin_tags() {
+        assert_eq!(
+            apply("duration", Value::new_string("1h30m")),
+            Ok(Value::new_duration(Duration::from_secs(5400))));
+        assert_eq!(
+            apply("duration", Value::new_string("250ms")),
+            Ok(Value::new_duration(Duration::from_millis(250))));
+        assert_eq!(apply("bytes", Value::new_string("2KiB")), Ok(Value::new_byte_size(2048)));
+        assert_eq!(apply("regex", Value::new_string("^a.*")), Ok(Value::new_string("^a.*")));
+        assert!(apply("duration", Value::new_string("5 minutes")).is_err());
+        assert!(apply("bytes", Value::new_string("16EiB")).is_err());
+        assert_eq!(
+            apply("duration", Value::new_int(5)),
+            Err("expected a string, found int".to_string()));
+
+        env::set_var("FIGTREE_TAG_TEST", "set");
+        assert_eq!(apply("env", Value::new_string("FIGTREE_TAG_TEST")), Ok(Value::new_string("set")));
+        assert_eq!(
+            apply("env", Value::new_string("FIGTREE_TAG_TEST_UNSET")),
+            Err("environment variable `FIGTREE_TAG_TEST_UNSET` is not set".to_string()));
+
+        let home = env::var("HOME").unwrap_or_default();
+        if !home.is_empty() {
+            assert_eq!(apply("path", Value::new_string("~/x")), Ok(Value::new_string(home + "/x")));
+        }
+        assert_eq!(apply("path", Value::new_string("~x/y")), Ok(Value::new_string("~x/y")));
+        assert_eq!(apply("path", Value::new_string("/etc")), Ok(Value::new_string("/etc")));
+
+        assert!(TagHandlers::builtin().apply("unknown", &Value::new_null()).is_none());
+    }
+
+    #[test]
+    fn check_patterns() {
+        for pattern in &["", "a|b", "(?:a+?)*", r"\(x\)", "[]a]", "[^]\\]]", "a{2,3}", "(a|)"] {
+            assert_eq!(check_pattern(pattern), Ok(()), "{}", pattern);
+        }
+        for pattern in &["*a", "a|+", "(a", "a)", "[a", r"a\", "({1})"] {
+            assert!(check_pattern(pattern).is_err(), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn compare_handlers() {
+        let handlers = TagHandlers::builtin();
+        assert_eq!(handlers.clone(), handlers);
+        assert_ne!(TagHandlers::builtin(), handlers);
+        assert_eq!(format!("{:?}", handlers), r#"{"bytes", "duration", "env", "path", "regex"}"#);
+    }
+}
@@ -16,9 +16,24 @@
 //!     Value::new_int(4032));
 //! ```
 
+use std::cmp::Ordering;
+use std::convert::TryFrom;
 use std::collections::HashMap;
-use std::collections::hash_map::Iter;
+use std::collections::hash_map::{self, Iter};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::ops::Index;
+use std::slice;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use super::datetime::DateTime;
+use super::lexer::{Lexer, LexToken};
 use super::parser::ParsedValue;
+use super::path::Path;
+use super::units;
+use super::writer;
 
 /// A type to represent a figtree dict
 ///
@@ -41,11 +56,24 @@ pub type List = Vec<Value>;
 /// let value = Value::new_string("hello!");
 /// assert!(value.get_str() == Some("hello!"));
 /// assert!(value.get_int() == None);
-#[derive(Debug, PartialEq)]
+/// ```
+///
+/// # Equality and ordering
+/// Values are totally ordered, so they can be sorted and used as keys in maps and sets.
+/// Values of different kinds are ordered by kind, in the order null, bool, int, float,
+/// duration, byte size, date and time, bytes, string, identifier, list, dict; an int is
+/// never equal to a float.  Floats are compared numerically, except that all NaNs are
+/// equal to each other and greater than every other float, and `0.0` and `-0.0` are
+/// equal.  Dicts are compared as lists of entries sorted by key.
+#[derive(Debug, Clone)]
 pub enum Value {
     Str(String),
     Int(i64),
     Float(f64),
+    Duration(Duration),
+    ByteSize(u64),
+    DateTime(DateTime),
+    Bytes(Vec<u8>),
     Bool(bool),
     Ident(String),
     Dict(Dict),
@@ -74,6 +102,26 @@ impl Value {
         Value::Float(s)
     }
 
+    /// Construct a new duration `Value`.
+    pub fn new_duration(d: Duration) -> Self {
+        Value::Duration(d)
+    }
+
+    /// Construct a new byte size `Value`, from a number of bytes.
+    pub fn new_byte_size(bytes: u64) -> Self {
+        Value::ByteSize(bytes)
+    }
+
+    /// Construct a new date and time `Value`.
+    pub fn new_datetime(d: DateTime) -> Self {
+        Value::DateTime(d)
+    }
+
+    /// Construct a new bytes `Value`.
+    pub fn new_bytes<B>(b: B) -> Self where B: Into<Vec<u8>> {
+        Value::Bytes(b.into())
+    }
+
     /// Construct a new boolean `Value`.
     pub fn new_bool(s: bool) -> Self {
         Value::Bool(s)
@@ -88,10 +136,18 @@ impl Value {
         match val {
             ParsedValue::Str(s) => Self::new_string(s),
             ParsedValue::Float(f) => Self::new_float(f),
+            ParsedValue::Duration(d) => Self::new_duration(d),
+            ParsedValue::ByteSize(b) => Self::new_byte_size(b),
+            ParsedValue::DateTime(d) => Self::new_datetime(d),
+            ParsedValue::Bytes(b, _) => Self::new_bytes(b),
+            ParsedValue::Literal(literal) => literal.value,
             ParsedValue::Bool(b) => Self::new_bool(b),
             ParsedValue::Int(i) => Self::new_int(i),
             ParsedValue::Ident(i) => Self::new_ident(i),
             ParsedValue::Null => Self::new_null(),
+            // placeholders, until `Figtree` resolves the references
+            ParsedValue::Alias(_) | ParsedValue::Reference(_) | ParsedValue::Interpolated(_) =>
+                Self::new_null(),
         }
     }
 
@@ -119,6 +175,93 @@ impl Value {
         }
     }
 
+    /// Get the value as a duration.
+    ///
+    /// Durations are returned as they are, and strings are parsed as durations such as
+    /// `"30s"` or `"1h30m"`.  Returns `None` for strings that are not durations and for
+    /// any other kind of value.
+    ///
+    /// # Examples
+    /// ```
+    /// # use figtree::types::Value;
+    /// # use std::time::Duration;
+    /// assert_eq!(Value::new_string("1.5h").as_duration(), Some(Duration::from_secs(5400)));
+    /// assert_eq!(Value::new_string("soon").as_duration(), None);
+    /// ```
+    pub fn as_duration(&self) -> Option<Duration> {
+        match *self {
+            Value::Duration(d) => Some(d),
+            Value::Str(ref s) => units::parse_duration(s).ok(),
+            _ => None
+        }
+    }
+
+    /// Get the value as a number of bytes.
+    ///
+    /// Byte sizes are returned as they are, non-negative integers are taken to be a
+    /// number of bytes, and strings are parsed as sizes such as `"512MiB"` or `"1.5GB"`.
+    /// Returns `None` for anything else.
+    ///
+    /// # Examples
+    /// ```
+    /// # use figtree::types::Value;
+    /// assert_eq!(Value::new_string("2KiB").as_byte_size(), Some(2048));
+    /// assert_eq!(Value::new_int(100).as_byte_size(), Some(100));
+    /// ```
+    pub fn as_byte_size(&self) -> Option<u64> {
+        match *self {
+            Value::ByteSize(b) => Some(b),
+            Value::Int(i) => u64::try_from(i).ok(),
+            Value::Str(ref s) => units::parse_byte_size(s).ok(),
+            _ => None
+        }
+    }
+
+    /// Extract the contained value if it is a date, time, or date and time
+    pub fn get_datetime(&self) -> Option<DateTime> {
+        match *self {
+            Value::DateTime(d) => Some(d),
+            _ => None
+        }
+    }
+
+    /// Extract the contained value if it is a byte string
+    pub fn get_bytes(&self) -> Option<&[u8]> {
+        match *self {
+            Value::Bytes(ref b) => Some(b),
+            _ => None
+        }
+    }
+
+    /// Extract a mutable reference to the contained value if it is a byte string
+    pub fn get_bytes_mut(&mut self) -> Option<&mut Vec<u8>> {
+        match *self {
+            Value::Bytes(ref mut b) => Some(b),
+            _ => None
+        }
+    }
+
+    /// Get the value as an instant.
+    ///
+    /// Dates and times with an offset are converted with `DateTime::to_system_time`, and
+    /// strings are parsed as RFC 3339 dates and times first.  Returns `None` for anything
+    /// that is not a single instant.
+    ///
+    /// # Examples
+    /// ```
+    /// # use figtree::types::Value;
+    /// # use std::time::UNIX_EPOCH;
+    /// let value = Value::new_string("1970-01-01T00:00:00Z");
+    /// assert_eq!(value.as_system_time(), Some(UNIX_EPOCH));
+    /// ```
+    pub fn as_system_time(&self) -> Option<SystemTime> {
+        match *self {
+            Value::DateTime(d) => d.to_system_time(),
+            Value::Str(ref s) => s.parse::<DateTime>().ok().and_then(|d| d.to_system_time()),
+            _ => None
+        }
+    }
+
     /// Extract the contained value if it is a boolean
     pub fn get_bool(&self) -> Option<bool> {
         match *self {
@@ -151,12 +294,542 @@ impl Value {
         }
     }
 
+    /// Extract a mutable reference to the contained value if it is a dict
+    pub fn get_dict_mut(&mut self) -> Option<&mut Dict> {
+        match *self {
+            Value::Dict(ref mut s) => Some(s),
+            _ => None
+        }
+    }
+
+    /// Extract a mutable reference to the contained value if it is a list
+    pub fn get_list_mut(&mut self) -> Option<&mut List> {
+        match *self {
+            Value::List(ref mut s) => Some(s),
+            _ => None
+        }
+    }
+
     pub fn is_null(&self) -> bool {
         match *self {
             Value::Null => true,
             _ => false,
         }
     }
+
+    /// Get the name of the kind of this value, for use in error messages.
+    ///
+    /// One of `"string"`, `"int"`, `"float"`, `"duration"`, `"byte size"`, `"datetime"`,
+    /// `"bytes"`, `"bool"`, `"identifier"`, `"dict"`, `"list"` or `"null"`.
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            Value::Str(_) => "string",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Duration(_) => "duration",
+            Value::ByteSize(_) => "byte size",
+            Value::DateTime(_) => "datetime",
+            Value::Bytes(_) => "bytes",
+            Value::Bool(_) => "bool",
+            Value::Ident(_) => "identifier",
+            Value::Dict(_) => "dict",
+            Value::List(_) => "list",
+            Value::Null => "null",
+        }
+    }
+
+    // the position of this kind of value in the ordering between kinds
+    fn rank(&self) -> u8 {
+        match *self {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Int(_) => 2,
+            Value::Float(_) => 3,
+            Value::Duration(_) => 4,
+            Value::ByteSize(_) => 5,
+            Value::DateTime(_) => 6,
+            Value::Bytes(_) => 7,
+            Value::Str(_) => 8,
+            Value::Ident(_) => 9,
+            Value::List(_) => 10,
+            Value::Dict(_) => 11,
+        }
+    }
+}
+
+fn cmp_floats(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.partial_cmp(&b).unwrap(),
+    }
+}
+
+fn hash_float<H: Hasher>(f: f64, state: &mut H) {
+    // values that compare equal must hash the same
+    let f = if f.is_nan() {
+        f64::NAN
+    } else if f == 0.0 {
+        0.0
+    } else {
+        f
+    };
+    f.to_bits().hash(state);
+}
+
+// Splits a finite float into an odd mantissa and a power of two, so that floats can be
+// compared exactly with the numbers written in the source.
+fn float_parts(f: f64) -> (u64, i64) {
+    let bits = f.abs().to_bits();
+    let exponent = (bits >> 52) as i64;
+    let fraction = bits & ((1 << 52) - 1);
+    let (mantissa, exponent) = if exponent == 0 {
+        (fraction, -1074)
+    } else {
+        (fraction | 1 << 52, exponent - 1075)
+    };
+    if mantissa == 0 {
+        return (0, 0);
+    }
+    let zeros = mantissa.trailing_zeros();
+    (mantissa >> zeros, exponent + i64::from(zeros))
+}
+
+// Removes trailing zeros from a string of digits, adding them to a power of the base.
+fn strip_zeros(digits: &str, exponent: i64) -> (&str, i64) {
+    let digits = digits.trim_start_matches('0');
+    let significant = digits.trim_end_matches('0');
+    (significant, exponent + (digits.len() - significant.len()) as i64)
+}
+
+// Tests whether a float literal, in decimal or hex, has more precision than the float it
+// was read as.  Hex is compared bit for bit, and decimal with the shortest decimal that
+// reads back as the same float, so `0.1` holds its precision but `0.1000000000000000001`
+// does not.
+fn float_literal_loses_precision(lexeme: &str, value: f64) -> bool {
+    let text: String = lexeme.trim_start_matches(['+', '-']).chars()
+        .filter(|&ch| ch != '_')
+        .collect();
+    if !value.is_finite() {
+        return text != "inf";
+    }
+
+    if let Some(hex) = text.strip_prefix("0x") {
+        let (number, exponent) = hex.split_once(['p', 'P']).unwrap_or((hex, "0"));
+        let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+        let written = format!("{}{}", whole, fraction);
+        let (digits, shift) = strip_zeros(&written, -(fraction.len() as i64));
+        let parsed = (u64::from_str_radix(digits, 16), exponent.parse::<i64>());
+        let (mantissa, exponent) = match parsed {
+            (Ok(mantissa), Ok(exponent)) => (mantissa, exponent),
+            _ => { return !(digits.is_empty() && value == 0.0); },
+        };
+        let zeros = mantissa.trailing_zeros();
+        return (mantissa >> zeros, exponent + 4 * shift + i64::from(zeros)) != float_parts(value);
+    }
+
+    decimal_parts(&text) != decimal_parts(&format!("{:e}", value.abs()))
+}
+
+// Splits a decimal number into its significant digits and a power of ten, or `None` if
+// it is not a decimal number.
+fn decimal_parts(text: &str) -> Option<(String, i64)> {
+    let (number, exponent) = text.split_once(['e', 'E']).unwrap_or((text, "0"));
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    let exponent: i64 = exponent.parse().ok()?;
+    let written = format!("{}{}", whole, fraction);
+    let (digits, power) = strip_zeros(&written, exponent - fraction.len() as i64);
+    // every way of writing zero is the same
+    Some(if digits.is_empty() { (String::new(), 0) } else { (digits.to_string(), power) })
+}
+
+fn sorted_entries<T>(map: &HashMap<String, T>) -> Vec<(&String, &T)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+/// Unlike `f64`, a float value is equal to itself even if it is NaN, so that a document
+/// containing `nan` is equal to itself and to a copy of itself.
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Str(a), Value::Str(b)) => a.cmp(b),
+            (Value::Int(a), Value::Int(b)) => a.cmp(b),
+            (Value::Float(a), Value::Float(b)) => cmp_floats(*a, *b),
+            (Value::Duration(a), Value::Duration(b)) => a.cmp(b),
+            (Value::ByteSize(a), Value::ByteSize(b)) => a.cmp(b),
+            (Value::DateTime(a), Value::DateTime(b)) => a.cmp(b),
+            (Value::Bytes(a), Value::Bytes(b)) => a.cmp(b),
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Ident(a), Value::Ident(b)) => a.cmp(b),
+            (Value::List(a), Value::List(b)) => a.cmp(b),
+            (Value::Dict(a), Value::Dict(b)) => {
+                if a.len() == b.len() && a.iter().all(|(k, v)| b.get(k) == Some(v)) {
+                    Ordering::Equal
+                } else {
+                    sorted_entries(a).cmp(&sorted_entries(b))
+                }
+            },
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rank().hash(state);
+        match *self {
+            Value::Str(ref s) | Value::Ident(ref s) => s.hash(state),
+            Value::Int(i) => i.hash(state),
+            Value::Float(f) => hash_float(f, state),
+            Value::Duration(d) => d.hash(state),
+            Value::ByteSize(b) => b.hash(state),
+            Value::DateTime(d) => d.hash(state),
+            Value::Bytes(ref b) => b.hash(state),
+            Value::Bool(b) => b.hash(state),
+            Value::List(ref list) => list.hash(state),
+            Value::Dict(ref dict) => sorted_entries(dict).hash(state),
+            Value::Null => {},
+        }
+    }
+}
+
+/// Values are displayed as figtree source, as they would be written by the `writer`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&writer::value_to_string(self))
+    }
+}
+
+static NULL: Value = Value::Null;
+
+/// Look up a key in a dict.
+///
+/// Returns `Value::Null` if this is not a dict or the key is not present, so that
+/// lookups can be chained without checking each step.
+impl Index<&str> for Value {
+    type Output = Value;
+
+    fn index(&self, key: &str) -> &Value {
+        match *self {
+            Value::Dict(ref dict) => dict.get(key).unwrap_or(&NULL),
+            _ => &NULL,
+        }
+    }
+}
+
+/// Look up an element of a list.
+///
+/// Returns `Value::Null` if this is not a list or the index is out of bounds, so that
+/// lookups can be chained without checking each step.
+impl Index<usize> for Value {
+    type Output = Value;
+
+    fn index(&self, index: usize) -> &Value {
+        match *self {
+            Value::List(ref list) => list.get(index).unwrap_or(&NULL),
+            _ => &NULL,
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Self {
+        Value::Int(i)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(i: i32) -> Self {
+        Value::Int(i64::from(i))
+    }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Self {
+        Value::Float(f)
+    }
+}
+
+impl From<Duration> for Value {
+    fn from(d: Duration) -> Self {
+        Value::Duration(d)
+    }
+}
+
+impl From<DateTime> for Value {
+    fn from(d: DateTime) -> Self {
+        Value::DateTime(d)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Str(s)
+    }
+}
+
+/// `None` is converted to `Value::Null`.
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(option: Option<T>) -> Self {
+        option.map_or(Value::Null, Into::into)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(list: Vec<T>) -> Self {
+        Value::List(list.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<S: Into<String>, T: Into<Value>> From<HashMap<S, T>> for Value {
+    fn from(dict: HashMap<S, T>) -> Self {
+        Value::Dict(dict.into_iter().map(|(k, v)| (k.into(), v.into())).collect())
+    }
+}
+
+/// The error returned when a `Value` is converted to a type that it does not hold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WrongType {
+    /// The kind of value that was expected, as given by `Value::type_name`.
+    pub expected: &'static str,
+    /// The kind of value that was found.
+    pub found: &'static str,
+}
+
+impl fmt::Display for WrongType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expected {}, found {}", self.expected, self.found)
+    }
+}
+
+impl WrongType {
+    fn new(expected: &'static str, value: &Value) -> Self {
+        WrongType { expected, found: value.type_name() }
+    }
+}
+
+impl TryFrom<&Value> for i64 {
+    type Error = WrongType;
+
+    fn try_from(value: &Value) -> Result<Self, WrongType> {
+        value.get_int().ok_or_else(|| WrongType::new("int", value))
+    }
+}
+
+impl TryFrom<&Value> for f64 {
+    type Error = WrongType;
+
+    fn try_from(value: &Value) -> Result<Self, WrongType> {
+        value.get_float().ok_or_else(|| WrongType::new("float", value))
+    }
+}
+
+impl TryFrom<&Value> for bool {
+    type Error = WrongType;
+
+    fn try_from(value: &Value) -> Result<Self, WrongType> {
+        value.get_bool().ok_or_else(|| WrongType::new("bool", value))
+    }
+}
+
+impl TryFrom<&Value> for String {
+    type Error = WrongType;
+
+    fn try_from(value: &Value) -> Result<Self, WrongType> {
+        value.get_str().map(str::to_string).ok_or_else(|| WrongType::new("string", value))
+    }
+}
+
+/// Strings are parsed as durations, as with `Value::as_duration`.
+impl TryFrom<&Value> for Duration {
+    type Error = WrongType;
+
+    fn try_from(value: &Value) -> Result<Self, WrongType> {
+        value.as_duration().ok_or_else(|| WrongType::new("duration", value))
+    }
+}
+
+/// An annotation attached to an attribute or node, such as `@secret` or
+/// `@deprecated("use timeout_ms")`.
+///
+/// Annotations hold metadata about an entry, rather than being part of its value, and
+/// are left for tools and applications to act on.  The annotations `@secret`,
+/// `@deprecated` and `@doc` are conventional, but any name can be used.
+///
+/// # Examples
+/// ```
+/// use figtree::Figtree;
+/// let doc = Figtree::from_string("@deprecated('use timeout_ms') 'timeout': 30")
+///     .parse().ok().expect("invalid document");
+/// let annotation = doc.attr_annotation("timeout", "deprecated").expect("no annotation");
+/// assert_eq!(annotation.message(), Some("use timeout_ms"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Annotation {
+    pub name: String,
+    pub args: Vec<Value>,
+}
+
+impl Annotation {
+    /// Construct an annotation without any arguments.
+    pub fn new<S>(name: S) -> Self where S: Into<String> {
+        Annotation { name: name.into(), args: Vec::new() }
+    }
+
+    /// Construct an annotation with the given arguments.
+    pub fn with_args<S>(name: S, args: Vec<Value>) -> Self where S: Into<String> {
+        Annotation { name: name.into(), args }
+    }
+
+    /// Get the first argument of the annotation, if it is a string, as in the message of
+    /// `@deprecated("use timeout_ms")` or the text of `@doc("...")`.
+    pub fn message(&self) -> Option<&str> {
+        self.args.first().and_then(Value::get_str)
+    }
+}
+
+/// The encoding a byte string was written in, kept so that it can be written back in
+/// the same encoding.
+///
+/// # Examples
+/// ```
+/// use figtree::Figtree;
+/// use figtree::types::BytesEncoding;
+/// let doc = Figtree::from_string("'key': hex\"00ff\"").parse().ok().expect("invalid document");
+/// assert_eq!(doc.attr_encoding("key"), BytesEncoding::Hex);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum BytesEncoding {
+    /// Base64, as in `b64"AP8="`, which is how bytes are written by default.
+    #[default]
+    Base64,
+    /// Hex, as in `hex"00ff"`.
+    Hex,
+}
+
+// How a number or byte string inside an attribute was written.
+#[derive(Debug, Clone)]
+pub(crate) enum SourceForm {
+    Number(NumericLiteral),
+    Bytes(BytesEncoding),
+}
+
+/// The source text of a number, kept so that it can be written back in the same form.
+///
+/// The lexer reduces `0xFF_FF`, `0b1010`, `1_000_000` and `8.e4` to plain numbers, so a
+/// literal is kept alongside each number in an attribute, including those in its lists
+/// and dicts, that was written in a form the writer would not reproduce, or with more
+/// precision than a float can hold.  The writer then writes the literal rather than the
+/// value, as long as the value has not changed.
+///
+/// # Examples
+/// ```
+/// use figtree::Figtree;
+/// let doc = Figtree::from_string("'mask': 0xFF_FF, 'ratio': 0.1000000000000000001")
+///     .parse().ok().expect("invalid document");
+/// let mask = doc.attr_literal("mask").expect("no literal");
+/// assert_eq!((mask.lexeme(), mask.radix()), ("0xFF_FF", 16));
+/// assert!(doc.attr_literal("ratio").expect("no literal").loses_precision());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NumericLiteral {
+    lexeme: String,
+    radix: u32,
+    value: Value,
+}
+
+impl NumericLiteral {
+    // The radix is given by the prefix of the lexeme, after any sign.
+    pub(crate) fn new<S>(lexeme: S, value: Value) -> Self where S: Into<String> {
+        let lexeme = lexeme.into();
+        let radix = match lexeme.trim_start_matches(['+', '-']).get(..2) {
+            Some("0x") => 16,
+            Some("0o") => 8,
+            Some("0b") => 2,
+            _ => 10,
+        };
+        NumericLiteral { lexeme, radix, value }
+    }
+
+    /// Get the source text of the number, as in `0xFF_FF`.
+    pub fn lexeme(&self) -> &str {
+        &self.lexeme
+    }
+
+    /// Get the radix the number was written in: 2, 8, 10 or 16.
+    pub fn radix(&self) -> u32 {
+        self.radix
+    }
+
+    /// Get the value the number was read as, which is an int or a float.
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    /// Test whether the number was written with more precision than a float can hold, as
+    /// in `0.1000000000000000001` or `9007199254740993.0`, so that writing the value
+    /// would give a different number.  Ints never lose precision, and neither do floats
+    /// such as `0.1` that read and write back as the same number, although the float is
+    /// not exactly one tenth.
+    pub fn loses_precision(&self) -> bool {
+        match self.value {
+            Value::Float(f) => float_literal_loses_precision(&self.lexeme, f),
+            _ => false,
+        }
+    }
+
+    // Whether anything about the literal would be lost by writing its value instead.
+    pub(crate) fn would_be_lost(&self) -> bool {
+        self.lexeme != self.value.to_string() || self.loses_precision()
+    }
+}
+
+/// Parse a single int or float literal, as it would be written in a document.
+impl FromStr for NumericLiteral {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        let mut lexer = Lexer::lex(io::Cursor::new(text.to_string().into_bytes()));
+        let value = match lexer.next() {
+            Some(Ok(LexToken::IntegerLit(i))) => Value::new_int(i),
+            Some(Ok(LexToken::FloatLit(f))) => Value::new_float(f),
+            Some(Err(err)) => { return Err(err.to_string()); },
+            _ => { return Err(format!("`{}` is not a number", text)); },
+        };
+        if lexer.numeric_lexeme() != text {
+            return Err(format!("`{}` is not a number", text));
+        }
+        Ok(NumericLiteral::new(text, value))
+    }
 }
 
 /// A struct representing an individual node in a parsed document
@@ -197,10 +870,47 @@ impl Value {
 /// node.insert_attr("key", Value::new_int(5));
 /// assert!(node.attr_count() == 1);
 /// ```
-#[derive(Debug, PartialEq)]
+///
+/// Repeated subnodes
+///
+/// A node can hold several subnodes with the same name, in order.  Methods that get a
+/// single subnode by name use the first one.
+///
+/// ```
+/// use figtree::types::*;
+/// let mut node = Node::new();
+/// node.push_node("server", Node::new());
+/// node.push_node("server", Node::new());
+/// assert!(node.get_nodes("server").len() == 2);
+/// assert!(node.node_count() == 2);
+/// ```
+///
+/// Annotations
+///
+/// A node keeps the annotations written before each of its attributes, and each subnode
+/// keeps the annotations written before it.  Annotations are metadata, so they are not
+/// taken into account when comparing or hashing nodes, and neither are the
+/// `NumericLiteral`s and `BytesEncoding`s kept for attributes.
+///
+/// ```
+/// use figtree::types::*;
+/// let mut node = Node::new();
+/// node.insert_attr("password", Value::new_string("hunter2"));
+/// node.set_attr_annotations("password", vec![Annotation::new("secret")]);
+/// assert!(node.attr_annotation("password", "secret").is_some());
+/// ```
+#[derive(Debug, Clone)]
 pub struct Node {
-    subnodes: HashMap<String, Node>,
+    // the lists of nodes are never empty
+    subnodes: HashMap<String, Vec<Node>>,
     attributes: HashMap<String, Value>,
+    labels: Vec<String>,
+    annotations: Vec<Annotation>,
+    // the annotations of attributes, which are never empty
+    attr_annotations: HashMap<String, Vec<Annotation>>,
+    // how numbers and byte strings were written in attributes, by their path within
+    // their attribute's value, which may be out of date
+    attr_literals: HashMap<String, HashMap<Path, SourceForm>>,
 }
 
 impl Node {
@@ -209,50 +919,185 @@ impl Node {
         Node {
             subnodes: HashMap::new(),
             attributes: HashMap::new(),
+            labels: Vec::new(),
+            annotations: Vec::new(),
+            attr_annotations: HashMap::new(),
+            attr_literals: HashMap::new(),
         }
     }
 
+    /// Construct a new, empty node with the given labels.
+    pub fn with_labels<I, S>(labels: I) -> Self
+        where I: IntoIterator<Item=S>, S: Into<String> {
+
+        let mut node = Node::new();
+        node.set_labels(labels);
+        node
+    }
+
+    /// Get the labels written after the name of this node, as in `backend "api" {}`.
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// Replace the labels of this node.
+    pub fn set_labels<I, S>(&mut self, labels: I) where I: IntoIterator<Item=S>, S: Into<String> {
+        self.labels = labels.into_iter().map(Into::into).collect();
+    }
+
+    /// Get the annotations written before this node, as in `@doc("...") server {}`.
+    pub fn annotations(&self) -> &[Annotation] {
+        &self.annotations
+    }
+
+    /// Get the annotation of this node with the given name.
+    pub fn annotation(&self, name: &str) -> Option<&Annotation> {
+        self.annotations.iter().find(|annotation| annotation.name == name)
+    }
+
+    /// Replace the annotations of this node.
+    pub fn set_annotations(&mut self, annotations: Vec<Annotation>) {
+        self.annotations = annotations;
+    }
+
     /// Construct a new node and automatically insert it as a subnode.
     ///
     /// Returns a mutable reference to the new node.  If there is a subnode already
     /// present with the given name, this method will not insert a new node and instead
     /// just return the old node.
     pub fn new_node_or_get<S>(&mut self, name: S) -> &mut Self where S: Into<String> {
-        self.subnodes.entry(name.into()).or_insert(Self::new())
+        let nodes = self.subnodes.entry(name.into()).or_default();
+        if nodes.is_empty() {
+            nodes.push(Node::new());
+        }
+        &mut nodes[0]
     }
 
     /// Inserts a node into this node as a subnode.
     ///
-    /// If there is already a node with the given name, replace it and return the
-    /// old node.
+    /// If there are already nodes with the given name, replace all of them and return
+    /// the first old node.
     pub fn insert_node<S>(&mut self, name: S, node: Node) -> Option<Node>
         where S: Into<String> {
 
-        self.subnodes.insert(name.into(), node)
+        self.subnodes.insert(name.into(), vec![node])
+            .and_then(|nodes| nodes.into_iter().next())
+    }
+
+    /// Add a subnode after any other subnodes with the same name.
+    pub fn push_node<S>(&mut self, name: S, node: Node) where S: Into<String> {
+        self.subnodes.entry(name.into()).or_default().push(node);
+    }
+
+    /// Insert a subnode among the subnodes with the same name, before the one at the
+    /// given index.
+    ///
+    /// # Panics
+    /// Panics if the index is greater than the number of subnodes with the name.
+    pub fn insert_node_at<S>(&mut self, name: S, index: usize, node: Node)
+        where S: Into<String> {
+
+        self.subnodes.entry(name.into()).or_default().insert(index, node);
+    }
+
+    /// Remove a subnode from this node.
+    ///
+    /// If there are several subnodes with the name, all of them are removed.  Returns
+    /// the first deleted node.
+    pub fn delete_node<S>(&mut self, name: S) -> Option<Node> where S: Into<String> {
+        self.delete_nodes(name).into_iter().next()
+    }
+
+    /// Remove all of the subnodes with the given name.
+    ///
+    /// Returns the deleted nodes, in order.
+    pub fn delete_nodes<S>(&mut self, name: S) -> Vec<Node> where S: Into<String> {
+        self.subnodes.remove(&name.into()).unwrap_or_default()
     }
 
-    /// Remove a subnode from this node.
+    /// Remove one of the subnodes with the given name.
     ///
-    /// Returns the deleted node.
-    pub fn delete_node<S>(&mut self, name: S) -> Option<Node> where S: Into<String> {
-        self.subnodes.remove(&name.into())
+    /// Returns the deleted node, or `None` if there is no subnode at the index.
+    pub fn remove_node_at<S>(&mut self, name: S, index: usize) -> Option<Node>
+        where S: Into<String> {
+
+        let name = name.into();
+        let (removed, now_empty) = match self.subnodes.get_mut(&name) {
+            Some(nodes) if index < nodes.len() => (nodes.remove(index), nodes.is_empty()),
+            _ => { return None; },
+        };
+        if now_empty {
+            self.subnodes.remove(&name);
+        }
+        Some(removed)
     }
 
     /// Get a reference to the specified subnode
+    ///
+    /// If there are several subnodes with the name, this is the first of them.
     pub fn get_node<S>(&self, name: S) -> Option<&Self> where S: Into<String> {
-        self.subnodes.get(&name.into())
+        self.subnodes.get(&name.into()).and_then(|nodes| nodes.first())
     }
 
     /// Get a mutable reference to the specified subnode
+    ///
+    /// If there are several subnodes with the name, this is the first of them.
     pub fn get_node_mut<S>(&mut self, name: S) -> Option<&mut Self>
         where S: Into<String> {
 
-        self.subnodes.get_mut(&name.into())
+        self.subnodes.get_mut(&name.into()).and_then(|nodes| nodes.first_mut())
+    }
+
+    /// Get a reference to the first subnode with the given name and labels.
+    pub fn get_labelled_node<S, L>(&self, name: S, labels: &[L]) -> Option<&Self>
+        where S: Into<String>, L: AsRef<str> {
+
+        self.get_nodes(name).iter().find(|node| node.has_labels(labels))
+    }
+
+    /// Get a mutable reference to the first subnode with the given name and labels.
+    pub fn get_labelled_node_mut<S, L>(&mut self, name: S, labels: &[L]) -> Option<&mut Self>
+        where S: Into<String>, L: AsRef<str> {
+
+        self.get_nodes_mut(name).iter_mut().find(|node| node.has_labels(labels))
+    }
+
+    /// Test if this node has exactly the given labels.
+    pub fn has_labels<L>(&self, labels: &[L]) -> bool where L: AsRef<str> {
+        self.labels.len() == labels.len()
+            && self.labels.iter().zip(labels).all(|(a, b)| a == b.as_ref())
+    }
+
+    /// Get all of the subnodes with the given name, in order.
+    pub fn get_nodes<S>(&self, name: S) -> &[Node] where S: Into<String> {
+        match self.subnodes.get(&name.into()) {
+            Some(nodes) => nodes,
+            None => &[],
+        }
+    }
+
+    /// Get mutable references to all of the subnodes with the given name, in order.
+    pub fn get_nodes_mut<S>(&mut self, name: S) -> &mut [Node] where S: Into<String> {
+        match self.subnodes.get_mut(&name.into()) {
+            Some(nodes) => nodes,
+            None => &mut [],
+        }
+    }
+
+    /// Get an iterator over the subnodes with the given name, in order.
+    pub fn iter_nodes_named<S>(&self, name: S) -> slice::Iter<'_, Node> where S: Into<String> {
+        self.get_nodes(name).iter()
     }
 
     /// Get an iterable of (&name, &node) pairs
-    pub fn iter_nodes(&self) -> Iter<String, Node> {
-        self.subnodes.iter()
+    ///
+    /// Repeated subnodes are each produced in turn, in order.
+    pub fn iter_nodes(&self) -> NodeIter<'_> {
+        NodeIter {
+            names: self.subnodes.iter(),
+            current: None,
+            remaining: self.node_count(),
+        }
     }
 
     /// Insert a new value into this node.
@@ -266,11 +1111,141 @@ impl Node {
     }
 
 
-    /// Remove an attribute from this node.
+    /// Remove an attribute from this node, along with its annotations.
     ///
     /// Returns the deleted value.
     pub fn delete_attr<S>(&mut self, name: S) -> Option<Value> where S: Into<String> {
-        self.attributes.remove(&name.into())
+        let name = name.into();
+        self.attr_annotations.remove(&name);
+        self.attr_literals.remove(&name);
+        self.attributes.remove(&name)
+    }
+
+    /// Get the annotations written before an attribute, as in `@secret "password": ""`.
+    pub fn attr_annotations<S>(&self, name: S) -> &[Annotation] where S: Into<String> {
+        self.attr_annotations.get(&name.into()).map_or(&[], Vec::as_slice)
+    }
+
+    /// Get the annotation of an attribute with the given name.
+    pub fn attr_annotation<S>(&self, name: S, annotation: &str) -> Option<&Annotation>
+        where S: Into<String> {
+
+        self.attr_annotations(name).iter().find(|candidate| candidate.name == annotation)
+    }
+
+    /// Replace the annotations of an attribute.
+    pub fn set_attr_annotations<S>(&mut self, name: S, annotations: Vec<Annotation>)
+        where S: Into<String> {
+
+        if annotations.is_empty() {
+            self.attr_annotations.remove(&name.into());
+        } else {
+            self.attr_annotations.insert(name.into(), annotations);
+        }
+    }
+
+    /// Get the source text an attribute's number was written as, as in `0xFF_FF`.  Only
+    /// numbers that the writer would not write the same way are kept, and the literal is
+    /// forgotten once the attribute has a different value.
+    pub fn attr_literal<S>(&self, name: S) -> Option<&NumericLiteral> where S: Into<String> {
+        self.value_literal(name, &Path::new())
+    }
+
+    /// Replace the source text of an attribute's number.
+    pub fn set_attr_literal<S>(&mut self, name: S, literal: Option<NumericLiteral>)
+        where S: Into<String> {
+
+        self.set_value_literal(name, Path::new(), literal)
+    }
+
+    /// Get the source text of a number inside an attribute's list or dict, at a path
+    /// relative to the attribute's value, such as `Path::new().index(1)` for the second
+    /// element of a list.  As with `attr_literal`, the literal is forgotten once the
+    /// number has a different value.
+    pub fn value_literal<S>(&self, name: S, path: &Path) -> Option<&NumericLiteral>
+        where S: Into<String> {
+
+        let name = name.into();
+        let value = self.attributes.get(&name).and_then(|value| path.lookup_in(value));
+        match self.source_form(&name, path) {
+            Some(SourceForm::Number(literal)) if value == Some(literal.value()) => Some(literal),
+            _ => None,
+        }
+    }
+
+    /// Replace the source text of a number inside an attribute's value.
+    pub fn set_value_literal<S>(&mut self, name: S, path: Path, literal: Option<NumericLiteral>)
+        where S: Into<String> {
+
+        self.set_source_form(name.into(), path, literal.map(SourceForm::Number))
+    }
+
+    /// Get the encoding an attribute's byte string was written in.  Bytes are written
+    /// in base64 unless they were read from hex.
+    pub fn attr_encoding<S>(&self, name: S) -> BytesEncoding where S: Into<String> {
+        self.value_encoding(name, &Path::new())
+    }
+
+    /// Get the encoding of a byte string inside an attribute's list or dict, at a path
+    /// relative to the attribute's value.
+    pub fn value_encoding<S>(&self, name: S, path: &Path) -> BytesEncoding
+        where S: Into<String> {
+
+        match self.source_form(&name.into(), path) {
+            Some(&SourceForm::Bytes(encoding)) => encoding,
+            _ => BytesEncoding::default(),
+        }
+    }
+
+    /// Replace the encoding of a byte string inside an attribute's value, where an empty
+    /// path is the attribute's value itself.
+    pub fn set_value_encoding<S>(&mut self, name: S, path: Path, encoding: BytesEncoding)
+        where S: Into<String> {
+
+        let form = match encoding {
+            BytesEncoding::Base64 => None,
+            encoding => Some(SourceForm::Bytes(encoding)),
+        };
+        self.set_source_form(name.into(), path, form)
+    }
+
+    fn source_form(&self, name: &str, path: &Path) -> Option<&SourceForm> {
+        self.attr_literals.get(name).and_then(|literals| literals.get(path))
+    }
+
+    fn set_source_form(&mut self, name: String, path: Path, form: Option<SourceForm>) {
+        match form {
+            Some(form) => {
+                self.attr_literals.entry(name).or_default().insert(path, form);
+            },
+            None => {
+                if let Some(literals) = self.attr_literals.get_mut(&name) {
+                    literals.remove(&path);
+                    if literals.is_empty() {
+                        self.attr_literals.remove(&name);
+                    }
+                }
+            },
+        }
+    }
+
+    // Replaces the forms of all of the numbers and byte strings in an attribute.
+    pub(crate) fn replace_attr_literals<I>(&mut self, name: &str, literals: I)
+        where I: IntoIterator<Item=(Path, SourceForm)> {
+
+        let literals: HashMap<_, _> = literals.into_iter().collect();
+        if literals.is_empty() {
+            self.attr_literals.remove(name);
+        } else {
+            self.attr_literals.insert(name.to_string(), literals);
+        }
+    }
+
+    // Copies the forms of all of the numbers and byte strings in an attribute from
+    // another node.
+    pub(crate) fn copy_attr_literals(&mut self, name: &str, from: &Node) {
+        let literals = from.attr_literals.get(name).cloned().unwrap_or_default();
+        self.replace_attr_literals(name, literals);
     }
 
     /// Get a reference to the specified attribute value
@@ -305,9 +1280,9 @@ impl Node {
         !self.subnodes.is_empty()
     }
 
-    /// Returns the number of subnodes.
+    /// Returns the number of subnodes, counting each repeated subnode.
     pub fn node_count(&self) -> usize {
-        self.subnodes.len()
+        self.subnodes.values().map(Vec::len).sum()
     }
 
     /// Test if this node had an attribute with the given key.
@@ -326,8 +1301,118 @@ impl Node {
     }
 }
 
+/// Get the distinct sets of labels used by some lists of nodes that share a name, in
+/// the order they first appear.
+pub(crate) fn label_sets<'a>(lists: &[&'a [Node]]) -> Vec<&'a [String]> {
+    let mut sets: Vec<&[String]> = Vec::new();
+    for node in lists.iter().flat_map(|nodes| nodes.iter()) {
+        if !sets.contains(&node.labels()) {
+            sets.push(node.labels());
+        }
+    }
+    sets
+}
+
+/// Get the nodes in a list that have exactly the given labels.
+pub(crate) fn labelled<'a>(nodes: &'a [Node], labels: &[String]) -> Vec<&'a Node> {
+    nodes.iter().filter(|node| node.has_labels(labels)).collect()
+}
+
+/// An iterator over the (&name, &node) pairs of the subnodes of a node.
+pub struct NodeIter<'a> {
+    names: hash_map::Iter<'a, String, Vec<Node>>,
+    current: Option<(&'a String, slice::Iter<'a, Node>)>,
+    remaining: usize,
+}
+
+impl<'a> Iterator for NodeIter<'a> {
+    type Item = (&'a String, &'a Node);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((name, ref mut nodes)) = self.current {
+                if let Some(node) = nodes.next() {
+                    self.remaining -= 1;
+                    return Some((name, node));
+                }
+            }
+            let (name, nodes) = self.names.next()?;
+            self.current = Some((name, nodes.iter()));
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for NodeIter<'a> {}
+
+impl Default for Node {
+    fn default() -> Self {
+        Node::new()
+    }
+}
+
+// Subnodes with different labels are distinct, so only the order of subnodes that
+// share both a name and labels is significant when comparing nodes.
+fn grouped_by_labels(nodes: &[Node]) -> Vec<&Node> {
+    let mut grouped: Vec<&Node> = nodes.iter().collect();
+    grouped.sort_by(|a, b| a.labels.cmp(&b.labels));
+    grouped
+}
+
+impl PartialEq for Node {
+    fn eq(&self, other: &Node) -> bool {
+        self.labels == other.labels
+            && self.attributes == other.attributes
+            && self.subnodes.len() == other.subnodes.len()
+            && self.subnodes.iter().all(|(name, nodes)| match other.subnodes.get(name) {
+                Some(others) => grouped_by_labels(nodes) == grouped_by_labels(others),
+                None => false,
+            })
+    }
+}
+
+impl Eq for Node {}
+
+impl Hash for Node {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        sorted_entries(&self.attributes).hash(state);
+        for (name, nodes) in sorted_entries(&self.subnodes) {
+            name.hash(state);
+            grouped_by_labels(nodes).hash(state);
+        }
+        self.labels.hash(state);
+    }
+}
+
+/// Nodes are displayed as a block of figtree source, without a name.
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = String::new();
+        writer::write_block(self, 0, &mut out);
+        f.write_str(&out)
+    }
+}
+
+/// Get the value of an attribute.
+///
+/// Returns `Value::Null` if the node has no attribute with the given name, in the same
+/// way as indexing a `Value`, so that lookups can be chained without checking each step.
+impl Index<&str> for Node {
+    type Output = Value;
+
+    fn index(&self, name: &str) -> &Value {
+        self.attributes.get(name).unwrap_or(&NULL)
+    }
+}
+
 /// A struct representing a parsed figtree document.
 ///
+/// A document holds named nodes, and can also hold top-level attributes that are not
+/// inside any node.
+///
 /// # Examples
 ///
 /// ```
@@ -349,87 +1434,324 @@ impl Node {
 ///     let mut node = doc.get_node_mut("node name").expect("no such node");
 ///     // node can be modified here
 /// }
+///
+/// doc.insert_attr("version", Value::new_int(2));
+/// assert!(doc.attr_count() == 1);
 /// ```
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Document {
-    nodes: HashMap<String, Node>,
+    root: Node,
 }
 
 impl Document {
     /// Construct a new, empty document
     pub fn new() -> Self {
         Document {
-            nodes: HashMap::new(),
+            root: Node::new(),
+        }
+    }
+
+    /// Construct a document whose top-level nodes and attributes are those of a node.
+    pub fn from_node(node: Node) -> Self {
+        Document {
+            root: node,
         }
     }
 
+    /// Get the top level of the document as a node.
+    ///
+    /// The nodes of the document are the subnodes of this node, and the top-level
+    /// attributes of the document are its attributes.  This allows code that works on
+    /// nodes to work on whole documents as well.
+    pub fn as_node(&self) -> &Node {
+        &self.root
+    }
+
+    /// Get the top level of the document as a mutable node.
+    pub fn as_node_mut(&mut self) -> &mut Node {
+        &mut self.root
+    }
+
+    /// Convert the document into a node holding its top-level nodes and attributes.
+    pub fn into_node(self) -> Node {
+        self.root
+    }
+
     /// Construct a new node and insert it into the document.
     ///
     /// Returns a mutable reference to the new node.  If there is a node already
     /// present with the given name, this method will not insert a new node and instead
     /// just return the old node.
     pub fn new_node_or_get<S>(&mut self, name: S) -> &mut Node where S: Into<String> {
-        self.nodes.entry(name.into()).or_insert(Node::new())
+        self.root.new_node_or_get(name)
     }
 
     /// Inserts a node into the document.
     ///
-    /// If there is already a node with the given name, replace it and return the
-    /// old node.
+    /// If there are already nodes with the given name, replace all of them and return
+    /// the first old node.
     pub fn insert_node<S>(&mut self, name: S, node: Node) -> Option<Node>
         where S: Into<String> {
 
-        self.nodes.insert(name.into(), node)
+        self.root.insert_node(name, node)
     }
 
     /// Remove a node from the document.
     ///
-    /// Returns the deleted node, if it exists.
+    /// If there are several nodes with the name, all of them are removed.  Returns the
+    /// first deleted node, if it exists.
     pub fn delete_node<S>(&mut self, name: S) -> Option<Node> where S: Into<String> {
-        self.nodes.remove(&name.into())
+        self.root.delete_node(name)
     }
 
     /// Get a reference to a specified node
     ///
-    /// Essentially a thin wrapper around the `Document.nodes` mapping, but it allows for
-    /// &str arguments, and allows users to do common operations without having to know
-    /// about the internal structure of the node.
+    /// If there are several nodes with the name, this is the first of them.  This
+    /// allows for &str arguments, and allows users to do common operations without
+    /// having to know about the internal structure of the document.
     pub fn get_node<S>(&self, name: S) -> Option<&Node> where S: Into<String> {
-        self.nodes.get(&name.into())
+        self.root.get_node(name)
     }
 
     /// Get a mutable reference to a specified node
     ///
-    /// Essentially a thin wrapper around the `Document.nodes` mapping, but it allows for
-    /// &str arguments, and allows users to do common operations without having to know
-    /// about the internal structure of the node.
+    /// If there are several nodes with the name, this is the first of them.  This
+    /// allows for &str arguments, and allows users to do common operations without
+    /// having to know about the internal structure of the document.
     pub fn get_node_mut<S>(&mut self, name: S) -> Option<&mut Node> where S: Into<String> {
-        self.nodes.get_mut(&name.into())
+        self.root.get_node_mut(name)
+    }
+
+    /// Add a node after any other nodes with the same name.
+    pub fn push_node<S>(&mut self, name: S, node: Node) where S: Into<String> {
+        self.root.push_node(name, node)
+    }
+
+    /// Insert a node among the nodes with the same name, before the one at the given
+    /// index.
+    ///
+    /// # Panics
+    /// Panics if the index is greater than the number of nodes with the name.
+    pub fn insert_node_at<S>(&mut self, name: S, index: usize, node: Node)
+        where S: Into<String> {
+
+        self.root.insert_node_at(name, index, node)
+    }
+
+    /// Remove all of the nodes with the given name.
+    ///
+    /// Returns the deleted nodes, in order.
+    pub fn delete_nodes<S>(&mut self, name: S) -> Vec<Node> where S: Into<String> {
+        self.root.delete_nodes(name)
+    }
+
+    /// Remove one of the nodes with the given name.
+    ///
+    /// Returns the deleted node, or `None` if there is no node at the index.
+    pub fn remove_node_at<S>(&mut self, name: S, index: usize) -> Option<Node>
+        where S: Into<String> {
+
+        self.root.remove_node_at(name, index)
+    }
+
+    /// Get all of the nodes with the given name, in order.
+    pub fn get_nodes<S>(&self, name: S) -> &[Node] where S: Into<String> {
+        self.root.get_nodes(name)
+    }
+
+    /// Get mutable references to all of the nodes with the given name, in order.
+    pub fn get_nodes_mut<S>(&mut self, name: S) -> &mut [Node] where S: Into<String> {
+        self.root.get_nodes_mut(name)
+    }
+
+    /// Get a reference to the first node with the given name and labels.
+    pub fn get_labelled_node<S, L>(&self, name: S, labels: &[L]) -> Option<&Node>
+        where S: Into<String>, L: AsRef<str> {
+
+        self.root.get_labelled_node(name, labels)
+    }
+
+    /// Get a mutable reference to the first node with the given name and labels.
+    pub fn get_labelled_node_mut<S, L>(&mut self, name: S, labels: &[L]) -> Option<&mut Node>
+        where S: Into<String>, L: AsRef<str> {
+
+        self.root.get_labelled_node_mut(name, labels)
+    }
+
+    /// Get an iterator over the nodes with the given name, in order.
+    pub fn iter_nodes_named<S>(&self, name: S) -> slice::Iter<'_, Node> where S: Into<String> {
+        self.root.iter_nodes_named(name)
     }
 
     /// Get an iterable of (&name, &node) pairs
-    pub fn iter_nodes(&self) -> Iter<String, Node> {
-        self.nodes.iter()
+    ///
+    /// Repeated nodes are each produced in turn, in order.
+    pub fn iter_nodes(&self) -> NodeIter<'_> {
+        self.root.iter_nodes()
+    }
+
+    /// Insert a new top-level attribute into the document.
+    ///
+    /// If there is already a value with the given name, replace it and return the old
+    /// value.
+    pub fn insert_attr<S>(&mut self, name: S, value: Value) -> Option<Value>
+        where S: Into<String> {
+
+        self.root.insert_attr(name, value)
+    }
+
+    /// Remove a top-level attribute from the document, along with its annotations.
+    ///
+    /// Returns the deleted value.
+    pub fn delete_attr<S>(&mut self, name: S) -> Option<Value> where S: Into<String> {
+        self.root.delete_attr(name)
+    }
+
+    /// Get the annotations written before a top-level attribute.
+    pub fn attr_annotations<S>(&self, name: S) -> &[Annotation] where S: Into<String> {
+        self.root.attr_annotations(name)
+    }
+
+    /// Get the annotation of a top-level attribute with the given name.
+    pub fn attr_annotation<S>(&self, name: S, annotation: &str) -> Option<&Annotation>
+        where S: Into<String> {
+
+        self.root.attr_annotation(name, annotation)
+    }
+
+    /// Replace the annotations of a top-level attribute.
+    pub fn set_attr_annotations<S>(&mut self, name: S, annotations: Vec<Annotation>)
+        where S: Into<String> {
+
+        self.root.set_attr_annotations(name, annotations)
+    }
+
+    /// Get the source text a top-level attribute's number was written as.
+    pub fn attr_literal<S>(&self, name: S) -> Option<&NumericLiteral> where S: Into<String> {
+        self.root.attr_literal(name)
+    }
+
+    /// Replace the source text of a top-level attribute's number.
+    pub fn set_attr_literal<S>(&mut self, name: S, literal: Option<NumericLiteral>)
+        where S: Into<String> {
+
+        self.root.set_attr_literal(name, literal)
+    }
+
+    /// Get the source text of a number inside a top-level attribute's list or dict.
+    pub fn value_literal<S>(&self, name: S, path: &Path) -> Option<&NumericLiteral>
+        where S: Into<String> {
+
+        self.root.value_literal(name, path)
+    }
+
+    /// Replace the source text of a number inside a top-level attribute's value.
+    pub fn set_value_literal<S>(&mut self, name: S, path: Path, literal: Option<NumericLiteral>)
+        where S: Into<String> {
+
+        self.root.set_value_literal(name, path, literal)
+    }
+
+    /// Get the encoding a top-level attribute's byte string was written in.
+    pub fn attr_encoding<S>(&self, name: S) -> BytesEncoding where S: Into<String> {
+        self.root.attr_encoding(name)
+    }
+
+    /// Get the encoding of a byte string inside a top-level attribute's list or dict.
+    pub fn value_encoding<S>(&self, name: S, path: &Path) -> BytesEncoding
+        where S: Into<String> {
+
+        self.root.value_encoding(name, path)
+    }
+
+    /// Replace the encoding of a byte string inside a top-level attribute's value.
+    pub fn set_value_encoding<S>(&mut self, name: S, path: Path, encoding: BytesEncoding)
+        where S: Into<String> {
+
+        self.root.set_value_encoding(name, path, encoding)
+    }
+
+    /// Get a reference to the specified top-level attribute value
+    pub fn get_attr<S>(&self, name: S) -> Option<&Value> where S: Into<String> {
+        self.root.get_attr(name)
+    }
+
+    /// Get a mutable reference to the specified top-level attribute value
+    pub fn get_attr_mut<S>(&mut self, name: S) -> Option<&mut Value>
+        where S: Into<String> {
+
+        self.root.get_attr_mut(name)
+    }
+
+    /// Get an iterable of (&name, &attribute) pairs for the top-level attributes
+    pub fn iter_attrs(&self) -> Iter<String, Value> {
+        self.root.iter_attrs()
     }
 
-    /// Test if the document is empty - if it has no nodes.
+    /// Test if the document is empty - if it has no nodes or attributes.
     pub fn is_empty(&self) -> bool {
-        self.nodes.is_empty()
+        self.root.is_empty()
     }
 
     /// Test if the document has a given node.
     pub fn has_node(&self, name: &String) -> bool {
-        self.nodes.contains_key(name)
+        self.root.has_node(name)
     }
 
     /// Test if the document has any nodes.
     pub fn has_nodes(&self) -> bool {
-        !self.nodes.is_empty()
+        self.root.has_nodes()
     }
 
     /// Returns the number of nodes in the document.
     pub fn node_count(&self) -> usize {
-        self.nodes.len()
+        self.root.node_count()
+    }
+
+    /// Test if the document has a top-level attribute with the given key.
+    pub fn has_attr(&self, name: &String) -> bool {
+        self.root.has_attr(name)
+    }
+
+    /// Test if the document has any top-level attributes.
+    pub fn has_attrs(&self) -> bool {
+        self.root.has_attrs()
+    }
+
+    /// Returns the number of top-level attributes.
+    pub fn attr_count(&self) -> usize {
+        self.root.attr_count()
+    }
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Document::new()
+    }
+}
+
+impl From<Node> for Document {
+    fn from(node: Node) -> Self {
+        Document::from_node(node)
+    }
+}
+
+/// Documents are displayed as figtree source, as they would be written by the `writer`.
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&writer::to_string(self))
+    }
+}
+
+/// Get the value of a top-level attribute.
+///
+/// Returns `Value::Null` if the document has no top-level attribute with the given
+/// name, in the same way as indexing a `Node`.
+impl Index<&str> for Document {
+    type Output = Value;
+
+    fn index(&self, name: &str) -> &Value {
+        &self.root[name]
     }
 }
 
@@ -440,7 +1762,7 @@ mod tests {
     #[test]
     fn type_creations() {
         let doc = Document::new();
-        assert_eq!(doc.nodes.len(), 0);
+        assert_eq!(doc.root.subnodes.len(), 0);
 
         let node = Node::new();
         assert_eq!(node.subnodes.len(), 0);
@@ -566,4 +1888,157 @@ mod tests {
             Some((&("subnode".to_string()), &Node::new())));
         assert_eq!(iterable.next(), None);
     }
+
+    fn hash_of<T: Hash>(value: &T) -> u64 {
+        use std::collections::hash_map::DefaultHasher;
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn value_ordering() {
+        let nan = Value::new_float(f64::NAN);
+        assert_eq!(nan, nan.clone());
+        assert_eq!(hash_of(&nan), hash_of(&Value::new_float(-f64::NAN)));
+        assert!(nan > Value::new_float(f64::INFINITY));
+        assert_eq!(Value::new_float(0.0), Value::new_float(-0.0));
+        assert_eq!(hash_of(&Value::new_float(0.0)), hash_of(&Value::new_float(-0.0)));
+        assert!(Value::new_float(-1.5) < Value::new_float(2.0));
+        assert_ne!(Value::new_int(1), Value::new_float(1.0));
+
+        let mut values = vec![
+            Value::from(vec![1]), Value::from("a"), Value::new_float(0.5), Value::from(3),
+            Value::new_null(), Value::from(false), Value::new_ident("a")];
+        values.sort();
+        assert_eq!(values, vec![
+            Value::new_null(), Value::from(false), Value::from(3), Value::new_float(0.5),
+            Value::from("a"), Value::new_ident("a"), Value::from(vec![1])]);
+
+        let mut a = HashMap::new();
+        a.insert("x", 1);
+        a.insert("y", 2);
+        let mut b = a.clone();
+        assert_eq!(Value::from(a.clone()), Value::from(b.clone()));
+        assert_eq!(hash_of(&Value::from(a.clone())), hash_of(&Value::from(b.clone())));
+        b.insert("y", 3);
+        assert!(Value::from(a) < Value::from(b));
+    }
+
+    #[test]
+    fn value_conversions() {
+        assert_eq!(Value::from(4i64), Value::new_int(4));
+        assert_eq!(Value::from(2.5), Value::new_float(2.5));
+        assert_eq!(Value::from(true), Value::new_bool(true));
+        assert_eq!(Value::from("s".to_string()), Value::new_string("s"));
+        assert_eq!(Value::from(None::<i64>), Value::new_null());
+        assert_eq!(Value::from(vec!["a", "b"]).type_name(), "list");
+
+        let mut value = Value::from(vec![Value::from(1)]);
+        value.get_list_mut().unwrap().push(Value::from(2));
+        assert_eq!(value, Value::from(vec![1, 2]));
+        assert!(value.get_dict_mut().is_none());
+
+        let mut dict = HashMap::new();
+        dict.insert("list", value);
+        let value = Value::from(dict);
+        assert_eq!(value["list"][1], Value::from(2));
+        assert_eq!(value["list"][5], Value::new_null());
+        assert_eq!(value["missing"]["deeper"], Value::new_null());
+        assert_eq!(value.to_string(), "{\"list\": [1, 2]}");
+
+        assert_eq!(i64::try_from(&Value::new_int(4)), Ok(4));
+        assert_eq!(String::try_from(&Value::new_string("s")), Ok("s".to_string()));
+        assert_eq!(Duration::try_from(&Value::new_string("1s")), Ok(Duration::from_secs(1)));
+        let err = bool::try_from(&Value::new_int(1)).unwrap_err();
+        assert_eq!(err, WrongType { expected: "bool", found: "int" });
+        assert_eq!(err.to_string(), "expected bool, found int");
+    }
+
+    #[test]
+    fn quantity_accessors() {
+        let minute = Duration::from_secs(60);
+        assert_eq!(Value::new_duration(minute).as_duration(), Some(minute));
+        assert_eq!(Value::new_string("1m").as_duration(), Some(minute));
+        assert_eq!(Value::new_string("1").as_duration(), None);
+        assert_eq!(Value::new_int(60).as_duration(), None);
+        assert_eq!(Value::from(minute).type_name(), "duration");
+
+        assert_eq!(Value::new_byte_size(1024).as_byte_size(), Some(1024));
+        assert_eq!(Value::new_string("1KiB").as_byte_size(), Some(1024));
+        assert_eq!(Value::new_int(1024).as_byte_size(), Some(1024));
+        assert_eq!(Value::new_int(-1).as_byte_size(), None);
+        assert_eq!(Value::new_string("1KiB!").as_byte_size(), None);
+        assert_eq!(Value::new_byte_size(1).type_name(), "byte size");
+
+        assert!(Value::new_float(1e9) < Value::new_duration(minute));
+        assert!(Value::new_duration(minute) < Value::new_byte_size(0));
+        assert!(Value::new_byte_size(0) < Value::new_string(""));
+        assert_ne!(Value::new_byte_size(1024), Value::new_int(1024));
+    }
+
+    #[test]
+    fn numeric_literals() {
+        let literal = |text: &str| text.parse::<NumericLiteral>().unwrap();
+        assert_eq!(literal("0xFF_FF").value(), &Value::new_int(65535));
+        assert_eq!(literal("0xFF_FF").radix(), 16);
+        assert_eq!(literal("0b1010").radix(), 2);
+        assert_eq!(literal("0o17").radix(), 8);
+        assert_eq!(literal("8.e4").value(), &Value::new_float(80000.0));
+        assert_eq!(literal("0x1.8p3").radix(), 16);
+        assert!("0xFF ".parse::<NumericLiteral>().is_err());
+        assert!("inf".parse::<NumericLiteral>().is_err());
+        assert!("1s".parse::<NumericLiteral>().is_err());
+
+        for text in &["1_000_000", "0.1", "8.e4", "1e-7", "0.000_1", "-0.0", "0x1.8p3", "0x10p-4",
+                      "0x1p-1074", "-inf", "9007199254740992.0", "1.7976931348623157e308"] {
+            assert!(!literal(text).loses_precision(), "{}", text);
+        }
+        for text in &["0.1000000000000000001", "9007199254740993.0", "1e400", "1e-400",
+                      "0x1.00000000000001p0"] {
+            assert!(literal(text).loses_precision(), "{}", text);
+        }
+
+        assert!(literal("0xff").would_be_lost());
+        assert!(literal("+1").would_be_lost());
+        assert!(!literal("-1").would_be_lost());
+        assert!(!literal("2.5").would_be_lost());
+
+        let mut node = Node::new();
+        node.insert_attr("mask", Value::new_int(65535));
+        node.set_attr_literal("mask", Some(literal("0xFF_FF")));
+        assert_eq!(node.attr_literal("mask").map(NumericLiteral::lexeme), Some("0xFF_FF"));
+        let mut without = node.clone();
+        without.set_attr_literal("mask", None);
+        assert_eq!(node, without);
+        // a literal is only kept for the value it was written as
+        *node.get_attr_mut("mask").unwrap() = Value::new_int(255);
+        assert_eq!(node.attr_literal("mask"), None);
+        node.insert_attr("mask", Value::new_int(65535));
+        node.delete_attr("mask");
+        node.insert_attr("mask", Value::new_int(65535));
+        assert_eq!(node.attr_literal("mask"), None);
+    }
+
+    #[test]
+    fn node_and_document_traits() {
+        let mut doc = Document::default();
+        {
+            let node = doc.new_node_or_get("server");
+            node.insert_attr("port", Value::from(80));
+            node.new_node_or_get("tls");
+        }
+        doc.insert_attr("version", Value::from(2));
+        let server = doc.get_node("server").unwrap();
+        assert_eq!(server["port"], Value::from(80));
+        assert_eq!(server["missing"], Value::new_null());
+        assert_eq!(server["missing"]["deeper"], Value::new_null());
+        assert_eq!(server.to_string(), "{\n    \"port\": 80,\n\n    tls {}\n}\n");
+        assert_eq!(doc.to_string(), format!("\"version\": 2,\n\nserver {}", server));
+        assert_eq!(doc["version"], Value::from(2));
+        assert_eq!(doc["server"], Value::new_null());
+        assert_eq!(doc["missing"]["deeper"], Value::new_null());
+        assert_eq!(hash_of(&doc), hash_of(&doc.clone()));
+        assert_eq!(hash_of(&Node::default()), hash_of(&Node::new()));
+    }
 }
@@ -0,0 +1,232 @@
+//! Parsing of durations and byte sizes written with units, such as `1h30m` or `512MiB`.
+//!
+//! Quantities are made up of a decimal number followed by a unit, and a duration can be
+//! made up of several quantities in decreasing order of unit, as in `1h30m`.  Fractions
+//! are allowed as long as the result is exact, so `1.5h` is 90 minutes but `1.5ns` is
+//! an error.  Arithmetic is checked, so a quantity that is too large to represent is an
+//! error rather than wrapping around.
+//!
+//! Durations and byte sizes are written back out in their shortest exact form, so that
+//! they read back as the same value.
+
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// The units a duration can be written in, with the number of nanoseconds in each.
+pub const DURATION_UNITS: &[(&str, u128)] = &[
+    ("ns", 1),
+    ("us", 1_000),
+    ("µs", 1_000),
+    ("ms", 1_000_000),
+    ("s", 1_000_000_000),
+    ("m", 60 * 1_000_000_000),
+    ("h", 60 * 60 * 1_000_000_000),
+    ("d", 24 * 60 * 60 * 1_000_000_000),
+];
+
+/// The units a byte size can be written in, with the number of bytes in each.  Decimal
+/// prefixes are powers of 1000, and binary prefixes are powers of 1024.
+pub const BYTE_UNITS: &[(&str, u128)] = &[
+    ("B", 1),
+    ("kB", 1_000),
+    ("KB", 1_000),
+    ("MB", 1_000_000),
+    ("GB", 1_000_000_000),
+    ("TB", 1_000_000_000_000),
+    ("PB", 1_000_000_000_000_000),
+    ("EB", 1_000_000_000_000_000_000),
+    ("KiB", 1 << 10),
+    ("MiB", 1 << 20),
+    ("GiB", 1 << 30),
+    ("TiB", 1 << 40),
+    ("PiB", 1 << 50),
+    ("EiB", 1 << 60),
+];
+
+fn unit_scale(units: &[(&str, u128)], unit: &str) -> Option<u128> {
+    units.iter().find(|&&(name, _)| name == unit).map(|&(_, scale)| scale)
+}
+
+// Splits a quantity into its number and its unit, returning the rest of the text.
+fn split_quantity(text: &str) -> Option<(&str, &str, &str)> {
+    let number_end = text.find(|ch: char| !ch.is_ascii_digit() && ch != '.' && ch != '_')?;
+    let unit_end = text[number_end..].find(|ch: char| ch.is_ascii_digit())
+        .map_or(text.len(), |end| number_end + end);
+    Some((&text[..number_end], &text[number_end..unit_end], &text[unit_end..]))
+}
+
+// Multiplies a decimal number by the size of a unit, failing if the result is not a
+// whole number of the smallest unit or does not fit.
+fn scale_number(number: &str, scale: u128, smallest: &str) -> Result<u128, String> {
+    let digits: String = number.chars().filter(|&ch| ch != '_').collect();
+    let (whole, fraction) = match digits.find('.') {
+        Some(point) => (&digits[..point], &digits[point + 1..]),
+        None => (&digits[..], ""),
+    };
+    if whole.is_empty() && fraction.is_empty() || fraction.contains('.') {
+        return Err(format!("`{}` is not a number", number));
+    }
+
+    let overflow = || format!("`{}` is too large", number);
+    let whole: u128 = if whole.is_empty() { 0 } else { whole.parse().map_err(|_| overflow())? };
+    let mut total = whole.checked_mul(scale).ok_or_else(overflow)?;
+    if !fraction.is_empty() {
+        let fraction = fraction.trim_end_matches('0');
+        let denominator = 10u128.checked_pow(fraction.len() as u32)
+            .ok_or_else(|| format!("`{}` is too precise", number))?;
+        let numerator: u128 = if fraction.is_empty() { 0 } else {
+            fraction.parse().map_err(|_| format!("`{}` is too precise", number))?
+        };
+        let scaled = numerator.checked_mul(scale).ok_or_else(overflow)?;
+        if scaled % denominator != 0 {
+            return Err(format!("`{}` is more precise than {}", number, smallest));
+        }
+        total = total.checked_add(scaled / denominator).ok_or_else(overflow)?;
+    }
+    Ok(total)
+}
+
+/// Parse a duration such as `30s`, `1.5h` or `1h30m`.
+///
+/// # Failures
+/// Fails with a message if the text is not a duration, uses an unknown unit, is more
+/// precise than a nanosecond, or is too long to represent.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let invalid = || format!("`{}` is not a duration", text);
+    let mut rest = text;
+    let mut nanos: u128 = 0;
+    let mut last_scale = None;
+    while !rest.is_empty() {
+        let (number, unit, after) = split_quantity(rest).ok_or_else(invalid)?;
+        let scale = unit_scale(DURATION_UNITS, unit)
+            .ok_or_else(|| format!("`{}` is not a unit of time", unit))?;
+        if number.is_empty() || last_scale.is_some_and(|last| scale >= last) {
+            return Err(invalid());
+        }
+        let quantity = scale_number(number, scale, "a nanosecond")?;
+        nanos = nanos.checked_add(quantity).ok_or_else(|| format!("`{}` is too long", text))?;
+        last_scale = Some(scale);
+        rest = after;
+    }
+    if last_scale.is_none() {
+        return Err(invalid());
+    }
+
+    let seconds = u64::try_from(nanos / 1_000_000_000)
+        .map_err(|_| format!("`{}` is too long", text))?;
+    Ok(Duration::new(seconds, (nanos % 1_000_000_000) as u32))
+}
+
+/// Parse a byte size such as `512MiB`, `1.5GB` or `100B`.
+///
+/// # Failures
+/// Fails with a message if the text is not a byte size, uses an unknown unit, is not a
+/// whole number of bytes, or does not fit in 64 bits.
+pub fn parse_byte_size(text: &str) -> Result<u64, String> {
+    let (number, unit, rest) = split_quantity(text)
+        .ok_or_else(|| format!("`{}` has no unit", text))?;
+    if number.is_empty() || !rest.is_empty() {
+        return Err(format!("`{}` is not a byte size", text));
+    }
+    let scale = unit_scale(BYTE_UNITS, unit)
+        .ok_or_else(|| format!("`{}` is not a unit of size", unit))?;
+    let bytes = scale_number(number, scale, "a byte")?;
+    u64::try_from(bytes).map_err(|_| format!("`{}` is too large", text))
+}
+
+/// Write a duration in the largest units that add up to it exactly, as in `1h30m` or
+/// `1s500ms`.
+pub fn format_duration(duration: Duration) -> String {
+    let mut nanos = duration.as_nanos();
+    if nanos == 0 {
+        return "0s".to_string();
+    }
+    let mut text = String::new();
+    // `µs` is skipped in favour of its ASCII spelling
+    for &(unit, scale) in DURATION_UNITS.iter().rev().filter(|&&(unit, _)| unit != "µs") {
+        if nanos >= scale {
+            text.push_str(&format!("{}{}", nanos / scale, unit));
+            nanos %= scale;
+        }
+    }
+    text
+}
+
+/// Write a byte size in the largest unit that divides it exactly, preferring binary
+/// units, as in `512MiB` or `1500B`.
+pub fn format_byte_size(bytes: u64) -> String {
+    if bytes == 0 {
+        return "0B".to_string();
+    }
+    let bytes = u128::from(bytes);
+    let &(unit, scale) = BYTE_UNITS.iter().rev()
+        .filter(|&&(unit, _)| unit != "KB")
+        .find(|&&(_, scale)| bytes % scale == 0)
+        .expect("every size is a whole number of bytes");
+    format!("{}{}", bytes / scale, unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_durations() {
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_duration("2d4h1s"), Ok(Duration::from_secs(2 * 86400 + 4 * 3600 + 1)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("1_000us"), Ok(Duration::from_millis(1)));
+        assert_eq!(parse_duration("0.000000001s"), Ok(Duration::from_nanos(1)));
+        assert_eq!(parse_duration(".5s"), Ok(Duration::from_millis(500)));
+
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("30x").is_err());
+        assert!(parse_duration("30m1h").is_err());
+        assert!(parse_duration("1s1s").is_err());
+        assert!(parse_duration("1.5ns").is_err());
+        assert!(parse_duration("1.2.3s").is_err());
+        assert!(parse_duration("99999999999999999999999d").is_err());
+        assert!(parse_duration("213503982334601d").is_ok());
+        assert!(parse_duration("213503982334602d").is_err());
+    }
+
+    #[test]
+    fn format_durations() {
+        assert_eq!(format_duration(Duration::from_secs(0)), "0s");
+        assert_eq!(format_duration(Duration::from_secs(5400)), "1h30m");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1s500ms");
+        assert_eq!(format_duration(Duration::new(86400 + 1, 1)), "1d1s1ns");
+
+        let longest = Duration::new(u64::MAX, 999_999_999);
+        assert_eq!(parse_duration(&format_duration(longest)), Ok(longest));
+    }
+
+    #[test]
+    fn parse_byte_sizes() {
+        assert_eq!(parse_byte_size("100B"), Ok(100));
+        assert_eq!(parse_byte_size("512MiB"), Ok(512 << 20));
+        assert_eq!(parse_byte_size("1.5kB"), Ok(1500));
+        assert_eq!(parse_byte_size("1.5KiB"), Ok(1536));
+        assert!(parse_byte_size("16EiB").is_err());
+        assert_eq!(parse_byte_size("15EiB"), Ok(15 << 60));
+
+        assert!(parse_byte_size("100").is_err());
+        assert!(parse_byte_size("1.1B").is_err());
+        assert!(parse_byte_size("10mb").is_err());
+        assert!(parse_byte_size("1MiB2").is_err());
+    }
+
+    #[test]
+    fn format_byte_sizes() {
+        assert_eq!(format_byte_size(0), "0B");
+        assert_eq!(format_byte_size(512 << 20), "512MiB");
+        assert_eq!(format_byte_size(1536), "1536B");
+        assert_eq!(format_byte_size(2_000), "2kB");
+        assert_eq!(format_byte_size(u64::MAX), format!("{}B", u64::MAX));
+        assert_eq!(parse_byte_size(&format_byte_size(15 << 60)), Ok(15 << 60));
+    }
+}
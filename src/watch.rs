@@ -0,0 +1,496 @@
+//! Watching config files and reloading them when they change.
+//!
+//! A `Watcher` loads a figtree file, then watches it on a background thread.  When the
+//! file changes, the watcher waits for the changes to settle, parses the file again,
+//! and publishes the new document to its subscribers as an `Arc<Document>`.  If the
+//! new version cannot be read, parsed, or validated, the watcher keeps the last good
+//! document and publishes the error instead.
+//!
+//! Figtree has no way for one file to include another, so files that a config depends
+//! on (for instance, files that the application reads paths to from the config) can be
+//! listed in `WatchOptions::extra_files`.  A change to any of them reloads the main
+//! file.
+//!
+//! Changes are found by polling the modification time and size of each file, which
+//! works everywhere.  With the `inotify` feature enabled, `Backend::Inotify` can be used
+//! on Linux to be notified of changes by the kernel instead, which also catches
+//! rewrites that leave the modification time and size unchanged.
+//!
+//! # Examples
+//! ```no_run
+//! use figtree::watch::{Watcher, WatchEvent, WatchOptions};
+//!
+//! let watcher = Watcher::new("config.ft", WatchOptions::default())
+//!     .ok().expect("could not load config");
+//! let events = watcher.subscribe();
+//! for event in events {
+//!     match event {
+//!         WatchEvent::Reloaded(config) => println!("reloaded: {:?}", config),
+//!         WatchEvent::Error(err) => println!("keeping old config: {}", err),
+//!     }
+//! }
+//! ```
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use super::figtree::{Figtree, LoadOptions};
+use super::parser::ParseError;
+use super::position::Position;
+use super::types::Document;
+
+/// The way a `Watcher` finds out that files have changed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    /// Check the modification time and size of each file every `poll_interval`.
+    Poll,
+    /// Use inotify to be told about changes.  Only available on Linux, with the
+    /// `inotify` feature enabled.
+    #[cfg(feature = "inotify")]
+    Inotify,
+}
+
+/// Options controlling how a `Watcher` looks for changes, and how it loads the file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchOptions {
+    /// How the watcher finds out that files have changed.
+    pub backend: Backend,
+    /// How often to check for changes.
+    pub poll_interval: Duration,
+    /// How long the files must go unchanged before they are reloaded, so that a file
+    /// that is written in several steps is only reloaded once.
+    pub debounce: Duration,
+    /// Other files that should cause a reload when they change.
+    pub extra_files: Vec<PathBuf>,
+    /// The options the file is loaded with each time, such as its profiles and
+    /// variables.
+    pub load: LoadOptions,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions {
+            backend: Backend::Poll,
+            poll_interval: Duration::from_millis(500),
+            debounce: Duration::from_millis(200),
+            extra_files: Vec::new(),
+            load: LoadOptions::default(),
+        }
+    }
+}
+
+/// An enum representing an error that occurs while loading a watched file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchError {
+    ReadError(PathBuf, io::ErrorKind),
+    ParseError(PathBuf, ParseError, Position),
+    ValidationError(PathBuf, String),
+}
+
+/// An event published to the subscribers of a `Watcher`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchEvent {
+    /// The file was reloaded successfully.
+    Reloaded(Arc<Document>),
+    /// The file changed, but could not be loaded.  The previous document is kept.
+    Error(WatchError),
+}
+
+type Validator = Box<dyn Fn(&Document) -> Result<(), String> + Send>;
+
+struct Shared {
+    current: Mutex<Arc<Document>>,
+    subscribers: Mutex<Vec<Sender<WatchEvent>>>,
+}
+
+/// A handle to a watched config file.
+///
+/// The background thread is stopped when the watcher is dropped.
+pub struct Watcher {
+    shared: Arc<Shared>,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watcher {
+    /// Load a file and start watching it for changes.
+    ///
+    /// # Failures
+    /// Fails if the file cannot be loaded the first time, or if the inotify backend
+    /// cannot be started.
+    pub fn new<P: Into<PathBuf>>(path: P, options: WatchOptions) -> Result<Watcher, WatchError> {
+        Watcher::with_validator(path, options, |_| Ok(()))
+    }
+
+    /// Load a file and start watching it for changes, checking each version of the
+    /// document with a validation function.
+    ///
+    /// Documents that the validator rejects are treated like documents that cannot be
+    /// parsed: the message it returns is reported as a `WatchError::ValidationError`,
+    /// and the last good document is kept.
+    ///
+    /// # Failures
+    /// Fails if the file cannot be loaded or validated the first time, or if the inotify
+    /// backend cannot be started.
+    pub fn with_validator<P, F>(path: P, options: WatchOptions, validator: F)
+        -> Result<Watcher, WatchError>
+        where P: Into<PathBuf>, F: Fn(&Document) -> Result<(), String> + Send + 'static {
+
+        let path = path.into();
+        let validator: Validator = Box::new(validator);
+        let initial = load(&path, &options.load, &validator)?;
+
+        let mut files = vec![path.clone()];
+        files.extend(options.extra_files.iter().cloned());
+        let detector = Detector::new(options.backend, files)
+            .map_err(|err| WatchError::ReadError(path.clone(), err.kind()))?;
+
+        let shared = Arc::new(Shared {
+            current: Mutex::new(Arc::new(initial)),
+            subscribers: Mutex::new(Vec::new()),
+        });
+        let (stop, stopped) = mpsc::channel();
+        let thread_shared = shared.clone();
+        let thread = thread::spawn(move || {
+            run(path, options, validator, detector, thread_shared, stopped);
+        });
+
+        Ok(Watcher { shared, stop: Some(stop), thread: Some(thread) })
+    }
+
+    /// Get the most recent document that was loaded successfully.
+    pub fn current(&self) -> Arc<Document> {
+        self.shared.current.lock().unwrap().clone()
+    }
+
+    /// Subscribe to reloads and errors.
+    ///
+    /// Each subscriber receives every event published after it subscribed.  The
+    /// receiver is disconnected when the watcher is dropped.
+    pub fn subscribe(&self) -> Receiver<WatchEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.shared.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        // dropping the sender wakes the thread up and tells it to stop
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl fmt::Debug for Watcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watcher").field("current", &self.current()).finish()
+    }
+}
+
+impl fmt::Display for WatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WatchError::ReadError(ref path, kind) =>
+                write!(f, "{}: could not read file: {:?}", path.display(), kind),
+            WatchError::ParseError(ref path, ref err, ref pos) =>
+                write!(f, "{}:{}: {}", path.display(), pos, err),
+            WatchError::ValidationError(ref path, ref message) =>
+                write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+fn load(path: &Path, options: &LoadOptions, validator: &Validator) -> Result<Document, WatchError> {
+    let filename = path.to_string_lossy().into_owned();
+    let mut figgy = Figtree::from_filename(filename)
+        .map_err(|err| WatchError::ReadError(path.to_path_buf(), err.kind()))?;
+    let doc = figgy.parse_with(options)
+        .map_err(|(err, pos)| WatchError::ParseError(path.to_path_buf(), err, pos))?;
+    validator(&doc).map_err(|message| WatchError::ValidationError(path.to_path_buf(), message))?;
+    Ok(doc)
+}
+
+fn run(path: PathBuf, options: WatchOptions, validator: Validator, mut detector: Detector,
+       shared: Arc<Shared>, stopped: Receiver<()>) {
+
+    let mut pending: Option<Instant> = None;
+    loop {
+        match stopped.recv_timeout(options.poll_interval) {
+            Err(RecvTimeoutError::Timeout) => {},
+            _ => { return; },
+        }
+
+        if detector.changed() {
+            // restart the debounce period on every change
+            pending = Some(Instant::now());
+        }
+        match pending {
+            Some(since) if since.elapsed() >= options.debounce => { pending = None; },
+            _ => { continue; },
+        }
+
+        let event = match load(&path, &options.load, &validator) {
+            Ok(doc) => {
+                let doc = Arc::new(doc);
+                *shared.current.lock().unwrap() = doc.clone();
+                WatchEvent::Reloaded(doc)
+            },
+            Err(err) => WatchEvent::Error(err),
+        };
+        shared.subscribers.lock().unwrap()
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+enum Detector {
+    Poll {
+        files: Vec<PathBuf>,
+        stamps: Vec<Option<(SystemTime, u64)>>,
+    },
+    #[cfg(feature = "inotify")]
+    Inotify(inotify_backend::InotifyDetector),
+}
+
+impl Detector {
+    fn new(backend: Backend, files: Vec<PathBuf>) -> io::Result<Detector> {
+        match backend {
+            Backend::Poll => {
+                let stamps = files.iter().map(|file| stamp(file)).collect();
+                Ok(Detector::Poll { files, stamps })
+            },
+            #[cfg(feature = "inotify")]
+            Backend::Inotify => inotify_backend::InotifyDetector::new(files).map(Detector::Inotify),
+        }
+    }
+
+    fn changed(&mut self) -> bool {
+        match *self {
+            Detector::Poll { ref files, ref mut stamps } => {
+                let mut changed = false;
+                for (file, old) in files.iter().zip(stamps.iter_mut()) {
+                    let new = stamp(file);
+                    if new != *old {
+                        *old = new;
+                        changed = true;
+                    }
+                }
+                changed
+            },
+            #[cfg(feature = "inotify")]
+            Detector::Inotify(ref mut detector) => detector.changed(),
+        }
+    }
+}
+
+fn stamp(file: &Path) -> Option<(SystemTime, u64)> {
+    fs::metadata(file).ok()
+        .and_then(|metadata| metadata.modified().ok().map(|time| (time, metadata.len())))
+}
+
+#[cfg(feature = "inotify")]
+mod inotify_backend {
+    extern crate inotify;
+
+    use std::collections::HashSet;
+    use std::ffi::OsString;
+    use std::io;
+    use std::path::PathBuf;
+
+    use self::inotify::{Inotify, WatchMask};
+
+    pub struct InotifyDetector {
+        inotify: Inotify,
+        names: HashSet<OsString>,
+        buffer: Vec<u8>,
+    }
+
+    impl InotifyDetector {
+        // Watches the directories containing the files rather than the files
+        // themselves, so that files that are replaced by renaming another file over
+        // them (as many editors do) are still noticed.
+        pub fn new(files: Vec<PathBuf>) -> io::Result<InotifyDetector> {
+            let inotify = Inotify::init()?;
+            let mut names = HashSet::new();
+            for file in files {
+                let directory = match file.parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                    _ => PathBuf::from("."),
+                };
+                inotify.watches().add(
+                    directory,
+                    WatchMask::MODIFY | WatchMask::CLOSE_WRITE | WatchMask::CREATE
+                        | WatchMask::DELETE | WatchMask::MOVED_TO | WatchMask::MOVED_FROM)?;
+                if let Some(name) = file.file_name() {
+                    names.insert(name.to_os_string());
+                }
+            }
+            Ok(InotifyDetector { inotify, names, buffer: vec![0; 4096] })
+        }
+
+        pub fn changed(&mut self) -> bool {
+            let InotifyDetector { ref mut inotify, ref names, ref mut buffer } = *self;
+            let mut changed = false;
+            loop {
+                match inotify.read_events(buffer) {
+                    Ok(events) => {
+                        let mut any = false;
+                        for event in events {
+                            any = true;
+                            if event.name.map(|name| names.contains(name)).unwrap_or(false) {
+                                changed = true;
+                            }
+                        }
+                        if !any {
+                            return changed;
+                        }
+                    },
+                    Err(_) => { return changed; },
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("figtree-watch-{}-{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn options(backend: Backend, extra_files: Vec<PathBuf>) -> WatchOptions {
+        WatchOptions {
+            backend,
+            poll_interval: Duration::from_millis(10),
+            debounce: Duration::from_millis(30),
+            extra_files,
+            load: LoadOptions::default(),
+        }
+    }
+
+    fn next_event(events: &Receiver<WatchEvent>) -> WatchEvent {
+        events.recv_timeout(Duration::from_secs(10)).expect("no event published")
+    }
+
+    fn parse(source: &str) -> Document {
+        Figtree::from_string(source).parse().unwrap()
+    }
+
+    fn check_reloads(backend: Backend, name: &str) {
+        let dir = temp_dir(name);
+        let file = dir.join("config.ft");
+        let extra = dir.join("extra.txt");
+        fs::write(&file, "server { 'port': 80 }").unwrap();
+
+        let watcher = Watcher::new(file.clone(), options(backend, vec![extra.clone()])).unwrap();
+        let events = watcher.subscribe();
+        assert_eq!(*watcher.current(), parse("server { 'port': 80 }"));
+
+        fs::write(&file, "server { 'port': 8080 }").unwrap();
+        assert_eq!(
+            next_event(&events),
+            WatchEvent::Reloaded(Arc::new(parse("server { 'port': 8080 }"))));
+        assert_eq!(*watcher.current(), parse("server { 'port': 8080 }"));
+
+        fs::write(&file, "server { 'port': }").unwrap();
+        match next_event(&events) {
+            WatchEvent::Error(WatchError::ParseError(path, _, _)) => assert_eq!(path, file),
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert_eq!(*watcher.current(), parse("server { 'port': 8080 }"));
+
+        fs::write(&file, "server { 'port': 9090 }").unwrap();
+        assert!(matches!(next_event(&events), WatchEvent::Reloaded(_)));
+        fs::write(&extra, "changed").unwrap();
+        assert!(matches!(next_event(&events), WatchEvent::Reloaded(_)));
+
+        drop(watcher);
+        assert!(events.recv().is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reloads_changed_files() {
+        check_reloads(Backend::Poll, "poll");
+    }
+
+    #[cfg(feature = "inotify")]
+    #[test]
+    fn reloads_with_inotify() {
+        check_reloads(Backend::Inotify, "inotify");
+    }
+
+    #[test]
+    fn loads_with_options() {
+        let dir = temp_dir("load-options");
+        let file = dir.join("config.ft");
+        fs::write(&file, "server { 'port': 80, when(region == 'eu') { 'port': 8080 } }").unwrap();
+        assert!(matches!(
+            Watcher::new(file.clone(), options(Backend::Poll, vec![])),
+            Err(WatchError::ParseError(..))));
+
+        let mut options = options(Backend::Poll, vec![]);
+        options.load.variables.insert("region".to_string(), "eu".to_string());
+        let watcher = Watcher::new(file.clone(), options).unwrap();
+        let events = watcher.subscribe();
+        assert_eq!(*watcher.current(), parse("server { 'port': 8080 }"));
+
+        fs::write(&file, "server { 'port': 80, when(region == 'eu') { 'port': 9090 } }").unwrap();
+        assert_eq!(
+            next_event(&events),
+            WatchEvent::Reloaded(Arc::new(parse("server { 'port': 9090 }"))));
+
+        drop(watcher);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn validates_documents() {
+        let dir = temp_dir("validates");
+        let file = dir.join("config.ft");
+        let has_server = |doc: &Document| match doc.get_node("server") {
+            Some(_) => Ok(()),
+            None => Err("missing server node".to_string()),
+        };
+
+        fs::write(&file, "client {}").unwrap();
+        assert_eq!(
+            Watcher::with_validator(file.clone(), options(Backend::Poll, vec![]), has_server).unwrap_err(),
+            WatchError::ValidationError(file.clone(), "missing server node".to_string()));
+
+        fs::write(&file, "server {}").unwrap();
+        let watcher = Watcher::with_validator(file.clone(), options(Backend::Poll, vec![]), has_server).unwrap();
+        let events = watcher.subscribe();
+        fs::write(&file, "client { 'x': 1 }").unwrap();
+        assert_eq!(
+            next_event(&events),
+            WatchEvent::Error(WatchError::ValidationError(
+                file.clone(), "missing server node".to_string())));
+        assert_eq!(*watcher.current(), parse("server {}"));
+
+        drop(watcher);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_files() {
+        let file = temp_dir("missing").join("missing.ft");
+        assert_eq!(
+            Watcher::new(file.clone(), options(Backend::Poll, vec![])).unwrap_err(),
+            WatchError::ReadError(file, io::ErrorKind::NotFound));
+    }
+}
@@ -0,0 +1,576 @@
+//! Writes documents out as figtree source.
+//!
+//! The writer produces a canonical layout: one attribute per line, a trailing comma
+//! after every attribute, and four spaces of indentation per level of nesting.
+//! Attributes, nodes and dict keys are all written in sorted order, so that the same
+//! document is always written out in the same way.
+//!
+//! # Examples
+//! ```
+//! use figtree::types::*;
+//! use figtree::writer;
+//!
+//! let mut doc = Document::new();
+//! doc.new_node_or_get("server").insert_attr("port", Value::new_int(80));
+//! assert_eq!(writer::to_string(&doc), "server {\n    \"port\": 80,\n}\n");
+//! ```
+
+use std::io;
+
+use super::types::*;
+use super::encoding;
+use super::path::Path;
+use super::units;
+use super::utils::{ident_head, ident_body};
+
+/// Test whether a name can be written as a bare identifier, or whether it needs to be
+/// wrapped in backticks.
+pub(crate) fn is_bare_ident(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(ch) if ident_head(ch) => chars.all(ident_body),
+        _ => false,
+    }
+}
+
+fn write_escaped(s: &str, quote: Option<char>, out: &mut String) {
+    for ch in s.chars() {
+        match ch {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\x08' => out.push_str("\\b"),
+            '\x0c' => out.push_str("\\f"),
+            ch if Some(ch) == quote => {
+                out.push('\\');
+                out.push(ch);
+            },
+            ch if (ch as u32) < 0x20 => {
+                out.push_str(&format!("\\u{:04x}", ch as u32));
+            },
+            ch => out.push(ch),
+        }
+    }
+}
+
+/// Write a string literal, quoted with double quotes.
+pub(crate) fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    // `${` would start an interpolation, so it is written as `$${`
+    write_escaped(&s.replace("${", "$${"), Some('"'), out);
+    out.push('"');
+}
+
+/// Write a string that spans several lines as a triple-quoted string, with each line
+/// and the closing quotes indented one level deeper than `indent`.
+pub(crate) fn write_block_string(s: &str, indent: usize, out: &mut String) {
+    out.push_str("\"\"\"\n");
+    for line in s.replace("${", "$${").split('\n') {
+        if !line.is_empty() {
+            push_indent(indent + 1, out);
+            // quotes only need escaping where three in a row would end the string
+            let quote = if line.contains("\"\"\"") { Some('"') } else { None };
+            write_escaped(line, quote, out);
+        }
+        out.push('\n');
+    }
+    push_indent(indent + 1, out);
+    out.push_str("\"\"\"");
+}
+
+/// Write a node name or identifier, using backticks if it cannot be written bare.
+pub(crate) fn write_name(name: &str, out: &mut String) {
+    if is_bare_ident(name) {
+        out.push_str(name);
+    } else {
+        out.push('`');
+        write_escaped(name, Some('`'), out);
+        out.push('`');
+    }
+}
+
+fn write_float(f: f64, out: &mut String) {
+    // Debug formatting always includes either a decimal point or an exponent, so the
+    // lexer will read the literal back as a float.  It writes infinities as `inf` and
+    // `-inf`, which are keywords, but NaN needs spelling in lowercase.
+    if f.is_nan() {
+        out.push_str("nan");
+    } else {
+        out.push_str(&format!("{:?}", f));
+    }
+}
+
+/// Write a value on a single line.
+///
+/// Dict keys are written in sorted order so that output is stable between runs.
+pub(crate) fn write_value(value: &Value, out: &mut String) {
+    write_value_in(value, None, out)
+}
+
+// Writes a value on a single line, writing the numbers and byte strings in it as they
+// were read if it is the value of an attribute, given as the node it belongs to, its
+// name, and the path of the value within it.
+fn write_value_in(value: &Value, attr: Option<(&Node, &str, Path)>, out: &mut String) {
+    let literal = attr.as_ref().and_then(|&(node, name, ref path)| node.value_literal(name, path));
+    if let Some(literal) = literal {
+        out.push_str(literal.lexeme());
+        return;
+    }
+    match *value {
+        Value::Str(ref s) => write_string(s, out),
+        Value::Int(i) => out.push_str(&i.to_string()),
+        Value::Float(f) => write_float(f, out),
+        // quantities are tagged, so that they can be read without quantity literals
+        Value::Duration(d) => {
+            out.push_str("!duration ");
+            write_string(&units::format_duration(d), out);
+        },
+        Value::ByteSize(b) => {
+            out.push_str("!bytes ");
+            write_string(&units::format_byte_size(b), out);
+        },
+        Value::DateTime(d) => out.push_str(&d.to_string()),
+        Value::Bytes(ref b) => {
+            let read_as = attr.as_ref()
+                .map_or(BytesEncoding::default(), |&(node, name, ref path)| {
+                    node.value_encoding(name, path)
+                });
+            match read_as {
+                BytesEncoding::Base64 => {
+                    out.push_str("b64\"");
+                    out.push_str(&encoding::encode_base64(b));
+                },
+                BytesEncoding::Hex => {
+                    out.push_str("hex\"");
+                    out.push_str(&encoding::encode_hex(b));
+                },
+            }
+            out.push('"');
+        },
+        Value::Bool(b) => out.push_str(if b { "true" } else { "false" }),
+        Value::Ident(ref i) => {
+            out.push('!');
+            write_name(i, out);
+        },
+        Value::Null => out.push_str("null"),
+        Value::List(ref list) => {
+            out.push('[');
+            for (index, item) in list.iter().enumerate() {
+                if index > 0 {
+                    out.push_str(", ");
+                }
+                let item_attr = attr.as_ref()
+                    .map(|&(node, name, ref path)| (node, name, path.index(index)));
+                write_value_in(item, item_attr, out);
+            }
+            out.push(']');
+        },
+        Value::Dict(ref dict) => {
+            let mut keys: Vec<&String> = dict.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (index, key) in keys.into_iter().enumerate() {
+                if index > 0 {
+                    out.push_str(", ");
+                }
+                write_string(key, out);
+                out.push_str(": ");
+                let item_attr = attr.as_ref()
+                    .map(|&(node, name, ref path)| (node, name, path.child(key.as_str())));
+                write_value_in(&dict[key], item_attr, out);
+            }
+            out.push('}');
+        },
+    }
+}
+
+pub(crate) fn push_indent(indent: usize, out: &mut String) {
+    for _ in 0..indent {
+        out.push_str("    ");
+    }
+}
+
+pub(crate) fn sorted<'a, T, I>(iter: I) -> Vec<(&'a String, &'a T)>
+    where I: Iterator<Item=(&'a String, &'a T)> {
+
+    let mut items: Vec<_> = iter.collect();
+    items.sort_by(|a, b| a.0.cmp(b.0));
+    items
+}
+
+/// Write the annotations of an attribute or node, each followed by a space.
+pub(crate) fn write_annotations(annotations: &[Annotation], out: &mut String) {
+    for annotation in annotations {
+        out.push('@');
+        write_name(&annotation.name, out);
+        if !annotation.args.is_empty() {
+            out.push('(');
+            for (index, arg) in annotation.args.iter().enumerate() {
+                if index > 0 {
+                    out.push_str(", ");
+                }
+                write_value(arg, out);
+            }
+            out.push(')');
+        }
+        out.push(' ');
+    }
+}
+
+/// Write an attribute of a node on a line of its own, along with its annotations.
+pub(crate) fn write_attr(node: &Node, key: &str, value: &Value, indent: usize, out: &mut String) {
+    push_indent(indent, out);
+    write_annotations(node.attr_annotations(key), out);
+    write_string(key, out);
+    out.push_str(": ");
+    match *value {
+        Value::Str(ref s) if s.contains('\n') => write_block_string(s, indent, out),
+        // numbers and bytes are written as they were read, as in `0xFF_FF`
+        _ => write_value_in(value, Some((node, key, Path::new())), out),
+    }
+    out.push_str(",\n");
+}
+
+fn write_node_body(node: &Node, indent: usize, out: &mut String) {
+    for (key, value) in sorted(node.iter_attrs()) {
+        write_attr(node, key, value, indent, out);
+    }
+
+    for (index, (name, subnode)) in sorted(node.iter_nodes()).into_iter().enumerate() {
+        if index > 0 || node.has_attrs() {
+            out.push('\n');
+        }
+        write_node(name, subnode, indent, out);
+    }
+}
+
+/// Write the braced block of a node, starting on the current line.
+pub(crate) fn write_block(node: &Node, indent: usize, out: &mut String) {
+    if node.is_empty() {
+        out.push_str("{}\n");
+    } else {
+        out.push_str("{\n");
+        write_node_body(node, indent + 1, out);
+        push_indent(indent, out);
+        out.push_str("}\n");
+    }
+}
+
+/// Write the name and any labels of a node, followed by a space.
+pub(crate) fn write_header(name: &str, node: &Node, out: &mut String) {
+    write_name(name, out);
+    out.push(' ');
+    for label in node.labels() {
+        write_string(label, out);
+        out.push(' ');
+    }
+}
+
+pub(crate) fn write_node(name: &str, node: &Node, indent: usize, out: &mut String) {
+    push_indent(indent, out);
+    write_annotations(node.annotations(), out);
+    write_header(name, node, out);
+    write_block(node, indent, out);
+}
+
+/// Write a document out as a string of figtree source.
+pub fn to_string(doc: &Document) -> String {
+    let mut out = String::new();
+    write_node_body(doc.as_node(), 0, &mut out);
+    out
+}
+
+/// Write a single named node out as a string of figtree source.
+pub fn node_to_string(name: &str, node: &Node) -> String {
+    let mut out = String::new();
+    write_node(name, node, 0, &mut out);
+    out
+}
+
+/// Write a single value out as a string of figtree source.
+pub fn value_to_string(value: &Value) -> String {
+    let mut out = String::new();
+    write_value(value, &mut out);
+    out
+}
+
+/// Write a document out as figtree source to a `Write` implementor.
+///
+/// # Failures
+/// Fails if writing to the output fails.
+pub fn write<W: io::Write>(doc: &Document, mut output: W) -> io::Result<()> {
+    output.write_all(to_string(doc).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::super::figtree::{Figtree, LoadOptions};
+    use std::time::Duration;
+
+    fn written(value: &Value) -> String {
+        value_to_string(value)
+    }
+
+    #[test]
+    fn bare_idents() {
+        assert!(is_bare_ident("node"));
+        assert!(is_bare_ident("node_2"));
+        assert!(!is_bare_ident("2node"));
+        assert!(!is_bare_ident("has space"));
+        assert!(!is_bare_ident(""));
+    }
+
+    #[test]
+    fn write_values() {
+        assert_eq!(written(&Value::new_string("a \"b\"\n")), "\"a \\\"b\\\"\\n\"");
+        assert_eq!(written(&Value::new_int(-4)), "-4");
+        assert_eq!(written(&Value::new_float(3.0)), "3.0");
+        assert_eq!(written(&Value::new_bool(true)), "true");
+        assert_eq!(written(&Value::new_ident("an ident")), "!`an ident`");
+        assert_eq!(written(&Value::new_null()), "null");
+        assert_eq!(
+            written(&Value::new_datetime("2026-10-16T12:00:00.5+01:00".parse().unwrap())),
+            "2026-10-16T12:00:00.5+01:00");
+        assert_eq!(
+            written(&Value::List(vec![Value::new_int(1), Value::List(Vec::new())])),
+            "[1, []]");
+
+        let mut dict = Dict::new();
+        dict.insert("b".to_string(), Value::new_int(2));
+        dict.insert("a".to_string(), Value::new_int(1));
+        assert_eq!(written(&Value::Dict(dict)), "{\"a\": 1, \"b\": 2}");
+    }
+
+    #[test]
+    fn write_quantities() {
+        let values = vec![
+            Value::new_duration(Duration::from_secs(5400)),
+            Value::new_duration(Duration::from_millis(1500)),
+            Value::new_duration(Duration::from_secs(0)),
+            Value::new_byte_size(512 << 20),
+            Value::new_byte_size(1500),
+        ];
+        let list = Value::List(values.clone());
+        assert_eq!(written(&list), concat!(
+            "[!duration \"1h30m\", !duration \"1s500ms\", !duration \"0s\", ",
+            "!bytes \"512MiB\", !bytes \"1500B\"]"));
+
+        let source = format!("'quantities': {}", written(&list));
+        let reparsed = Figtree::from_string(source).parse().unwrap();
+        assert_eq!(reparsed.get_attr("quantities"), Some(&list));
+    }
+
+    #[test]
+    fn write_numeric_literals() {
+        let source = "'mask': 0xFF_FF, 'flags': 0b1010, 'count': 1_000_000, 'scale': 8.e4, \
+                      'list': [0x10]";
+        let mut doc = Figtree::from_string(source).parse().unwrap();
+        assert_eq!(to_string(&doc), concat!(
+            "\"count\": 1_000_000,\n",
+            "\"flags\": 0b1010,\n",
+            "\"list\": [0x10],\n",
+            "\"mask\": 0xFF_FF,\n",
+            "\"scale\": 8.e4,\n"));
+
+        // a literal is not written once the value has changed
+        doc.insert_attr("mask", Value::new_int(255));
+        assert!(to_string(&doc).contains("\"mask\": 255,\n"));
+
+        // numbers in lists and dicts keep their literals too
+        let source = "a { 'x': 0xFF, 'l': [0xFF, 1_000, 2], 'd': {'k': 0o17, 'm': {'n': [0b1]}} }";
+        let mut doc = Figtree::from_string(source).parse().unwrap();
+        let written = to_string(&doc);
+        assert_eq!(written, concat!(
+            "a {\n",
+            "    \"d\": {\"k\": 0o17, \"m\": {\"n\": [0b1]}},\n",
+            "    \"l\": [0xFF, 1_000, 2],\n",
+            "    \"x\": 0xFF,\n",
+            "}\n"));
+        assert_eq!(Figtree::from_string(written.as_str()).parse().unwrap(), doc);
+
+        let node = doc.get_node_mut("a").unwrap();
+        node.get_attr_mut("l").unwrap().get_list_mut().unwrap().remove(0);
+        assert!(to_string(&doc).contains("\"l\": [1000, 2],\n"));
+    }
+
+    #[test]
+    fn write_special_floats() {
+        let list = Value::List(vec![
+            Value::new_float(f64::INFINITY), Value::new_float(f64::NEG_INFINITY),
+            Value::new_float(f64::NAN), Value::new_float(f64::from_bits(1))]);
+        assert_eq!(written(&list), "[inf, -inf, nan, 5e-324]");
+
+        let source = format!("'floats': {}", written(&list));
+        let reparsed = Figtree::from_string(source).parse().unwrap();
+        assert_eq!(reparsed.get_attr("floats"), Some(&list));
+    }
+
+    #[test]
+    fn write_bytes() {
+        let list = Value::List(vec![Value::new_bytes(vec![0xde, 0xad, 0xbe, 0xef]), Value::new_bytes(Vec::new())]);
+        assert_eq!(written(&list), "[b64\"3q2+7w==\", b64\"\"]");
+
+        let source = format!("'bytes': {}", written(&list));
+        let reparsed = Figtree::from_string(source).parse().unwrap();
+        assert_eq!(reparsed.get_attr("bytes"), Some(&list));
+
+        // bytes read from hex are written back as hex
+        let source = "'key': hex\"DEAD beef\", 'keys': [b64\"AP8=\", hex\"00ff\"], \
+                      'd': {'k': hex\"01\"}";
+        let mut doc = Figtree::from_string(source).parse().unwrap();
+        let written = to_string(&doc);
+        assert_eq!(written, concat!(
+            "\"d\": {\"k\": hex\"01\"},\n",
+            "\"key\": hex\"deadbeef\",\n",
+            "\"keys\": [b64\"AP8=\", hex\"00ff\"],\n"));
+        assert_eq!(Figtree::from_string(written.as_str()).parse().unwrap(), doc);
+
+        doc.set_value_encoding("key", Path::new(), BytesEncoding::Base64);
+        assert!(to_string(&doc).contains("\"key\": b64\"3q2+7w==\",\n"));
+    }
+
+    #[test]
+    fn write_block_strings() {
+        let mut doc = Document::new();
+        doc.new_node_or_get("query")
+            .insert_attr("sql", Value::new_string("SELECT *\n  FROM t\n\n-- \"\"\" ${x}\n"));
+        doc.insert_attr("list", Value::from(vec!["a\nb"]));
+
+        let written = to_string(&doc);
+        assert_eq!(written, concat!(
+            "\"list\": [\"a\\nb\"],\n",
+            "\n",
+            "query {\n",
+            "    \"sql\": \"\"\"\n",
+            "        SELECT *\n",
+            "          FROM t\n",
+            "\n",
+            "        -- \\\"\\\"\\\" $${x}\n",
+            "\n",
+            "        \"\"\",\n",
+            "}\n"));
+        assert_eq!(Figtree::from_string(written).parse().unwrap(), doc);
+    }
+
+    #[test]
+    fn write_dollar_braces() {
+        let value = Value::new_string("${a} $${b} $c");
+        assert_eq!(written(&value), "\"$${a} $$${b} $c\"");
+        let doc = Figtree::from_string(format!("'x': {}", written(&value))).parse().unwrap();
+        assert_eq!(doc.get_attr("x"), Some(&value));
+    }
+
+    #[test]
+    fn write_documents() {
+        let mut doc = Document::new();
+        assert_eq!(to_string(&doc), "");
+
+        {
+            let node = doc.new_node_or_get("node");
+            node.insert_attr("b", Value::new_int(2));
+            node.insert_attr("a", Value::List(vec![Value::new_string("x")]));
+            node.new_node_or_get("empty");
+            node.new_node_or_get("sub node").insert_attr("c", Value::new_null());
+        }
+        doc.new_node_or_get("another");
+        doc.insert_attr("version", Value::new_int(2));
+
+        let written = to_string(&doc);
+        assert_eq!(written, concat!(
+            "\"version\": 2,\n",
+            "\n",
+            "another {}\n",
+            "\n",
+            "node {\n",
+            "    \"a\": [\"x\"],\n",
+            "    \"b\": 2,\n",
+            "\n",
+            "    empty {}\n",
+            "\n",
+            "    `sub node` {\n",
+            "        \"c\": null,\n",
+            "    }\n",
+            "}\n"));
+
+        let reparsed = Figtree::from_string(written).parse().unwrap();
+        assert_eq!(reparsed, doc);
+    }
+
+    #[test]
+    fn write_repeated_nodes() {
+        let mut doc = Document::new();
+        for &port in &[80, 443] {
+            let mut server = Node::new();
+            server.insert_attr("port", Value::new_int(port));
+            doc.push_node("server", server);
+        }
+        doc.new_node_or_get("admin");
+
+        let written = to_string(&doc);
+        assert_eq!(written, concat!(
+            "admin {}\n",
+            "\n",
+            "server {\n",
+            "    \"port\": 80,\n",
+            "}\n",
+            "\n",
+            "server {\n",
+            "    \"port\": 443,\n",
+            "}\n"));
+
+        let options = LoadOptions { repeated_nodes: true, ..LoadOptions::default() };
+        let reparsed = Figtree::from_string(written).parse_with(&options).unwrap();
+        assert_eq!(reparsed, doc);
+    }
+
+    #[test]
+    fn write_annotated_entries() {
+        let mut doc = Document::new();
+        doc.insert_attr("timeout", Value::new_int(30));
+        doc.set_attr_annotations("timeout", vec![
+            Annotation::with_args("deprecated", vec![Value::new_string("use timeout_ms")]),
+            Annotation::new("doc"),
+        ]);
+        {
+            let node = doc.new_node_or_get("db");
+            node.set_annotations(vec![Annotation::with_args(
+                "limits", vec![Value::new_int(1), Value::new_bool(false)])]);
+            node.insert_attr("password", Value::new_string("hunter2"));
+            node.set_attr_annotations("password", vec![Annotation::new("secret")]);
+        }
+
+        let written = to_string(&doc);
+        assert_eq!(written, concat!(
+            "@deprecated(\"use timeout_ms\") @doc \"timeout\": 30,\n",
+            "\n",
+            "@limits(1, false) db {\n",
+            "    @secret \"password\": \"hunter2\",\n",
+            "}\n"));
+
+        let reparsed = Figtree::from_string(written).parse().unwrap();
+        assert_eq!(reparsed.attr_annotations("timeout"), doc.attr_annotations("timeout"));
+        let db = reparsed.get_node("db").unwrap();
+        assert_eq!(db.annotations(), doc.get_node("db").unwrap().annotations());
+        assert_eq!(db.attr_annotations("password"), &[Annotation::new("secret")]);
+    }
+
+    #[test]
+    fn write_labelled_nodes() {
+        let mut doc = Document::new();
+        doc.push_node("backend", Node::with_labels(vec!["api", "eu \"west\""]));
+        doc.push_node("backend", Node::with_labels(vec!["web"]));
+        doc.get_nodes_mut("backend")[1].insert_attr("port", Value::new_int(80));
+
+        let written = to_string(&doc);
+        assert_eq!(written, concat!(
+            "backend \"api\" \"eu \\\"west\\\"\" {}\n",
+            "\n",
+            "backend \"web\" {\n",
+            "    \"port\": 80,\n",
+            "}\n"));
+        assert_eq!(Figtree::from_string(written).parse().unwrap(), doc);
+    }
+}
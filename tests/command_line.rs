@@ -0,0 +1,256 @@
+use std::process::{Command, Output};
+
+const SAMPLE: &str = "tests/resources/sample.ft";
+const INVALID: &str = "tests/resources/invalid.ft";
+
+fn figtree(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_figtree"))
+        .args(args)
+        .output()
+        .expect("could not run figtree binary")
+}
+
+#[test]
+fn checking_files() {
+    let output = figtree(&["check", SAMPLE]);
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+
+    let output = figtree(&["check", SAMPLE, INVALID]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "tests/resources/invalid.ft:3:5: unexpected '}'\n");
+}
+
+#[test]
+fn getting_values() {
+    let output = figtree(&["get", "test.string", SAMPLE]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "value\n");
+
+    let output = figtree(&["get", "test.list[3]", SAMPLE]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "4.0\n");
+
+    let output = figtree(&["get", "test.subtest.dict.\"an identifier\"", SAMPLE]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "!jello_shots\n");
+
+    let output = figtree(&["get", "test.missing", SAMPLE]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn getting_nodes() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("getting_nodes");
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("labelled.ft");
+    let file = file.to_str().unwrap();
+    std::fs::write(file, "backend \"api\" { 'port': 80 }\nbackend \"web\" {}").unwrap();
+
+    // node paths can end in a label
+    let output = figtree(&["get", "backend[\"api\"]", file]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "backend \"api\" {\n    \"port\": 80,\n}\n");
+
+    // or in an index
+    let output = figtree(&["get", "test.subtest[0]", SAMPLE]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().starts_with("subtest {\n"));
+}
+
+#[test]
+fn dumping_documents() {
+    let output = figtree(&["dump", SAMPLE]);
+    assert!(output.status.success());
+    let dumped = String::from_utf8(output.stdout).unwrap();
+    assert!(dumped.starts_with("test {\n    \"list\": [\"value 1\", \"value 2\", 3, 4.0, \"value 5\"],\n"));
+}
+
+#[test]
+fn diffing_files() {
+    let output = figtree(&["diff", SAMPLE, SAMPLE]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("diffing_files");
+    std::fs::create_dir_all(&dir).unwrap();
+    let changed = dir.join("changed.ft");
+    let source = std::fs::read_to_string(SAMPLE).unwrap()
+        .replace("\"seven\": 7", "\"seven\": 8");
+    std::fs::write(&changed, source).unwrap();
+
+    let output = figtree(&["diff", SAMPLE, changed.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "~ test.subtest.dict.seven: 7 -> 8\n");
+}
+
+#[test]
+fn patching_files() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("patching_files");
+    std::fs::create_dir_all(&dir).unwrap();
+    let patch = dir.join("patch.json");
+    let patch = patch.to_str().unwrap();
+
+    std::fs::write(patch, r#"[
+        {"op": "test", "path": "test.string", "value": "value"},
+        {"op": "replace", "path": "test.subtest.dict.seven", "value": 8}
+    ]"#).unwrap();
+    let output = figtree(&["patch", patch, SAMPLE]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().contains("\"seven\": 8"));
+
+    std::fs::write(patch, r#"[{"op": "test", "path": "test.string", "value": "other"}]"#)
+        .unwrap();
+    let output = figtree(&["patch", patch, SAMPLE]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!("{}: operation 0: test failed at `test.string`\n", patch));
+}
+
+#[test]
+fn merging_files() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("merging_files");
+    std::fs::create_dir_all(&dir).unwrap();
+    let write = |name: &str, source: &str| {
+        let path = dir.join(name);
+        std::fs::write(&path, source).unwrap();
+        path.to_str().unwrap().to_string()
+    };
+    let base = write("base.ft", "server { 'port': 80, 'workers': 4 }");
+    let ours = write("ours.ft", "server { 'port': 8080, 'workers': 4 }");
+    let theirs = write("theirs.ft", "server { 'port': 80, 'workers': 8 }");
+    let conflicting = write("conflicting.ft", "server { 'port': 8000, 'workers': 4 }");
+
+    let output = figtree(&["merge", &base, &ours, &theirs]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "server {\n    \"port\": 8080,\n    \"workers\": 8,\n}\n");
+
+    let output = figtree(&["merge", &base, &ours, &conflicting]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stdout).unwrap().contains("<<<<<<< ours\n"));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "conflict at `server.port`: changed differently in both\n");
+}
+
+#[test]
+fn loading_with_conditions() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("loading_with_conditions");
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("conditional.ft");
+    let file = file.to_str().unwrap();
+    std::fs::write(file, concat!(
+        "server { 'x': 0, when(region == 'eu') { 'x': 1 } ",
+        "when(profile == 'prod') { 'y': 2 } }")).unwrap();
+
+    // without a value for `region`, the blocks that use it are left out
+    let output = figtree(&["check", file]);
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+
+    // but the rest of the file is still loaded in full
+    for (name, source, message) in [
+        ("reference.ft", "a { 'x': '${b.y}' }", "1:11: `${b.y}` does not refer to anything\n"),
+        ("cycle.ft", "a : b {} b : a {}", "1:7: `b` inherits from the node that extends it\n"),
+        ("tag.ft", "a { 'x': !nope 1 }", "1:10: unknown tag `!nope`\n"),
+    ] {
+        let invalid = dir.join(name);
+        std::fs::write(&invalid, source).unwrap();
+        let output = figtree(&["check", invalid.to_str().unwrap()]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(String::from_utf8(output.stderr).unwrap().ends_with(message));
+    }
+
+    let output = figtree(&["get", "server.x", file]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("`region` has no value"));
+
+    let output = figtree(&["get", "--var", "region=eu", "server.x", file]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1
+");
+
+    let output = figtree(&["dump", file, "--var", "region=us", "--profile", "prod"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "server {\n    \"x\": 0,\n    \"y\": 2,\n}\n");
+
+    let output = figtree(&["check", "--var", "tier=db", file]);
+    assert_eq!(output.status.code(), Some(1));
+
+    assert_eq!(figtree(&["check", "--var", "region", file]).status.code(), Some(2));
+    assert_eq!(figtree(&["check", file, "--profile"]).status.code(), Some(2));
+}
+
+#[test]
+fn loading_repeated_nodes() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("loading_repeated_nodes");
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("repeated.ft");
+    let file = file.to_str().unwrap();
+    std::fs::write(file, "server { 'p': 1 }\nserver { 'p': 2 }\n").unwrap();
+
+    let output = figtree(&["check", file]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().ends_with("repeated node `server`\n"));
+
+    assert!(figtree(&["check", "--repeated", file]).status.success());
+    let output = figtree(&["get", "--repeated", "server[1].p", file]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "2\n");
+    let output = figtree(&["dump", file, "--repeated"]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "server {\n    \"p\": 1,\n}\n\nserver {\n    \"p\": 2,\n}\n");
+
+    // the layout does not depend on how the nodes are loaded
+    assert!(figtree(&["fmt", file]).status.success());
+    assert!(figtree(&["fmt", "--check", file]).status.success());
+}
+
+#[test]
+fn usage_errors() {
+    assert_eq!(figtree(&[]).status.code(), Some(2));
+    assert_eq!(figtree(&["frobnicate"]).status.code(), Some(2));
+    assert_eq!(figtree(&["get", "test.string"]).status.code(), Some(2));
+    assert_eq!(figtree(&["fmt", "--check"]).status.code(), Some(2));
+}
+
+#[test]
+fn formatting_files() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("formatting_files");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(".figtreefmt"), "format { 'indent_width': 2 }").unwrap();
+    let file = dir.join("unformatted.ft");
+    let file = file.to_str().unwrap();
+    std::fs::write(file, "node{'key' :1 // comment\n}").unwrap();
+
+    let output = figtree(&["fmt", "--check", file]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("{}\n", file));
+
+    let output = figtree(&["fmt", file]);
+    assert!(output.status.success());
+    assert_eq!(
+        std::fs::read_to_string(file).unwrap(),
+        "node {\n  \"key\": 1, // comment\n}\n");
+
+    let output = figtree(&["fmt", "--check", file]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let output = figtree(&["fmt", "--check", INVALID]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "tests/resources/invalid.ft:3:5: unexpected '}'\n");
+}